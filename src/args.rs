@@ -1,77 +1,565 @@
-use std::{path::PathBuf, process::exit};
+use crate::toolchain::{Target, Toolchain};
+use std::{
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 const HELP: &str = "\
 Black Lang
 
 \x1b[92mUSAGE\x1b[00m:
-  \x1b[33mblack [OPTIONS] <FILE(s)>\x1b[00m
+  \x1b[33mblack <COMMAND> [OPTIONS] [-- ARGS...]\x1b[00m
+
+\x1b[92mCOMMANDS\x1b[00m:
+  run [FILE(s)]         \x1b[90mBuild and run a file or the project, -i interprets it instead\x1b[00m
+  build [FILE(s)]       \x1b[90mBuild a file, or the project of black.toml into target/\x1b[00m
+  check [FILE(s)]       \x1b[90mParse and type-check a file or the project, without running it\x1b[00m
+  repl                  \x1b[90mStart the interactive mode (default without arguments)\x1b[00m
+  fmt [FILE(s)]         \x1b[90mFormat files in place, or stdin to stdout\x1b[00m
+  lint [FILE(s)]        \x1b[90mReport likely mistakes in files, or in stdin\x1b[00m
+  lsp                   \x1b[90mStart the language server, over stdin and stdout\x1b[00m
 
 \x1b[92mFLAGS\x1b[00m:
-  -i, --interpreter     \x1b[90mUse interpreter instead of compiling to a binary\x1b[00m
-  -r, --run             \x1b[90mBuild and run a file\x1b[00m
-  -s, --static          \x1b[90mStaticaly link output binary\x1b[00m
-  -h, --help            \x1b[90mPrints help information\x1b[00m
+  -h, --help            \x1b[90mPrints help information, `black <COMMAND> --help` for a command\x1b[00m
   -V, --version         \x1b[90mPrints black version\x1b[00m
 
+\x1b[92mDEPRECATED\x1b[00m:
+  black FILE            \x1b[90mUse `black build FILE`\x1b[00m
+  black -i FILE         \x1b[90mUse `black run -i FILE`\x1b[00m
+  black -r FILE         \x1b[90mUse `black run FILE`\x1b[00m
+";
+
+const RUN_HELP: &str = "\
+Builds and runs a file, or the project of the black.toml in the current directory or its parents
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack run [OPTIONS] [FILE] [MODULES...] [-- ARGS...]\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+  -i, --interpreter     \x1b[90mInterpret the program instead of compiling it\x1b[00m
+  -s, --static          \x1b[90mStaticaly link the binary\x1b[00m
+  -h, --help            \x1b[90mPrints help information\x1b[00m
+
+\x1b[92mOPTIONS\x1b[00m:
+  -o, --output PATH     \x1b[90mSets the binary path (default: out.app)\x1b[00m
+      --qbe PATH        \x1b[90mThe qbe to use (default: $BLACK_QBE, or qbe in PATH)\x1b[00m
+      --cc PATH         \x1b[90mThe C compiler to use (default: $BLACK_CC, or cc in PATH)\x1b[00m
+  -l NAME               \x1b[90mLinks the library\x1b[00m
+  -L DIR                \x1b[90mSearches the directory for libraries\x1b[00m
+      --target TARGET   \x1b[90mamd64_sysv, amd64_apple, arm64, arm64_apple or rv64 (default: host)\x1b[00m
+      --sysroot DIR     \x1b[90mThe sysroot given to cc, for other targets\x1b[00m
+      --backend NAME    \x1b[90mqbe, or native for the built-in x86-64 one (default: qbe)\x1b[00m
+";
+
+const BUILD_HELP: &str = "\
+Builds a file, or the project of the black.toml in the current directory or its parents
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack build [OPTIONS] [FILE] [MODULES...]\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+  -s, --static          \x1b[90mStaticaly link the binary\x1b[00m
+  -h, --help            \x1b[90mPrints help information\x1b[00m
+
 \x1b[92mOPTIONS\x1b[00m:
-  -o, --output PATH     \x1b[90mSets an output path (default: out.app)\x1b[00m
+  -o, --output PATH     \x1b[90mSets the binary path (default: out.app, target/ for projects)\x1b[00m
+      --emit KIND(s)    \x1b[90mComma separated stages to write: tokens, ast, ir, asm, obj, exe\x1b[00m
+                        \x1b[90m(default: exe). They're written next to the binary, with the\x1b[00m
+                        \x1b[90mextension of the stage, or to PATH for a single one. `-o -`\x1b[00m
+                        \x1b[90mwrites to stdout\x1b[00m
+      --qbe PATH        \x1b[90mThe qbe to use (default: $BLACK_QBE, or qbe in PATH)\x1b[00m
+      --cc PATH         \x1b[90mThe C compiler to use (default: $BLACK_CC, or cc in PATH)\x1b[00m
+  -l NAME               \x1b[90mLinks the library\x1b[00m
+  -L DIR                \x1b[90mSearches the directory for libraries\x1b[00m
+      --target TARGET   \x1b[90mamd64_sysv, amd64_apple, arm64, arm64_apple or rv64 (default: host)\x1b[00m
+      --sysroot DIR     \x1b[90mThe sysroot given to cc, for other targets\x1b[00m
+      --backend NAME    \x1b[90mqbe, or native for the built-in x86-64 one (default: qbe)\x1b[00m
+";
+
+const CHECK_HELP: &str = "\
+Parses and type-checks a file or the project, reporting the first error without running anything
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack check [FILE] [MODULES...]\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+  -h, --help            \x1b[90mPrints help information\x1b[00m
+";
+
+const REPL_HELP: &str = "\
+Starts the interactive mode, an empty line runs the input and `exit` or `quit` leaves
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack repl\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+  -h, --help            \x1b[90mPrints help information\x1b[00m
+";
+
+const FMT_HELP: &str = "\
+Formats files in place, or stdin to stdout when no file is given
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack fmt [OPTIONS] [FILE(s)]\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+      --check           \x1b[90mOnly check that the files are formatted\x1b[00m
+  -h, --help            \x1b[90mPrints help information\x1b[00m
+";
+
+const LINT_HELP: &str = "\
+Reports likely mistakes in files, or in stdin when no file is given
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack lint [FILE(s)]\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+  -h, --help            \x1b[90mPrints help information\x1b[00m
+";
+
+const LSP_HELP: &str = "\
+Starts the language server, speaking the Language Server Protocol over stdin and stdout
+
+\x1b[92mUSAGE\x1b[00m:
+  \x1b[33mblack lsp\x1b[00m
+
+\x1b[92mFLAGS\x1b[00m:
+  -h, --help            \x1b[90mPrints help information\x1b[00m
 ";
 
 const VERSION: &str = "Black version: \x1b[92mv0.0.1\x1b[00m";
 
-#[derive(Debug, PartialEq)]
+/// Command given as the first argument
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Subcommand {
+    /// Builds a file, or the project described by `black.toml`
+    Build,
+    /// Builds and runs a file, or the project described by `black.toml`
+    Run,
+    /// Parses and type-checks a file or the project
+    Check,
+    /// Runs the interactive mode
+    Repl,
+    /// Formats source files
+    Fmt,
+    /// Lints source files
+    Lint,
+    /// Runs the language server
+    Lsp,
+}
+
+impl Subcommand {
+    fn from_name(name: &str) -> Option<Subcommand> {
+        match name {
+            "build" => Some(Subcommand::Build),
+            "run" => Some(Subcommand::Run),
+            "check" => Some(Subcommand::Check),
+            "repl" => Some(Subcommand::Repl),
+            "fmt" => Some(Subcommand::Fmt),
+            "lint" => Some(Subcommand::Lint),
+            "lsp" => Some(Subcommand::Lsp),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Subcommand::Build => "build",
+            Subcommand::Run => "run",
+            Subcommand::Check => "check",
+            Subcommand::Repl => "repl",
+            Subcommand::Fmt => "fmt",
+            Subcommand::Lint => "lint",
+            Subcommand::Lsp => "lsp",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Subcommand::Build => BUILD_HELP,
+            Subcommand::Run => RUN_HELP,
+            Subcommand::Check => CHECK_HELP,
+            Subcommand::Repl => REPL_HELP,
+            Subcommand::Fmt => FMT_HELP,
+            Subcommand::Lint => LINT_HELP,
+            Subcommand::Lsp => LSP_HELP,
+        }
+    }
+
+    /// Whether the command accepts the flag, `None` being the deprecated form without a command
+    fn accepts(command: Option<Subcommand>, flag: &str) -> bool {
+        match command {
+            None => true,
+            Some(Subcommand::Run) => {
+                matches!(
+                    flag,
+                    "-i" | "-s"
+                        | "-o"
+                        | "--qbe"
+                        | "--cc"
+                        | "-l"
+                        | "-L"
+                        | "--target"
+                        | "--sysroot"
+                        | "--backend"
+                        | "--"
+                )
+            }
+            Some(Subcommand::Build) => {
+                matches!(
+                    flag,
+                    "-s" | "-o"
+                        | "--emit"
+                        | "--qbe"
+                        | "--cc"
+                        | "-l"
+                        | "-L"
+                        | "--target"
+                        | "--sysroot"
+                        | "--backend"
+                )
+            }
+            Some(Subcommand::Fmt) => flag == "--check",
+            _ => false,
+        }
+    }
+}
+
+/// Stage of the compilation written by `--emit`, in the order they're produced
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    /// QBE intermediate language
+    Ir,
+    Asm,
+    /// Object file of the program and the runtime, linkable with `cc`
+    Obj,
+    Exe,
+}
+
+impl Emit {
+    pub const ALL: [Emit; 6] = [
+        Emit::Tokens,
+        Emit::Ast,
+        Emit::Ir,
+        Emit::Asm,
+        Emit::Obj,
+        Emit::Exe,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Emit::Tokens => "tokens",
+            Emit::Ast => "ast",
+            Emit::Ir => "ir",
+            Emit::Asm => "asm",
+            Emit::Obj => "obj",
+            Emit::Exe => "exe",
+        }
+    }
+
+    /// Path of the stage written next to the binary
+    pub fn path(self, output: &Path) -> PathBuf {
+        match self {
+            Emit::Tokens => output.with_extension("tokens"),
+            Emit::Ast => output.with_extension("ast"),
+            Emit::Ir => output.with_extension("ssa"),
+            Emit::Asm => output.with_extension("s"),
+            Emit::Obj => output.with_extension("o"),
+            Emit::Exe => output.to_path_buf(),
+        }
+    }
+}
+
+/// Code generator of the compiler
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum Backend {
+    /// Optimizing code generation by QBE, for every target
+    #[default]
+    Qbe,
+    /// Built-in x86-64 code generation, needing no QBE
+    Native,
+}
+
+#[derive(Debug, Default, PartialEq)]
 pub struct AppArgs {
     // Flags
     pub static_link: bool,
     pub interpreter: bool,
     pub build_and_run: bool,
+    /// Whether `fmt` only checks that the files are formatted
+    pub check: bool,
 
     // Options
     pub input: Option<PathBuf>,
+    /// Further source files, modules which any file of the program can import by name
+    pub modules: Vec<PathBuf>,
+    /// Entry files of the dependencies of a project, by the names they're imported as
+    pub packages: Vec<(String, PathBuf)>,
+    pub command: Option<Subcommand>,
+    /// Files given to `fmt` or `lint`
+    pub files: Vec<PathBuf>,
     pub output: PathBuf,
+    /// Tools given by `--qbe` and `--cc`
+    pub qbe: Option<PathBuf>,
+    pub cc: Option<PathBuf>,
+    /// `-l` and `-L` flags passed to the linker
+    pub link_args: Vec<String>,
+    /// Target given by `--target`, the host when `None`
+    pub target: Option<Target>,
+    pub sysroot: Option<PathBuf>,
+    /// Code generator turning the IR into assembly
+    pub backend: Backend,
+    /// Stages written by `--emit` and their paths, only the binary when empty
+    pub emit: Vec<(Emit, PathBuf)>,
+    /// Arguments after `--`, passed to the program by the `-i` and `-r` modes
+    pub program_args: Vec<String>,
 }
 
-pub fn get_args(args: &Vec<String>) -> AppArgs {
-    let mut args = args.iter().skip(1); // Skip the program name
+impl AppArgs {
+    /// Path the stage is written to, if it's emitted
+    pub fn emit_path(&self, stage: Emit) -> Option<&Path> {
+        if self.emit.is_empty() {
+            return (stage == Emit::Exe).then_some(self.output.as_path());
+        }
+        self.emit
+            .iter()
+            .find(|(kind, _)| *kind == stage)
+            .map(|(_, path)| path.as_path())
+    }
+
+    /// Last stage the compilation goes through
+    pub fn last_stage(&self) -> Emit {
+        self.emit
+            .iter()
+            .map(|(kind, _)| *kind)
+            .max()
+            .unwrap_or(Emit::Exe)
+    }
+}
+
+/// Parses the backend given to `--backend`
+fn backend_named(name: &str) -> Backend {
+    match name {
+        "qbe" => Backend::Qbe,
+        "native" => Backend::Native,
+        _ => {
+            eprintln!("Error: Unknown backend '{name}', the backends are: qbe, native");
+            exit(1);
+        }
+    }
+}
+
+/// Parses the target given to `--target`
+fn target_named(name: &str) -> Target {
+    Target::from_name(name).unwrap_or_else(|| {
+        let names: Vec<_> = Target::ALL.iter().map(|target| target.name()).collect();
+        eprintln!(
+            "Error: Unknown target '{name}', the targets are: {}",
+            names.join(", ")
+        );
+        exit(1);
+    })
+}
+
+/// Parses the comma separated stages of `--emit`
+fn emit_kinds(list: &str) -> Vec<Emit> {
+    let mut kinds = Vec::new();
+    for name in list.split(',').map(str::trim) {
+        let Some(kind) = Emit::ALL.into_iter().find(|kind| kind.name() == name) else {
+            let names: Vec<_> = Emit::ALL.iter().map(|kind| kind.name()).collect();
+            eprintln!(
+                "Error: Unknown --emit kind '{name}', the kinds are: {}",
+                names.join(", ")
+            );
+            exit(1);
+        };
+        if !kinds.contains(&kind) {
+            kinds.push(kind);
+        }
+    }
+    kinds
+}
+
+pub fn get_args(args: &[String]) -> AppArgs {
+    let mut args = args.iter().skip(1).peekable(); // Skip the program name
 
     let mut input = None;
+    let mut modules = Vec::new();
     let mut output = PathBuf::from("out.app");
+    let mut output_given = false;
+    let mut emit = Vec::new();
+    let mut qbe = None;
+    let mut cc = None;
+    let mut link_args = Vec::new();
+    let mut target = None;
+    let mut backend = Backend::Qbe;
+    let mut sysroot = None;
     let mut interpreter = false;
     let mut build_and_run = false;
     let mut static_link = false;
+    let mut check = false;
+    let mut files = Vec::new();
+    let mut program_args = Vec::new();
+
+    // Without arguments black starts the interactive mode
+    let command = match args.peek() {
+        None => Some(Subcommand::Repl),
+        Some(arg) => Subcommand::from_name(arg),
+    };
+    if command.is_some() {
+        args.next();
+    }
 
     while let Some(arg) = args.next() {
-        match arg.as_str() {
+        // `--emit=KINDS` is the same as `--emit KINDS`, and `-lNAME` the same as `-l NAME`
+        let (arg, value) = match arg.split_once('=') {
+            Some((
+                flag @ ("--emit" | "--qbe" | "--cc" | "--target" | "--sysroot" | "--backend"),
+                value,
+            )) => (flag, Some(value.to_string())),
+            _ if arg.len() > 2 && (arg.starts_with("-l") || arg.starts_with("-L")) => {
+                (&arg[..2], Some(arg[2..].to_string()))
+            }
+            _ => (arg.as_str(), None),
+        };
+        let flag = match arg {
+            "--interpreter" => "-i",
+            "--run" => "-r",
+            "--static" => "-s",
+            "--output" => "-o",
+            flag => flag,
+        };
+        match flag {
             "-h" | "--help" => {
-                print!("{}", HELP);
+                print!("{}", command.map_or(HELP, Subcommand::help));
                 exit(0);
             }
             "-V" | "--version" => {
                 println!("{}", VERSION);
                 exit(0);
             }
-            "-i" | "--interpreter" => interpreter = true,
-            "-r" | "--run" => build_and_run = true,
-            "-s" | "--static" => static_link = true,
-            "-o" | "--output" => {
+            "-i" | "-r" | "-s" | "-o" | "--emit" | "--qbe" | "--cc" | "-l" | "-L" | "--target"
+            | "--sysroot" | "--backend" | "--check" | "--"
+                if !Subcommand::accepts(command, flag) =>
+            {
+                let name = command.map_or("", Subcommand::name);
+                eprintln!("Error: `black {name}` doesn't take '{arg}', see `black {name} --help`");
+                exit(1);
+            }
+            "-i" => interpreter = true,
+            "-r" => build_and_run = true,
+            "-s" => static_link = true,
+            "--check" => check = true,
+            "-o" => {
                 output = args.next().map(PathBuf::from).unwrap_or_else(|| {
                     eprintln!("Error: Missing output path after -o/--output");
                     exit(1);
                 });
+                output_given = true;
             }
-            _ if input.is_none() => input = Some(PathBuf::from(arg)),
-            _ => {
+            "--emit" => {
+                let list = value.or_else(|| args.next().cloned()).unwrap_or_else(|| {
+                    eprintln!("Error: Missing stages after --emit");
+                    exit(1);
+                });
+                emit = emit_kinds(&list);
+            }
+            "--qbe" | "--cc" | "-l" | "-L" | "--target" | "--sysroot" | "--backend" => {
+                let value = value.or_else(|| args.next().cloned()).unwrap_or_else(|| {
+                    eprintln!("Error: Missing value after {flag}");
+                    exit(1);
+                });
+                match flag {
+                    "--qbe" => qbe = Some(PathBuf::from(value)),
+                    "--cc" => cc = Some(PathBuf::from(value)),
+                    "--sysroot" => sysroot = Some(PathBuf::from(value)),
+                    "--target" => target = Some(target_named(&value)),
+                    "--backend" => backend = backend_named(&value),
+                    _ => link_args.push(format!("{flag}{value}")),
+                }
+            }
+            "--" => program_args.extend(args.by_ref().cloned()),
+            _ if arg.starts_with('-') => {
                 eprintln!("Error: Unexpected argument '{}'", arg);
                 exit(1);
             }
+            _ => match command {
+                Some(Subcommand::Fmt | Subcommand::Lint) => files.push(PathBuf::from(arg)),
+                Some(Subcommand::Repl | Subcommand::Lsp) => {
+                    let name = command.map_or("", Subcommand::name);
+                    eprintln!("Error: `black {name}` takes no files, unexpected argument '{arg}'");
+                    exit(1);
+                }
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => modules.push(PathBuf::from(arg)),
+            },
         }
     }
 
+    // The flags of the deprecated form without a command, kept working for existing scripts
+    if command.is_none() {
+        let replacement = if interpreter {
+            "black run -i FILE"
+        } else if build_and_run {
+            "black run FILE"
+        } else {
+            "black build FILE"
+        };
+        eprintln!("Warning: Running black without a command is deprecated, use `{replacement}`");
+    }
+
+    if !emit.is_empty() && (interpreter || build_and_run) {
+        eprintln!("Error: --emit only works when building, see `black build --help`");
+        exit(1);
+    }
+    let runs = build_and_run || (command == Some(Subcommand::Run) && !interpreter);
+    if runs && !Toolchain::runs_on_host(target) {
+        let name = target.map_or("", Target::name);
+        eprintln!(
+            "Error: Binaries for {name} don't run on this machine, build them with `black build`"
+        );
+        exit(1);
+    }
+
+    // A single stage goes to the output path itself
+    let single = output_given && emit.len() == 1;
+    let emit = emit
+        .into_iter()
+        .map(|kind| {
+            let path = if single {
+                output.clone()
+            } else {
+                kind.path(&output)
+            };
+            (kind, path)
+        })
+        .collect();
+
+    // Files given to `run` are built and run, unless interpreted
+    if command == Some(Subcommand::Run) && input.is_some() {
+        build_and_run = !interpreter;
+    }
+
     AppArgs {
         input,
+        modules,
+        packages: Vec::new(),
+        command,
+        files,
         interpreter,
+        check,
         build_and_run,
         static_link,
         output,
+        emit,
+        qbe,
+        cc,
+        link_args,
+        target,
+        sysroot,
+        backend,
+        program_args,
     }
 }
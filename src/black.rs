@@ -1,35 +1,55 @@
 use crate::{
     compiler::Compiler,
     interpreter::Interpreter,
-    utils::{display_error, get_tmp_fname, ErrorType, Output},
+    utils::{display_error, get_tmp_fname, write_output, ErrorType, Output},
 };
 use std::{
     fs::{canonicalize, read_to_string},
     io::stdin,
+    path::Path,
     process::{exit, Command, Stdio},
 };
 
 // TODO:
 // - if, else expr
 // - fn expr
-// - type checker
 // - static qbe in release gh
 // - comments in the parser (will enable formatter)
 // - single quote support (should it do something different than double quotes? maybe multi line?)
-// - linter (another bin)
-// - formatter (another bin)
-// - build system (toml? github repos as packages. line eg. Ernest1338/package = "0.1")
 // - more test cases
 
 mod args;
-use args::get_args;
+use args::{get_args, Emit, Subcommand};
+
+mod builtins;
+
+mod checker;
+use checker::Checker;
 
 mod compiler;
 
+mod formatter;
+
 mod interpreter;
 
+mod json;
+
+mod linter;
+
+mod lsp;
+
+mod modules;
+
+mod native;
+use modules::Loader;
+
+mod project;
+use project::{Project, MANIFEST};
+
 mod parser;
-use parser::{lexer, preprocess, Expr, Parser};
+use parser::{lexer_with_spans, preprocess, Expr, Parser};
+
+mod toolchain;
 
 mod utils;
 use utils::{dbg, dbg_pretty, measure_time, print_and_flush};
@@ -47,70 +67,53 @@ const INTERACTIVE_BANNER: &str = "\
 
 /// Entry point of the language CLI
 fn main() {
-    let env_args = std::env::args().collect();
-    let args = get_args(&env_args);
-
-    if env_args.len() == 1 {
-        // ----------------
-        // Interactive mode
-        // ----------------
-        print_and_flush(INTERACTIVE_BANNER);
-        let mut interpreter = Interpreter::default();
-        loop {
-            print_and_flush(">>> ");
-            let mut input = String::new();
-            loop {
-                let mut tmp = String::new();
-                stdin()
-                    .read_line(&mut tmp)
-                    .expect("Error: reading user input");
-
-                // Short circuit exit on "exit" or "quit"
-                if ["exit", "quit"].contains(&tmp.trim()) {
-                    exit(0);
-                }
-
-                input.push_str(&tmp);
-                if input.ends_with("\n\n") {
-                    break;
-                }
-
-                print_and_flush("  … ");
-            }
-            input = input.trim().to_string();
-
-            let code = preprocess(&input);
-
-            let tmp_code_fname = get_tmp_fname("black_interactive");
-            std::fs::write(&tmp_code_fname, &code).expect("Failed to write temporary file");
-
-            let tokens = match lexer(&code) {
-                Ok(tokens) => tokens,
-                Err(err) => {
-                    display_error(err, &tmp_code_fname, Output::Stdout);
-                    continue;
-                }
-            };
-            let mut parser = Parser::new(&tokens);
-            let ast = match parser.parse() {
-                Ok(ast) => ast,
-                Err(err) => {
-                    display_error(err, &tmp_code_fname, Output::Stdout);
-                    continue;
-                }
-            };
-            interpreter.ast = ast;
-
-            // Clear last line
-            print!("\x1b[1A\x1b[2K");
-
-            let res = interpreter.run();
-            if let Err(err) = res {
-                display_error(err, &tmp_code_fname, Output::Stdout);
-            }
+    let env_args: Vec<String> = std::env::args().collect();
+    let mut args = get_args(&env_args);
 
-            std::fs::remove_file(tmp_code_fname).expect("Failed to remove temporary file");
-        }
+    // ---------
+    // Formatter
+    // ---------
+    if args.command == Some(Subcommand::Fmt) {
+        exit(formatter::format_files(&args.files, args.check));
+    }
+
+    // ------
+    // Linter
+    // ------
+    if args.command == Some(Subcommand::Lint) {
+        exit(linter::lint_files(&args.files));
+    }
+
+    // ---------------
+    // Language server
+    // ---------------
+    if args.command == Some(Subcommand::Lsp) {
+        exit(lsp::serve());
+    }
+
+    // ----------------
+    // Interactive mode
+    // ----------------
+    if args.command == Some(Subcommand::Repl) {
+        repl();
+    }
+
+    // ------------------
+    // Project (black.toml)
+    // ------------------
+    // Commands given no file work on the project around the current directory
+    if args.command.is_some() && args.input.is_none() {
+        let project = Project::find().and_then(|root| {
+            let project = Project::load(&root)?;
+            project.app_args(&args)
+        });
+        args = match project {
+            Ok(project_args) => project_args,
+            Err(err) => {
+                display_error(err, MANIFEST, Output::Stderr);
+                exit(1);
+            }
+        };
     }
 
     // -------------------
@@ -140,57 +143,105 @@ fn main() {
     // ----------------
     // Lexical Analysis
     // ----------------
-    let tokens = measure_time("Lexical Analysis", || match lexer(&source_code) {
-        Ok(tokens) => tokens,
-        Err(err) => {
-            display_error(err, input_file, Output::Stderr);
-            exit(1);
+    let (tokens, spans) = measure_time("Lexical Analysis", || {
+        match lexer_with_spans(&source_code) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                display_error(err, input_file, Output::Stderr);
+                exit(1);
+            }
         }
     });
     dbg("Tokens", &tokens);
 
+    if let Some(path) = args.emit_path(Emit::Tokens) {
+        let listing: String = tokens
+            .iter()
+            .zip(&spans)
+            .map(|(token, span)| format!("{}:{} {token:?}\n", span.line, span.col))
+            .collect();
+        emit(path, &listing, input_file);
+    }
+    if args.last_stage() == Emit::Tokens {
+        exit(0);
+    }
+
     // -------
     // Parsing
     // -------
-    let mut parser = Parser::new(&tokens);
-    let ast = measure_time("Parsing", || match parser.parse() {
+    let mut parser = Parser::with_spans(&tokens, &spans);
+    let mut ast = measure_time("Parsing", || match parser.parse() {
         Ok(ast) => ast,
         Err(err) => {
-            let message = match err {
-                ErrorType::SyntaxError(e) => e,
-                _ => "".to_string(),
-            };
-            display_error(ErrorType::SyntaxError(message), input_file, Output::Stderr);
+            display_error(err, input_file, Output::Stderr);
             exit(1);
         }
     });
     dbg_pretty("AST", &ast);
 
+    if let Some(path) = args.emit_path(Emit::Ast) {
+        emit(path, &format!("{ast:#?}\n"), input_file);
+    }
+    if args.last_stage() == Emit::Ast {
+        exit(0);
+    }
+
+    // -------
+    // Modules
+    // -------
+    measure_time("Loading Modules", || {
+        if let Err(err) =
+            Loader::new(&args.modules, &args.packages).load(&mut ast, Path::new(input_file))
+        {
+            display_error(err, input_file, Output::Stderr);
+            exit(1);
+        }
+    });
+
+    // -----------------
+    // Semantic Analysis
+    // -----------------
+    let mut checker = Checker::new();
+    measure_time("Semantic Analysis", || {
+        if let Err(err) = checker.check(&mut ast) {
+            display_error(err, input_file, Output::Stderr);
+            exit(1);
+        }
+    });
+    dbg("Checked Variables", &checker.variables);
+
+    if args.command == Some(Subcommand::Check) {
+        exit(0);
+    }
+
     if args.interpreter {
         // -----------
         // Interpreter
         // -----------
         let mut interpreter = Interpreter::from_ast(ast);
+        interpreter.args = args.program_args.clone();
         measure_time("Interpreter Execution", || {
             if let Err(err) = interpreter.run() {
                 display_error(err, input_file, Output::Stderr);
                 exit(1);
             }
         });
+        exit(interpreter.exit_code);
     } else if args.build_and_run {
         // ---------------
         // Compile and run
         // ---------------
         let mut compiler = Compiler::from_ast(ast);
-        measure_time("Full Compiler Execution", || {
-            if let Err(err) = compiler.compile(&args) {
-                display_error(err, input_file, Output::Stderr);
-                exit(1);
-            }
-        });
+        compiler.filename = input_file.to_string();
+        let compiled = measure_time("Full Compiler Execution", || compiler.compile(&args));
+        if let Err(err) = compiled {
+            display_error(err, input_file, Output::Stderr);
+            exit(1);
+        }
         let absolute_path =
             canonicalize(args.output).expect("Error: Failed to get binary absolute path");
-        Command::new(absolute_path)
+        let status = Command::new(absolute_path)
+            .args(&args.program_args)
             .stdout(Stdio::inherit())
             .stdin(Stdio::inherit())
             .stderr(Stdio::inherit())
@@ -198,16 +249,101 @@ fn main() {
             .expect("Failed to execute binary")
             .wait()
             .expect("Failed to wait for the binary to finish execution");
+        // The program's own exit status, programs killed by a signal count as failed
+        exit(status.code().unwrap_or(1));
     } else {
         // --------
         // Compiler
         // --------
         let mut compiler = Compiler::from_ast(ast);
-        measure_time("Full Compiler Execution", || {
-            if let Err(err) = compiler.compile(&args) {
-                display_error(err, input_file, Output::Stderr);
-                exit(1);
+        compiler.filename = input_file.to_string();
+        let compiled = measure_time("Full Compiler Execution", || compiler.compile(&args));
+        if let Err(err) = compiled {
+            display_error(err, input_file, Output::Stderr);
+            exit(1);
+        }
+    }
+}
+
+/// Writes a stage given to `--emit`, exiting when it can't be written
+fn emit(path: &Path, data: &str, input_file: &str) {
+    if let Err(err) = write_output(path, data.as_bytes()) {
+        display_error(err, input_file, Output::Stderr);
+        exit(1);
+    }
+}
+
+/// Runs the interactive mode until `exit` or `quit`
+fn repl() -> ! {
+    print_and_flush(INTERACTIVE_BANNER);
+    let mut interpreter = Interpreter::default();
+    let mut checker = Checker::new();
+    loop {
+        print_and_flush(">>> ");
+        let mut input = String::new();
+        loop {
+            let mut tmp = String::new();
+            stdin()
+                .read_line(&mut tmp)
+                .expect("Error: reading user input");
+
+            // Short circuit exit on "exit" or "quit"
+            if ["exit", "quit"].contains(&tmp.trim()) {
+                exit(0);
             }
-        });
+
+            input.push_str(&tmp);
+            if input.ends_with("\n\n") {
+                break;
+            }
+
+            print_and_flush("  … ");
+        }
+        input = input.trim().to_string();
+
+        let code = preprocess(&input);
+
+        let tmp_code_fname = get_tmp_fname("black_interactive");
+        std::fs::write(&tmp_code_fname, &code).expect("Failed to write temporary file");
+
+        let (tokens, spans) = match lexer_with_spans(&code) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                display_error(err, &tmp_code_fname, Output::Stdout);
+                continue;
+            }
+        };
+        let mut parser = Parser::with_spans(&tokens, &spans);
+        let mut ast = match parser.parse() {
+            Ok(ast) => ast,
+            Err(err) => {
+                display_error(err, &tmp_code_fname, Output::Stdout);
+                continue;
+            }
+        };
+        if ast.iter().any(|node| matches!(node, Expr::Import(_))) {
+            let message = "Modules can't be imported in the interactive mode".to_string();
+            display_error(ErrorType::Generic(message), &tmp_code_fname, Output::Stdout);
+            continue;
+        }
+
+        // Keep the checker state untouched when the input is rejected
+        let snapshot = checker.clone();
+        if let Err(err) = checker.check(&mut ast) {
+            checker = snapshot;
+            display_error(err, &tmp_code_fname, Output::Stdout);
+            continue;
+        }
+        interpreter.ast = ast;
+
+        // Clear last line
+        print!("\x1b[1A\x1b[2K");
+
+        let res = interpreter.run();
+        if let Err(err) = res {
+            display_error(err, &tmp_code_fname, Output::Stdout);
+        }
+
+        std::fs::remove_file(tmp_code_fname).expect("Failed to remove temporary file");
     }
 }
@@ -0,0 +1,304 @@
+use crate::{
+    compiler::Compiler,
+    interpreter::Interpreter,
+    parser::{FuncCall, Type, Variable},
+    utils::ErrorType,
+};
+
+/// Type of a builtin parameter. Builtins work with collections of any type, so a parameter
+/// describes a kind of types rather than a single type
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    /// A value of exactly this type
+    Exact(Type),
+    /// A value of any type
+    Any,
+    /// An int, long, float or double
+    Number,
+    /// A number or a string to parse
+    NumberOrStr,
+    /// A value of the type of the first argument
+    SameAsFirst,
+    /// A list of any element type
+    List,
+    /// A list of values of the given type
+    ListOf(Type),
+    /// A map of any key and value types
+    Map,
+    /// A list, a map or a string
+    Sized,
+    /// A value of the element type of the list passed as the first argument
+    Elem,
+    /// A value of the key type of the map passed as the first argument
+    Key,
+}
+
+/// Type of the result of a builtin, which can depend on the types of its arguments
+#[derive(Debug, Clone, PartialEq)]
+pub enum Returns {
+    Exact(Type),
+    /// An optional value of the given type
+    Optional(Type),
+    /// A list of values of the given type
+    List(Type),
+    /// A tuple of values of the given types
+    Tuple(&'static [Type]),
+    /// The type of the first argument
+    First,
+    /// The element type of the list passed as the first argument
+    Elem,
+    /// A list of the keys of the map passed as the first argument
+    Keys,
+}
+
+/// Implementation of a builtin in the interpreter
+type Interpret = fn(&mut Interpreter, &FuncCall) -> Result<Variable, ErrorType>;
+
+/// Implementation of a builtin in the compiler, returns the temporary holding the result along
+/// with its type
+type Compile = fn(&mut Compiler, &FuncCall) -> Result<(String, Type), ErrorType>;
+
+/// A function provided by the language itself. The checker validates calls against the
+/// signature, each backend runs its own implementation
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static [Param],
+    /// Whether the last parameter takes any number of arguments, including none
+    pub variadic: bool,
+    pub returns: Returns,
+    pub interpret: Interpret,
+    pub compile: Compile,
+}
+
+/// Every builtin function. User defined functions can't reuse their names
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "print",
+        params: &[Param::Any],
+        variadic: true,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_print,
+        compile: Compiler::builtin_print,
+    },
+    Builtin {
+        name: "len",
+        params: &[Param::Sized],
+        variadic: false,
+        returns: Returns::Exact(Type::Int),
+        interpret: Interpreter::builtin_len,
+        compile: Compiler::builtin_len,
+    },
+    Builtin {
+        name: "push",
+        params: &[Param::List, Param::Elem],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_push,
+        compile: Compiler::builtin_push,
+    },
+    Builtin {
+        name: "pop",
+        params: &[Param::List],
+        variadic: false,
+        returns: Returns::Elem,
+        interpret: Interpreter::builtin_pop,
+        compile: Compiler::builtin_pop,
+    },
+    Builtin {
+        name: "has",
+        params: &[Param::Map, Param::Key],
+        variadic: false,
+        returns: Returns::Exact(Type::Bool),
+        interpret: Interpreter::builtin_has,
+        compile: Compiler::builtin_has,
+    },
+    Builtin {
+        name: "remove",
+        params: &[Param::Map, Param::Key],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_remove,
+        compile: Compiler::builtin_remove,
+    },
+    Builtin {
+        name: "keys",
+        params: &[Param::Map],
+        variadic: false,
+        returns: Returns::Keys,
+        interpret: Interpreter::builtin_keys,
+        compile: Compiler::builtin_keys,
+    },
+    Builtin {
+        name: "error",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_error,
+        compile: Compiler::builtin_error,
+    },
+    Builtin {
+        name: "str",
+        params: &[Param::Any],
+        variadic: false,
+        returns: Returns::Exact(Type::Str),
+        interpret: Interpreter::builtin_str,
+        compile: Compiler::builtin_str,
+    },
+    Builtin {
+        name: "int",
+        params: &[Param::NumberOrStr],
+        variadic: false,
+        returns: Returns::Exact(Type::Int),
+        interpret: Interpreter::builtin_int,
+        compile: Compiler::builtin_int,
+    },
+    Builtin {
+        name: "float",
+        params: &[Param::NumberOrStr],
+        variadic: false,
+        returns: Returns::Exact(Type::Float),
+        interpret: Interpreter::builtin_float,
+        compile: Compiler::builtin_float,
+    },
+    Builtin {
+        name: "abs",
+        params: &[Param::Number],
+        variadic: false,
+        returns: Returns::First,
+        interpret: Interpreter::builtin_abs,
+        compile: Compiler::builtin_abs,
+    },
+    Builtin {
+        name: "min",
+        params: &[Param::Number, Param::SameAsFirst],
+        variadic: false,
+        returns: Returns::First,
+        interpret: Interpreter::builtin_min,
+        compile: Compiler::builtin_min,
+    },
+    Builtin {
+        name: "max",
+        params: &[Param::Number, Param::SameAsFirst],
+        variadic: false,
+        returns: Returns::First,
+        interpret: Interpreter::builtin_max,
+        compile: Compiler::builtin_max,
+    },
+    Builtin {
+        name: "type_of",
+        params: &[Param::Any],
+        variadic: false,
+        returns: Returns::Exact(Type::Str),
+        interpret: Interpreter::builtin_type_of,
+        compile: Compiler::builtin_type_of,
+    },
+    Builtin {
+        name: "input",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::Str),
+        interpret: Interpreter::builtin_input,
+        compile: Compiler::builtin_input,
+    },
+    Builtin {
+        name: "read_line",
+        params: &[],
+        variadic: false,
+        returns: Returns::Optional(Type::Str),
+        interpret: Interpreter::builtin_read_line,
+        compile: Compiler::builtin_read_line,
+    },
+    Builtin {
+        name: "read_file",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::Str),
+        interpret: Interpreter::builtin_read_file,
+        compile: Compiler::builtin_read_file,
+    },
+    Builtin {
+        name: "write_file",
+        params: &[Param::Exact(Type::Str), Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_write_file,
+        compile: Compiler::builtin_write_file,
+    },
+    Builtin {
+        name: "append_file",
+        params: &[Param::Exact(Type::Str), Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_append_file,
+        compile: Compiler::builtin_append_file,
+    },
+    Builtin {
+        name: "exists",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::Bool),
+        interpret: Interpreter::builtin_exists,
+        compile: Compiler::builtin_exists,
+    },
+    Builtin {
+        name: "list_dir",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::List(Type::Str),
+        interpret: Interpreter::builtin_list_dir,
+        compile: Compiler::builtin_list_dir,
+    },
+    Builtin {
+        name: "remove_file",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_remove_file,
+        compile: Compiler::builtin_remove_file,
+    },
+    Builtin {
+        name: "args",
+        params: &[],
+        variadic: false,
+        returns: Returns::List(Type::Str),
+        interpret: Interpreter::builtin_args,
+        compile: Compiler::builtin_args,
+    },
+    Builtin {
+        name: "env",
+        params: &[Param::Exact(Type::Str)],
+        variadic: false,
+        returns: Returns::Optional(Type::Str),
+        interpret: Interpreter::builtin_env,
+        compile: Compiler::builtin_env,
+    },
+    Builtin {
+        name: "exit",
+        params: &[Param::Exact(Type::Int)],
+        variadic: false,
+        returns: Returns::Exact(Type::None),
+        interpret: Interpreter::builtin_exit,
+        compile: Compiler::builtin_exit,
+    },
+    Builtin {
+        name: "run",
+        params: &[Param::Exact(Type::Str), Param::ListOf(Type::Str)],
+        variadic: false,
+        returns: Returns::Tuple(&[Type::Int, Type::Str, Type::Str]),
+        interpret: Interpreter::builtin_run,
+        compile: Compiler::builtin_run,
+    },
+    Builtin {
+        name: "run_stream",
+        params: &[Param::Exact(Type::Str), Param::ListOf(Type::Str)],
+        variadic: false,
+        returns: Returns::Exact(Type::Int),
+        interpret: Interpreter::builtin_run_stream,
+        compile: Compiler::builtin_run_stream,
+    },
+];
+
+/// Looks up a builtin by its name
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
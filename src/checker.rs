@@ -0,0 +1,1364 @@
+use crate::{
+    builtins::{builtin, Builtin, Param, Returns, BUILTINS},
+    parser::{
+        Assignment, Ast, BinExpr, Bool, Coalesce, Destructure, EnumVariant, FieldAccess, ForLoop,
+        FuncCall, FuncDecl, IfStatement, Import, Index, Match, Pattern, Span, StructLiteral,
+        TryCatch, TupleIndex, Type, VariableDeclaration,
+    },
+    utils::{edit_distance, ErrorType},
+    Expr,
+};
+use std::{
+    cell::OnceCell,
+    collections::{HashMap, HashSet},
+};
+
+/// Parameter and return types of a user defined function
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncSignature {
+    pub params: Vec<Type>,
+    pub return_type: Type,
+}
+
+/// Static semantic analysis pass run between parsing and execution. Resolves every identifier
+/// and infers the type of every expression bottom-up, so that the interpreter and the compiler
+/// share one set of semantic rules and invalid programs are rejected before anything runs
+#[derive(Debug, Clone, Default)]
+pub struct Checker {
+    pub variables: HashMap<String, Type>,
+    /// Fields of every declared struct, in declaration order
+    pub structs: HashMap<String, Vec<(String, Type)>>,
+    /// Variants of every declared enum along with the types of their values
+    pub enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    pub functions: HashMap<String, FuncSignature>,
+    /// Name and return type of the function whose body is being checked
+    pub function: Option<(String, Type)>,
+    /// Unique name of the module whose code is being checked, `None` in the main file
+    pub module: Option<String>,
+    /// Functions, structs and enums of modules declared with `pub`
+    pub public: HashSet<String>,
+    /// Index of the top-level node of the AST being checked, the one an error comes from
+    pub node: usize,
+}
+
+impl Checker {
+    /// Creates a new `Checker` instance with no declared variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks every expression of the AST and stores inferred types on variable declarations.
+    /// Declarations are remembered, so a single checker can be reused across multiple ASTs (eg. in
+    /// the interactive mode)
+    pub fn check(&mut self, ast: &mut Ast) -> Result<(), ErrorType> {
+        // Imported modules come first, the rest of the file uses their items
+        for (i, node) in ast.iter_mut().enumerate() {
+            if let Expr::Import(import) = node {
+                self.node = i;
+                self.check_import(import)?;
+            }
+        }
+        self.node = 0;
+        self.declare_items(ast)?;
+
+        for (i, node) in ast.iter_mut().enumerate() {
+            self.node = i;
+            match node {
+                Expr::StructDecl(_) | Expr::EnumDecl(_) | Expr::Import(_) => {}
+                Expr::FuncDecl(func_decl) => self
+                    .check_func(func_decl)
+                    .map_err(|err| err.at(func_decl.span))?,
+                node => self.check_expr(node)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the code of an imported module, which is only there at the first import of the
+    /// module. Variables of the module are private to it
+    fn check_import(&mut self, import: &mut Import) -> Result<(), ErrorType> {
+        let outer_variables = std::mem::take(&mut self.variables);
+        let outer_module = self.module.replace(import.module.clone());
+        let outer_node = self.node;
+        let result = self.check(&mut import.body);
+        self.variables = outer_variables;
+        self.module = outer_module;
+        self.node = outer_node;
+
+        result.map_err(|err| err.in_file(&import.file))
+    }
+
+    /// Checks that an item used by the code being checked is either declared by the same module
+    /// or public. Items of modules are named after the module: `math.square`
+    fn check_visible(&self, kind: &str, name: &str) -> Result<(), ErrorType> {
+        match name.split_once('.') {
+            Some((module, item))
+                if self.module.as_deref() != Some(module) && !self.public.contains(name) =>
+            {
+                Err(ErrorType::Generic(format!(
+                    "{kind} `{item}` of module `{module}` is private, declare it with `pub` to use it here"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Declares the structs, enums and functions of the AST up front, so that they can be used
+    /// before their declaration (eg. by recursive functions). Types of fields, variants and
+    /// parameters are resolved in place
+    fn declare_items(&mut self, ast: &mut Ast) -> Result<(), ErrorType> {
+        // Type names come first, types can only be resolved once every name is known
+        for node in ast.iter() {
+            match node {
+                Expr::StructDecl(decl) => {
+                    if self.structs.contains_key(&decl.name) || self.enums.contains_key(&decl.name)
+                    {
+                        return Err(ErrorType::SemanticError(
+                            format!("Struct `{}` is already defined", decl.name),
+                            decl.span,
+                        ));
+                    }
+                    self.structs.insert(decl.name.clone(), Vec::new());
+                    if decl.public {
+                        self.public.insert(decl.name.clone());
+                    }
+                }
+                Expr::EnumDecl(decl) => {
+                    if self.structs.contains_key(&decl.name) || self.enums.contains_key(&decl.name)
+                    {
+                        return Err(ErrorType::SemanticError(
+                            format!("Enum `{}` is already defined", decl.name),
+                            decl.span,
+                        ));
+                    }
+                    self.enums.insert(decl.name.clone(), Vec::new());
+                    if decl.public {
+                        self.public.insert(decl.name.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for node in ast.iter_mut() {
+            match node {
+                Expr::StructDecl(decl) => {
+                    for (_, typ) in &mut decl.fields {
+                        self.resolve_type(typ).map_err(|err| err.at(decl.span))?;
+                    }
+                    self.structs.insert(decl.name.clone(), decl.fields.clone());
+                }
+                Expr::EnumDecl(decl) => {
+                    for (i, (variant, _)) in decl.variants.iter().enumerate() {
+                        if decl.variants[..i].iter().any(|(name, _)| name == variant) {
+                            return Err(ErrorType::SemanticError(
+                                format!(
+                                    "Variant `{variant}` is already defined in `{}`",
+                                    decl.name
+                                ),
+                                decl.span,
+                            ));
+                        }
+                    }
+                    for typ in decl.variants.iter_mut().flat_map(|(_, types)| types) {
+                        self.resolve_type(typ).map_err(|err| err.at(decl.span))?;
+                    }
+                    self.enums.insert(decl.name.clone(), decl.variants.clone());
+                }
+                Expr::FuncDecl(decl) => {
+                    if builtin(&decl.name).is_some() || self.functions.contains_key(&decl.name) {
+                        return Err(ErrorType::SemanticError(
+                            format!("Function `{}` is already defined", decl.name),
+                            decl.span,
+                        ));
+                    }
+                    for typ in decl.params.iter_mut().map(|(_, typ)| typ) {
+                        self.resolve_type(typ).map_err(|err| err.at(decl.span))?;
+                    }
+                    self.resolve_type(&mut decl.return_type)
+                        .map_err(|err| err.at(decl.span))?;
+                    let signature = FuncSignature {
+                        params: decl.params.iter().map(|(_, typ)| typ.clone()).collect(),
+                        return_type: decl.return_type.clone(),
+                    };
+                    self.functions.insert(decl.name.clone(), signature);
+                    if decl.public {
+                        self.public.insert(decl.name.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for node in ast.iter() {
+            if let Expr::StructDecl(decl) = node {
+                if self.contains_struct(&decl.name, &decl.name, &mut HashSet::new()) {
+                    return Err(ErrorType::SemanticError(
+                        format!("Struct `{}` cannot contain itself", decl.name),
+                        decl.span,
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every named type used by the type is declared and that maps have valid keys.
+    /// Names of enums, parsed as struct types, are turned into enum types
+    fn resolve_type(&self, typ: &mut Type) -> Result<(), ErrorType> {
+        match typ {
+            Type::List(elem) | Type::Optional(elem) => self.resolve_type(elem),
+            Type::Tuple(elems) => elems
+                .iter_mut()
+                .try_for_each(|elem| self.resolve_type(elem)),
+            Type::Map(key, value) => {
+                self.resolve_type(key)?;
+                self.resolve_type(value)?;
+                check_key_types(typ)
+            }
+            Type::Struct(name) if self.enums.contains_key(name) => {
+                self.check_visible("Enum", name)?;
+                *typ = Type::Enum(name.clone());
+                Ok(())
+            }
+            Type::Struct(name) if !self.structs.contains_key(name) => {
+                Err(ErrorType::Generic(format!("Unknown type `{name}`")))
+            }
+            Type::Struct(name) => self.check_visible("Struct", name),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the struct `name` holds a value of struct `target` directly (not through a list, a
+    /// map or an enum), which would make its size infinite when `target` is the struct itself
+    fn contains_struct(&self, name: &str, target: &str, seen: &mut HashSet<String>) -> bool {
+        if !seen.insert(name.to_string()) {
+            return false;
+        }
+
+        self.structs[name].iter().any(|(_, typ)| match typ {
+            Type::Struct(field) => field == target || self.contains_struct(field, target, seen),
+            _ => false,
+        })
+    }
+
+    /// Checks the body of a function, with its parameters as the only variables in scope
+    fn check_func(&mut self, func_decl: &mut FuncDecl) -> Result<(), ErrorType> {
+        let params = func_decl.params.iter().cloned().collect();
+        let outer = std::mem::replace(&mut self.variables, params);
+        self.function = Some((func_decl.name.clone(), func_decl.return_type.clone()));
+
+        let result = func_decl
+            .block
+            .iter_mut()
+            .try_for_each(|node| self.check_expr(node));
+
+        self.variables = outer;
+        self.function = None;
+        result?;
+
+        if func_decl.return_type != Type::None && !always_returns(&func_decl.block) {
+            return Err(ErrorType::Generic(format!(
+                "Function `{}` must return a value of type `{}`",
+                func_decl.name, func_decl.return_type
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks one expression in statement position. Errors without a location of their own are
+    /// located at the statement
+    fn check_expr(&mut self, expr: &mut Expr) -> Result<(), ErrorType> {
+        let result = self.check_statement(expr);
+        match span_of(expr) {
+            Some(span) => result.map_err(|err| err.at(span)),
+            None => result,
+        }
+    }
+
+    /// Checks one statement, dispatching on its kind
+    fn check_statement(&mut self, expr: &mut Expr) -> Result<(), ErrorType> {
+        match expr {
+            Expr::VariableDeclaration(var_decl) => self.check_var_decl(var_decl)?,
+
+            Expr::Destructure(destructure) => self.check_destructure(destructure)?,
+
+            Expr::Block(block) => {
+                for node in block {
+                    self.check_expr(node)?;
+                }
+            }
+
+            Expr::Return(ret) => self.check_return(&ret.value)?,
+
+            Expr::StructDecl(_) | Expr::EnumDecl(_) | Expr::FuncDecl(_) => {
+                return Err(ErrorType::Generic(
+                    "Structs, enums and functions can only be declared at the top level"
+                        .to_string(),
+                ))
+            }
+
+            Expr::Assignment(assignment) => self.check_assignment(assignment)?,
+
+            Expr::For(for_loop) => self.check_for(for_loop)?,
+
+            Expr::IfStatement(if_statement) => self.check_if(if_statement)?,
+
+            Expr::TryCatch(try_catch) => self.check_try(try_catch)?,
+
+            _ => {
+                self.infer(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks a returned value against the return type of the enclosing function
+    fn check_return(&self, value: &Expr) -> Result<(), ErrorType> {
+        let typ = self.infer(value)?;
+        // A top-level `return` ends the program with the returned exit status
+        let Some((name, return_type)) = &self.function else {
+            if let Some(module) = &self.module {
+                return Err(ErrorType::Generic(format!(
+                    "Top-level `return` isn't allowed in module `{module}`"
+                )));
+            }
+            if !accepts(&Type::Int, &typ) {
+                return Err(ErrorType::Generic(format!(
+                    "Top-level `return` sets the exit status, which must be an `int`, found `{typ}`"
+                )));
+            }
+            return Ok(());
+        };
+
+        if *return_type == Type::None {
+            return Err(ErrorType::Generic(format!(
+                "Function `{name}` doesn't return a value"
+            )));
+        }
+        if !accepts(return_type, &typ) {
+            return Err(ErrorType::Generic(format!(
+                "Function `{name}` must return a value of type `{return_type}`, found `{typ}`"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks a variable declaration against its (optional) type annotation, stores the resulting
+    /// type on the declaration and declares the variable
+    fn check_var_decl(&mut self, var_decl: &mut VariableDeclaration) -> Result<(), ErrorType> {
+        let value_type = match &var_decl.value {
+            Expr::VariableDeclaration(_) | Expr::Block(_) | Expr::Return(_) => None,
+            value => Some(self.infer(value)?),
+        };
+        let value_type = match value_type {
+            // `none` needs an optional type annotation
+            Some(Type::None) if var_decl.value == Expr::None => Type::None,
+            Some(Type::None) | None => {
+                return Err(ErrorType::Generic(
+                    "Can only store strings and numbers in variables".to_string(),
+                ))
+            }
+            Some(typ) => typ,
+        };
+
+        if let Some(var_type) = &mut var_decl.typ {
+            self.resolve_type(var_type)
+                .map_err(|err| err.at(var_decl.typ_span.unwrap_or(var_decl.value_span)))?;
+            if !accepts(var_type, &value_type) {
+                return Err(mismatch(
+                    var_type,
+                    var_decl.typ_span,
+                    &value_type,
+                    var_decl.value_span,
+                ));
+            }
+        }
+
+        if var_decl.typ.is_none() && has_unknown(&value_type) {
+            return Err(ErrorType::Generic(format!(
+                "Cannot infer the type of `{}`, add a type annotation",
+                var_decl.identifier
+            )));
+        }
+
+        let typ = var_decl.typ.get_or_insert(value_type).clone();
+        self.variables.insert(var_decl.identifier.clone(), typ);
+
+        Ok(())
+    }
+
+    /// Checks a destructuring declaration, declaring one variable per element of the tuple
+    fn check_destructure(&mut self, destructure: &Destructure) -> Result<(), ErrorType> {
+        let elems = match self.infer(&destructure.value)? {
+            Type::Tuple(elems) if elems.len() == destructure.names.len() => elems,
+            typ => {
+                return Err(ErrorType::Generic(format!(
+                    "Cannot destructure value of type `{typ}` into {} variables",
+                    destructure.names.len()
+                )))
+            }
+        };
+
+        for (name, typ) in destructure.names.iter().zip(elems) {
+            if name == "_" {
+                continue;
+            }
+            if has_unknown(&typ) {
+                return Err(ErrorType::Generic(format!(
+                    "Cannot infer the type of `{name}`, add a type annotation"
+                )));
+            }
+            self.variables.insert(name.clone(), typ);
+        }
+
+        Ok(())
+    }
+
+    /// Checks an assignment to an existing variable, list element, map entry or struct field
+    fn check_assignment(&mut self, assignment: &mut Assignment) -> Result<(), ErrorType> {
+        if modifies_tuple(&assignment.target) {
+            return Err(ErrorType::Generic(
+                "Tuple elements cannot be modified".to_string(),
+            ));
+        }
+
+        let target_type = self.infer(&assignment.target)?;
+        let value_type = self.infer(&assignment.value)?;
+
+        if !accepts(&target_type, &value_type) {
+            return Err(mismatch(&target_type, None, &value_type, assignment.span));
+        }
+        assignment.typ = Some(target_type);
+
+        Ok(())
+    }
+
+    /// Checks a for loop, declaring the loop variable with the element type of the iterated list.
+    /// Iterating over a map binds its keys, and optionally its values to the second variable.
+    /// The loop variables and variables declared inside of the body aren't visible after the loop
+    fn check_for(&mut self, for_loop: &mut ForLoop) -> Result<(), ErrorType> {
+        let (elem_type, value_type) = match self.infer(&for_loop.iterable)? {
+            Type::List(elem) if for_loop.value_variable.is_none() => (*elem, None),
+            Type::Map(key, value) => (*key, Some(*value)),
+            typ @ Type::List(_) => {
+                return Err(ErrorType::Generic(format!(
+                    "Cannot iterate over value of type `{typ}` with two loop variables"
+                )))
+            }
+            typ => {
+                return Err(ErrorType::Generic(format!(
+                    "Cannot iterate over value of type `{typ}`"
+                )))
+            }
+        };
+        let outer = self.variables.clone();
+        self.variables.insert(for_loop.variable.clone(), elem_type);
+        if let (Some(id), Some(typ)) = (&for_loop.value_variable, value_type) {
+            self.variables.insert(id.clone(), typ);
+        }
+
+        let result = for_loop
+            .block
+            .iter_mut()
+            .try_for_each(|node| self.check_expr(node));
+        self.variables = outer;
+        result
+    }
+
+    /// Checks an if statement. The condition has to be a boolean, or an optional for `if let`,
+    /// whose value is bound inside of the block. Variables declared inside of the blocks aren't
+    /// visible after the if statement
+    fn check_if(&mut self, if_statement: &mut IfStatement) -> Result<(), ErrorType> {
+        let typ = self.infer(&if_statement.condition)?;
+        let outer = self.variables.clone();
+
+        match (&if_statement.binding, typ) {
+            (Some(name), Type::Optional(inner)) => {
+                self.variables.insert(name.clone(), *inner);
+            }
+            (Some(_), typ) => {
+                return Err(ErrorType::Generic(format!(
+                    "`if let` expects an optional value, found `{typ}`"
+                )))
+            }
+            (None, Type::Bool) => {}
+            (None, typ) => return Err(mismatch(&Type::Bool, None, &typ, if_statement.span)),
+        }
+
+        let result = if_statement
+            .block
+            .iter_mut()
+            .try_for_each(|node| self.check_expr(node));
+        self.variables = outer.clone();
+        result?;
+
+        let result = if_statement
+            .else_block
+            .iter_mut()
+            .try_for_each(|node| self.check_expr(node));
+        self.variables = outer;
+        result
+    }
+
+    /// Checks a try statement. Like for if statements, variables declared inside of the blocks
+    /// aren't visible after it. The catch block sees the error message as a string
+    fn check_try(&mut self, try_catch: &mut TryCatch) -> Result<(), ErrorType> {
+        let outer = self.variables.clone();
+
+        let result = try_catch
+            .block
+            .iter_mut()
+            .try_for_each(|node| self.check_expr(node));
+        self.variables = outer.clone();
+        result?;
+
+        if let Some(name) = &try_catch.variable {
+            self.variables.insert(name.clone(), Type::Str);
+        }
+        let result = try_catch
+            .catch_block
+            .iter_mut()
+            .try_for_each(|node| self.check_expr(node));
+        self.variables = outer;
+        result
+    }
+
+    /// Infers the type of an expression in value position. Errors without a location of their
+    /// own are located at the innermost expression which knows its location
+    pub fn infer(&self, expr: &Expr) -> Result<Type, ErrorType> {
+        let result = self.infer_expr(expr);
+        match span_of(expr) {
+            Some(span) => result.map_err(|err| err.at(span)),
+            None => result,
+        }
+    }
+
+    /// Infers the type of an expression, dispatching on its kind
+    fn infer_expr(&self, expr: &Expr) -> Result<Type, ErrorType> {
+        match expr {
+            Expr::Number(_) => Ok(Type::Int),
+            Expr::Bool(_) => Ok(Type::Bool),
+            Expr::StringLiteral(_) => Ok(Type::Str),
+            Expr::None => Ok(Type::None),
+            Expr::Identifier(id) => self.get_var(id),
+            Expr::BinExpr(bin_expr) => self.infer_bin_expr(bin_expr),
+            Expr::FuncCall(func_call) => self.infer_func_call(func_call),
+            Expr::List(elements, typ) => Ok(record(typ, self.infer_list(elements)?)),
+            Expr::Map(entries, typ) => Ok(record(typ, self.infer_map(entries)?)),
+            Expr::StructLiteral(literal) => self.infer_struct_literal(literal),
+            Expr::Field(field) => self.infer_field(field),
+            Expr::Index(index) => self.infer_index(index),
+            Expr::Tuple(elements) => Ok(Type::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.infer(element))
+                    .collect::<Result<_, _>>()?,
+            )),
+            Expr::TupleIndex(index) => self.infer_tuple_index(index),
+            Expr::EnumVariant(variant) => self.infer_enum_variant(variant),
+            Expr::Match(match_expr) => self.infer_match(match_expr),
+            Expr::Coalesce(coalesce) => self.infer_coalesce(coalesce),
+            _ => Err(ErrorType::Generic(format!(
+                "Expression `{expr:?}` cannot be used as a value"
+            ))),
+        }
+    }
+
+    /// Retrieves the type of a declared variable
+    fn get_var(&self, ident: &str) -> Result<Type, ErrorType> {
+        self.variables
+            .get(ident)
+            .cloned()
+            .ok_or(ErrorType::Generic(format!(
+                "Variable doesn't exist: `{ident}`"
+            )))
+    }
+
+    /// Infers the type of a binary expression. Both operands have to be numeric, the result is
+    /// the wider of the two operand types
+    fn infer_bin_expr(&self, bin_expr: &BinExpr) -> Result<Type, ErrorType> {
+        let lhs = self.infer(&bin_expr.lhs)?;
+        let rhs = self.infer(&bin_expr.rhs)?;
+
+        if let Some(typ) = [&lhs, &rhs]
+            .into_iter()
+            .find(|typ| matches!(typ, Type::Optional(_)))
+        {
+            let message =
+                format!("value of type `{typ}` may be none, unwrap it with `??` or `if let`");
+            return Err(ErrorType::TypeError(
+                message,
+                vec![(bin_expr.span, format!("this is of type `{typ}`"))],
+            ));
+        }
+
+        match (numeric_rank(&lhs), numeric_rank(&rhs)) {
+            (Some(l), Some(r)) => Ok(if l >= r { lhs } else { rhs }),
+            _ => Err(ErrorType::Generic(
+                "Cannot add variable which is not a number".to_string(),
+            )),
+        }
+    }
+
+    /// Infers the type of a list literal. All elements have to be of the same type, the element
+    /// type of an empty list is unknown (`none`) until it's given by a type annotation. `none`
+    /// elements make the element type optional
+    fn infer_list(&self, elements: &[Expr]) -> Result<Type, ErrorType> {
+        let mut elem_type = Type::None;
+        let mut has_none = false;
+
+        for element in elements {
+            let typ = self.infer(element)?;
+            if typ == Type::None {
+                has_none = true;
+            } else if elem_type == Type::None || accepts(&typ, &elem_type) {
+                elem_type = typ;
+            } else if !accepts(&elem_type, &typ) {
+                return Err(ErrorType::Generic(format!(
+                    "List elements must have the same type, expected {elem_type}, found {typ}"
+                )));
+            }
+        }
+
+        Ok(Type::List(Box::new(optional(elem_type, has_none))))
+    }
+
+    /// Infers the type of a map literal. Like list elements, all keys and all values have to be
+    /// of the same type
+    fn infer_map(&self, entries: &[(Expr, Expr)]) -> Result<Type, ErrorType> {
+        let mut key_type = Type::None;
+        let mut value_type = Type::None;
+        let mut has_none = [false, false];
+
+        for (key, value) in entries {
+            for (i, (expr, unified)) in [(key, &mut key_type), (value, &mut value_type)]
+                .into_iter()
+                .enumerate()
+            {
+                let typ = self.infer(expr)?;
+                if typ == Type::None {
+                    has_none[i] = true;
+                } else if *unified == Type::None || accepts(&typ, unified) {
+                    *unified = typ;
+                } else if !accepts(unified, &typ) {
+                    return Err(ErrorType::Generic(format!(
+                        "Map entries must have the same type, expected {unified}, found {typ}"
+                    )));
+                }
+            }
+        }
+
+        let typ = Type::Map(
+            Box::new(optional(key_type, has_none[0])),
+            Box::new(optional(value_type, has_none[1])),
+        );
+        check_key_types(&typ)?;
+        Ok(typ)
+    }
+
+    /// Returns the fields of a declared struct
+    fn get_struct(&self, name: &str) -> Result<&Vec<(String, Type)>, ErrorType> {
+        self.structs
+            .get(name)
+            .ok_or(ErrorType::Generic(format!("Unknown type `{name}`")))
+    }
+
+    /// Checks a struct literal, every field of the struct has to be given exactly once
+    fn infer_struct_literal(&self, literal: &StructLiteral) -> Result<Type, ErrorType> {
+        let fields = self.get_struct(&literal.name)?;
+        self.check_visible("Struct", &literal.name)?;
+
+        for (i, (name, value)) in literal.fields.iter().enumerate() {
+            let Some((_, field_type)) = fields.iter().find(|(field, _)| field == name) else {
+                return Err(ErrorType::Generic(format!(
+                    "Struct `{}` has no field `{name}`",
+                    literal.name
+                )));
+            };
+            if literal.fields[..i].iter().any(|(field, _)| field == name) {
+                return Err(ErrorType::Generic(format!(
+                    "Field `{name}` is specified more than once"
+                )));
+            }
+
+            let typ = self.infer(value)?;
+            if !accepts(field_type, &typ) {
+                return Err(mismatch(field_type, None, &typ, literal.span));
+            }
+        }
+
+        if let Some((name, _)) = fields
+            .iter()
+            .find(|(field, _)| !literal.fields.iter().any(|(given, _)| given == field))
+        {
+            return Err(ErrorType::Generic(format!(
+                "Missing field `{name}` in `{}`",
+                literal.name
+            )));
+        }
+
+        Ok(Type::Struct(literal.name.clone()))
+    }
+
+    /// Infers the type of a field access, which is the declared type of the field
+    fn infer_field(&self, field: &FieldAccess) -> Result<Type, ErrorType> {
+        let name = match self.infer(&field.target)? {
+            Type::Struct(name) => name,
+            typ => {
+                return Err(ErrorType::Generic(format!(
+                    "Cannot access field `{}` on value of type `{typ}`",
+                    field.field
+                )))
+            }
+        };
+
+        self.get_struct(&name)?
+            .iter()
+            .find(|(f, _)| *f == field.field)
+            .map(|(_, typ)| typ.clone())
+            .ok_or(ErrorType::Generic(format!(
+                "Struct `{name}` has no field `{}`",
+                field.field
+            )))
+    }
+
+    /// Returns the variants of a declared enum
+    fn get_enum(&self, name: &str) -> Result<&Vec<(String, Vec<Type>)>, ErrorType> {
+        self.enums
+            .get(name)
+            .ok_or(ErrorType::Generic(format!("Unknown type `{name}`")))
+    }
+
+    /// Returns the types of the values held by an enum variant
+    fn get_variant(&self, enum_name: &str, variant: &str) -> Result<&Vec<Type>, ErrorType> {
+        let variants = self.get_enum(enum_name)?;
+        self.check_visible("Enum", enum_name)?;
+        variants
+            .iter()
+            .find(|(name, _)| name == variant)
+            .map(|(_, types)| types)
+            .ok_or(ErrorType::Generic(format!(
+                "Enum `{enum_name}` has no variant `{variant}`"
+            )))
+    }
+
+    /// Checks the construction of an enum value against the declared values of the variant
+    fn infer_enum_variant(&self, variant: &EnumVariant) -> Result<Type, ErrorType> {
+        let types = self.get_variant(&variant.enum_name, &variant.variant)?;
+        if types.len() != variant.arguments.len() {
+            return Err(ErrorType::Generic(format!(
+                "Variant `{}::{}` takes {} value(s) but {} were given",
+                variant.enum_name,
+                variant.variant,
+                types.len(),
+                variant.arguments.len()
+            )));
+        }
+
+        for (typ, arg) in types.iter().zip(&variant.arguments) {
+            let found = self.infer(arg)?;
+            if !accepts(typ, &found) {
+                return Err(mismatch(typ, None, &found, variant.span));
+            }
+        }
+        // Already set when the variant was checked before
+        let _ = variant.types.set(types.clone());
+
+        Ok(Type::Enum(variant.enum_name.clone()))
+    }
+
+    /// Checks a match expression. Every arm is checked with the variables bound by its pattern,
+    /// the arms have to be of the same type and together cover every possible value
+    fn infer_match(&self, match_expr: &Match) -> Result<Type, ErrorType> {
+        let typ = self.infer(&match_expr.value)?;
+        let mut result: Option<Type> = None;
+        let mut has_none = false;
+
+        for arm in &match_expr.arms {
+            let mut scope = self.clone();
+            scope
+                .bind_pattern(&arm.pattern, &typ, arm.span)
+                .map_err(|err| err.at(arm.span))?;
+
+            // Statements (eg. blocks) can be used as arms, they don't result in a value
+            let arm_type = match &arm.body {
+                Expr::Block(_)
+                | Expr::VariableDeclaration(_)
+                | Expr::Assignment(_)
+                | Expr::Return(_)
+                | Expr::For(_) => {
+                    scope.check_expr(&mut arm.body.clone())?;
+                    Type::None
+                }
+                body => scope.infer(body)?,
+            };
+
+            result = match result {
+                // Arms without a value (`none`) make the result optional
+                _ if arm_type == Type::None => {
+                    has_none = true;
+                    result
+                }
+                None => Some(arm_type),
+                Some(unified) if accepts(&arm_type, &unified) => Some(arm_type),
+                Some(unified) if accepts(&unified, &arm_type) => Some(unified),
+                Some(unified) => {
+                    return Err(ErrorType::Generic(format!(
+                        "Match arms must have the same type, expected {unified}, found {arm_type}"
+                    )))
+                }
+            };
+        }
+
+        let rows = match_expr
+            .arms
+            .iter()
+            .map(|arm| vec![&arm.pattern])
+            .collect();
+        if let Some(missing) = self.missing_patterns(rows, &[typ]) {
+            return Err(ErrorType::Generic(format!(
+                "Non-exhaustive match, `{}` is not covered",
+                missing[0]
+            )));
+        }
+
+        Ok(optional(result.unwrap_or(Type::None), has_none))
+    }
+
+    /// Infers the type of an optional value with a fallback. The result is optional only when
+    /// the fallback is optional as well
+    fn infer_coalesce(&self, coalesce: &Coalesce) -> Result<Type, ErrorType> {
+        let inner = match self.infer(&coalesce.value)? {
+            Type::Optional(inner) => *inner,
+            typ => {
+                return Err(ErrorType::Generic(format!(
+                    "Left side of `??` must be optional, found `{typ}`"
+                )))
+            }
+        };
+
+        let default = self.infer(&coalesce.default)?;
+        if accepts(&inner, &default) {
+            return Ok(record(&coalesce.typ, inner));
+        }
+        let typ = Type::Optional(Box::new(inner));
+        if accepts(&typ, &default) {
+            return Ok(record(&coalesce.typ, typ));
+        }
+
+        Err(mismatch(&typ, None, &default, coalesce.span))
+    }
+
+    /// Checks a pattern against the type of the matched value and declares the variables it binds
+    fn bind_pattern(&mut self, pattern: &Pattern, typ: &Type, span: Span) -> Result<(), ErrorType> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Binding(name) => {
+                self.variables.insert(name.clone(), typ.clone());
+                Ok(())
+            }
+            Pattern::Literal(literal) => {
+                let found = self.infer(literal)?;
+                if found != *typ && !(found == Type::Int && *typ == Type::Long) {
+                    return Err(mismatch(typ, None, &found, span));
+                }
+                Ok(())
+            }
+            Pattern::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let types = self.get_variant(enum_name, variant)?.clone();
+                let found = Type::Enum(enum_name.clone());
+                if found != *typ {
+                    return Err(mismatch(typ, None, &found, span));
+                }
+                if types.len() != fields.len() {
+                    return Err(ErrorType::Generic(format!(
+                        "Pattern `{enum_name}::{variant}` expects {} value(s), found {}",
+                        types.len(),
+                        fields.len()
+                    )));
+                }
+
+                for (field, typ) in fields.iter().zip(&types) {
+                    self.bind_pattern(field, typ, span)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Looks for values of the given types not matched by any row of patterns. Returns an example
+    /// of such values (one pattern per type), or `None` when the rows cover every value. Only
+    /// enums and booleans have a finite set of values, other types are covered by a pattern
+    /// matching anything
+    fn missing_patterns(&self, rows: Vec<Vec<&Pattern>>, types: &[Type]) -> Option<Vec<String>> {
+        let Some((typ, rest)) = types.split_first() else {
+            return rows.is_empty().then(Vec::new);
+        };
+
+        let constructors = match typ {
+            Type::Enum(name) => Some(self.enums.get(name).cloned().unwrap_or_default()),
+            Type::Bool => Some(vec![
+                ("true".to_string(), Vec::new()),
+                ("false".to_string(), Vec::new()),
+            ]),
+            _ => None,
+        };
+        let heads: HashSet<String> = rows.iter().filter_map(|row| head(row[0])).collect();
+
+        // Every constructor is matched by some row, each of them has to be covered completely
+        if let Some(constructors) = &constructors {
+            if constructors.iter().all(|(name, _)| heads.contains(name)) {
+                for (name, types) in constructors {
+                    let rows = rows
+                        .iter()
+                        .filter_map(|row| {
+                            let fields = match (head(row[0]), row[0]) {
+                                (None, _) => vec![WILDCARD; types.len()],
+                                (Some(head), Pattern::Variant { fields, .. }) if head == *name => {
+                                    fields.iter().collect()
+                                }
+                                (Some(head), _) if head == *name => Vec::new(),
+                                _ => return None,
+                            };
+                            Some([fields, row[1..].to_vec()].concat())
+                        })
+                        .collect();
+
+                    let expanded = [types.clone(), rest.to_vec()].concat();
+                    if let Some(missing) = self.missing_patterns(rows, &expanded) {
+                        let (fields, rest) = missing.split_at(types.len());
+                        let mut result = vec![show_constructor(typ, name, fields)];
+                        result.extend_from_slice(rest);
+                        return Some(result);
+                    }
+                }
+                return None;
+            }
+        }
+
+        // Some constructor isn't matched, only the rows matching anything can cover it
+        let rows = rows
+            .iter()
+            .filter(|row| head(row[0]).is_none())
+            .map(|row| row[1..].to_vec())
+            .collect();
+        let missing = self.missing_patterns(rows, rest)?;
+        let first = match constructors {
+            Some(constructors) => constructors
+                .iter()
+                .find(|(name, _)| !heads.contains(name))
+                .map(|(name, types)| {
+                    show_constructor(typ, name, &vec!["_".to_string(); types.len()])
+                })
+                .unwrap_or("_".to_string()),
+            None => "_".to_string(),
+        };
+
+        Some([vec![first], missing].concat())
+    }
+
+    /// Infers the type of a tuple element access
+    fn infer_tuple_index(&self, index: &TupleIndex) -> Result<Type, ErrorType> {
+        match self.infer(&index.target)? {
+            Type::Tuple(elems) => {
+                elems
+                    .get(index.index)
+                    .cloned()
+                    .ok_or(ErrorType::Generic(format!(
+                        "Tuple of type `{}` has no element {}",
+                        Type::Tuple(elems.clone()),
+                        index.index
+                    )))
+            }
+            typ => Err(ErrorType::Generic(format!(
+                "Cannot access element {} on value of type `{typ}`",
+                index.index
+            ))),
+        }
+    }
+
+    /// Checks a call of a user defined function against its signature
+    fn infer_user_call(&self, func_call: &FuncCall) -> Result<Type, ErrorType> {
+        let Some(signature) = self.functions.get(&func_call.name) else {
+            let names = BUILTINS
+                .iter()
+                .map(|builtin| builtin.name)
+                .chain(self.functions.keys().map(String::as_str));
+            let suggestion = match closest(&func_call.name, names) {
+                Some(name) => format!(", did you mean `{name}`?"),
+                None => String::new(),
+            };
+            return Err(ErrorType::Generic(format!(
+                "Function `{}` is not implemented{suggestion}",
+                func_call.name
+            )));
+        };
+        self.check_visible("Function", &func_call.name)?;
+        self.check_arity(func_call, signature.params.len())?;
+
+        for (param, arg) in signature.params.iter().zip(&func_call.arguments) {
+            let typ = self.infer(arg)?;
+            if !accepts(param, &typ) {
+                return Err(mismatch(param, None, &typ, func_call.span));
+            }
+        }
+
+        Ok(signature.return_type.clone())
+    }
+
+    /// Infers the type of an index access, which is the element type of the indexed list or the
+    /// value type of the indexed map
+    fn infer_index(&self, index: &Index) -> Result<Type, ErrorType> {
+        let elem_type = match self.infer(&index.target)? {
+            Type::Map(key, value) => {
+                self.check_key(&key, &index.index, index.span)?;
+                return Ok(*value);
+            }
+            Type::List(elem) => *elem,
+            typ => {
+                return Err(ErrorType::Generic(format!(
+                    "Cannot index into value of type `{typ}`"
+                )))
+            }
+        };
+
+        match self.infer(&index.index)? {
+            Type::Int | Type::Long => Ok(elem_type),
+            typ => Err(ErrorType::Generic(format!(
+                "List index must be an integer, found `{typ}`"
+            ))),
+        }
+    }
+
+    /// Checks the number of arguments passed to a function
+    fn check_arity(&self, func_call: &FuncCall, expected: usize) -> Result<(), ErrorType> {
+        if func_call.arguments.len() != expected {
+            return Err(ErrorType::Generic(format!(
+                "Function `{}` takes {expected} argument(s) but {} were given",
+                func_call.name,
+                func_call.arguments.len()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that an expression used as a key has the key type of the map
+    fn check_key(&self, key_type: &Type, key: &Expr, span: Span) -> Result<(), ErrorType> {
+        let typ = self.infer(key)?;
+        if !accepts(key_type, &typ) {
+            return Err(mismatch(key_type, None, &typ, span));
+        }
+
+        Ok(())
+    }
+
+    /// Checks a function call and returns the type of its result
+    fn infer_func_call(&self, func_call: &FuncCall) -> Result<Type, ErrorType> {
+        match builtin(&func_call.name) {
+            Some(builtin) => self.infer_builtin_call(builtin, func_call),
+            None => self.infer_user_call(func_call),
+        }
+    }
+
+    /// Checks a call of a builtin against its signature. The types of the arguments are recorded
+    /// on the call for the backends
+    fn infer_builtin_call(
+        &self,
+        builtin: &Builtin,
+        func_call: &FuncCall,
+    ) -> Result<Type, ErrorType> {
+        if !builtin.variadic {
+            self.check_arity(func_call, builtin.params.len())?;
+        }
+
+        let mut types: Vec<Type> = Vec::with_capacity(func_call.arguments.len());
+        for (i, arg) in func_call.arguments.iter().enumerate() {
+            if let Expr::VariableDeclaration(_) | Expr::Block(_) | Expr::Return(_) = arg {
+                return Err(ErrorType::Generic(format!(
+                    "Invalid argument to {}",
+                    builtin.name
+                )));
+            }
+
+            let param = &builtin.params[i.min(builtin.params.len() - 1)];
+            let typ = self.infer(arg)?;
+            let expected = match (param, types.first()) {
+                (Param::Exact(expected), _) => expected.clone(),
+                (Param::SameAsFirst, Some(first)) => first.clone(),
+                (Param::Elem, Some(Type::List(elem))) => *elem.clone(),
+                (Param::Key, Some(Type::Map(key, _))) => *key.clone(),
+                (Param::Any, _) => typ.clone(),
+                (Param::Number, _) => match typ {
+                    Type::Int | Type::Long | Type::Float | Type::Double => typ.clone(),
+                    _ => return Err(expects(builtin, "a number", &typ)),
+                },
+                (Param::NumberOrStr, _) => match typ {
+                    Type::Int | Type::Long | Type::Float | Type::Double | Type::Str => typ.clone(),
+                    _ => return Err(expects(builtin, "a number or a string", &typ)),
+                },
+                (Param::List, _) => match typ {
+                    Type::List(_) => typ.clone(),
+                    _ => return Err(expects(builtin, "a list", &typ)),
+                },
+                (Param::ListOf(elem), _) => Type::List(Box::new(elem.clone())),
+                (Param::Map, _) => match typ {
+                    Type::Map(..) => typ.clone(),
+                    _ => return Err(expects(builtin, "a map", &typ)),
+                },
+                (Param::Sized, _) => match typ {
+                    Type::List(_) | Type::Map(..) | Type::Str => typ.clone(),
+                    _ => return Err(expects(builtin, "a list, a map or a string", &typ)),
+                },
+                (Param::SameAsFirst | Param::Elem | Param::Key, _) => {
+                    unreachable!("builtin `{}` is declared incorrectly", builtin.name)
+                }
+            };
+            if !accepts(&expected, &typ) {
+                return Err(mismatch(&expected, None, &typ, func_call.span));
+            }
+            types.push(typ);
+        }
+
+        let result = match (&builtin.returns, types.first()) {
+            (Returns::Exact(typ), _) => typ.clone(),
+            (Returns::Optional(typ), _) => Type::Optional(Box::new(typ.clone())),
+            (Returns::List(typ), _) => Type::List(Box::new(typ.clone())),
+            (Returns::Tuple(types), _) => Type::Tuple(types.to_vec()),
+            (Returns::First, Some(first)) => first.clone(),
+            (Returns::Elem, Some(Type::List(elem))) => *elem.clone(),
+            (Returns::Keys, Some(Type::Map(key, _))) => Type::List(key.clone()),
+            _ => unreachable!("builtin `{}` is declared incorrectly", builtin.name),
+        };
+        // Already set when the call was checked before
+        let _ = func_call.arg_types.set(types);
+
+        Ok(result)
+    }
+}
+
+/// Records a type found by the checker into the AST for the interpreter, returning it. The cell
+/// is already set when the expression was checked before
+fn record<T: Clone>(cell: &OnceCell<T>, value: T) -> T {
+    let _ = cell.set(value.clone());
+    value
+}
+
+/// Error for an argument of a builtin which isn't of the expected kind
+fn expects(builtin: &Builtin, expected: &str, found: &Type) -> ErrorType {
+    ErrorType::Generic(format!(
+        "Function `{}` expects {expected}, found `{found}`",
+        builtin.name
+    ))
+}
+
+/// Returns the name most similar to a misspelled one, if any is close enough to be a likely typo
+fn closest<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    names
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2 && *distance < candidate.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Whether a block of statements always ends in a `return` (or raises an error or exits),
+/// either directly, in every arm of a match or in both branches of an if or try statement
+fn always_returns(block: &[Expr]) -> bool {
+    block.iter().any(|node| match node {
+        Expr::Return(_) => true,
+        Expr::FuncCall(func_call) => ["error", "exit"].contains(&func_call.name.as_str()),
+        Expr::TryCatch(try_catch) => {
+            always_returns(&try_catch.block) && always_returns(&try_catch.catch_block)
+        }
+        Expr::Block(block) => always_returns(block),
+        Expr::IfStatement(if_statement) => {
+            always_returns(&if_statement.block) && always_returns(&if_statement.else_block)
+        }
+        Expr::Match(match_expr) => match_expr
+            .arms
+            .iter()
+            .all(|arm| always_returns(std::slice::from_ref(&arm.body))),
+        _ => false,
+    })
+}
+
+/// Location of an expression, for the kinds of expressions which know theirs
+fn span_of(expr: &Expr) -> Option<Span> {
+    match expr {
+        Expr::FuncCall(func_call) => Some(func_call.span),
+        Expr::BinExpr(bin_expr) => Some(bin_expr.span),
+        Expr::Index(index) => Some(index.span),
+        Expr::Field(field) => Some(field.span),
+        Expr::TupleIndex(index) => Some(index.span),
+        Expr::StructLiteral(literal) => Some(literal.span),
+        Expr::EnumVariant(variant) => Some(variant.span),
+        Expr::Match(match_expr) => Some(match_expr.span),
+        Expr::Coalesce(coalesce) => Some(coalesce.span),
+        Expr::VariableDeclaration(var_decl) => Some(var_decl.value_span),
+        Expr::Destructure(destructure) => Some(destructure.value_span),
+        Expr::Assignment(assignment) => Some(assignment.span),
+        Expr::Return(ret) => Some(ret.span),
+        Expr::For(for_loop) => Some(for_loop.span),
+        Expr::IfStatement(if_statement) => Some(if_statement.span),
+        Expr::TryCatch(try_catch) => Some(try_catch.span),
+        Expr::StructDecl(decl) => Some(decl.span),
+        Expr::EnumDecl(decl) => Some(decl.span),
+        Expr::FuncDecl(decl) => Some(decl.span),
+        _ => None,
+    }
+}
+
+/// Whether an assignment target is a tuple element, or a field of a struct stored in a tuple
+fn modifies_tuple(target: &Expr) -> bool {
+    match target {
+        Expr::TupleIndex(_) => true,
+        Expr::Field(field) => modifies_tuple(&field.target),
+        _ => false,
+    }
+}
+
+/// Makes the type optional when some of the values it was unified from are `none`
+fn optional(typ: Type, has_none: bool) -> Type {
+    match typ {
+        Type::None | Type::Optional(_) => typ,
+        typ if has_none => Type::Optional(Box::new(typ)),
+        typ => typ,
+    }
+}
+
+/// Pattern matching anything, used when expanding patterns during the exhaustiveness check
+const WILDCARD: &Pattern = &Pattern::Wildcard;
+
+/// Returns the name of the constructor a pattern matches: an enum variant, or the value of a
+/// literal. `None` for patterns matching anything
+fn head(pattern: &Pattern) -> Option<String> {
+    match pattern {
+        Pattern::Wildcard | Pattern::Binding(_) => None,
+        Pattern::Variant { variant, .. } => Some(variant.clone()),
+        Pattern::Literal(Expr::Number(n)) => Some(n.to_string()),
+        Pattern::Literal(Expr::StringLiteral(s)) => Some(format!("{s:?}")),
+        Pattern::Literal(Expr::Bool(Bool::True)) => Some("true".to_string()),
+        Pattern::Literal(_) => Some("false".to_string()),
+    }
+}
+
+/// Formats a constructor of the given type applied to patterns of its values, as shown in
+/// exhaustiveness errors
+fn show_constructor(typ: &Type, name: &str, fields: &[String]) -> String {
+    match (typ, fields.is_empty()) {
+        (Type::Enum(enum_name), true) => format!("{enum_name}::{name}"),
+        (Type::Enum(enum_name), false) => format!("{enum_name}::{name}({})", fields.join(", ")),
+        _ => name.to_string(),
+    }
+}
+
+/// Builds a type mismatch error pointing at both the expected type and the offending value
+fn mismatch(
+    expected: &Type,
+    expected_span: Option<Span>,
+    found: &Type,
+    found_span: Span,
+) -> ErrorType {
+    let mut labels = Vec::new();
+    if let Some(span) = expected_span {
+        labels.push((span, format!("expected `{expected}` because of this")));
+    }
+    labels.push((found_span, format!("this is of type `{found}`")));
+
+    ErrorType::TypeError(format!("expected {expected}, found {found}"), labels)
+}
+
+/// Whether the type matches the expected type exactly, treating unknown parts as wildcards
+fn fits(expected: &Type, found: &Type) -> bool {
+    match (expected, found) {
+        (_, Type::None) => true,
+        (Type::List(expected), Type::List(found)) => fits(expected, found),
+        (Type::Map(expected_key, expected), Type::Map(found_key, found)) => {
+            fits(expected_key, found_key) && fits(expected, found)
+        }
+        (Type::Tuple(expected), Type::Tuple(found)) => {
+            expected.len() == found.len()
+                && expected
+                    .iter()
+                    .zip(found)
+                    .all(|(expected, found)| fits(expected, found))
+        }
+        (Type::Optional(expected), Type::Optional(found)) => fits(expected, found),
+        _ => expected == found,
+    }
+}
+
+/// Whether the type still contains an unknown part, eg. the element type of an empty list
+fn has_unknown(typ: &Type) -> bool {
+    match typ {
+        Type::None => true,
+        Type::List(elem) | Type::Optional(elem) => has_unknown(elem),
+        Type::Map(key, value) => has_unknown(key) || has_unknown(value),
+        Type::Tuple(elems) => elems.iter().any(has_unknown),
+        _ => false,
+    }
+}
+
+/// Checks that every map inside of the type is keyed by numbers, strings or booleans
+fn check_key_types(typ: &Type) -> Result<(), ErrorType> {
+    match typ {
+        Type::List(elem) | Type::Optional(elem) => check_key_types(elem),
+        Type::Tuple(elems) => elems.iter().try_for_each(check_key_types),
+        Type::Map(key, value) => match **key {
+            Type::Int | Type::Long | Type::Str | Type::Bool | Type::None => check_key_types(value),
+            ref key => Err(ErrorType::Generic(format!(
+                "Map keys must be integers, strings or booleans, found `{key}`"
+            ))),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Returns the position of a numeric type in the widening order, `None` for non numeric types
+fn numeric_rank(typ: &Type) -> Option<u8> {
+    match typ {
+        Type::Int => Some(0),
+        Type::Long => Some(1),
+        Type::Float => Some(2),
+        Type::Double => Some(3),
+        _ => None,
+    }
+}
+
+/// Whether a value of type `found` can be stored in a variable of type `expected`. Apart from
+/// exact matches, numeric values can be widened (eg. an `int` stored in a `float`)
+pub fn accepts(expected: &Type, found: &Type) -> bool {
+    if expected == found {
+        return true;
+    }
+
+    // Lists, maps and tuples are invariant in their element types, apart from the unknown element
+    // types of empty literals which fit any list or map
+    if let (Type::List(_), Type::List(_))
+    | (Type::Map(..), Type::Map(..))
+    | (Type::Tuple(_), Type::Tuple(_))
+    | (Type::Optional(_), Type::Optional(_)) = (expected, found)
+    {
+        return fits(expected, found);
+    }
+
+    // Optionals hold either `none` or a value of their inner type
+    if let Type::Optional(inner) = expected {
+        return *found == Type::None || accepts(inner, found);
+    }
+
+    match (numeric_rank(expected), numeric_rank(found)) {
+        (Some(e), Some(f)) => e >= f,
+        _ => false,
+    }
+}
@@ -1,71 +1,140 @@
 #![allow(dead_code)]
 
 use crate::{
-    args::AppArgs,
-    parser::{type_check, Ast, BinExpr, Bool, FuncCall, Variable, VariableDeclaration},
+    args::{AppArgs, Backend, Emit},
+    builtins::builtin,
+    checker::{Checker, FuncSignature},
+    native,
+    parser::{
+        Assignment, Ast, BinExpr, BinOpKind, Bool, Coalesce, Destructure, EnumVariant, FieldAccess,
+        ForLoop, FuncCall, FuncDecl, IfStatement, Import, Index, Match, Pattern, Span,
+        StructLiteral, TryCatch, Type, VariableDeclaration,
+    },
+    toolchain::Toolchain,
     utils::{
-        dbg, dbg_file_if_env, dbg_plain, escape_string, get_tmp_fname, measure_time, ErrorType,
+        dbg, dbg_file_if_env, dbg_plain, escape_string, get_tmp_fname, measure_time, write_output,
+        ErrorType,
     },
     Expr,
 };
 use std::{
-    collections::HashMap,
-    fs::{File, OpenOptions},
-    io::{Read, Write},
-    os::unix::fs::PermissionsExt,
-    process::{exit, Command, Stdio},
+    collections::{HashMap, HashSet},
+    path::Path,
 };
 
-// FIXME: not everywhere qbe is present in the /sbin/qbe path. Adjust accordingly
-const QBE_BINARY: &[u8] = &[]; //  include_bytes!("/sbin/qbe");
+/// Source of the Black runtime linked into every compiled binary
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// Returns the QBE class used to hold values of the given type. Floating point values live in
+/// `d` temporaries, everything else (integers, booleans, pointers) in `l` temporaries
+pub fn qbe_class(typ: &Type) -> &'static str {
+    match typ {
+        Type::Float | Type::Double => "d",
+        _ => "l",
+    }
+}
+
+/// Returns the type used for values of the given type in function signatures. Structs are
+/// passed and returned as QBE aggregates, which copies them
+fn abi_type(typ: &Type) -> String {
+    match typ {
+        Type::Struct(name) => format!(":{name}"),
+        typ => qbe_class(typ).to_string(),
+    }
+}
+
+/// Returns the unique name of the module declaring an item, items of modules are named after the
+/// module (`math.square`). `None` for items of the main file
+fn module_of(name: &str) -> Option<String> {
+    name.split_once('.').map(|(module, _)| module.to_string())
+}
 
-/// Unpacks QBE from memory into a temporary file. Don't forget to remove the tmp file afterwards
-// FIXME: qbe needs to be statically linked. As well as the output black binary
-fn get_qbe() -> Result<String, Box<dyn std::error::Error>> {
-    // Get a unique temporary file path
-    let tmp_path = get_tmp_fname("qbe");
+/// Whether values of the given type are boxed when stored in an optional. An optional is a
+/// pointer which is null for `none`, so numbers and booleans are stored behind a pointer while
+/// strings, collections, structs, enums and tuples (pointers themselves) are stored as they are
+fn boxed(typ: &Type) -> bool {
+    matches!(
+        typ,
+        Type::Int | Type::Long | Type::Float | Type::Double | Type::Bool
+    )
+}
 
-    // Write the embedded QBE binary to the temporary file
-    {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&tmp_path)?;
-        file.write_all(QBE_BINARY)?;
+/// Returns the element type of a list type
+fn elem_type(list_type: &Type) -> Type {
+    match list_type {
+        Type::List(elem) => *elem.clone(),
+        _ => Type::None,
     }
+}
 
-    // Make the file executable (Unix-specific; adjust for Windows)
-    #[cfg(unix)]
-    {
-        let mut permissions = File::open(&tmp_path)?.metadata()?.permissions();
-        permissions.set_mode(0o755); // Owner-executable permissions
-        File::open(&tmp_path)?.set_permissions(permissions)?;
+/// Returns the key and value types of a map type
+fn map_types(map_type: &Type) -> (Type, Type) {
+    match map_type {
+        Type::Map(key, value) => (*key.clone(), *value.clone()),
+        _ => (Type::None, Type::None),
     }
+}
 
-    Ok(tmp_path)
+/// Returns the runtime key kind of a map keyed by the given type, deciding how keys are hashed
+/// and compared
+fn key_kind(key_type: &Type) -> u8 {
+    match key_type {
+        Type::Str => 1,
+        Type::Bool => 2,
+        _ => 0,
+    }
 }
 
+/// Variables declared in a block, along with the type and slot of the variable they shadow
+type Scope = HashMap<String, Option<(Type, Option<String>)>>;
+
 /// Represents a compiler that processes an abstract syntax tree (AST) and generates intermediate
 /// representation (IR), as well as handles variable management and function calls
 pub struct Compiler {
     pub ast: Ast,
     pub ir: String,
     pub data: String,
+    /// Helper functions generated on demand (eg. printers for nested lists)
+    pub functions: String,
     pub pk: usize,
-    pub variables: HashMap<String, Variable>,
+    pub variables: HashMap<String, Type>,
+    /// Source file name used in runtime error messages
+    pub filename: String,
+    /// Fields of every declared struct, in declaration order
+    pub structs: HashMap<String, Vec<(String, Type)>>,
+    /// Variants of every declared enum along with the types of their values. An enum value is a
+    /// pointer to the index of its variant followed by one 8 byte slot per value. Enum values
+    /// can't be modified, so copies share the memory
+    pub enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    /// Signatures of the user defined functions
+    pub signatures: HashMap<String, FuncSignature>,
+    /// Aggregate type definitions of the declared structs
+    pub types: String,
+    printers: HashMap<String, String>,
+    emitted_types: HashSet<String>,
+    /// Stack slots of the local variables of the function being compiled
+    allocs: String,
+    /// Return type of the function being compiled, `None` at the top level
+    return_type: Option<Type>,
+    /// Labels of the catch blocks of the try statements enclosing the code being compiled, in
+    /// the function being compiled. Errors jump to the innermost one
+    catches: Vec<String>,
+    /// Unique name of the module whose code is being compiled, `None` for the main file
+    module: Option<String>,
+    /// Source files of the imported modules by their unique names
+    sources: HashMap<String, String>,
+    /// Functions, structs and enums of modules declared with `pub`
+    public: HashSet<String>,
+    /// Variables declared by the for, if, try and match blocks being compiled
+    scopes: Vec<Scope>,
+    /// Slots of the variables shadowing a variable of an outer block
+    shadows: HashMap<String, String>,
 }
 
 impl Compiler {
     /// Creates a new instance of the `Compiler` struct, initializing its fields to default values
     pub fn new() -> Self {
-        Self {
-            ast: Vec::new(),
-            ir: String::new(),
-            data: String::new(),
-            pk: 0,
-            variables: HashMap::new(),
-        }
+        Self::from_ast(Vec::new())
     }
 
     /// Loads the provided abstract syntax tree (AST) into the compiler, replacing any existing AST
@@ -79,17 +148,35 @@ impl Compiler {
             ast,
             ir: String::new(),
             data: String::new(),
+            functions: String::new(),
             pk: 0,
             variables: HashMap::new(),
+            filename: String::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            signatures: HashMap::new(),
+            types: String::new(),
+            printers: HashMap::new(),
+            emitted_types: HashSet::new(),
+            allocs: String::new(),
+            return_type: None,
+            catches: Vec::new(),
+            module: None,
+            sources: HashMap::new(),
+            public: HashSet::new(),
+            scopes: Vec::new(),
+            shadows: HashMap::new(),
         }
     }
 
-    /// Retrieves a variable by its identifier, returning it or exiting with an error if not found
-    fn get_var(&self, ident: &str) -> Result<Variable, String> {
+    /// Retrieves the type of a variable by its identifier, returning an error if not found
+    fn get_var(&self, ident: &str) -> Result<Type, ErrorType> {
         self.variables
             .get(ident)
             .cloned()
-            .ok_or(format!("Variable doesn't exist: `{ident}`"))
+            .ok_or(ErrorType::Generic(format!(
+                "Variable doesn't exist: `{ident}`"
+            )))
     }
 
     /// Increments and returns the primary key, used for generating unique variable labels
@@ -103,315 +190,2155 @@ impl Compiler {
         self.pk += 1;
     }
 
-    /// Handles a function call by dispatching to the appropriate handler
-    fn handle_func_call(&mut self, func_call: &FuncCall) -> Result<(), String> {
-        match func_call.name.as_ref() {
-            "print" => self.handle_print(func_call)?,
-            _ => return Err(format!("Function `{}` is not implemented", func_call.name)),
+    /// Handles a function call by dispatching to the appropriate handler. Returns the temporary
+    /// holding the result along with its type
+    fn handle_func_call(&mut self, func_call: &FuncCall) -> Result<(String, Type), ErrorType> {
+        match builtin(&func_call.name) {
+            Some(builtin) => (builtin.compile)(self, func_call),
+            None => self.call_user_func(func_call),
         }
+    }
 
-        Ok(())
+    /// Emits a call of a user defined function
+    fn call_user_func(&mut self, func_call: &FuncCall) -> Result<(String, Type), ErrorType> {
+        let signature = self
+            .signatures
+            .get(&func_call.name)
+            .cloned()
+            .ok_or(ErrorType::Generic(format!(
+                "Function `{}` is not implemented",
+                func_call.name
+            )))?;
+
+        let mut args = Vec::with_capacity(signature.params.len());
+        for (param, arg) in signature.params.iter().zip(&func_call.arguments) {
+            let (value, typ) = self.eval_expected(arg, param)?;
+            let value = self.convert(value, &typ, param);
+            args.push(format!("{} {value}", abi_type(param)));
+        }
+
+        // Runtime errors raised by functions of other modules name the file of the module
+        let module =
+            module_of(&func_call.name).filter(|module| Some(module) != self.module.as_ref());
+        let source = self.next_pk();
+        if let Some(module) = &module {
+            self.ir.push_str(&format!(
+                "  %source{source} =l call $black_set_source(l $source_{module})\n"
+            ));
+        }
+
+        let call = format!("call $fn_{}({})", func_call.name, args.join(", "));
+        let return_type = signature.return_type;
+        let result = match return_type {
+            Type::None => {
+                self.ir.push_str(&format!("  {call}\n"));
+                "0".to_string()
+            }
+            _ => {
+                let pk = self.next_pk();
+                self.ir
+                    .push_str(&format!("  %v{pk} ={} {call}\n", abi_type(&return_type)));
+                format!("%v{pk}")
+            }
+        };
+
+        if module.is_some() {
+            self.ir
+                .push_str(&format!("  call $black_set_source(l %source{source})\n"));
+        }
+        self.check_error();
+        Ok((result, return_type))
     }
 
-    /// Handles the `print` function call by generating IR to print its arguments
-    fn handle_print(&mut self, func_call: &FuncCall) -> Result<(), String> {
-        let args = func_call.arguments.iter();
-        let args_count = args.len();
-        for (i, arg) in args.enumerate() {
-            let pk = self.next_pk();
+    /// Emits IR checking whether an error was raised by the preceding call. A raised error jumps
+    /// to the innermost catch block, or returns from the function so that its caller handles the
+    /// error. Outside of try blocks at the top level errors exit the program right away, so
+    /// nothing needs to be checked
+    fn check_error(&mut self) {
+        let unwind = match (self.catches.last(), &self.return_type) {
+            (Some(catch), _) => format!("  jmp {catch}\n"),
+            (None, Some(return_type)) => {
+                let return_type = return_type.clone();
+                self.dummy_return(&return_type)
+            }
+            (None, None) => return,
+        };
 
-            match arg {
-                Expr::StringLiteral(message) => {
-                    let pk = self.emit_str(message);
-                    self.ir.push_str(&format!("  call $printf(l $v{pk})\n"));
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %raised{pk} =l loadl $black_error\n  jnz %raised{pk}, @raised{pk}, @no_error{pk}\n@raised{pk}\n{unwind}@no_error{pk}\n"
+        ));
+    }
+
+    /// Returns IR returning a placeholder value of the given type from the function being
+    /// compiled, used when an error unwinds out of it
+    fn dummy_return(&mut self, typ: &Type) -> String {
+        match typ {
+            Type::None => "  ret\n".to_string(),
+            Type::Float | Type::Double => "  ret d_0\n".to_string(),
+            Type::Struct(_) => {
+                let pk = self.next_pk();
+                format!(
+                    "  %dummy{pk} =l call $black_alloc(l {})\n  ret %dummy{pk}\n",
+                    self.size_of(typ)
+                )
+            }
+            _ => "  ret 0\n".to_string(),
+        }
+    }
+
+    /// Emits a call to the runtime print helper matching the type of the value
+    fn emit_print(&mut self, value: &str, typ: &Type) {
+        let call = match typ {
+            Type::Optional(inner) => {
+                let pk = self.next_pk();
+                self.ir.push_str(&format!(
+                    "  %some{pk} =w cnel {value}, 0\n  jnz %some{pk}, @print_some{pk}, @print_none{pk}\n@print_none{pk}\n  call $print_str(l $none)\n  jmp @printed{pk}\n@print_some{pk}\n"
+                ));
+                let value = self.unwrap(value, inner);
+                self.emit_print(&value, inner);
+                self.ir.push_str(&format!("@printed{pk}\n"));
+                return;
+            }
+            Type::Str => format!("$print_str(l {value})"),
+            Type::Bool => format!("$print_bool(l {value})"),
+            Type::Float | Type::Double => format!("$print_float(d {value})"),
+            Type::None => "$print_str(l $none)".to_string(),
+            Type::List(elem) => {
+                let printer = self.printer_for(elem);
+                format!("$black_print_list(l {value}, l {printer})")
+            }
+            Type::Map(key, value_type) => {
+                let key_printer = self.printer_for(key);
+                let value_printer = self.printer_for(value_type);
+                format!("$black_print_map(l {value}, l {key_printer}, l {value_printer})")
+            }
+            Type::Struct(_) | Type::Enum(_) | Type::Tuple(_) => {
+                let printer = self.printer_for(typ);
+                format!("{printer}(l {value})")
+            }
+            _ => format!("$print_int(l {value})"),
+        };
+        self.ir.push_str(&format!("  call {call}\n"));
+    }
+
+    /// Returns the runtime function printing a value of the given type nested inside of a
+    /// collection. The function receives the raw 8 byte slot of the value (the address for
+    /// structs). Printers for nested lists, maps and structs are generated on demand
+    fn printer_for(&mut self, typ: &Type) -> String {
+        match typ {
+            Type::Str => "$print_quoted".to_string(),
+            Type::Bool => "$print_bool".to_string(),
+            Type::Float | Type::Double => "$print_float_bits".to_string(),
+            Type::None => "$print_none".to_string(),
+            Type::Optional(inner) => {
+                if let Some(printer) = self.printers.get(&typ.to_string()) {
+                    return printer.clone();
                 }
 
-                Expr::Number(num) => {
-                    let pk = self.emit_str(&num.to_string());
-                    self.ir.push_str(&format!("  call $printf(l $v{pk})\n"));
+                let printer = format!("$print_optional{}", self.next_pk());
+                self.printers.insert(typ.to_string(), printer.clone());
+
+                let inner_printer = self.printer_for(inner);
+                let value = match boxed(inner) {
+                    true => "  %value =l loadl %p\n",
+                    false => "  %value =l copy %p\n",
+                };
+                self.functions.push_str(&format!(
+                    "function {printer}(l %p) {{\n@start\n  %some =w cnel %p, 0\n  jnz %some, @some, @none\n@none\n  call $print_str(l $none)\n  ret\n@some\n{value}  call {inner_printer}(l %value)\n  ret\n}}\n"
+                ));
+                printer
+            }
+            Type::List(elem) => {
+                if let Some(printer) = self.printers.get(&typ.to_string()) {
+                    return printer.clone();
                 }
 
-                Expr::BinExpr(bin_expr) => {
-                    let res_var = self.handle_bin_expr(bin_expr)?;
-                    self.ir
-                        .push_str(&format!("  call $printf(l $fmt_int, w {res_var})\n"));
+                let inner = self.printer_for(elem);
+                let printer = format!("$print_list{}", self.next_pk());
+                self.functions.push_str(&format!(
+                    "function {printer}(l %list) {{\n@start\n  call $black_print_list(l %list, l {inner})\n  ret\n}}\n"
+                ));
+                self.printers.insert(typ.to_string(), printer.clone());
+                printer
+            }
+            Type::Map(key, value) => {
+                if let Some(printer) = self.printers.get(&typ.to_string()) {
+                    return printer.clone();
                 }
 
-                Expr::Identifier(id) => {
-                    let var = self.get_var(id)?;
-                    match var {
-                        Variable::Number(_) => {
-                            // NOTE: here we could grab the number, save it to data section
-                            // as a string and print it using puts instead
-                            self.ir.push_str(&format!("  %v{pk} =w loadw ${id}\n"));
-                            self.ir
-                                .push_str(&format!("  call $printf(l $fmt_int, w %v{pk})\n"));
-                        }
-                        Variable::StringLiteral(_) => {
-                            self.ir.push_str(&format!("  call $printf(l ${id})\n"))
-                        }
-                        Variable::Bool(v) => match v {
-                            Bool::True => self.ir.push_str("  call $printf(l $str_true)\n"),
-                            Bool::False => self.ir.push_str("  call $printf(l $str_false)\n"),
-                        },
+                let key_printer = self.printer_for(key);
+                let value_printer = self.printer_for(value);
+                let printer = format!("$print_map{}", self.next_pk());
+                self.functions.push_str(&format!(
+                    "function {printer}(l %map) {{\n@start\n  call $black_print_map(l %map, l {key_printer}, l {value_printer})\n  ret\n}}\n"
+                ));
+                self.printers.insert(typ.to_string(), printer.clone());
+                printer
+            }
+            Type::Struct(name) => {
+                if let Some(printer) = self.printers.get(&typ.to_string()) {
+                    return printer.clone();
+                }
+
+                // Registered before generating the body, structs can contain themselves through
+                // lists
+                let printer = format!("$print_struct_{name}");
+                self.printers.insert(typ.to_string(), printer.clone());
+
+                let fields = self.structs.get(name).cloned().unwrap_or_default();
+                let mut body = String::new();
+                let mut offset = 0;
+                for (i, (field, field_type)) in fields.iter().enumerate() {
+                    let text = match i {
+                        0 => format!("{name} {{ {field}: "),
+                        _ => format!(", {field}: "),
+                    };
+                    let text = self.emit_str(&text);
+                    let inner = self.printer_for(field_type);
+                    let pk = self.next_pk();
+                    body.push_str(&format!(
+                        "  call $print_str(l $v{text})\n  %f{pk} =l add %p, {offset}\n"
+                    ));
+                    if let Type::Struct(_) = field_type {
+                        body.push_str(&format!("  call {inner}(l %f{pk})\n"));
+                    } else {
+                        body.push_str(&format!(
+                            "  %s{pk} =l loadl %f{pk}\n  call {inner}(l %s{pk})\n"
+                        ));
                     }
+                    offset += self.size_of(field_type);
                 }
+                let end = match fields.is_empty() {
+                    true => self.emit_str(&format!("{name} {{}}")),
+                    false => self.emit_str(" }"),
+                };
 
-                Expr::Bool(v) => match v {
-                    Bool::True => self.ir.push_str("  call $printf(l $str_true)\n"),
-                    Bool::False => self.ir.push_str("  call $printf(l $str_false)\n"),
-                },
+                self.functions.push_str(&format!(
+                    "function {printer}(l %p) {{\n@start\n{body}  call $print_str(l $v{end})\n  ret\n}}\n"
+                ));
+                printer
+            }
+            Type::Tuple(elems) => {
+                if let Some(printer) = self.printers.get(&typ.to_string()) {
+                    return printer.clone();
+                }
 
-                _ => {
-                    return Err("Invalid argument to print".to_string());
+                let printer = format!("$print_tuple{}", self.next_pk());
+                self.printers.insert(typ.to_string(), printer.clone());
+
+                let open = self.emit_str("(");
+                let separator = self.emit_str(", ");
+                let close = match elems.len() {
+                    1 => self.emit_str(",)"),
+                    _ => self.emit_str(")"),
+                };
+                let mut body = format!("  call $print_str(l $v{open})\n");
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        body.push_str(&format!("  call $print_str(l $v{separator})\n"));
+                    }
+                    let inner = self.printer_for(elem);
+                    let pk = self.next_pk();
+                    body.push_str(&format!(
+                        "  %f{pk} =l add %p, {}\n  %s{pk} =l loadl %f{pk}\n  call {inner}(l %s{pk})\n",
+                        8 * i
+                    ));
                 }
+
+                self.functions.push_str(&format!(
+                    "function {printer}(l %p) {{\n@start\n{body}  call $print_str(l $v{close})\n  ret\n}}\n"
+                ));
+                printer
             }
+            Type::Enum(name) => {
+                if let Some(printer) = self.printers.get(&typ.to_string()) {
+                    return printer.clone();
+                }
 
-            // Add space between arguments if not the last one
-            if i != args_count - 1 {
-                self.ir.push_str("  call $printf(l $space)\n");
+                // Registered before generating the body, enums can contain themselves
+                let printer = format!("$print_enum_{name}");
+                self.printers.insert(typ.to_string(), printer.clone());
+
+                let variants = self.enums.get(name).cloned().unwrap_or_default();
+                let separator = self.emit_str(", ");
+                let close = self.emit_str(")");
+                let mut body = String::from("  %tag =l loadl %p\n");
+                for (i, (variant, types)) in variants.iter().enumerate() {
+                    let text = match types.is_empty() {
+                        true => self.emit_str(variant),
+                        false => self.emit_str(&format!("{variant}(")),
+                    };
+                    let pk = self.next_pk();
+                    body.push_str(&format!(
+                        "  %is{pk} =w ceql %tag, {i}\n  jnz %is{pk}, @variant{pk}, @next{pk}\n@variant{pk}\n  call $print_str(l $v{text})\n"
+                    ));
+                    for (j, typ) in types.iter().enumerate() {
+                        if j > 0 {
+                            body.push_str(&format!("  call $print_str(l $v{separator})\n"));
+                        }
+                        let inner = self.printer_for(typ);
+                        let slot = self.next_pk();
+                        body.push_str(&format!(
+                            "  %f{slot} =l add %p, {}\n  %s{slot} =l loadl %f{slot}\n  call {inner}(l %s{slot})\n",
+                            8 * (j + 1)
+                        ));
+                    }
+                    if !types.is_empty() {
+                        body.push_str(&format!("  call $print_str(l $v{close})\n"));
+                    }
+                    body.push_str(&format!("  ret\n@next{pk}\n"));
+                }
+
+                self.functions.push_str(&format!(
+                    "function {printer}(l %p) {{\n@start\n{body}  ret\n}}\n"
+                ));
+                printer
             }
+            _ => "$print_int".to_string(),
         }
+    }
 
-        self.ir.push_str("  call $printf(l $endl)\n");
-
-        Ok(())
+    /// Returns the size of a value of the given type when stored inline. Nested structs are
+    /// stored inline in their parent, every other value takes one 8 byte slot
+    fn size_of(&self, typ: &Type) -> usize {
+        match typ {
+            Type::Struct(name) => self.structs[name]
+                .iter()
+                .map(|(_, field)| self.size_of(field))
+                .sum::<usize>()
+                .max(8),
+            _ => 8,
+        }
     }
 
-    /// Emits given string to IR data section and returns pk for the variable
-    fn emit_str(&mut self, s: &str) -> usize {
-        let escaped = escape_string(s);
-        let pk = self.next_pk();
-        self.data
-            .push_str(&format!("data $v{pk} = {{ b \"{escaped}\", b 0 }}\n"));
-        pk
+    /// Returns the offset of a struct field along with its type
+    fn field_offset(&self, name: &str, field: &str) -> Result<(usize, Type), ErrorType> {
+        let mut offset = 0;
+        for (field_name, typ) in &self.structs[name] {
+            if field_name == field {
+                return Ok((offset, typ.clone()));
+            }
+            offset += self.size_of(typ);
+        }
+
+        Err(ErrorType::Generic(format!(
+            "Struct `{name}` has no field `{field}`"
+        )))
     }
 
-    /// Evaluates an operand expression and returns its result temporary variable
-    fn eval_operand(&mut self, operand: &Expr) -> Result<String, String> {
-        let pk = self.next_pk();
-        match operand {
-            Expr::Number(n) => Ok(n.to_string()),
+    /// Emits the aggregate type of a struct, after the types of its nested structs
+    fn emit_type(&mut self, name: &str) {
+        if !self.emitted_types.insert(name.to_string()) {
+            return;
+        }
 
-            Expr::Identifier(id) => {
-                self.ir.push_str(&format!("  %op{pk} =w loadw ${id}\n"));
-                Ok(format!("%op{pk}"))
+        let mut items = Vec::new();
+        for (_, typ) in self.structs[name].clone() {
+            match typ {
+                Type::Struct(inner) => {
+                    self.emit_type(&inner);
+                    items.push(format!(":{inner}"));
+                }
+                typ => items.push(qbe_class(&typ).to_string()),
             }
+        }
+        if items.is_empty() {
+            items.push("l".to_string());
+        }
 
-            Expr::BinExpr(bin_expr) => self.handle_bin_expr(bin_expr),
+        self.types
+            .push_str(&format!("type :{name} = {{ {} }}\n", items.join(", ")));
+    }
+
+    /// Copies a struct value into newly allocated memory, so that it's not shared with the place
+    /// it was read from. Other values are returned as they are
+    fn copy_struct(&mut self, value: String, typ: &Type) -> String {
+        if let Type::Struct(_) = typ {
+            let size = self.size_of(typ);
+            let pk = self.next_pk();
+            self.ir.push_str(&format!(
+                "  %copy{pk} =l call $black_alloc(l {size})\n  blit {value}, %copy{pk}, {size}\n"
+            ));
+            return format!("%copy{pk}");
+        }
+
+        value
+    }
 
-            _ => Err("Cannot add variable which is not a number".to_string()),
+    /// Prepares the value of an expression for being stored into a variable, list or map. Structs
+    /// are values, so anything but a fresh struct literal is copied
+    fn own(&mut self, value: String, typ: &Type, expr: &Expr) -> String {
+        match expr {
+            Expr::StructLiteral(_) => value,
+            _ => self.copy_struct(value, typ),
         }
     }
 
-    /// Handles a binary expression and generates corresponding IR. Returns temporary variable
-    /// containing the equation result
-    fn handle_bin_expr(&mut self, bin_expr: &BinExpr) -> Result<String, String> {
-        let lhs = self.eval_operand(&bin_expr.lhs)?;
-        let rhs = self.eval_operand(&bin_expr.rhs)?;
-        let pk = self.next_pk();
+    /// Stores a value into memory laid out inline (a struct field), copying nested structs
+    fn store_inline(&mut self, addr: &str, value: &str, typ: &Type) {
+        if let Type::Struct(_) = typ {
+            let size = self.size_of(typ);
+            self.ir
+                .push_str(&format!("  blit {value}, {addr}, {size}\n"));
+        } else {
+            self.ir
+                .push_str(&format!("  store{} {value}, {addr}\n", qbe_class(typ)));
+        }
+    }
 
+    /// Handles a struct literal by allocating the struct and storing every field
+    fn handle_struct_literal(
+        &mut self,
+        literal: &StructLiteral,
+    ) -> Result<(String, Type), ErrorType> {
+        let typ = Type::Struct(literal.name.clone());
+        let pk = self.next_pk();
         self.ir.push_str(&format!(
-            "  %v{pk} =w {} {lhs}, {rhs}\n",
-            bin_expr.kind.to_str()
+            "  %v{pk} =l call $black_alloc(l {})\n",
+            self.size_of(&typ)
         ));
 
-        Ok(format!("%v{pk}"))
+        for (field, value) in &literal.fields {
+            let (offset, field_type) = self.field_offset(&literal.name, field)?;
+            let (value, value_type) = self.eval_expected(value, &field_type)?;
+            let value = self.convert(value, &value_type, &field_type);
+            let addr = self.next_pk();
+            self.ir
+                .push_str(&format!("  %field{addr} =l add %v{pk}, {offset}\n"));
+            self.store_inline(&format!("%field{addr}"), &value, &field_type);
+        }
+
+        Ok((format!("%v{pk}"), typ))
     }
 
-    /// Handles a variable declaration, storing the variable in the `variables` map and generating
-    /// corresponding data and IR
-    fn handle_var_decl(
+    /// Handles a tuple literal by allocating one 8 byte slot per element. Like enums, tuples can't
+    /// be modified, so copies of a tuple share its memory
+    fn handle_tuple(
         &mut self,
-        variable_declaration: &VariableDeclaration,
-    ) -> Result<(), String> {
-        let var_label = format!("${}", variable_declaration.identifier);
+        elements: &[Expr],
+        expected: &Type,
+    ) -> Result<(String, Type), ErrorType> {
+        let hints = match expected {
+            Type::Tuple(elems) => elems.clone(),
+            _ => Vec::new(),
+        };
 
-        if let Some(var_type) = &variable_declaration.typ {
-            if !type_check(var_type, &variable_declaration.value) {
-                return Err(format!(
-                    "Variable type `{var_type}` does not match value type",
-                ));
-            }
+        let mut slots = Vec::with_capacity(elements.len());
+        let mut types = Vec::with_capacity(elements.len());
+        for (i, element) in elements.iter().enumerate() {
+            let hint = hints.get(i).cloned().unwrap_or(Type::None);
+            let (value, typ) = self.eval_expected(element, &hint)?;
+            let value = self.own(value, &typ, element);
+            let (value, typ) = match hint {
+                Type::None => (value, typ),
+                hint => (self.convert(value, &typ, &hint), hint),
+            };
+            slots.push(self.slot_from_value(value, &typ));
+            types.push(typ);
         }
 
-        let value = match &variable_declaration.value {
-            Expr::Number(n) => {
-                self.data
-                    .push_str(&format!("data {var_label} = {{ w {} }}\n", *n));
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %v{pk} =l call $black_alloc(l {})\n",
+            8 * elements.len().max(1)
+        ));
+        for (i, slot) in slots.iter().enumerate() {
+            let addr = self.next_pk();
+            self.ir.push_str(&format!(
+                "  %field{addr} =l add %v{pk}, {}\n  storel {slot}, %field{addr}\n",
+                8 * i
+            ));
+        }
 
-                Variable::Number(*n)
-            }
+        Ok((format!("%v{pk}"), Type::Tuple(types)))
+    }
 
-            Expr::StringLiteral(s) => {
-                self.data.push_str(&format!(
-                    "data {var_label} = {{ b \"{}\", b 0 }}\n",
-                    Variable::StringLiteral(s.to_owned())
-                ));
+    /// Emits IR loading an element of the evaluated tuple
+    fn emit_tuple_elem(
+        &mut self,
+        tuple: &str,
+        tuple_type: &Type,
+        index: usize,
+    ) -> Result<(String, Type), ErrorType> {
+        let typ = match tuple_type {
+            Type::Tuple(elems) => elems.get(index).cloned(),
+            _ => None,
+        }
+        .ok_or(ErrorType::Generic(format!(
+            "Cannot access element {index} on value of type `{tuple_type}`"
+        )))?;
 
-                Variable::StringLiteral(s.to_owned())
-            }
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %field{pk} =l add {tuple}, {}\n  %slot{pk} =l loadl %field{pk}\n",
+            8 * index
+        ));
+        let value = self.value_from_slot(format!("%slot{pk}"), &typ);
+        Ok((value, typ))
+    }
 
-            Expr::BinExpr(bin_expr) => {
-                let res_var = self.handle_bin_expr(bin_expr)?;
-                self.data
-                    .push_str(&format!("data {var_label} = {{ w 0 }}\n"));
-                self.ir
-                    .push_str(&format!("  storew {res_var}, {var_label}\n"));
+    /// Handles a destructuring declaration by storing every element of the tuple into its own
+    /// variable
+    fn handle_destructure(&mut self, destructure: &Destructure) -> Result<(), ErrorType> {
+        let (tuple, tuple_type) = self.eval_expr(&destructure.value)?;
 
-                Variable::Number(0)
+        for (i, name) in destructure.names.iter().enumerate() {
+            if name == "_" {
+                continue;
             }
+            let (value, typ) = self.emit_tuple_elem(&tuple, &tuple_type, i)?;
+            let value = self.copy_struct(value, &typ);
+            self.declare_var(name, typ.clone());
+            let addr = self.var_addr(name);
+            self.ir
+                .push_str(&format!("  store{} {value}, {addr}\n", qbe_class(&typ)));
+        }
 
-            Expr::Bool(v) => match v {
-                Bool::True => Variable::Bool(Bool::True),
-                Bool::False => Variable::Bool(Bool::False),
-            },
+        Ok(())
+    }
 
-            _ => {
-                return Err("Can only store strings and numbers in variables".to_string());
-            }
-        };
+    /// Returns the index of an enum variant along with the types of its values
+    fn variant_of(&self, enum_name: &str, variant: &str) -> Result<(usize, Vec<Type>), ErrorType> {
+        self.enums
+            .get(enum_name)
+            .and_then(|variants| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (name, _))| name == variant)
+                    .map(|(i, (_, types))| (i, types.clone()))
+            })
+            .ok_or(ErrorType::Generic(format!(
+                "Enum `{enum_name}` has no variant `{variant}`"
+            )))
+    }
+
+    /// Handles the construction of an enum value by allocating it and storing the index of the
+    /// variant followed by its values
+    fn handle_enum_variant(&mut self, variant: &EnumVariant) -> Result<(String, Type), ErrorType> {
+        let (index, types) = self.variant_of(&variant.enum_name, &variant.variant)?;
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %v{pk} =l call $black_alloc(l {})\n  storel {index}, %v{pk}\n",
+            8 * (types.len() + 1)
+        ));
 
-        let id = variable_declaration.identifier.clone();
-        self.variables.insert(id, value);
+        for (i, (arg, typ)) in variant.arguments.iter().zip(&types).enumerate() {
+            let (value, value_type) = self.eval_expected(arg, typ)?;
+            let value = self.convert(value, &value_type, typ);
+            let value = self.own(value, typ, arg);
+            let slot = self.slot_from_value(value, typ);
+            let addr = self.next_pk();
+            self.ir.push_str(&format!(
+                "  %field{addr} =l add %v{pk}, {}\n  storel {slot}, %field{addr}\n",
+                8 * (i + 1)
+            ));
+        }
 
-        Ok(())
+        Ok((format!("%v{pk}"), Type::Enum(variant.enum_name.clone())))
     }
 
-    /// Evaluate one expression
-    pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<(), ErrorType> {
-        match expr {
-            Expr::FuncCall(func_call) => self.handle_func_call(func_call)?,
+    /// Handles a match expression of the given result type. Every arm tests its pattern and
+    /// jumps to the next arm when it doesn't match, the value of the matching arm is stored into
+    /// the result temporary
+    fn handle_match(
+        &mut self,
+        match_expr: &Match,
+        typ: &Type,
+    ) -> Result<(String, Type), ErrorType> {
+        let (value, value_type) = self.eval_expr(&match_expr.value)?;
+        let pk = self.next_pk();
+        let result = format!("%match{pk}");
+
+        for (i, arm) in match_expr.arms.iter().enumerate() {
+            self.ir.push_str(&format!("@arm{pk}_{i}\n"));
+            let next = format!("@arm{pk}_{}", i + 1);
+            // Variables bound by the pattern are only visible in the arm
+            self.scoped(|compiler| {
+                compiler.emit_pattern(&arm.pattern, &value, &value_type, &next)?;
+
+                match &arm.body {
+                    Expr::Block(_)
+                    | Expr::VariableDeclaration(_)
+                    | Expr::Assignment(_)
+                    | Expr::Return(_)
+                    | Expr::For(_) => {
+                        compiler.evaluate_expr(&arm.body)?;
+                        // Arms without a value result in `none`
+                        if *typ != Type::None {
+                            compiler.ir.push_str(&format!("  {result} =l copy 0\n"));
+                        }
+                    }
+                    body => {
+                        let (arm_value, arm_type) = compiler.eval_expected(body, typ)?;
+                        if *typ != Type::None {
+                            let arm_value = compiler.convert(arm_value, &arm_type, typ);
+                            compiler.ir.push_str(&format!(
+                                "  {result} ={} copy {arm_value}\n",
+                                qbe_class(typ)
+                            ));
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+            self.ir.push_str(&format!("  jmp @match_end{pk}\n"));
+        }
 
-            Expr::VariableDeclaration(variable_declaration) => {
-                self.handle_var_decl(variable_declaration)?
+        // The checker only accepts matches covering every value
+        self.ir.push_str(&format!(
+            "@arm{pk}_{}\n  hlt\n@match_end{pk}\n",
+            match_expr.arms.len()
+        ));
+
+        match typ {
+            Type::None => Ok(("0".to_string(), Type::None)),
+            typ => Ok((result, typ.clone())),
+        }
+    }
+
+    /// Emits IR testing whether the value matches the pattern, jumping to the `fail` label when
+    /// it doesn't. Variables bound by the pattern are stored along the way
+    fn emit_pattern(
+        &mut self,
+        pattern: &Pattern,
+        value: &str,
+        typ: &Type,
+        fail: &str,
+    ) -> Result<(), ErrorType> {
+        match pattern {
+            Pattern::Wildcard => {}
+
+            Pattern::Binding(name) => {
+                let value = self.copy_struct(value.to_string(), typ);
+                self.declare_var(name, typ.clone());
+                let addr = self.var_addr(name);
+                self.ir
+                    .push_str(&format!("  store{} {value}, {addr}\n", qbe_class(typ)));
             }
 
-            Expr::Block(block) => {
-                for node in block {
-                    self.evaluate_expr(node)?;
+            Pattern::Literal(literal) => {
+                let pk = self.next_pk();
+                if let Expr::StringLiteral(s) = literal {
+                    let s = self.emit_str(s);
+                    self.ir.push_str(&format!(
+                        "  %cmp{pk} =w call $strcmp(l {value}, l $v{s})\n  %ok{pk} =w ceqw %cmp{pk}, 0\n"
+                    ));
+                } else {
+                    let (literal, _) = self.eval_expr(literal)?;
+                    self.ir
+                        .push_str(&format!("  %ok{pk} =w ceql {value}, {literal}\n"));
                 }
+                self.ir.push_str(&format!(
+                    "  jnz %ok{pk}, @matched{pk}, {fail}\n@matched{pk}\n"
+                ));
             }
 
-            _ => {
-                return Err(ErrorType::Generic(format!(
-                    "Expression `{expr:?}` in this context is not yet implemented"
-                )));
+            Pattern::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let (index, types) = self.variant_of(enum_name, variant)?;
+                let pk = self.next_pk();
+                self.ir.push_str(&format!(
+                    "  %tag{pk} =l loadl {value}\n  %ok{pk} =w ceql %tag{pk}, {index}\n  jnz %ok{pk}, @matched{pk}, {fail}\n@matched{pk}\n"
+                ));
+
+                for (i, (field, typ)) in fields.iter().zip(&types).enumerate() {
+                    let slot = self.next_pk();
+                    self.ir.push_str(&format!(
+                        "  %field{slot} =l add {value}, {}\n  %slot{slot} =l loadl %field{slot}\n",
+                        8 * (i + 1)
+                    ));
+                    let field_value = self.value_from_slot(format!("%slot{slot}"), typ);
+                    self.emit_pattern(field, &field_value, typ, fail)?;
+                }
             }
         }
 
         Ok(())
     }
 
-    /// Generates the intermediate representation (IR) for the AST and returns it as a string
-    pub fn generate_ir(&mut self) -> Result<String, ErrorType> {
-        self.ir.push_str("export function w $main() {\n@start\n");
-
-        let ast = self.ast.clone();
-
-        for node in &ast {
-            self.evaluate_expr(node)?;
-        }
+    /// Emits IR computing the address of a struct field. Returns the temporary holding the
+    /// address along with the field type
+    fn emit_field_addr(&mut self, field: &FieldAccess) -> Result<(String, Type), ErrorType> {
+        let (target, target_type) = self.eval_expr(&field.target)?;
+        let Type::Struct(name) = target_type else {
+            return Err(ErrorType::Generic(format!(
+                "Cannot access field `{}`",
+                field.field
+            )));
+        };
+        let (offset, typ) = self.field_offset(&name, &field.field)?;
 
-        self.ir.push_str("  ret 0\n}");
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %field{pk} =l add {target}, {offset}\n"));
+        Ok((format!("%field{pk}"), typ))
+    }
 
-        Ok(format!("{}\n{}", self.data, self.ir))
+    /// Emits given string to IR data section and returns pk for the variable
+    fn emit_str(&mut self, s: &str) -> usize {
+        let escaped = escape_string(s);
+        let pk = self.next_pk();
+        self.data
+            .push_str(&format!("data $v{pk} = {{ b \"{escaped}\", b 0 }}\n"));
+        pk
     }
 
-    /// Compiles the AST by generating IR, running it through the `qbe` compiler, and then
-    /// assembling and linking the output with `cc` to produce the final executable
-    pub fn compile(&mut self, args: &AppArgs) -> Result<(), ErrorType> {
-        let ir = format!("{}{}", include_str!("ext.ssa"), self.generate_ir()?);
+    /// Converts a value to the class required by the target type (eg. an integer stored into a
+    /// `float` variable), wrapping values stored into optionals. Returns the converted value
+    fn convert(&mut self, value: String, from: &Type, to: &Type) -> String {
+        if let Type::Optional(inner) = to {
+            return match from {
+                // `none` is a null pointer
+                Type::Optional(_) | Type::None => value,
+                from => {
+                    let value = self.convert(value, from, inner);
+                    self.wrap(value, inner)
+                }
+            };
+        }
 
-        dbg("Variables", &self.variables);
-        dbg_plain("Compiled IR", &ir);
-        dbg_file_if_env(&ir, "debug.ir", "SAVE_IR");
+        if qbe_class(from) == qbe_class(to) {
+            return value;
+        }
 
-        let out_file_str = args.output.to_str().expect("invalid output file");
+        let pk = self.next_pk();
+        self.ir.push_str(&format!("  %v{pk} =d sltof {value}\n"));
+        format!("%v{pk}")
+    }
 
-        let mut qbe_output = String::new();
+    /// Turns a value into an optional holding it. Numbers and booleans are boxed, structs are
+    /// copied so that the optional doesn't share them
+    fn wrap(&mut self, value: String, typ: &Type) -> String {
+        if !boxed(typ) {
+            return self.copy_struct(value, typ);
+        }
 
-        // Use system installed qbe
-        let qbe_path = String::from("qbe");
-        // Use qbe included with the compiler
-        // let qbe_path = get_qbe().expect("Failed to dynamically unpack QBE. This is a bug.");
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %box{pk} =l call $black_alloc(l 8)\n  store{} {value}, %box{pk}\n",
+            qbe_class(typ)
+        ));
+        format!("%box{pk}")
+    }
 
-        measure_time("QBE execution", || {
-            let mut qbe = Command::new(&qbe_path)
-                .arg("-")
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("Failed to start qbe");
+    /// Returns the value held by an optional which isn't `none`
+    fn unwrap(&mut self, value: &str, typ: &Type) -> String {
+        if !boxed(typ) {
+            return value.to_string();
+        }
 
-            // Write ir to the qbe process stdin
-            if let Some(mut stdin) = qbe.stdin.take() {
-                stdin
-                    .write_all(ir.as_bytes())
-                    .expect("Failed to write to qbe stdin");
-            }
+        let class = qbe_class(typ);
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} ={class} load{class} {value}\n"));
+        format!("%v{pk}")
+    }
 
-            // Get the qbe output assembly
-            if let Some(mut stdout) = qbe.stdout.take() {
-                stdout
-                    .read_to_string(&mut qbe_output)
-                    .expect("Failed to read qbe stdout");
-            }
+    /// Handles an optional value with a fallback. The fallback is only evaluated when the value
+    /// is `none`
+    fn handle_coalesce(
+        &mut self,
+        coalesce: &Coalesce,
+        typ: &Type,
+    ) -> Result<(String, Type), ErrorType> {
+        let (value, value_type) = self.eval_expr(&coalesce.value)?;
+        let pk = self.next_pk();
+        let class = qbe_class(typ);
+        self.ir.push_str(&format!(
+            "  %some{pk} =w cnel {value}, 0\n  jnz %some{pk}, @some{pk}, @default{pk}\n@some{pk}\n"
+        ));
 
-            let status = qbe.wait().expect("Failed to wait for qbe process");
-            if !status.success() {
-                eprintln!("Error: QBE backend error. This is a bug.");
-                exit(1);
+        let value = match (typ, value_type) {
+            (Type::Optional(_), _) => value,
+            (_, Type::Optional(inner)) => {
+                let value = self.unwrap(&value, &inner);
+                self.convert(value, &inner, typ)
             }
-        });
-
-        dbg("QBE output", &qbe_output);
-        dbg_file_if_env(&qbe_output, "debug.asm", "SAVE_ASM");
-
-        let mut cc_output = String::new();
-
-        let cc_args = if args.static_link {
-            vec!["-x", "assembler", "-static", "-o", out_file_str, "-"]
-        } else {
-            vec!["-x", "assembler", "-o", out_file_str, "-"]
+            _ => value,
         };
+        self.ir.push_str(&format!(
+            "  %coalesce{pk} ={class} copy {value}\n  jmp @coalesce_end{pk}\n@default{pk}\n"
+        ));
 
-        measure_time("CC execution", || {
-            let mut cc = Command::new("cc")
-                .args(cc_args)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("Failed to start cc");
+        let (default, default_type) = self.eval_expected(&coalesce.default, typ)?;
+        let default = self.convert(default, &default_type, typ);
+        self.ir.push_str(&format!(
+            "  %coalesce{pk} ={class} copy {default}\n@coalesce_end{pk}\n"
+        ));
 
-            // Write assembly from qbe to stdin of cc compiler
-            if let Some(mut stdin) = cc.stdin.take() {
-                stdin
-                    .write_all(qbe_output.as_bytes())
-                    .expect("Failed to write to qbe stdin");
-            }
+        Ok((format!("%coalesce{pk}"), typ.clone()))
+    }
 
-            // Get the CC output
-            if let Some(mut stdout) = cc.stdout.take() {
-                stdout
-                    .read_to_string(&mut cc_output)
-                    .expect("Failed to read cc stdout");
-            }
+    /// Reinterprets a value as the raw 8 byte slot stored inside of lists and maps
+    fn slot_from_value(&mut self, value: String, typ: &Type) -> String {
+        if qbe_class(typ) == "l" {
+            return value;
+        }
 
-            let status = cc.wait().expect("Failed to wait for cc process");
-            if !status.success() {
-                eprintln!("Error: CC execution failed. This is a bug.");
-                exit(1);
-            }
-        });
+        let pk = self.next_pk();
+        self.ir.push_str(&format!("  %v{pk} =l cast {value}\n"));
+        format!("%v{pk}")
+    }
 
-        if !cc_output.is_empty() {
-            dbg("WARNING non 0 exit code: CC output", &cc_output);
+    /// Reinterprets a raw 8 byte list or map slot as a value of the given type
+    fn value_from_slot(&mut self, slot: String, typ: &Type) -> String {
+        if qbe_class(typ) == "l" {
+            return slot;
         }
 
-        // Clean up temporary qbe
-        // std::fs::remove_file(&qbe_path).unwrap();
-
+        let pk = self.next_pk();
+        self.ir.push_str(&format!("  %v{pk} =d cast {slot}\n"));
+        format!("%v{pk}")
+    }
+
+    /// Evaluates a value expression. Returns the temporary (or constant) holding the result
+    /// along with its type
+    fn eval_expr(&mut self, expr: &Expr) -> Result<(String, Type), ErrorType> {
+        match expr {
+            Expr::Number(n) => Ok((n.to_string(), Type::Int)),
+
+            Expr::Bool(v) => match v {
+                Bool::True => Ok(("1".to_string(), Type::Bool)),
+                Bool::False => Ok(("0".to_string(), Type::Bool)),
+            },
+
+            Expr::StringLiteral(s) => {
+                let pk = self.emit_str(s);
+                Ok((format!("$v{pk}"), Type::Str))
+            }
+
+            Expr::None => Ok(("0".to_string(), Type::None)),
+
+            Expr::Identifier(id) => {
+                let typ = self.get_var(id)?;
+                let class = qbe_class(&typ);
+                let addr = self.var_addr(id);
+                let pk = self.next_pk();
+                self.ir
+                    .push_str(&format!("  %v{pk} ={class} load{class} {addr}\n"));
+                Ok((format!("%v{pk}"), typ))
+            }
+
+            Expr::StructLiteral(literal) => self.handle_struct_literal(literal),
+
+            Expr::EnumVariant(variant) => self.handle_enum_variant(variant),
+
+            Expr::Tuple(elements) => self.handle_tuple(elements, &Type::None),
+
+            Expr::TupleIndex(index) => {
+                let (tuple, tuple_type) = self.eval_expr(&index.target)?;
+                self.emit_tuple_elem(&tuple, &tuple_type, index.index)
+            }
+
+            Expr::Match(match_expr) => {
+                let typ = self.infer(expr);
+                self.handle_match(match_expr, &typ)
+            }
+
+            Expr::Coalesce(coalesce) => {
+                let typ = self.infer(expr);
+                self.handle_coalesce(coalesce, &typ)
+            }
+
+            Expr::Field(field) => {
+                let (addr, typ) = self.emit_field_addr(field)?;
+                // Nested structs are stored inline, their value is their address
+                if let Type::Struct(_) = typ {
+                    return Ok((addr, typ));
+                }
+                let class = qbe_class(&typ);
+                let pk = self.next_pk();
+                self.ir
+                    .push_str(&format!("  %v{pk} ={class} load{class} {addr}\n"));
+                Ok((format!("%v{pk}"), typ))
+            }
+
+            Expr::BinExpr(bin_expr) => self.handle_bin_expr(bin_expr),
+
+            Expr::FuncCall(func_call) => self.handle_func_call(func_call),
+
+            // The inferred type decides eg. whether `none` elements make the elements optional
+            Expr::List(elements, _) => {
+                let typ = self.infer(expr);
+                self.handle_list(elements, &typ)
+            }
+
+            Expr::Map(entries, _) => {
+                let typ = self.infer(expr);
+                self.handle_map(entries, &typ)
+            }
+
+            Expr::Index(index) => {
+                let (target, target_type) = self.eval_expr(&index.target)?;
+                if let Type::Map(..) = target_type {
+                    let (_, value_type) = map_types(&target_type);
+                    let (key, _) = self.eval_expr(&index.index)?;
+                    let pk = self.next_pk();
+                    self.ir.push_str(&format!(
+                        "  %v{pk} =l call $black_map_get(l {target}, l {key}, l {}, l {})\n",
+                        index.span.line, index.span.col
+                    ));
+                    self.check_error();
+                    let value = self.value_from_slot(format!("%v{pk}"), &value_type);
+                    return Ok((value, value_type));
+                }
+
+                let (addr, typ) = self.emit_elem_addr(&target, &target_type, index)?;
+                let class = qbe_class(&typ);
+                let pk = self.next_pk();
+                self.ir
+                    .push_str(&format!("  %v{pk} ={class} load{class} {addr}\n"));
+                Ok((format!("%v{pk}"), typ))
+            }
+
+            _ => Err(ErrorType::Generic(
+                "Can only store strings and numbers in variables".to_string(),
+            )),
+        }
+    }
+
+    /// Evaluates an operand expression and returns its result temporary variable
+    fn eval_operand(&mut self, operand: &Expr) -> Result<(String, Type), ErrorType> {
+        match operand {
+            Expr::Number(_)
+            | Expr::Identifier(_)
+            | Expr::BinExpr(_)
+            | Expr::Index(_)
+            | Expr::Field(_)
+            | Expr::TupleIndex(_)
+            | Expr::Coalesce(_)
+            | Expr::FuncCall(_) => {
+                let (value, typ) = self.eval_expr(operand)?;
+                match typ {
+                    Type::Int | Type::Long | Type::Float | Type::Double => Ok((value, typ)),
+                    _ => Err(ErrorType::Generic(
+                        "Cannot add variable which is not a number".to_string(),
+                    )),
+                }
+            }
+
+            _ => Err(ErrorType::Generic(
+                "Cannot add variable which is not a number".to_string(),
+            )),
+        }
+    }
+
+    /// Handles a binary expression and generates corresponding IR. Returns temporary variable
+    /// containing the equation result
+    fn handle_bin_expr(&mut self, bin_expr: &BinExpr) -> Result<(String, Type), ErrorType> {
+        let (lhs, lhs_type) = self.eval_operand(&bin_expr.lhs)?;
+        let (rhs, rhs_type) = self.eval_operand(&bin_expr.rhs)?;
+
+        // Mixed integer and floating point operands are computed in floating point
+        let typ = if qbe_class(&lhs_type) == "d" {
+            lhs_type.clone()
+        } else if qbe_class(&rhs_type) == "d" {
+            rhs_type.clone()
+        } else if lhs_type == Type::Long {
+            Type::Long
+        } else {
+            rhs_type.clone()
+        };
+        let lhs = self.convert(lhs, &lhs_type, &typ);
+        let rhs = self.convert(rhs, &rhs_type, &typ);
+        if bin_expr.kind == BinOpKind::Divide && qbe_class(&typ) == "l" {
+            self.emit_zero_check(&rhs, bin_expr.span);
+        }
+
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %v{pk} ={} {} {lhs}, {rhs}\n",
+            qbe_class(&typ),
+            bin_expr.kind.to_str()
+        ));
+
+        Ok((format!("%v{pk}"), typ))
+    }
+
+    /// Emits a check of an integer divisor, which raises an error at the source location of the
+    /// division when it's zero
+    fn emit_zero_check(&mut self, divisor: &str, span: Span) {
+        let message = self.emit_str("Division by zero");
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %nonzero{pk} =w cnel {divisor}, 0
+  jnz %nonzero{pk}, @divisible{pk}, @division_by_zero{pk}
+@division_by_zero{pk}
+  call $black_error_at(l {}, l {}, l $v{message})
+",
+            span.line, span.col
+        ));
+        self.check_error();
+        self.ir.push_str(&format!("  hlt\n@divisible{pk}\n"));
+    }
+
+    /// Evaluates a value expression which is stored into a place of the expected type. Unlike
+    /// `eval_expr`, empty collection literals get their element types from the expected type
+    /// (eg. the key type deciding how an empty map hashes its keys)
+    fn eval_expected(&mut self, expr: &Expr, expected: &Type) -> Result<(String, Type), ErrorType> {
+        let expected = match expected {
+            Type::Optional(inner) => inner,
+            expected => expected,
+        };
+        match expr {
+            Expr::List(elements, _) => self.handle_list(elements, expected),
+            Expr::Map(entries, _) => self.handle_map(entries, expected),
+            Expr::Tuple(elements) => self.handle_tuple(elements, expected),
+            _ => self.eval_expr(expr),
+        }
+    }
+
+    /// Returns the static type of an expression, without emitting any IR
+    fn infer(&self, expr: &Expr) -> Type {
+        let checker = Checker {
+            variables: self.variables.clone(),
+            structs: self.structs.clone(),
+            enums: self.enums.clone(),
+            functions: self.signatures.clone(),
+            function: None,
+            module: self.module.clone(),
+            public: self.public.clone(),
+            node: 0,
+        };
+        checker.infer(expr).unwrap_or(Type::None)
+    }
+
+    /// Handles a list literal by allocating a runtime list and pushing every element into it
+    fn handle_list(
+        &mut self,
+        elements: &[Expr],
+        expected: &Type,
+    ) -> Result<(String, Type), ErrorType> {
+        let mut values = Vec::with_capacity(elements.len());
+        let hint = elem_type(expected);
+        let mut elem_type = hint.clone();
+        for element in elements {
+            let (value, typ) = self.eval_expected(element, &hint)?;
+            let value = self.own(value, &typ, element);
+            if hint == Type::None && (elem_type == Type::None || qbe_class(&typ) == "d") {
+                elem_type = typ.clone();
+            }
+            values.push((value, typ));
+        }
+
+        let pk = self.next_pk();
+        let list = format!("%v{pk}");
+        self.ir.push_str(&format!(
+            "  {list} =l call $black_list_new(l {})\n",
+            elements.len()
+        ));
+        for (value, typ) in values {
+            let value = self.convert(value, &typ, &elem_type);
+            let slot = self.slot_from_value(value, &elem_type);
+            self.ir
+                .push_str(&format!("  call $black_list_push(l {list}, l {slot})\n"));
+        }
+
+        Ok((list, Type::List(Box::new(elem_type))))
+    }
+
+    /// Handles a map literal by allocating a runtime map and setting every entry
+    fn handle_map(
+        &mut self,
+        entries: &[(Expr, Expr)],
+        expected: &Type,
+    ) -> Result<(String, Type), ErrorType> {
+        let mut values = Vec::with_capacity(entries.len());
+        let (key_hint, value_hint) = map_types(expected);
+        let (mut key_type, mut value_type) = (key_hint.clone(), value_hint.clone());
+        for (key, value) in entries {
+            let (key, typ) = self.eval_expr(key)?;
+            if key_hint == Type::None && (key_type == Type::None || typ == Type::Long) {
+                key_type = typ;
+            }
+            let (item, typ) = self.eval_expected(value, &value_hint)?;
+            let item = self.own(item, &typ, value);
+            if value_hint == Type::None && (value_type == Type::None || qbe_class(&typ) == "d") {
+                value_type = typ.clone();
+            }
+            values.push((key, item, typ));
+        }
+
+        let pk = self.next_pk();
+        let map = format!("%v{pk}");
+        self.ir.push_str(&format!(
+            "  {map} =l call $black_map_new(l {})\n",
+            key_kind(&key_type)
+        ));
+        for (key, value, typ) in values {
+            let value = self.convert(value, &typ, &value_type);
+            let slot = self.slot_from_value(value, &value_type);
+            self.ir.push_str(&format!(
+                "  call $black_map_set(l {map}, l {key}, l {slot})\n"
+            ));
+        }
+
+        Ok((map, Type::Map(Box::new(key_type), Box::new(value_type))))
+    }
+
+    /// Emits IR computing the address of an element of the evaluated list, including a bounds
+    /// check which aborts the program with the source location of the index access. Returns the
+    /// temporary holding the address along with the element type
+    fn emit_elem_addr(
+        &mut self,
+        list: &str,
+        list_type: &Type,
+        index: &Index,
+    ) -> Result<(String, Type), ErrorType> {
+        let (i, _) = self.eval_expr(&index.index)?;
+        let pk = self.next_pk();
+        let span = index.span;
+
+        self.ir.push_str(&format!(
+            "  %lenp{pk} =l add {list}, 8
+  %len{pk} =l loadl %lenp{pk}
+  %ok{pk} =w cultl {i}, %len{pk}
+  jnz %ok{pk}, @in_bounds{pk}, @out_of_bounds{pk}
+@out_of_bounds{pk}
+  call $black_index_error(l {i}, l %len{pk}, l {}, l {})
+",
+            span.line, span.col
+        ));
+        self.check_error();
+        self.ir.push_str(&format!(
+            "  hlt
+@in_bounds{pk}
+  %data{pk} =l loadl {list}
+  %offset{pk} =l mul {i}, 8
+  %addr{pk} =l add %data{pk}, %offset{pk}
+"
+        ));
+
+        Ok((format!("%addr{pk}"), elem_type(list_type)))
+    }
+
+    /// Declares the slot of a variable if it doesn't exist yet and records its type
+    fn declare_var(&mut self, id: &str, typ: Type) {
+        // Every variable gets one 8 byte slot, redeclarations reuse it. Variables of functions
+        // live on the stack, so that recursive calls don't share them
+        let outer = self.variables.get(id).cloned();
+        let shadowing = match self.scopes.last_mut() {
+            Some(scope) if !scope.contains_key(id) => {
+                let shadows = self.shadows.get(id).cloned();
+                scope.insert(id.to_string(), outer.clone().map(|typ| (typ, shadows)));
+                outer.is_some()
+            }
+            _ => false,
+        };
+
+        // Variables of outer blocks keep their value, the one shadowing them gets its own slot
+        if shadowing {
+            let addr = format!("{}.{}", self.slot(id), self.next_pk());
+            self.alloc_slot(&addr);
+            self.shadows.insert(id.to_string(), addr);
+        } else if outer.is_none() {
+            self.alloc_slot(&self.slot(id));
+        }
+        self.variables.insert(id.to_string(), typ);
+    }
+
+    fn alloc_slot(&mut self, addr: &str) {
+        if self.return_type.is_some() {
+            self.allocs.push_str(&format!("  {addr} =l alloc8 8\n"));
+        } else {
+            self.data.push_str(&format!("data {addr} = {{ l 0 }}\n"));
+        }
+    }
+
+    /// Compiles the block of a for, if, try or match in a new scope. Variables declared inside
+    /// of it aren't visible after it, the ones they shadow get their type and slot back
+    fn scoped(
+        &mut self,
+        compile: impl FnOnce(&mut Self) -> Result<(), ErrorType>,
+    ) -> Result<(), ErrorType> {
+        self.scopes.push(HashMap::new());
+        let result = compile(self);
+
+        for (name, outer) in self.scopes.pop().unwrap_or_default() {
+            match outer {
+                Some((typ, shadows)) => {
+                    self.variables.insert(name.clone(), typ);
+                    match shadows {
+                        Some(addr) => self.shadows.insert(name, addr),
+                        None => self.shadows.remove(&name),
+                    };
+                }
+                // The checker rejects uses after the block. The variable stays declared, so
+                // that its slot is reused
+                None => {
+                    self.shadows.remove(&name);
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the address of the slot of a variable
+    fn var_addr(&self, id: &str) -> String {
+        match self.shadows.get(id) {
+            Some(addr) => addr.clone(),
+            None => self.slot(id),
+        }
+    }
+
+    /// Returns the address of the first slot of a variable of the name. Top-level variables of
+    /// modules are named after the module, so that they don't clash with the ones of the
+    /// importing file
+    fn slot(&self, id: &str) -> String {
+        match (&self.return_type, &self.module) {
+            (Some(_), _) => format!("%var_{id}"),
+            (None, Some(module)) => format!("$var_{module}.{id}"),
+            (None, None) => format!("$var_{id}"),
+        }
+    }
+
+    /// Handles a variable declaration, storing the variable type in the `variables` map and
+    /// generating IR storing the value into the variable's data slot
+    fn handle_var_decl(
+        &mut self,
+        variable_declaration: &VariableDeclaration,
+    ) -> Result<(), ErrorType> {
+        let (value, value_type) = match &variable_declaration.typ {
+            Some(typ) => self.eval_expected(&variable_declaration.value, typ)?,
+            None => self.eval_expr(&variable_declaration.value)?,
+        };
+        let typ = variable_declaration
+            .typ
+            .clone()
+            .unwrap_or(value_type.clone());
+        let value = self.convert(value, &value_type, &typ);
+        let value = self.own(value, &typ, &variable_declaration.value);
+
+        let id = &variable_declaration.identifier;
+        self.declare_var(id, typ.clone());
+        let addr = self.var_addr(id);
+        self.ir
+            .push_str(&format!("  store{} {value}, {addr}\n", qbe_class(&typ)));
+
+        Ok(())
+    }
+
+    /// Handles an assignment to an existing variable, list element, map entry or struct field
+    fn handle_assignment(&mut self, assignment: &Assignment) -> Result<(), ErrorType> {
+        let target_type = self.infer(&assignment.target);
+        let (value, value_type) = self.eval_expected(&assignment.value, &target_type)?;
+
+        let (addr, typ) = match &assignment.target {
+            Expr::Identifier(id) => (self.var_addr(id), self.get_var(id)?),
+            Expr::Field(field) => {
+                let (addr, typ) = self.emit_field_addr(field)?;
+                let value = self.convert(value, &value_type, &typ);
+                self.store_inline(&addr, &value, &typ);
+                return Ok(());
+            }
+            Expr::Index(index) => {
+                let (target, target_type) = self.eval_expr(&index.target)?;
+                if let Type::Map(..) = target_type {
+                    let (_, typ) = map_types(&target_type);
+                    let (key, _) = self.eval_expr(&index.index)?;
+                    let value = self.convert(value, &value_type, &typ);
+                    let value = self.own(value, &typ, &assignment.value);
+                    let slot = self.slot_from_value(value, &typ);
+                    self.ir.push_str(&format!(
+                        "  call $black_map_set(l {target}, l {key}, l {slot})\n"
+                    ));
+                    return Ok(());
+                }
+                self.emit_elem_addr(&target, &target_type, index)?
+            }
+            _ => return Err(ErrorType::Generic("Invalid assignment target".to_string())),
+        };
+        let value = self.convert(value, &value_type, &typ);
+        let value = self.own(value, &typ, &assignment.value);
+        self.ir
+            .push_str(&format!("  store{} {value}, {addr}\n", qbe_class(&typ)));
+
+        Ok(())
+    }
+
+    /// Handles an if statement. For `if let`, the optional is tested for `none` and its value is
+    /// stored into the variable before the block runs. The binding shadows a variable of the
+    /// same name like declarations inside of the block do
+    fn handle_if(&mut self, if_statement: &IfStatement) -> Result<(), ErrorType> {
+        let (value, typ) = self.eval_expr(&if_statement.condition)?;
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %cond{pk} =w cnel {value}, 0\n  jnz %cond{pk}, @then{pk}, @else{pk}\n@then{pk}\n"
+        ));
+
+        self.scoped(|compiler| {
+            if let (Some(name), Type::Optional(inner)) = (&if_statement.binding, &typ) {
+                let value = compiler.unwrap(&value, inner);
+                let value = compiler.copy_struct(value, inner);
+                compiler.declare_var(name, *inner.clone());
+                let addr = compiler.var_addr(name);
+                compiler
+                    .ir
+                    .push_str(&format!("  store{} {value}, {addr}\n", qbe_class(inner)));
+            }
+            compiler.evaluate_block(&if_statement.block)
+        })?;
+        self.ir.push_str(&format!("  jmp @endif{pk}\n@else{pk}\n"));
+
+        self.scoped(|compiler| compiler.evaluate_block(&if_statement.else_block))?;
+        self.ir.push_str(&format!("@endif{pk}\n"));
+
+        Ok(())
+    }
+
+    fn evaluate_block(&mut self, block: &[Expr]) -> Result<(), ErrorType> {
+        block.iter().try_for_each(|node| self.evaluate_expr(node))
+    }
+
+    /// Handles a try statement. The runtime counts the try blocks being run, an error raised
+    /// inside of one is recorded instead of exiting and the code jumps to the catch block, which
+    /// receives the error message
+    fn handle_try(&mut self, try_catch: &TryCatch) -> Result<(), ErrorType> {
+        let pk = self.next_pk();
+        self.ir.push_str("  call $black_try_enter()\n");
+
+        self.catches.push(format!("@catch{pk}"));
+        let result = self.scoped(|compiler| compiler.evaluate_block(&try_catch.block));
+        self.catches.pop();
+        result?;
+
+        self.ir.push_str(&format!(
+            "  call $black_try_exit()\n  jmp @try_end{pk}\n@catch{pk}\n  %message{pk} =l call $black_catch()\n"
+        ));
+        self.scoped(|compiler| {
+            if let Some(name) = &try_catch.variable {
+                compiler.declare_var(name, Type::Str);
+                let addr = compiler.var_addr(name);
+                compiler
+                    .ir
+                    .push_str(&format!("  storel %message{pk}, {addr}\n"));
+            }
+            compiler.evaluate_block(&try_catch.catch_block)
+        })?;
+        self.ir.push_str(&format!("@try_end{pk}\n"));
+
+        Ok(())
+    }
+
+    /// Handles a for loop. The length of the list is checked before every iteration, so that
+    /// the list can be modified from inside of the loop
+    fn handle_for(&mut self, for_loop: &ForLoop) -> Result<(), ErrorType> {
+        let (list, list_type) = self.eval_expr(&for_loop.iterable)?;
+        if let Type::Map(..) = list_type {
+            return self.handle_for_map(for_loop, &list, &list_type);
+        }
+        let elem_type = elem_type(&list_type);
+        let class = qbe_class(&elem_type);
+        let pk = self.next_pk();
+
+        self.ir.push_str(&format!(
+            "  %i{pk} =l copy 0
+@for_cond{pk}
+  %lenp{pk} =l add {list}, 8
+  %len{pk} =l loadl %lenp{pk}
+  %continue{pk} =w csltl %i{pk}, %len{pk}
+  jnz %continue{pk}, @for_body{pk}, @for_end{pk}
+@for_body{pk}
+  %data{pk} =l loadl {list}
+  %offset{pk} =l mul %i{pk}, 8
+  %addr{pk} =l add %data{pk}, %offset{pk}
+  %item{pk} ={class} load{class} %addr{pk}
+"
+        ));
+
+        self.scoped(|compiler| {
+            // The loop variable holds a copy of the element
+            let item = compiler.copy_struct(format!("%item{pk}"), &elem_type);
+            compiler.declare_var(&for_loop.variable, elem_type.clone());
+            let addr = compiler.var_addr(&for_loop.variable);
+            compiler
+                .ir
+                .push_str(&format!("  store{class} {item}, {addr}\n"));
+
+            compiler.evaluate_block(&for_loop.block)
+        })?;
+
+        self.ir.push_str(&format!(
+            "  %i{pk} =l add %i{pk}, 1\n  jmp @for_cond{pk}\n@for_end{pk}\n"
+        ));
+
+        Ok(())
+    }
+
+    /// Handles a for loop over a map, visiting the entries in insertion order. Like for lists,
+    /// the length is checked before every iteration
+    fn handle_for_map(
+        &mut self,
+        for_loop: &ForLoop,
+        map: &str,
+        map_type: &Type,
+    ) -> Result<(), ErrorType> {
+        let (key_type, value_type) = map_types(map_type);
+        let pk = self.next_pk();
+
+        self.ir.push_str(&format!(
+            "  %i{pk} =l copy 0
+@for_cond{pk}
+  %len{pk} =l call $black_map_len(l {map})
+  %continue{pk} =w csltl %i{pk}, %len{pk}
+  jnz %continue{pk}, @for_body{pk}, @for_end{pk}
+@for_body{pk}
+  %key{pk} =l call $black_map_key_at(l {map}, l %i{pk})
+"
+        ));
+
+        self.scoped(|compiler| {
+            compiler.declare_var(&for_loop.variable, key_type);
+            let addr = compiler.var_addr(&for_loop.variable);
+            compiler
+                .ir
+                .push_str(&format!("  storel %key{pk}, {addr}\n"));
+
+            if let Some(value_id) = &for_loop.value_variable {
+                compiler.ir.push_str(&format!(
+                    "  %slot{pk} =l call $black_map_value_at(l {map}, l %i{pk})\n"
+                ));
+                let value = compiler.value_from_slot(format!("%slot{pk}"), &value_type);
+                let value = compiler.copy_struct(value, &value_type);
+                compiler.declare_var(value_id, value_type.clone());
+                let addr = compiler.var_addr(value_id);
+                compiler.ir.push_str(&format!(
+                    "  store{} {value}, {addr}\n",
+                    qbe_class(&value_type)
+                ));
+            }
+
+            compiler.evaluate_block(&for_loop.block)
+        })?;
+
+        self.ir.push_str(&format!(
+            "  %i{pk} =l add %i{pk}, 1\n  jmp @for_cond{pk}\n@for_end{pk}\n"
+        ));
+
         Ok(())
     }
+
+    /// Evaluate one expression
+    pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<(), ErrorType> {
+        match expr {
+            Expr::FuncCall(func_call) => {
+                self.handle_func_call(func_call)?;
+            }
+
+            Expr::VariableDeclaration(variable_declaration) => {
+                self.handle_var_decl(variable_declaration)?
+            }
+
+            Expr::Assignment(assignment) => self.handle_assignment(assignment)?,
+
+            Expr::Destructure(destructure) => self.handle_destructure(destructure)?,
+
+            Expr::For(for_loop) => self.handle_for(for_loop)?,
+
+            Expr::IfStatement(if_statement) => self.handle_if(if_statement)?,
+
+            Expr::TryCatch(try_catch) => self.handle_try(try_catch)?,
+
+            Expr::Block(block) => {
+                for node in block {
+                    self.evaluate_expr(node)?;
+                }
+            }
+
+            Expr::Match(match_expr) => {
+                let typ = self.infer(expr);
+                self.handle_match(match_expr, &typ)?;
+            }
+
+            // Compiled up front by `generate_ir`
+            Expr::StructDecl(_) | Expr::EnumDecl(_) | Expr::FuncDecl(_) => {}
+
+            Expr::Import(import) => self.handle_import(import)?,
+
+            // A top-level `return` sets the exit status of the program
+            Expr::Return(ret) if self.return_type.is_none() => {
+                let (value, _) = self.eval_expr(&ret.value)?;
+                let pk = self.next_pk();
+                self.ir
+                    .push_str(&format!("  ret {value}\n@after_return{pk}\n"));
+            }
+
+            Expr::Return(ret) => {
+                let return_type = self.return_type.clone().unwrap_or(Type::None);
+                let (value, typ) = self.eval_expected(&ret.value, &return_type)?;
+                let value = self.convert(value, &typ, &return_type);
+                // Returning from inside of try blocks leaves them
+                for _ in &self.catches {
+                    self.ir.push_str("  call $black_try_exit()\n");
+                }
+                // Instructions following the `ret` need a block of their own
+                let pk = self.next_pk();
+                self.ir
+                    .push_str(&format!("  ret {value}\n@after_return{pk}\n"));
+            }
+
+            _ => {
+                return Err(ErrorType::Generic(format!(
+                    "Expression `{expr:?}` in this context is not yet implemented"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles a user defined function into the `functions` section. Parameters are copied into
+    /// stack slots like any other local variable
+    fn compile_func(&mut self, func_decl: &FuncDecl) -> Result<(), ErrorType> {
+        let outer_ir = std::mem::take(&mut self.ir);
+        let outer_variables = std::mem::take(&mut self.variables);
+        let outer_scopes = std::mem::take(&mut self.scopes);
+        let outer_shadows = std::mem::take(&mut self.shadows);
+        let outer_catches = std::mem::take(&mut self.catches);
+        let outer_module = std::mem::replace(&mut self.module, module_of(&func_decl.name));
+        self.return_type = Some(func_decl.return_type.clone());
+
+        let mut params = Vec::with_capacity(func_decl.params.len());
+        for (name, typ) in &func_decl.params {
+            self.declare_var(name, typ.clone());
+            params.push(format!("{} %p_{name}", abi_type(typ)));
+            self.ir.push_str(&format!(
+                "  store{} %p_{name}, %var_{name}\n",
+                qbe_class(typ)
+            ));
+        }
+
+        let result = func_decl
+            .block
+            .iter()
+            .try_for_each(|node| self.evaluate_expr(node));
+
+        // The checker makes sure that functions returning a value end with a `return`
+        let (signature, end) = match &func_decl.return_type {
+            Type::None => (String::new(), "ret"),
+            typ => (format!("{} ", abi_type(typ)), "hlt"),
+        };
+        let body = std::mem::replace(&mut self.ir, outer_ir);
+        let allocs = std::mem::take(&mut self.allocs);
+        self.variables = outer_variables;
+        self.scopes = outer_scopes;
+        self.shadows = outer_shadows;
+        self.catches = outer_catches;
+        self.module = outer_module;
+        self.return_type = None;
+        result?;
+
+        self.functions.push_str(&format!(
+            "function {signature}$fn_{}({}) {{\n@start\n{allocs}{body}  {end}\n}}\n",
+            func_decl.name,
+            params.join(", ")
+        ));
+
+        Ok(())
+    }
+
+    /// Declares the structs, enums and functions of the AST and of the modules it imports
+    fn declare_items(&mut self, ast: &Ast) {
+        for node in ast {
+            let public = match node {
+                Expr::StructDecl(decl) => decl.public.then_some(&decl.name),
+                Expr::EnumDecl(decl) => decl.public.then_some(&decl.name),
+                Expr::FuncDecl(decl) => decl.public.then_some(&decl.name),
+                _ => None,
+            };
+            self.public.extend(public.cloned());
+            match node {
+                Expr::StructDecl(decl) => {
+                    self.structs.insert(decl.name.clone(), decl.fields.clone());
+                }
+                Expr::EnumDecl(decl) => {
+                    self.enums.insert(decl.name.clone(), decl.variants.clone());
+                }
+                Expr::FuncDecl(decl) => {
+                    let signature = FuncSignature {
+                        params: decl.params.iter().map(|(_, typ)| typ.clone()).collect(),
+                        return_type: decl.return_type.clone(),
+                    };
+                    self.signatures.insert(decl.name.clone(), signature);
+                }
+                Expr::Import(import) if !import.body.is_empty() => {
+                    self.sources
+                        .insert(import.module.clone(), import.file.clone());
+                    self.declare_items(&import.body);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Compiles the functions and emits the struct types of the AST and of the modules it imports
+    fn compile_items(&mut self, ast: &Ast) -> Result<(), ErrorType> {
+        for node in ast {
+            match node {
+                Expr::StructDecl(decl) => self.emit_type(&decl.name),
+                Expr::FuncDecl(decl) => self.compile_func(decl)?,
+                Expr::Import(import) => self.compile_items(&import.body)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emits the top-level code of a module at its first import. Variables of the module are
+    /// private to it, and runtime errors raised by its code name the file of the module
+    fn handle_import(&mut self, import: &Import) -> Result<(), ErrorType> {
+        if import.body.is_empty() {
+            return Ok(());
+        }
+
+        let outer_variables = std::mem::take(&mut self.variables);
+        let outer_scopes = std::mem::take(&mut self.scopes);
+        let outer_shadows = std::mem::take(&mut self.shadows);
+        let outer_module = self.module.replace(import.module.clone());
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %source{pk} =l call $black_set_source(l $source_{})\n",
+            import.module
+        ));
+
+        let result = import
+            .body
+            .iter()
+            .try_for_each(|node| self.evaluate_expr(node));
+
+        self.ir
+            .push_str(&format!("  call $black_set_source(l %source{pk})\n"));
+        self.variables = outer_variables;
+        self.scopes = outer_scopes;
+        self.shadows = outer_shadows;
+        self.module = outer_module;
+        result
+    }
+
+    /// Generates the intermediate representation (IR) for the AST and returns it as a string
+    pub fn generate_ir(&mut self) -> Result<String, ErrorType> {
+        let ast = self.ast.clone();
+
+        // Structs, enums and functions are declared before anything else, so that they can be used
+        // before their declaration
+        self.declare_items(&ast);
+        self.compile_items(&ast)?;
+
+        self.ir.push_str(
+            "export function w $main(w %argc, l %argv) {\n@start\n  call $black_init(w %argc, l %argv)\n",
+        );
+
+        for node in &ast {
+            self.evaluate_expr(node)?;
+        }
+
+        self.ir.push_str("  ret 0\n}");
+
+        // Source file names referenced by runtime error messages
+        self.data.push_str(&format!(
+            "export data $black_source = {{ b \"{}\", b 0 }}\n",
+            escape_string(&self.filename)
+        ));
+        for (module, file) in &self.sources {
+            self.data.push_str(&format!(
+                "data $source_{module} = {{ b \"{}\", b 0 }}\n",
+                escape_string(file)
+            ));
+        }
+
+        Ok(format!(
+            "{}{}\n{}{}",
+            self.types, self.data, self.functions, self.ir
+        ))
+    }
+
+    /// Compiles the AST by generating IR, turning it into assembly with the backend, and then
+    /// assembling and linking the output with `cc` to produce the final executable. Stops after
+    /// the last stage given to `--emit`, writing each of them
+    pub fn compile(&mut self, args: &AppArgs) -> Result<(), ErrorType> {
+        let ir = format!("{}{}", include_str!("ext.ssa"), self.generate_ir()?);
+
+        // The tools needed by the stages are looked for before any of them runs
+        let toolchain = Toolchain::new(args);
+        toolchain.check_target(args.static_link, args.backend)?;
+        toolchain.check(
+            args.backend == Backend::Qbe && args.last_stage() >= Emit::Asm,
+            args.last_stage() >= Emit::Obj,
+        )?;
+
+        dbg("Variables", &self.variables);
+        dbg_plain("Compiled IR", &ir);
+        dbg_file_if_env(&ir, "debug.ir", "SAVE_IR");
+
+        if let Some(path) = args.emit_path(Emit::Ir) {
+            write_output(path, ir.as_bytes())?;
+        }
+        if args.last_stage() <= Emit::Ir {
+            return Ok(());
+        }
+
+        let asm = match args.backend {
+            Backend::Qbe => toolchain.qbe(&ir)?,
+            Backend::Native => measure_time("Native code generation", || native::compile(&ir))?,
+        };
+
+        dbg("Assembly", &asm);
+        dbg_file_if_env(&asm, "debug.asm", "SAVE_ASM");
+
+        if let Some(path) = args.emit_path(Emit::Asm) {
+            write_output(path, asm.as_bytes())?;
+        }
+        if args.last_stage() <= Emit::Asm {
+            return Ok(());
+        }
+
+        // The runtime is compiled from C source together with the generated assembly
+        let runtime_path = format!("{}.c", get_tmp_fname("black_runtime"));
+        write_output(Path::new(&runtime_path), RUNTIME.as_bytes())?;
+
+        let linked = (|| {
+            // The object file is a relocatable link of the program and the runtime
+            if let Some(path) = args.emit_path(Emit::Obj) {
+                let cc_args = ["-r", "-nostdlib", "-x", "c", &runtime_path];
+                toolchain.cc(&cc_args, &asm, path)?;
+            }
+
+            if let Some(path) = args.emit_path(Emit::Exe) {
+                let mut cc_args = vec!["-x", "c", &runtime_path];
+                if args.static_link {
+                    cc_args.push("-static");
+                }
+                toolchain.cc(&cc_args, &asm, path)?;
+            }
+            Ok(())
+        })();
+
+        let _ = std::fs::remove_file(&runtime_path);
+        linked
+    }
+}
+
+/// Implementations of the builtin functions, see `builtins.rs`. Arguments are already checked
+impl Compiler {
+    /// Generates IR calling the typed print helpers of the runtime for each argument
+    pub(crate) fn builtin_print(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let args = func_call.arguments.iter();
+        let args_count = args.len();
+        for (i, arg) in args.enumerate() {
+            match arg {
+                Expr::VariableDeclaration(_) | Expr::Block(_) | Expr::Return(_) => {
+                    return Err(ErrorType::Generic("Invalid argument to print".to_string()));
+                }
+
+                _ => {
+                    let (value, typ) = self.eval_expr(arg)?;
+                    self.emit_print(&value, &typ);
+                }
+            }
+
+            // Add space between arguments if not the last one
+            if i != args_count - 1 {
+                self.ir.push_str("  call $print_str(l $space)\n");
+            }
+        }
+
+        self.ir.push_str("  call $print_str(l $endl)\n");
+
+        Ok(("0".to_string(), Type::None))
+    }
+
+    pub(crate) fn builtin_len(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (value, typ) = self.eval_expr(&func_call.arguments[0])?;
+        let pk = self.next_pk();
+        match typ {
+            Type::Map(..) => self
+                .ir
+                .push_str(&format!("  %v{pk} =l call $black_map_len(l {value})\n")),
+            Type::Str => self
+                .ir
+                .push_str(&format!("  %v{pk} =l call $strlen(l {value})\n")),
+            _ => {
+                self.ir
+                    .push_str(&format!("  %lenp{pk} =l add {value}, 8\n"));
+                self.ir.push_str(&format!("  %v{pk} =l loadl %lenp{pk}\n"));
+            }
+        }
+        Ok((format!("%v{pk}"), Type::Int))
+    }
+
+    pub(crate) fn builtin_push(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (list, list_type) = self.eval_expr(&func_call.arguments[0])?;
+        let elem_type = elem_type(&list_type);
+        let (value, value_type) = self.eval_expected(&func_call.arguments[1], &elem_type)?;
+        let value = self.convert(value, &value_type, &elem_type);
+        let value = self.own(value, &elem_type, &func_call.arguments[1]);
+        let slot = self.slot_from_value(value, &elem_type);
+        self.ir
+            .push_str(&format!("  call $black_list_push(l {list}, l {slot})\n"));
+        Ok(("0".to_string(), Type::None))
+    }
+
+    pub(crate) fn builtin_pop(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (list, list_type) = self.eval_expr(&func_call.arguments[0])?;
+        let elem_type = elem_type(&list_type);
+        let pk = self.next_pk();
+        let span = func_call.span;
+        self.ir.push_str(&format!(
+            "  %v{pk} =l call $black_list_pop(l {list}, l {}, l {})\n",
+            span.line, span.col
+        ));
+        self.check_error();
+        let value = self.value_from_slot(format!("%v{pk}"), &elem_type);
+        Ok((value, elem_type))
+    }
+
+    pub(crate) fn builtin_has(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (map, _) = self.eval_expr(&func_call.arguments[0])?;
+        let (key, _) = self.eval_expr(&func_call.arguments[1])?;
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %v{pk} =l call $black_map_has(l {map}, l {key})\n"
+        ));
+        Ok((format!("%v{pk}"), Type::Bool))
+    }
+
+    pub(crate) fn builtin_remove(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (map, _) = self.eval_expr(&func_call.arguments[0])?;
+        let (key, _) = self.eval_expr(&func_call.arguments[1])?;
+        self.ir
+            .push_str(&format!("  call $black_map_remove(l {map}, l {key})\n"));
+        Ok(("0".to_string(), Type::None))
+    }
+
+    pub(crate) fn builtin_keys(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (map, map_type) = self.eval_expr(&func_call.arguments[0])?;
+        let (key_type, _) = map_types(&map_type);
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} =l call $black_map_keys(l {map})\n"));
+        Ok((format!("%v{pk}"), Type::List(Box::new(key_type))))
+    }
+
+    pub(crate) fn builtin_error(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (message, _) = self.eval_expr(&func_call.arguments[0])?;
+        let span = func_call.span;
+        self.ir.push_str(&format!(
+            "  call $black_error_at(l {}, l {}, l {message})\n",
+            span.line, span.col
+        ));
+        self.check_error();
+        Ok(("0".to_string(), Type::None))
+    }
+
+    /// The value is printed into a string, so that it's formatted exactly like by `print`
+    pub(crate) fn builtin_str(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (value, typ) = self.eval_expr(&func_call.arguments[0])?;
+        if typ == Type::Str {
+            return Ok((value, Type::Str));
+        }
+
+        self.ir.push_str("  call $black_capture_start()\n");
+        self.emit_print(&value, &typ);
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} =l call $black_capture_end()\n"));
+        Ok((format!("%v{pk}"), Type::Str))
+    }
+
+    pub(crate) fn builtin_int(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (value, typ) = self.eval_expr(&func_call.arguments[0])?;
+        let pk = self.next_pk();
+        match typ {
+            Type::Str => {
+                let span = func_call.span;
+                self.ir.push_str(&format!(
+                    "  %v{pk} =l call $black_parse_int(l {value}, l {}, l {})\n",
+                    span.line, span.col
+                ));
+                self.check_error();
+            }
+            Type::Float | Type::Double => {
+                self.ir.push_str(&format!("  %v{pk} =l dtosi {value}\n"));
+            }
+            _ => return Ok((value, Type::Int)),
+        }
+        Ok((format!("%v{pk}"), Type::Int))
+    }
+
+    pub(crate) fn builtin_float(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (value, typ) = self.eval_expr(&func_call.arguments[0])?;
+        let pk = self.next_pk();
+        match typ {
+            Type::Str => {
+                let span = func_call.span;
+                self.ir.push_str(&format!(
+                    "  %v{pk} =d call $black_parse_float(l {value}, l {}, l {})\n",
+                    span.line, span.col
+                ));
+                self.check_error();
+            }
+            Type::Int | Type::Long => {
+                self.ir.push_str(&format!("  %v{pk} =d sltof {value}\n"));
+            }
+            _ => return Ok((value, Type::Float)),
+        }
+        Ok((format!("%v{pk}"), Type::Float))
+    }
+
+    pub(crate) fn builtin_abs(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        self.call_numeric("abs", func_call)
+    }
+
+    pub(crate) fn builtin_min(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        self.call_numeric("min", func_call)
+    }
+
+    pub(crate) fn builtin_max(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        self.call_numeric("max", func_call)
+    }
+
+    /// Emits a call of the runtime helper implementing a numeric builtin for the class of its
+    /// arguments, eg. `$black_min_long`. The result has the type of the first argument
+    fn call_numeric(
+        &mut self,
+        name: &str,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let mut args = Vec::with_capacity(func_call.arguments.len());
+        let mut result_type: Option<Type> = None;
+        for arg in &func_call.arguments {
+            let (value, typ) = self.eval_expr(arg)?;
+            // Ints given along with a float are converted, like `min(f, 1)`
+            let first = result_type.get_or_insert(typ.clone()).clone();
+            let value = self.convert(value, &typ, &first);
+            args.push(format!("{} {value}", qbe_class(&first)));
+        }
+
+        let typ = result_type.unwrap_or(Type::Int);
+        let class = qbe_class(&typ);
+        let helper = match class {
+            "d" => "double",
+            _ => "long",
+        };
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %v{pk} ={class} call $black_{name}_{helper}({})\n",
+            args.join(", ")
+        ));
+        Ok((format!("%v{pk}"), typ))
+    }
+
+    /// Prints the prompt and reads a line, the result is empty at the end of the input
+    pub(crate) fn builtin_input(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (prompt, _) = self.eval_expr(&func_call.arguments[0])?;
+        self.ir.push_str(&format!(
+            "  call $print_str(l {prompt})\n  call $fflush(l 0)\n"
+        ));
+        let line = self.emit_read_line();
+        let empty = self.emit_str("");
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %v{pk} =l copy $v{empty}\n  %read{pk} =w cnel {line}, 0\n  jnz %read{pk}, @line{pk}, @eof{pk}\n@line{pk}\n  %v{pk} =l copy {line}\n@eof{pk}\n"
+        ));
+        Ok((format!("%v{pk}"), Type::Str))
+    }
+
+    /// Reads a line, the result is `none` at the end of the input
+    pub(crate) fn builtin_read_line(&mut self, _: &FuncCall) -> Result<(String, Type), ErrorType> {
+        let line = self.emit_read_line();
+        Ok((line, Type::Optional(Box::new(Type::Str))))
+    }
+
+    /// Emits a `getline` call reading a line from the standard input into the line buffer of the
+    /// runtime. Returns the temporary holding a copy of the line without its line ending, or 0 at
+    /// the end of the input
+    fn emit_read_line(&mut self) -> String {
+        let pk = self.next_pk();
+        self.ir.push_str(&format!(
+            "  %stdin{pk} =l call $black_stdin()\n  %read{pk} =l call $getline(l $black_line, l $black_line_cap, l %stdin{pk})\n  %v{pk} =l call $black_take_line(l %read{pk})\n"
+        ));
+        format!("%v{pk}")
+    }
+
+    /// Values of different static types can share a representation, so the type is the one
+    /// recorded by the checker, like in the interpreter
+    pub(crate) fn builtin_type_of(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (_, typ) = self.eval_expr(&func_call.arguments[0])?;
+        let typ = func_call
+            .arg_types
+            .get()
+            .and_then(|types| types.first())
+            .cloned()
+            .unwrap_or(typ);
+        let pk = self.emit_str(&typ.to_string());
+        Ok((format!("$v{pk}"), Type::Str))
+    }
+
+    pub(crate) fn builtin_read_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let contents = self.call_fallible("black_read_file", func_call)?;
+        Ok((contents, Type::Str))
+    }
+
+    pub(crate) fn builtin_write_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        self.call_fallible("black_write_file", func_call)?;
+        Ok(("0".to_string(), Type::None))
+    }
+
+    pub(crate) fn builtin_append_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        self.call_fallible("black_append_file", func_call)?;
+        Ok(("0".to_string(), Type::None))
+    }
+
+    pub(crate) fn builtin_exists(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (path, _) = self.eval_expr(&func_call.arguments[0])?;
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} =l call $black_exists(l {path})\n"));
+        Ok((format!("%v{pk}"), Type::Bool))
+    }
+
+    pub(crate) fn builtin_list_dir(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let names = self.call_fallible("black_list_dir", func_call)?;
+        Ok((names, Type::List(Box::new(Type::Str))))
+    }
+
+    pub(crate) fn builtin_remove_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        self.call_fallible("black_remove_file", func_call)?;
+        Ok(("0".to_string(), Type::None))
+    }
+
+    /// Emits a call of a runtime function which can raise an error. The function receives the
+    /// arguments of the builtin (8 byte values) followed by the source location of the call.
+    /// Returns the temporary holding the result
+    fn call_fallible(&mut self, name: &str, func_call: &FuncCall) -> Result<String, ErrorType> {
+        let mut args = Vec::with_capacity(func_call.arguments.len() + 2);
+        for arg in &func_call.arguments {
+            let (value, _) = self.eval_expr(arg)?;
+            args.push(format!("l {value}"));
+        }
+        args.push(format!("l {}", func_call.span.line));
+        args.push(format!("l {}", func_call.span.col));
+
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} =l call ${name}({})\n", args.join(", ")));
+        self.check_error();
+        Ok(format!("%v{pk}"))
+    }
+
+    pub(crate) fn builtin_args(&mut self, _: &FuncCall) -> Result<(String, Type), ErrorType> {
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} =l call $black_args()\n"));
+        Ok((format!("%v{pk}"), Type::List(Box::new(Type::Str))))
+    }
+
+    /// `getenv` gives a null pointer for unset variables, which is `none`
+    pub(crate) fn builtin_env(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (name, _) = self.eval_expr(&func_call.arguments[0])?;
+        let pk = self.next_pk();
+        self.ir
+            .push_str(&format!("  %v{pk} =l call $getenv(l {name})\n"));
+        Ok((format!("%v{pk}"), Type::Optional(Box::new(Type::Str))))
+    }
+
+    pub(crate) fn builtin_exit(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let (code, _) = self.eval_expr(&func_call.arguments[0])?;
+        self.ir.push_str(&format!("  call $exit(w {code})\n"));
+        Ok(("0".to_string(), Type::None))
+    }
+
+    pub(crate) fn builtin_run(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let result = self.call_fallible("black_run", func_call)?;
+        Ok((result, Type::Tuple(vec![Type::Int, Type::Str, Type::Str])))
+    }
+
+    pub(crate) fn builtin_run_stream(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<(String, Type), ErrorType> {
+        let status = self.call_fallible("black_run_stream", func_call)?;
+        Ok((status, Type::Int))
+    }
 }
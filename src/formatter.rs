@@ -0,0 +1,393 @@
+use crate::{
+    parser::{lexer_with_spans, preprocess, Parser, Span, Token},
+    utils::{display_error, ErrorType, Output},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{read_to_string, write},
+    io::{read_to_string as read_all, stdin},
+    path::PathBuf,
+};
+
+/// Indentation of one level of nesting
+const INDENT: &str = "    ";
+
+/// A `//` comment, running to the end of its line
+#[derive(Debug)]
+struct Comment {
+    text: String,
+    line: usize,
+    /// Whether code comes before the comment on its line
+    trailing: bool,
+}
+
+/// Node of the lossless syntax tree of a file: every token and comment of the file, with the
+/// tokens between brackets grouped together
+#[derive(Debug)]
+enum Node {
+    /// A token, by its position in the file
+    Token(usize),
+    Comment(Comment),
+    /// Brackets along with everything between them, by the positions of the brackets
+    Group {
+        open: usize,
+        close: usize,
+        children: Vec<Node>,
+    },
+}
+
+/// Formats source code: canonical indentation and spacing, one statement per line and at most one
+/// blank line in a row. Comments are kept where they are. Fails on code which doesn't parse
+pub fn format(source: &str) -> Result<String, ErrorType> {
+    let code = preprocess(source);
+    let (tokens, spans) = lexer_with_spans(&code)?;
+    let mut parser = Parser::with_spans(&tokens, &spans);
+    parser.parse()?;
+
+    let lines: Vec<&str> = code.lines().collect();
+    let texts = spans
+        .iter()
+        .map(|span| &lines[span.line - 1][span.col - 1..span.end_col - 1])
+        .collect();
+    let found = comments(source, &spans);
+    let comment_count = found.len();
+
+    let mut formatter = Formatter {
+        tokens: &tokens,
+        texts,
+        spans: &spans,
+        line_starts: parser.line_starts.into_iter().collect(),
+        blocks: parser.blocks.into_iter().collect(),
+        opens: HashMap::new(),
+        out: String::new(),
+        indent: 0,
+        pending: false,
+        continues: false,
+        prev: None,
+        last_line: 0,
+        after_open: false,
+    };
+    let tree = formatter.tree(found)?;
+    formatter.nodes(&tree, false);
+    let mut formatted = formatter.out;
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+
+    // Formatting only ever changes whitespace, anything else would change the program
+    let (new_tokens, new_spans) = lexer_with_spans(&preprocess(&formatted))?;
+    if new_tokens != tokens || comments(&formatted, &new_spans).len() != comment_count {
+        return Err(ErrorType::Generic(
+            "Formatting would change the program. This is a bug.".to_string(),
+        ));
+    }
+
+    Ok(formatted)
+}
+
+/// Formats files in place, or stdin to stdout when no files are given. With `check`, nothing is
+/// written, files which aren't formatted are listed instead. Returns the exit status
+pub fn format_files(files: &[PathBuf], check: bool) -> i32 {
+    if files.is_empty() {
+        let source = read_all(stdin()).unwrap_or_default();
+        return match format(&source) {
+            Ok(formatted) if check => i32::from(formatted != source),
+            Ok(formatted) => {
+                print!("{formatted}");
+                0
+            }
+            Err(err) => {
+                display_error(err, "<stdin>", Output::Stderr);
+                1
+            }
+        };
+    }
+
+    let mut status = 0;
+    for file in files {
+        let name = file.display().to_string();
+        let Ok(source) = read_to_string(file) else {
+            display_error(
+                ErrorType::Generic(format!("Could not read \"{name}\"")),
+                &name,
+                Output::Stderr,
+            );
+            status = 1;
+            continue;
+        };
+        let formatted = match format(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                display_error(err, &name, Output::Stderr);
+                status = 1;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("Not formatted: {name}");
+            status = 1;
+        } else if write(file, formatted).is_err() {
+            display_error(
+                ErrorType::Generic(format!("Could not write \"{name}\"")),
+                &name,
+                Output::Stderr,
+            );
+            status = 1;
+        }
+    }
+
+    status
+}
+
+/// Finds the comments of the source. The parser drops everything after `//` on a line, so does
+/// the formatter
+fn comments(source: &str, spans: &[Span]) -> Vec<Comment> {
+    let code_lines: HashSet<usize> = spans.iter().map(|span| span.line).collect();
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let start = line.find("//")?;
+            Some(Comment {
+                text: line[start..].trim_end().to_string(),
+                line: i + 1,
+                trailing: code_lines.contains(&(i + 1)),
+            })
+        })
+        .collect()
+}
+
+/// Writes the syntax tree out formatted
+struct Formatter<'a> {
+    tokens: &'a [Token],
+    /// Source text of every token
+    texts: Vec<&'a str>,
+    spans: &'a [Span],
+    /// Positions of the tokens starting a statement or a match arm
+    line_starts: HashSet<usize>,
+    /// Positions of the `{` tokens opening a code block or the arms of a match
+    blocks: HashSet<usize>,
+    /// Positions of the opening brackets by the positions of the closing ones
+    opens: HashMap<usize, usize>,
+    out: String,
+    indent: usize,
+    /// Whether a line break comes before the next token or comment
+    pending: bool,
+    /// Whether the pending line break follows a comment after code, code continuing the statement
+    /// on the next line is indented one more level
+    continues: bool,
+    /// Position of the last written token
+    prev: Option<usize>,
+    /// Source line of the last written token or comment
+    last_line: usize,
+    /// Whether the last written token opened an indented group, no blank line follows it
+    after_open: bool,
+}
+
+impl Formatter<'_> {
+    /// Builds the syntax tree from the tokens and the comments, in source order
+    fn tree(&mut self, comments: Vec<Comment>) -> Result<Vec<Node>, ErrorType> {
+        let mut comments = comments.into_iter().peekable();
+        let mut stack: Vec<(usize, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+
+        for (i, token) in self.tokens.iter().enumerate() {
+            // A comment runs to the end of its line, so it comes after every token of its line
+            while let Some(comment) = comments.next_if(|c| c.line < self.spans[i].line) {
+                nodes.push(Node::Comment(comment));
+            }
+
+            match token {
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace => {
+                    stack.push((i, std::mem::take(&mut nodes)));
+                }
+                Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                    let Some((open, parent)) = stack.pop() else {
+                        return Err(ErrorType::SyntaxError(
+                            format!("Unmatched '{}'", self.texts[i]),
+                            self.spans[i],
+                        ));
+                    };
+                    let children = std::mem::replace(&mut nodes, parent);
+                    self.opens.insert(i, open);
+                    nodes.push(Node::Group {
+                        open,
+                        close: i,
+                        children,
+                    });
+                }
+                _ => nodes.push(Node::Token(i)),
+            }
+        }
+        if let Some((open, _)) = stack.last() {
+            return Err(ErrorType::SyntaxError(
+                format!("Unclosed '{}'", self.texts[*open]),
+                self.spans[*open],
+            ));
+        }
+        nodes.extend(comments.map(Node::Comment));
+
+        Ok(nodes)
+    }
+
+    /// Writes nodes. Items of a multiline group, separated by commas, each start a new line
+    fn nodes(&mut self, nodes: &[Node], items: bool) {
+        let mut item_start = items;
+        for node in nodes {
+            match node {
+                Node::Comment(comment) => self.comment(comment),
+                Node::Token(i) => {
+                    let line_start = item_start || self.line_starts.contains(i);
+                    self.token(*i, line_start);
+                    item_start = items && self.tokens[*i] == Token::Comma;
+                }
+                Node::Group {
+                    open,
+                    close,
+                    children,
+                } => {
+                    let line_start = item_start || self.line_starts.contains(open);
+                    self.group(*open, *close, children, line_start);
+                    item_start = false;
+                }
+            }
+        }
+    }
+
+    /// Writes a group. Code blocks and groups spanning several lines in the source are indented,
+    /// with their contents starting on a new line
+    fn group(&mut self, open: usize, close: usize, children: &[Node], line_start: bool) {
+        self.token(open, line_start);
+        let block = self.blocks.contains(&open);
+        let multiline = self.spans[open].line != self.spans[close].line;
+        if children.is_empty() || !(block || multiline) {
+            self.nodes(children, false);
+            self.token(close, false);
+            return;
+        }
+
+        self.indent += 1;
+        self.after_open = true;
+        self.nodes(children, !block);
+        self.indent -= 1;
+        self.token(close, true);
+    }
+
+    /// Writes a comment. Comments after code stay at the end of their line
+    fn comment(&mut self, comment: &Comment) {
+        // Code after a comment on a line of its own lines up with the comment
+        self.continues = comment.trailing && !self.pending;
+        if !self.continues {
+            self.pending = !self.out.is_empty();
+            self.line_break(comment.line, false, false);
+        } else {
+            self.out.push(' ');
+        }
+        self.out.push_str(&comment.text);
+        self.last_line = comment.line;
+        self.after_open = false;
+        self.pending = true;
+    }
+
+    /// Writes a token. Tokens starting a statement, an item of a multiline group or closing an
+    /// indented group start a new line
+    fn token(&mut self, i: usize, line_start: bool) {
+        let line = self.spans[i].line;
+        let closes = matches!(
+            self.tokens[i],
+            Token::RightParen | Token::RightBracket | Token::RightBrace
+        );
+        if line_start && !self.out.is_empty() {
+            self.pending = true;
+            self.line_break(line, false, closes);
+        } else if self.pending {
+            // A comment ended the line in the middle of a statement, which continues indented
+            // after a comment following code
+            self.line_break(line, self.continues, closes);
+        } else if self.prev.is_some_and(|prev| self.space(prev, i)) {
+            self.out.push(' ');
+        }
+
+        self.out.push_str(self.texts[i]);
+        self.prev = Some(i);
+        self.last_line = line;
+        self.after_open = false;
+    }
+
+    /// Starts a new line if one is pending, for code from the given source line. Blank lines in
+    /// the source are kept, at most one in a row, except at the start and end of a group
+    fn line_break(&mut self, line: usize, continuation: bool, closes: bool) {
+        if !self.pending {
+            return;
+        }
+        self.pending = false;
+
+        self.out.push('\n');
+        if !continuation && !self.after_open && !closes && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+        self.out
+            .push_str(&INDENT.repeat(self.indent + usize::from(continuation)));
+    }
+
+    /// Whether a space separates two consecutive tokens on a line
+    fn space(&self, prev: usize, i: usize) -> bool {
+        match (&self.tokens[prev], &self.tokens[i]) {
+            (Token::LeftParen | Token::LeftBracket, _) => false,
+            (_, Token::RightParen | Token::RightBracket) => false,
+            (Token::LeftBrace, Token::RightBrace) => false,
+            // Braces of structs and enums are padded, the ones of maps aren't
+            (Token::LeftBrace, _) => self.padded(prev),
+            (_, Token::RightBrace) => self.padded(self.opens[&i]),
+            (
+                _,
+                Token::Comma | Token::Colon | Token::Dot | Token::DoubleColon | Token::Question,
+            ) => false,
+            (Token::Dot | Token::DoubleColon, _) => false,
+            // Calls, and variants holding values
+            (Token::Identifier(_) | Token::Type(_), Token::LeftParen) => false,
+            // Indexing
+            (
+                Token::Identifier(_)
+                | Token::StringLiteral(_)
+                | Token::RightParen
+                | Token::RightBracket,
+                Token::LeftBracket,
+            ) => false,
+            (Token::Minus, _) => !self.negative(prev),
+            _ => true,
+        }
+    }
+
+    /// Whether the braces opening at the position are padded with spaces: the ones following a
+    /// name, of structs and enums
+    fn padded(&self, open: usize) -> bool {
+        open > 0 && matches!(self.tokens[open - 1], Token::Identifier(_))
+    }
+
+    /// Whether the `-` at the position negates a number, rather than subtracting
+    fn negative(&self, i: usize) -> bool {
+        i == 0
+            || self.line_starts.contains(&i)
+            || matches!(
+                self.tokens[i - 1],
+                Token::LeftParen
+                    | Token::LeftBracket
+                    | Token::LeftBrace
+                    | Token::Comma
+                    | Token::Colon
+                    | Token::Equals
+                    | Token::FatArrow
+                    | Token::Plus
+                    | Token::Minus
+                    | Token::Multiply
+                    | Token::Divide
+                    | Token::Coalesce
+                    | Token::Return
+            )
+    }
+}
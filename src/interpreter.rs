@@ -1,9 +1,24 @@
 use crate::{
-    parser::{type_check, Ast, BinExpr, BinOpKind, Bool, FuncCall, Variable, VariableDeclaration},
+    builtins::builtin,
+    parser::{
+        Assignment, Ast, BinExpr, BinOpKind, Bool, EnumValue, ForLoop, FuncCall, FuncDecl,
+        IfStatement, Import, Index, ListItems, MapEntries, MapItems, MapKey, Match, Pattern, Span,
+        StructLiteral, StructValue, TryCatch, Type, Variable, VariableDeclaration,
+    },
     utils::ErrorType,
     Expr,
 };
-use std::{collections::HashMap, fmt};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    env, fmt,
+    fs::{self, OpenOptions},
+    io::{self, stdin, stdout, Write},
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    process::{self, Command, ExitStatus},
+    rc::Rc,
+};
 
 /// Implements the `Display` trait for the `Variable` enum, allowing formatted output for eg.
 /// numbers and string literals
@@ -11,6 +26,7 @@ impl fmt::Display for Variable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Variable::Number(n) => write!(f, "{}", n),
+            Variable::Float(n) => write!(f, "{}", n),
             Variable::StringLiteral(s) => write!(f, "{}", s),
             Variable::Bool(v) => {
                 write!(
@@ -22,6 +38,98 @@ impl fmt::Display for Variable {
                     }
                 )
             }
+            Variable::List(items) => {
+                let items: Vec<String> = items.borrow().iter().map(|item| item.repr()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Variable::Map(entries) => {
+                let entries = entries.borrow();
+                let items: Vec<String> = entries
+                    .keys
+                    .iter()
+                    .map(|key| {
+                        let value = &entries.values[key];
+                        format!("{}: {}", Variable::from(key.clone()).repr(), value.repr())
+                    })
+                    .collect();
+                write!(f, "{{{}}}", items.join(", "))
+            }
+            Variable::Struct(value) => {
+                let fields: Vec<String> = value
+                    .fields
+                    .iter()
+                    .map(|(name, field)| format!("{name}: {}", field.repr()))
+                    .collect();
+                match fields.is_empty() {
+                    true => write!(f, "{} {{}}", value.name),
+                    false => write!(f, "{} {{ {} }}", value.name, fields.join(", ")),
+                }
+            }
+            Variable::Enum(value) => {
+                if value.values.is_empty() {
+                    return write!(f, "{}", value.variant);
+                }
+                let values: Vec<String> = value.values.iter().map(|value| value.repr()).collect();
+                write!(f, "{}({})", value.variant, values.join(", "))
+            }
+            Variable::Tuple(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.repr()).collect();
+                match values.len() {
+                    1 => write!(f, "({},)", values[0]),
+                    _ => write!(f, "({})", values.join(", ")),
+                }
+            }
+            Variable::None => write!(f, "none"),
+        }
+    }
+}
+
+impl Variable {
+    /// Returns the representation of a value nested inside of a collection, where strings are
+    /// quoted
+    pub fn repr(&self) -> String {
+        match self {
+            Variable::StringLiteral(s) => format!("\"{s}\""),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Converts the value into a map key
+    fn key(&self) -> Result<MapKey, ErrorType> {
+        match self {
+            Variable::Number(n) => Ok(MapKey::Number(*n)),
+            Variable::StringLiteral(s) => Ok(MapKey::StringLiteral(s.clone())),
+            Variable::Bool(v) => Ok(MapKey::Bool(v.clone())),
+            _ => Err(ErrorType::Generic(format!(
+                "Value `{}` cannot be used as a map key",
+                self.repr()
+            ))),
+        }
+    }
+}
+
+impl From<MapKey> for Variable {
+    fn from(key: MapKey) -> Self {
+        match key {
+            MapKey::Number(n) => Variable::Number(n),
+            MapKey::StringLiteral(s) => Variable::StringLiteral(s),
+            MapKey::Bool(v) => Variable::Bool(v),
+        }
+    }
+}
+
+impl MapEntries {
+    /// Inserts or replaces the value of a key, new keys are appended to the iteration order
+    fn insert(&mut self, key: MapKey, value: Variable) {
+        if self.values.insert(key.clone(), value).is_none() {
+            self.keys.push(key);
+        }
+    }
+
+    /// Removes a key along with its value, missing keys are ignored
+    fn remove(&mut self, key: &MapKey) {
+        if self.values.remove(key).is_some() {
+            self.keys.retain(|k| k != key);
         }
     }
 }
@@ -30,6 +138,26 @@ impl fmt::Display for Variable {
 pub struct Interpreter {
     pub ast: Ast,
     pub variables: HashMap<String, Variable>,
+    pub functions: HashMap<String, Rc<FuncDecl>>,
+    /// Fields of every declared struct, in declaration order
+    pub structs: HashMap<String, Vec<(String, Type)>>,
+    /// Value of the `return` being executed, set until the function call finishes
+    returned: Option<Variable>,
+    /// Number of function calls currently being executed
+    depth: usize,
+    /// Arguments passed to the program, returned by `args`
+    pub args: Vec<String>,
+    /// Exit status set by a top-level `return`
+    pub exit_code: i32,
+    /// Source files of the loaded modules by their unique names
+    sources: HashMap<String, String>,
+    /// Source files and structs and functions of the modules which aren't loaded yet, by their
+    /// unique names. A module is loaded at its first import, or at the first use of one of its
+    /// items when that comes earlier (eg. in a function called before the import)
+    pending: HashMap<String, (String, Ast)>,
+    /// Variables declared by the for, if, try and match blocks being run, along with the value
+    /// they shadow. They're restored at the end of the block
+    scopes: Vec<HashMap<String, Option<Variable>>>,
 }
 
 impl Interpreter {
@@ -38,36 +166,82 @@ impl Interpreter {
         Self {
             ast,
             variables: HashMap::new(),
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            returned: None,
+            depth: 0,
+            args: Vec::new(),
+            exit_code: 0,
+            sources: HashMap::new(),
+            pending: HashMap::new(),
+            scopes: Vec::new(),
         }
     }
 
     /// Creates a default `Interpreter` instance with an empty AST and no variables
     pub fn default() -> Self {
-        Self {
-            ast: Ast::default(),
-            variables: HashMap::new(),
-        }
+        Self::from_ast(Ast::default())
     }
 
     /// Evaluate one expression
     pub fn evaluate_expr(&mut self, expr: &Expr) -> Result<(), ErrorType> {
         match expr {
-            Expr::FuncCall(func_call) => self.handle_func_call(&func_call)?,
+            Expr::FuncCall(func_call) => {
+                self.handle_func_call(func_call)?;
+            }
+
+            Expr::VariableDeclaration(var_decl) => self.handle_var_decl(var_decl)?,
+
+            Expr::Destructure(destructure) => {
+                let values = match self.eval(&destructure.value)? {
+                    Variable::Tuple(values) => values,
+                    _ => return Err(ErrorType::Generic("Expected a tuple".to_string())),
+                };
+                for (name, value) in destructure.names.iter().zip(values) {
+                    if name != "_" {
+                        self.declare(name, value);
+                    }
+                }
+            }
+
+            Expr::Assignment(assignment) => self.handle_assignment(assignment)?,
+
+            Expr::For(for_loop) => self.handle_for(for_loop)?,
 
-            Expr::VariableDeclaration(var_decl) => self.handle_var_decl(&var_decl)?,
+            Expr::IfStatement(if_statement) => self.handle_if(if_statement)?,
+
+            Expr::TryCatch(try_catch) => self.handle_try(try_catch)?,
 
             Expr::Identifier(id) => {
                 // If it's a valid variable, print it
                 // Probably only useful in the interactive mode
                 // Should we only restrict this code to such condition?
-                let var = self.get_var(&id)?;
+                let var = self.get_var(id)?;
                 println!("{var}");
             }
 
-            Expr::Block(block) => {
-                for node in block {
-                    self.evaluate_expr(node)?;
-                }
+            Expr::Block(block) => self.run_block(block)?,
+
+            Expr::StructDecl(decl) => {
+                self.structs.insert(decl.name.clone(), decl.fields.clone());
+            }
+
+            Expr::FuncDecl(decl) => {
+                self.functions
+                    .insert(decl.name.clone(), Rc::new(*decl.clone()));
+            }
+
+            // Enum values carry their variant, the declaration isn't needed at runtime
+            Expr::EnumDecl(_) => {}
+
+            Expr::Import(import) => self.handle_import(import)?,
+
+            Expr::Match(match_expr) => {
+                self.eval_match(match_expr)?;
+            }
+
+            Expr::Return(ret) => {
+                self.returned = Some(self.eval(&ret.value)?);
             }
 
             _ => {
@@ -80,12 +254,139 @@ impl Interpreter {
         Ok(())
     }
 
+    /// Runs the statements of a block, stopping early at a `return`
+    fn run_block(&mut self, block: &[Expr]) -> Result<(), ErrorType> {
+        for node in block {
+            self.evaluate_expr(node)?;
+            if self.returned.is_some() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the block of a for, if, try or match with the variables bound by it
+    fn run_scoped(
+        &mut self,
+        block: &[Expr],
+        bindings: Vec<(String, Variable)>,
+    ) -> Result<(), ErrorType> {
+        self.scoped(bindings, |interpreter| interpreter.run_block(block))
+    }
+
+    /// Runs the function in a new scope with the variables bound. Variables declared inside of
+    /// the scope aren't visible after it, the ones they shadow get their values back
+    fn scoped<T>(
+        &mut self,
+        bindings: Vec<(String, Variable)>,
+        run: impl FnOnce(&mut Self) -> Result<T, ErrorType>,
+    ) -> Result<T, ErrorType> {
+        self.scopes.push(HashMap::new());
+        for (name, value) in bindings {
+            self.declare(&name, value);
+        }
+        let result = run(self);
+
+        for (name, outer) in self.scopes.pop().unwrap_or_default() {
+            match outer {
+                Some(outer) => self.variables.insert(name, outer),
+                None => self.variables.remove(&name),
+            };
+        }
+        result
+    }
+
+    /// Declares a variable, recording the value it shadows in the innermost scope
+    fn declare(&mut self, name: &str, value: Variable) {
+        if let Some(scope) = self.scopes.last_mut() {
+            if !scope.contains_key(name) {
+                scope.insert(name.to_string(), self.variables.get(name).cloned());
+            }
+        }
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Declares the structs and functions of the AST. Those of the modules it imports, directly
+    /// or not, are only declared once the modules are loaded
+    fn declare_items(&mut self, ast: &Ast) -> Result<(), ErrorType> {
+        for node in ast {
+            match node {
+                Expr::StructDecl(_) | Expr::FuncDecl(_) => self.evaluate_expr(node)?,
+                Expr::Import(import) if !import.body.is_empty() => self.add_pending(import),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a module to load later, along with the modules imported by it
+    fn add_pending(&mut self, import: &Import) {
+        let mut items = Vec::new();
+        for node in &import.body {
+            match node {
+                Expr::StructDecl(_) | Expr::FuncDecl(_) => items.push(node.clone()),
+                Expr::Import(import) if !import.body.is_empty() => self.add_pending(import),
+                _ => {}
+            }
+        }
+        self.pending
+            .insert(import.module.clone(), (import.file.clone(), items));
+    }
+
+    /// Loads a module which isn't loaded yet, declaring its structs and functions
+    fn load_module(&mut self, module: &str) -> Result<(), ErrorType> {
+        if let Some((file, items)) = self.pending.remove(module) {
+            items.iter().try_for_each(|item| self.evaluate_expr(item))?;
+            self.sources.insert(module.to_string(), file);
+        }
+
+        Ok(())
+    }
+
+    /// Loads a module used through the qualified name of one of its items (`math.square`), in
+    /// case it's used before its first import
+    fn load_module_of(&mut self, name: &str) -> Result<(), ErrorType> {
+        match name.split_once('.') {
+            Some((module, _)) => self.load_module(module),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads a module and runs its top-level code at its first import. Variables of the module
+    /// are private to it
+    fn handle_import(&mut self, import: &Import) -> Result<(), ErrorType> {
+        self.load_module(&import.module)?;
+        let outer = std::mem::take(&mut self.variables);
+        let outer_scopes = std::mem::take(&mut self.scopes);
+        let result = import
+            .body
+            .iter()
+            .filter(|node| !matches!(node, Expr::StructDecl(_) | Expr::FuncDecl(_)))
+            .try_for_each(|node| self.evaluate_expr(node));
+        self.variables = outer;
+        self.scopes = outer_scopes;
+
+        result.map_err(|err| err.in_file(&import.file))
+    }
+
     /// Runs the interpreter, processing each expression in the AST
     pub fn run(&mut self) -> Result<(), ErrorType> {
         let ast = self.ast.clone();
 
+        // Declarations come first, so that functions can be called before they're declared
+        self.declare_items(&ast)?;
+
         for node in &ast {
             self.evaluate_expr(node)?;
+            // A top-level `return` ends the program
+            if let Some(returned) = self.returned.take() {
+                if let Variable::Number(code) = returned {
+                    self.exit_code = code as i32;
+                }
+                break;
+            }
         }
 
         Ok(())
@@ -101,62 +402,533 @@ impl Interpreter {
         Err(format!("Variable doesn't exist: `{ident}`"))
     }
 
-    /// Evaluates an operand
-    fn eval_operand(&self, operand: &Expr) -> Result<i64, String> {
+    /// Evaluates an expression in value position
+    fn eval(&mut self, expr: &Expr) -> Result<Variable, ErrorType> {
+        match expr {
+            Expr::Number(n) => Ok(Variable::Number(*n)),
+            Expr::Bool(v) => Ok(Variable::Bool(v.clone())),
+            Expr::StringLiteral(s) => Ok(Variable::StringLiteral(s.to_owned())),
+            Expr::None => Ok(Variable::None),
+            Expr::Identifier(id) => Ok(self.get_var(id)?),
+            Expr::BinExpr(bin_expr) => self.handle_bin_expr(bin_expr),
+            Expr::FuncCall(func_call) => self.handle_func_call(func_call),
+
+            // Ints among floats are converted to the element type found by the checker
+            Expr::List(elements, typ) => {
+                let elem = match typ.get() {
+                    Some(Type::List(elem)) => elem,
+                    _ => &Type::None,
+                };
+                let mut items = Vec::with_capacity(elements.len());
+                for element in elements {
+                    items.push(convert(self.eval(element)?, elem));
+                }
+                Ok(Variable::List(Rc::new(RefCell::new(items))))
+            }
+
+            Expr::Map(entries, typ) => {
+                let value_type = match typ.get() {
+                    Some(Type::Map(_, value)) => value,
+                    _ => &Type::None,
+                };
+                let mut map = MapEntries::default();
+                for (key, value) in entries {
+                    let key = self.eval(key)?.key()?;
+                    map.insert(key, convert(self.eval(value)?, value_type));
+                }
+                Ok(Variable::Map(Rc::new(RefCell::new(map))))
+            }
+
+            Expr::StructLiteral(literal) => self.eval_struct_literal(literal),
+
+            Expr::Tuple(elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements {
+                    values.push(self.eval(element)?);
+                }
+                Ok(Variable::Tuple(values))
+            }
+
+            Expr::TupleIndex(index) => match self.eval(&index.target)? {
+                Variable::Tuple(mut values) if index.index < values.len() => {
+                    Ok(values.swap_remove(index.index))
+                }
+                _ => Err(ErrorType::Generic(format!(
+                    "Expected a tuple with element {}",
+                    index.index
+                ))),
+            },
+
+            Expr::EnumVariant(variant) => {
+                let types = variant.types.get().map_or(&[][..], Vec::as_slice);
+                let mut values = Vec::with_capacity(variant.arguments.len());
+                for (i, arg) in variant.arguments.iter().enumerate() {
+                    let value = self.eval(arg)?;
+                    values.push(match types.get(i) {
+                        Some(typ) => convert(value, typ),
+                        None => value,
+                    });
+                }
+                Ok(Variable::Enum(EnumValue {
+                    enum_name: variant.enum_name.clone(),
+                    variant: variant.variant.clone(),
+                    values,
+                }))
+            }
+
+            Expr::Match(match_expr) => self.eval_match(match_expr),
+
+            Expr::Coalesce(coalesce) => match self.eval(&coalesce.value)? {
+                Variable::None => {
+                    let value = self.eval(&coalesce.default)?;
+                    Ok(match coalesce.typ.get() {
+                        Some(typ) => convert(value, typ),
+                        None => value,
+                    })
+                }
+                value => Ok(value),
+            },
+
+            Expr::Field(field) => match self.eval(&field.target)? {
+                Variable::Struct(value) => value
+                    .fields
+                    .into_iter()
+                    .find(|(name, _)| *name == field.field)
+                    .map(|(_, value)| value)
+                    .ok_or(ErrorType::Generic(format!(
+                        "Unknown field `{}`",
+                        field.field
+                    ))),
+                _ => Err(ErrorType::Generic("Expected a struct".to_string())),
+            },
+
+            Expr::Index(index) => match self.eval(&index.target)? {
+                Variable::Map(entries) => {
+                    let key = self.eval(&index.index)?.key()?;
+                    let value = entries.borrow().values.get(&key).cloned();
+                    value.ok_or(runtime_error(
+                        &format!("Key {} not found in map", Variable::from(key).repr()),
+                        index.span,
+                    ))
+                }
+                target => {
+                    let (items, i) = self.eval_index(target, index)?;
+                    let item = items.borrow()[i].clone();
+                    Ok(item)
+                }
+            },
+
+            _ => Err(ErrorType::Generic(
+                "Can only store strings and numbers in variables".to_string(),
+            )),
+        }
+    }
+
+    /// Evaluates a struct literal. Fields are evaluated in the order they're written, but stored
+    /// in the order they're declared
+    fn eval_struct_literal(&mut self, literal: &StructLiteral) -> Result<Variable, ErrorType> {
+        let mut values = HashMap::new();
+        for (name, value) in &literal.fields {
+            values.insert(name.clone(), self.eval(value)?);
+        }
+
+        self.load_module_of(&literal.name)?;
+        let declared = self
+            .structs
+            .get(&literal.name)
+            .ok_or(ErrorType::Generic(format!(
+                "Unknown type `{}`",
+                literal.name
+            )))?;
+        let fields = declared
+            .iter()
+            .map(|(name, typ)| {
+                let value = values.remove(name).unwrap_or(Variable::None);
+                (name.clone(), convert(value, typ))
+            })
+            .collect();
+
+        Ok(Variable::Struct(StructValue {
+            name: literal.name.clone(),
+            fields,
+        }))
+    }
+
+    /// Evaluates a match expression by running the first arm whose pattern matches the value.
+    /// Variables bound by the pattern are declared before the arm runs
+    fn eval_match(&mut self, match_expr: &Match) -> Result<Variable, ErrorType> {
+        let value = self.eval(&match_expr.value)?;
+
+        for arm in &match_expr.arms {
+            let mut bindings = Vec::new();
+            if !pattern_matches(&arm.pattern, &value, &mut bindings) {
+                continue;
+            }
+            return match &arm.body {
+                Expr::Block(block) => {
+                    self.run_scoped(block, bindings)?;
+                    Ok(Variable::None)
+                }
+                Expr::VariableDeclaration(_)
+                | Expr::Assignment(_)
+                | Expr::Return(_)
+                | Expr::For(_) => {
+                    self.run_scoped(std::slice::from_ref(&arm.body), bindings)?;
+                    Ok(Variable::None)
+                }
+                body => self.scoped(bindings, |interpreter| interpreter.eval(body)),
+            };
+        }
+
+        // The checker only accepts matches covering every value
+        Err(ErrorType::Generic(format!(
+            "No match arm matches the value `{}`",
+            value.repr()
+        )))
+    }
+
+    /// Evaluates an operand, which is either a `Number` or a `Float`
+    fn eval_operand(&mut self, operand: &Expr) -> Result<Variable, ErrorType> {
         match operand {
-            Expr::BinExpr(bin_expr) => Ok(self.handle_bin_expr(bin_expr)?),
-            Expr::Number(n) => Ok(*n),
-            Expr::Identifier(id) => match self.get_var(id)? {
-                Variable::Number(n) => Ok(n),
-                _ => Err("Cannot add variable which is not a number".to_string()),
+            Expr::BinExpr(bin_expr) => self.handle_bin_expr(bin_expr),
+            Expr::Number(n) => Ok(Variable::Number(*n)),
+            Expr::Identifier(_)
+            | Expr::Index(_)
+            | Expr::Field(_)
+            | Expr::TupleIndex(_)
+            | Expr::Coalesce(_)
+            | Expr::FuncCall(_) => match self.eval(operand)? {
+                value @ (Variable::Number(_) | Variable::Float(_)) => Ok(value),
+                _ => Err("Cannot add variable which is not a number".to_string())?,
             },
-            _ => Err("Cannot add variable which is not a number".to_string()),
+            _ => Err("Cannot add variable which is not a number".to_string())?,
         }
     }
 
-    /// Handles the evaluation of a binary expression, returning the result of the operation
-    fn handle_bin_expr(&self, bin_expr: &BinExpr) -> Result<i64, String> {
+    /// Handles the evaluation of a binary expression, returning the result of the operation. The
+    /// result is a `Float` when either operand is
+    fn handle_bin_expr(&mut self, bin_expr: &BinExpr) -> Result<Variable, ErrorType> {
         let lhs = self.eval_operand(&bin_expr.lhs)?;
         let rhs = self.eval_operand(&bin_expr.rhs)?;
 
-        match bin_expr.kind {
-            BinOpKind::Plus => Ok(lhs + rhs),
-            BinOpKind::Minus => Ok(lhs - rhs),
-            BinOpKind::Multiply => Ok(lhs * rhs),
-            BinOpKind::Divide => Ok(lhs / rhs),
+        if let (Variable::Number(lhs), Variable::Number(rhs)) = (&lhs, &rhs) {
+            return Ok(Variable::Number(match bin_expr.kind {
+                BinOpKind::Plus => lhs + rhs,
+                BinOpKind::Minus => lhs - rhs,
+                BinOpKind::Multiply => lhs * rhs,
+                BinOpKind::Divide => match lhs.checked_div(*rhs) {
+                    Some(quotient) => quotient,
+                    None if *rhs == 0 => {
+                        return Err(runtime_error("Division by zero", bin_expr.span))
+                    }
+                    // `i64::MIN / -1` wraps, as it does in compiled programs
+                    None => lhs.wrapping_div(*rhs),
+                },
+            }));
         }
+
+        let (lhs, rhs) = (as_float(&lhs), as_float(&rhs));
+        Ok(Variable::Float(match bin_expr.kind {
+            BinOpKind::Plus => lhs + rhs,
+            BinOpKind::Minus => lhs - rhs,
+            BinOpKind::Multiply => lhs * rhs,
+            BinOpKind::Divide => lhs / rhs,
+        }))
     }
 
-    /// Handles function calls
-    fn handle_func_call(&self, func_call: &FuncCall) -> Result<(), String> {
-        match func_call.name.as_ref() {
-            "print" => self.handle_print(func_call)?,
+    /// Evaluates the index of an index access into the already evaluated list, checking the
+    /// bounds. Returns the list items along with the validated index
+    fn eval_index(
+        &mut self,
+        target: Variable,
+        index: &Index,
+    ) -> Result<(ListItems, usize), ErrorType> {
+        let items = match target {
+            Variable::List(items) => items,
+            _ => return Err(ErrorType::Generic("Expected a list".to_string())),
+        };
+        let i = match self.eval(&index.index)? {
+            Variable::Number(i) => i,
             _ => {
-                // TODO: handle user defined functions
-                return Err(format!("Function `{}` is not implemented", &func_call.name));
+                return Err(ErrorType::Generic(
+                    "List index must be a number".to_string(),
+                ))
+            }
+        };
+
+        let len = items.borrow().len();
+        if i < 0 || i as usize >= len {
+            return Err(runtime_error(
+                &format!("Index {i} out of bounds for list of length {len}"),
+                index.span,
+            ));
+        }
+
+        Ok((items, i as usize))
+    }
+
+    /// Evaluates an expression which has to result in a list
+    fn eval_list(&mut self, expr: &Expr) -> Result<ListItems, ErrorType> {
+        match self.eval(expr)? {
+            Variable::List(items) => Ok(items),
+            _ => Err(ErrorType::Generic("Expected a list".to_string())),
+        }
+    }
+
+    /// Evaluates an expression which has to result in a string
+    fn eval_str(&mut self, expr: &Expr) -> Result<String, ErrorType> {
+        match self.eval(expr)? {
+            Variable::StringLiteral(s) => Ok(s),
+            _ => Err(ErrorType::Generic("Expected a string".to_string())),
+        }
+    }
+
+    /// Evaluates an expression which has to result in a map
+    fn eval_map(&mut self, expr: &Expr) -> Result<MapItems, ErrorType> {
+        match self.eval(expr)? {
+            Variable::Map(entries) => Ok(entries),
+            _ => Err(ErrorType::Generic("Expected a map".to_string())),
+        }
+    }
+
+    /// Handles function calls
+    fn handle_func_call(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        match builtin(&func_call.name) {
+            Some(builtin) => (builtin.interpret)(self, func_call),
+            None => self.call_user_func(func_call),
+        }
+    }
+
+    /// Calls a user defined function. The body runs with the parameters as its only variables
+    fn call_user_func(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        self.load_module_of(&func_call.name)?;
+        let func = self
+            .functions
+            .get(&func_call.name)
+            .cloned()
+            .ok_or(ErrorType::Generic(format!(
+                "Function `{}` is not implemented",
+                &func_call.name
+            )))?;
+
+        let mut scope = HashMap::new();
+        for ((name, typ), arg) in func.params.iter().zip(&func_call.arguments) {
+            let value = self.eval(arg)?;
+            scope.insert(name.clone(), convert(value, typ));
+        }
+
+        let outer = std::mem::replace(&mut self.variables, scope);
+        let outer_scopes = std::mem::take(&mut self.scopes);
+        self.depth += 1;
+        let result = self.run_block(&func.block);
+        self.depth -= 1;
+        self.variables = outer;
+        self.scopes = outer_scopes;
+        let returned = self.returned.take();
+        // Errors raised by functions of modules name the file of the module
+        if let Err(err) = result {
+            return Err(match func_call.name.split_once('.') {
+                Some((module, _)) => err.in_file(&self.sources[module]),
+                None => err,
+            });
+        }
+
+        Ok(convert(
+            returned.unwrap_or(Variable::None),
+            &func.return_type,
+        ))
+    }
+
+    /// Handles variable declarations by storing the variable in the `variables` map and
+    /// evaluating its value
+    fn handle_var_decl(
+        &mut self,
+        variable_declaration: &VariableDeclaration,
+    ) -> Result<(), ErrorType> {
+        let mut value = self.eval(&variable_declaration.value)?;
+        if let Some(typ) = &variable_declaration.typ {
+            value = convert(value, typ);
+        }
+        self.declare(&variable_declaration.identifier, value);
+
+        Ok(())
+    }
+
+    /// Handles an assignment to an existing variable, list element or map entry
+    fn handle_assignment(&mut self, assignment: &Assignment) -> Result<(), ErrorType> {
+        let mut value = self.eval(&assignment.value)?;
+        if let Some(typ) = &assignment.typ {
+            value = convert(value, typ);
+        }
+
+        match &assignment.target {
+            Expr::Identifier(id) => {
+                self.get_var(id)?;
+                self.variables.insert(id.clone(), value);
+            }
+            Expr::Field(field) => {
+                let mut value = Some(value);
+                self.update_place(&field.target, &mut |target| {
+                    *field_mut(target, &field.field)? = value.take().unwrap_or(Variable::None);
+                    Ok(())
+                })?;
             }
+            Expr::Index(index) => match self.eval(&index.target)? {
+                Variable::Map(entries) => {
+                    let key = self.eval(&index.index)?.key()?;
+                    entries.borrow_mut().insert(key, value);
+                }
+                target => {
+                    let (items, i) = self.eval_index(target, index)?;
+                    items.borrow_mut()[i] = value;
+                }
+            },
+            _ => return Err(ErrorType::Generic("Invalid assignment target".to_string())),
         }
 
         Ok(())
     }
 
-    /// Handles the `print` function call
-    fn handle_print(&self, func_call: &FuncCall) -> Result<(), String> {
+    /// Runs `update` on the value stored at the given place (a variable, list element, map entry
+    /// or struct field), so that struct values can be modified in place
+    fn update_place(
+        &mut self,
+        place: &Expr,
+        update: &mut dyn FnMut(&mut Variable) -> Result<(), ErrorType>,
+    ) -> Result<(), ErrorType> {
+        match place {
+            Expr::Identifier(id) => match self.variables.get_mut(id) {
+                Some(value) => update(value),
+                None => Err(ErrorType::Generic(format!(
+                    "Variable doesn't exist: `{id}`"
+                ))),
+            },
+            Expr::Field(field) => self.update_place(&field.target, &mut |target| {
+                update(field_mut(target, &field.field)?)
+            }),
+            Expr::Index(index) => match self.eval(&index.target)? {
+                Variable::Map(entries) => {
+                    let key = self.eval(&index.index)?.key()?;
+                    let mut entries = entries.borrow_mut();
+                    match entries.values.get_mut(&key) {
+                        Some(value) => update(value),
+                        None => Err(runtime_error(
+                            &format!("Key {} not found in map", Variable::from(key).repr()),
+                            index.span,
+                        )),
+                    }
+                }
+                target => {
+                    let (items, i) = self.eval_index(target, index)?;
+                    let result = update(&mut items.borrow_mut()[i]);
+                    result
+                }
+            },
+            _ => Err(ErrorType::Generic("Invalid assignment target".to_string())),
+        }
+    }
+
+    /// Handles an if statement. For `if let`, the value of the optional is bound to the variable
+    /// while the block runs
+    fn handle_if(&mut self, if_statement: &IfStatement) -> Result<(), ErrorType> {
+        let value = self.eval(&if_statement.condition)?;
+        if let Variable::None | Variable::Bool(Bool::False) = value {
+            return self.run_scoped(&if_statement.else_block, Vec::new());
+        }
+        let bindings = match &if_statement.binding {
+            Some(name) => vec![(name.clone(), value)],
+            None => Vec::new(),
+        };
+
+        self.run_scoped(&if_statement.block, bindings)
+    }
+
+    /// Handles a try statement. Runtime errors raised inside of the try block (by `error` or by
+    /// builtins) run the catch block, other errors are passed on
+    fn handle_try(&mut self, try_catch: &TryCatch) -> Result<(), ErrorType> {
+        let message = match self.run_scoped(&try_catch.block, Vec::new()) {
+            Err(err) => match err.runtime_message() {
+                Some(message) => message.to_string(),
+                None => return Err(err),
+            },
+            ok => return ok,
+        };
+
+        let bindings = match &try_catch.variable {
+            Some(name) => vec![(name.clone(), Variable::StringLiteral(message))],
+            None => Vec::new(),
+        };
+
+        self.run_scoped(&try_catch.catch_block, bindings)
+    }
+
+    /// Handles a for loop, running the block once for every element of the list or every entry
+    /// of the map
+    fn handle_for(&mut self, for_loop: &ForLoop) -> Result<(), ErrorType> {
+        let items = match self.eval(&for_loop.iterable)? {
+            Variable::Map(entries) => return self.handle_for_map(for_loop, entries),
+            Variable::List(items) => items,
+            _ => return Err(ErrorType::Generic("Expected a list".to_string())),
+        };
+
+        // Iterate by index, so that the list can be modified from inside of the loop
+        let mut i = 0;
+        loop {
+            let item = match items.borrow().get(i) {
+                Some(item) => item.clone(),
+                None => break,
+            };
+            self.run_scoped(&for_loop.block, vec![(for_loop.variable.clone(), item)])?;
+            if self.returned.is_some() {
+                break;
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a for loop over a map, binding the key and optionally the value of every entry in
+    /// insertion order
+    fn handle_for_map(&mut self, for_loop: &ForLoop, entries: MapItems) -> Result<(), ErrorType> {
+        let mut i = 0;
+        loop {
+            let (key, value) = {
+                let entries = entries.borrow();
+                match entries.keys.get(i) {
+                    Some(key) => (key.clone(), entries.values[key].clone()),
+                    None => break,
+                }
+            };
+            let mut bindings = vec![(for_loop.variable.clone(), Variable::from(key))];
+            if let Some(id) = &for_loop.value_variable {
+                bindings.push((id.clone(), value));
+            }
+            self.run_scoped(&for_loop.block, bindings)?;
+            if self.returned.is_some() {
+                break;
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Implementations of the builtin functions, see `builtins.rs`. Arguments are already checked
+impl Interpreter {
+    /// Prints the arguments separated by spaces, followed by a newline
+    pub(crate) fn builtin_print(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
         let args = func_call.arguments.iter();
         let args_count = args.len();
         for (i, arg) in args.enumerate() {
             match arg {
-                Expr::FuncCall(func_call) => self.handle_func_call(func_call)?,
-                Expr::BinExpr(bin_expr) => print!("{}", self.handle_bin_expr(bin_expr)?),
-                Expr::Number(n) => print!("{n}"),
-                Expr::Identifier(id) => print!("{}", self.get_var(id)?),
-                Expr::StringLiteral(s) => print!("{s}"),
-                Expr::Bool(v) => match v {
-                    Bool::True => print!("true"),
-                    Bool::False => print!("false"),
-                },
+                Expr::VariableDeclaration(_) | Expr::Block(_) | Expr::Return(_) => {
+                    return Err(ErrorType::Generic("Invalid argument to print".to_string()));
+                }
                 _ => {
-                    return Err("Invalid argument to print".to_string());
+                    let value = self.eval(arg)?;
+                    print!("{value}");
                 }
             }
             if i != args_count - 1 {
@@ -166,39 +938,419 @@ impl Interpreter {
 
         println!();
 
-        Ok(())
+        Ok(Variable::None)
     }
 
-    /// Handles variable declarations by storing the variable in the `variables` map and
-    /// evaluating its value
-    fn handle_var_decl(
+    pub(crate) fn builtin_len(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let len = match self.eval(&func_call.arguments[0])? {
+            Variable::List(items) => items.borrow().len(),
+            Variable::Map(entries) => entries.borrow().keys.len(),
+            Variable::StringLiteral(s) => s.len(),
+            _ => return Err(ErrorType::Generic("Expected a list".to_string())),
+        };
+        Ok(Variable::Number(len as i64))
+    }
+
+    pub(crate) fn builtin_push(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let items = self.eval_list(&func_call.arguments[0])?;
+        let mut value = self.eval(&func_call.arguments[1])?;
+        if let Some(Type::List(elem)) = first_arg_type(func_call) {
+            value = convert(value, elem);
+        }
+        items.borrow_mut().push(value);
+        Ok(Variable::None)
+    }
+
+    pub(crate) fn builtin_pop(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let items = self.eval_list(&func_call.arguments[0])?;
+        let value = items.borrow_mut().pop();
+        value.ok_or(runtime_error(
+            "Cannot pop from an empty list",
+            func_call.span,
+        ))
+    }
+
+    pub(crate) fn builtin_has(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let entries = self.eval_map(&func_call.arguments[0])?;
+        let key = self.eval(&func_call.arguments[1])?.key()?;
+        let has = entries.borrow().values.contains_key(&key);
+        Ok(Variable::Bool(if has { Bool::True } else { Bool::False }))
+    }
+
+    pub(crate) fn builtin_remove(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let entries = self.eval_map(&func_call.arguments[0])?;
+        let key = self.eval(&func_call.arguments[1])?.key()?;
+        entries.borrow_mut().remove(&key);
+        Ok(Variable::None)
+    }
+
+    pub(crate) fn builtin_keys(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let entries = self.eval_map(&func_call.arguments[0])?;
+        let keys = entries
+            .borrow()
+            .keys
+            .iter()
+            .cloned()
+            .map(Variable::from)
+            .collect();
+        Ok(Variable::List(Rc::new(RefCell::new(keys))))
+    }
+
+    pub(crate) fn builtin_error(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let message = self.eval(&func_call.arguments[0])?;
+        Err(runtime_error(&message.to_string(), func_call.span))
+    }
+
+    pub(crate) fn builtin_str(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let value = self.eval(&func_call.arguments[0])?;
+        Ok(Variable::StringLiteral(value.to_string()))
+    }
+
+    /// Converts a number, dropping its fractional part, or parses a string
+    pub(crate) fn builtin_int(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        match self.eval(&func_call.arguments[0])? {
+            Variable::Float(n) => Ok(Variable::Number(n as i64)),
+            Variable::StringLiteral(s) => match s.trim().parse() {
+                Ok(n) => Ok(Variable::Number(n)),
+                Err(_) => Err(runtime_error(
+                    &format!("Cannot parse \"{s}\" as an int"),
+                    func_call.span,
+                )),
+            },
+            value => Ok(value),
+        }
+    }
+
+    /// Converts a number or parses a string
+    pub(crate) fn builtin_float(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        match self.eval(&func_call.arguments[0])? {
+            Variable::Number(n) => Ok(Variable::Float(n as f64)),
+            Variable::StringLiteral(s) => match s.trim().parse() {
+                Ok(n) => Ok(Variable::Float(n)),
+                Err(_) => Err(runtime_error(
+                    &format!("Cannot parse \"{s}\" as a float"),
+                    func_call.span,
+                )),
+            },
+            value => Ok(value),
+        }
+    }
+
+    pub(crate) fn builtin_abs(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        match self.eval(&func_call.arguments[0])? {
+            Variable::Number(n) => Ok(Variable::Number(n.wrapping_abs())),
+            Variable::Float(n) => Ok(Variable::Float(n.abs())),
+            _ => Err(ErrorType::Generic("Expected a number".to_string())),
+        }
+    }
+
+    pub(crate) fn builtin_min(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let a = self.eval(&func_call.arguments[0])?;
+        let b = self.eval(&func_call.arguments[1])?;
+        let value = if as_float(&b) < as_float(&a) { b } else { a };
+        Ok(match first_arg_type(func_call) {
+            Some(typ) => convert(value, typ),
+            None => value,
+        })
+    }
+
+    pub(crate) fn builtin_max(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let a = self.eval(&func_call.arguments[0])?;
+        let b = self.eval(&func_call.arguments[1])?;
+        let value = if as_float(&b) > as_float(&a) { b } else { a };
+        Ok(match first_arg_type(func_call) {
+            Some(typ) => convert(value, typ),
+            None => value,
+        })
+    }
+
+    /// Prints the prompt and reads a line, the result is empty at the end of the input
+    pub(crate) fn builtin_input(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let prompt = self.eval(&func_call.arguments[0])?;
+        print!("{prompt}");
+        stdout()
+            .flush()
+            .map_err(|err| runtime_error(&err.to_string(), func_call.span))?;
+        let line = read_line(func_call)?;
+        Ok(Variable::StringLiteral(line.unwrap_or_default()))
+    }
+
+    /// Reads a line, the result is `none` at the end of the input
+    pub(crate) fn builtin_read_line(
         &mut self,
-        variable_declaration: &VariableDeclaration,
-    ) -> Result<(), String> {
-        if let Some(var_type) = &variable_declaration.typ {
-            if !type_check(var_type, &variable_declaration.value) {
-                return Err(format!(
-                    "Variable type `{var_type}` does not match value type"
-                ));
-            }
-        }
-
-        self.variables.insert(
-            variable_declaration.identifier.clone(),
-            match &variable_declaration.value {
-                Expr::Number(n) => Variable::Number(*n),
-                Expr::Bool(v) => match v {
-                    Bool::True => Variable::Bool(Bool::True),
-                    Bool::False => Variable::Bool(Bool::False),
-                },
-                Expr::StringLiteral(s) => Variable::StringLiteral(s.to_owned()),
-                Expr::BinExpr(bin_expr) => Variable::Number(self.handle_bin_expr(bin_expr)?),
-                _ => {
-                    return Err("Can only store strings and number in variables".to_string());
-                }
+        func_call: &FuncCall,
+    ) -> Result<Variable, ErrorType> {
+        Ok(match read_line(func_call)? {
+            Some(line) => Variable::StringLiteral(line),
+            None => Variable::None,
+        })
+    }
+
+    /// Values don't carry their static type (eg. `int` and `long` are both numbers), so the
+    /// type recorded by the checker is used instead
+    pub(crate) fn builtin_type_of(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        self.eval(&func_call.arguments[0])?;
+        let typ = first_arg_type(func_call).ok_or(ErrorType::Generic(
+            "Type of the argument of `type_of` is unknown".to_string(),
+        ))?;
+        Ok(Variable::StringLiteral(typ.to_string()))
+    }
+
+    pub(crate) fn builtin_read_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<Variable, ErrorType> {
+        let path = self.eval_str(&func_call.arguments[0])?;
+        let contents =
+            fs::read(&path).map_err(|err| io_error("read", &path, err, func_call.span))?;
+        Ok(Variable::StringLiteral(
+            String::from_utf8_lossy(&contents).into_owned(),
+        ))
+    }
+
+    pub(crate) fn builtin_write_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<Variable, ErrorType> {
+        self.write_file(func_call, false)
+    }
+
+    pub(crate) fn builtin_append_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<Variable, ErrorType> {
+        self.write_file(func_call, true)
+    }
+
+    /// Writes the contents to the file, creating it when it doesn't exist
+    fn write_file(&mut self, func_call: &FuncCall, append: bool) -> Result<Variable, ErrorType> {
+        let path = self.eval_str(&func_call.arguments[0])?;
+        let contents = self.eval_str(&func_call.arguments[1])?;
+        OpenOptions::new()
+            .create(true)
+            .append(append)
+            .write(true)
+            .truncate(!append)
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|err| io_error("write", &path, err, func_call.span))?;
+        Ok(Variable::None)
+    }
+
+    pub(crate) fn builtin_exists(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let path = self.eval_str(&func_call.arguments[0])?;
+        let exists = Path::new(&path).exists();
+        Ok(Variable::Bool(if exists {
+            Bool::True
+        } else {
+            Bool::False
+        }))
+    }
+
+    /// Names of the entries of the directory, sorted
+    pub(crate) fn builtin_list_dir(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let path = self.eval_str(&func_call.arguments[0])?;
+        let mut names = fs::read_dir(&path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|err| io_error("list", &path, err, func_call.span))?;
+        names.sort();
+        let names = names.into_iter().map(Variable::StringLiteral).collect();
+        Ok(Variable::List(Rc::new(RefCell::new(names))))
+    }
+
+    pub(crate) fn builtin_remove_file(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<Variable, ErrorType> {
+        let path = self.eval_str(&func_call.arguments[0])?;
+        fs::remove_file(&path).map_err(|err| io_error("remove", &path, err, func_call.span))?;
+        Ok(Variable::None)
+    }
+
+    pub(crate) fn builtin_args(&mut self, _: &FuncCall) -> Result<Variable, ErrorType> {
+        let args = self
+            .args
+            .iter()
+            .cloned()
+            .map(Variable::StringLiteral)
+            .collect();
+        Ok(Variable::List(Rc::new(RefCell::new(args))))
+    }
+
+    pub(crate) fn builtin_env(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let name = self.eval_str(&func_call.arguments[0])?;
+        Ok(match env::var(name) {
+            Ok(value) => Variable::StringLiteral(value),
+            Err(_) => Variable::None,
+        })
+    }
+
+    /// Ends the program right away, like in compiled programs
+    pub(crate) fn builtin_exit(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let code = match self.eval(&func_call.arguments[0])? {
+            Variable::Number(code) => code as i32,
+            _ => return Err(ErrorType::Generic("Expected a number".to_string())),
+        };
+        stdout()
+            .flush()
+            .map_err(|err| runtime_error(&err.to_string(), func_call.span))?;
+        process::exit(code)
+    }
+
+    /// Runs a command to completion with its output captured, stdin isn't inherited
+    pub(crate) fn builtin_run(&mut self, func_call: &FuncCall) -> Result<Variable, ErrorType> {
+        let (name, mut command) = self.command(func_call)?;
+        let output = command
+            .output()
+            .map_err(|err| io_error("run", &name, err, func_call.span))?;
+        Ok(Variable::Tuple(vec![
+            Variable::Number(exit_status(output.status)),
+            Variable::StringLiteral(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Variable::StringLiteral(String::from_utf8_lossy(&output.stderr).into_owned()),
+        ]))
+    }
+
+    /// Runs a command to completion with the standard streams of the program
+    pub(crate) fn builtin_run_stream(
+        &mut self,
+        func_call: &FuncCall,
+    ) -> Result<Variable, ErrorType> {
+        let (name, mut command) = self.command(func_call)?;
+        // Output printed so far has to come before the output of the command
+        stdout()
+            .flush()
+            .map_err(|err| runtime_error(&err.to_string(), func_call.span))?;
+        let status = command
+            .status()
+            .map_err(|err| io_error("run", &name, err, func_call.span))?;
+        Ok(Variable::Number(exit_status(status)))
+    }
+
+    /// Evaluates the name and the arguments of a command passed to `run` or `run_stream`
+    fn command(&mut self, func_call: &FuncCall) -> Result<(String, Command), ErrorType> {
+        let name = self.eval_str(&func_call.arguments[0])?;
+        let mut command = Command::new(&name);
+        for arg in self.eval_list(&func_call.arguments[1])?.borrow().iter() {
+            match arg {
+                Variable::StringLiteral(arg) => command.arg(arg),
+                _ => return Err(ErrorType::Generic("Expected a string".to_string())),
+            };
+        }
+        Ok((name, command))
+    }
+}
+
+/// Returns the field of a struct value for modification
+fn field_mut<'a>(target: &'a mut Variable, field: &str) -> Result<&'a mut Variable, ErrorType> {
+    match target {
+        Variable::Struct(value) => value
+            .fields
+            .iter_mut()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value)
+            .ok_or(ErrorType::Generic(format!("Unknown field `{field}`"))),
+        _ => Err(ErrorType::Generic("Expected a struct".to_string())),
+    }
+}
+
+/// Whether a value matches the pattern, collecting the variables bound by the pattern
+fn pattern_matches(
+    pattern: &Pattern,
+    value: &Variable,
+    bindings: &mut Vec<(String, Variable)>,
+) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Binding(name), value) => {
+            bindings.push((name.clone(), value.clone()));
+            true
+        }
+        (Pattern::Literal(Expr::Number(n)), Variable::Number(value)) => n == value,
+        (Pattern::Literal(Expr::StringLiteral(s)), Variable::StringLiteral(value)) => s == value,
+        (Pattern::Literal(Expr::Bool(v)), Variable::Bool(value)) => v == value,
+        (
+            Pattern::Variant {
+                variant, fields, ..
             },
-        );
+            Variable::Enum(value),
+        ) => {
+            *variant == value.variant
+                && fields
+                    .iter()
+                    .zip(&value.values)
+                    .all(|(field, value)| pattern_matches(field, value, bindings))
+        }
+        _ => false,
+    }
+}
 
-        Ok(())
+/// Creates a runtime error pointing at the given source location
+fn runtime_error(message: &str, span: Span) -> ErrorType {
+    ErrorType::RuntimeError(message.to_string(), span)
+}
+
+/// Creates the runtime error of a failed file system operation. The reason is worded like by
+/// `strerror`, so that compiled programs report the same message
+fn io_error(action: &str, path: &str, err: io::Error, span: Span) -> ErrorType {
+    let reason = err.to_string();
+    let reason = match reason.find(" (os error") {
+        Some(end) => &reason[..end],
+        None => &reason,
+    };
+    runtime_error(&format!("Cannot {action} \"{path}\": {reason}"), span)
+}
+
+/// Exit status of a finished command. Like in shells, a command killed by a signal gets 128 plus
+/// the number of the signal
+fn exit_status(status: ExitStatus) -> i64 {
+    match status.code() {
+        Some(code) => code as i64,
+        None => 128 + status.signal().unwrap_or(0) as i64,
+    }
+}
+
+/// Converts ints stored where a float is expected into `Float`s, as the compiler does
+fn convert(value: Variable, typ: &Type) -> Variable {
+    match (value, typ) {
+        (Variable::Number(n), Type::Float | Type::Double) => Variable::Float(n as f64),
+        (value, Type::Optional(inner)) => convert(value, inner),
+        (value, _) => value,
+    }
+}
+
+/// Type of the first argument of a builtin call, recorded by the checker
+fn first_arg_type(func_call: &FuncCall) -> Option<&Type> {
+    func_call.arg_types.get().and_then(|types| types.first())
+}
+
+/// Returns the value of a `Number` or a `Float` as a float
+fn as_float(value: &Variable) -> f64 {
+    match value {
+        Variable::Number(n) => *n as f64,
+        Variable::Float(n) => *n,
+        _ => f64::NAN,
+    }
+}
+
+/// Reads a line from the standard input without its line ending, `None` at the end of the input
+fn read_line(func_call: &FuncCall) -> Result<Option<String>, ErrorType> {
+    let mut line = String::new();
+    let read = stdin()
+        .read_line(&mut line)
+        .map_err(|err| runtime_error(&err.to_string(), func_call.span))?;
+    if read == 0 {
+        return Ok(None);
+    }
+
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
     }
+    Ok(Some(line))
 }
@@ -0,0 +1,588 @@
+use crate::{
+    parser::{lexer_with_spans, preprocess, Bool, Expr, Parser, Span, Token, Type},
+    project::{read_manifest, Project},
+    utils::{display_error, display_warning, ErrorType, Output},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::read_to_string,
+    io::{read_to_string as read_all, stdin},
+    path::PathBuf,
+};
+
+/// Marker of the comments allowing rules: `// black:allow(unused_variable, redundant_type)`
+const ALLOW: &str = "black:allow(";
+
+/// Rules checked by the linter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Variables and parameters which are never read
+    UnusedVariable,
+    /// `let` declaring a variable which is already declared
+    ShadowedVariable,
+    /// Statements after a `return` in the same block
+    UnreachableCode,
+    /// `if` on `true` or `false`
+    ConstantCondition,
+    /// Type annotations spelling out the type of the literal they're given
+    RedundantType,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 5] = [
+        Rule::UnusedVariable,
+        Rule::ShadowedVariable,
+        Rule::UnreachableCode,
+        Rule::ConstantCondition,
+        Rule::RedundantType,
+    ];
+
+    /// Name of the rule in manifests and allow comments
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::UnusedVariable => "unused_variable",
+            Rule::ShadowedVariable => "shadowed_variable",
+            Rule::UnreachableCode => "unreachable_code",
+            Rule::ConstantCondition => "constant_condition",
+            Rule::RedundantType => "redundant_type",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+/// How a rule is reported, set per rule in the `[lint]` section of a manifest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    /// Not reported
+    Allow,
+    /// Reported as a warning, the default
+    Warn,
+    /// Reported as an error, making the linter fail
+    Deny,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "allow" => Some(Level::Allow),
+            "warn" => Some(Level::Warn),
+            "deny" => Some(Level::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// A problem found by the linter
+#[derive(Debug, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub message: String,
+    /// Labeled source locations, the last one is the location of the problem
+    pub labels: Vec<(Span, String)>,
+}
+
+impl Lint {
+    /// Location of the problem
+    pub fn span(&self) -> Span {
+        self.labels[self.labels.len() - 1].0
+    }
+}
+
+/// Lints source code. Problems on lines allowing their rule, or following a line which does, are
+/// left out. Fails on code which doesn't parse
+pub fn lint(source: &str) -> Result<Vec<Lint>, ErrorType> {
+    let code = preprocess(source);
+    let (tokens, spans) = lexer_with_spans(&code)?;
+    let mut parser = Parser::with_spans(&tokens, &spans);
+    let ast = parser.parse()?;
+
+    let mut linter = Linter {
+        tokens: &tokens,
+        spans: &spans,
+        scopes: Vec::new(),
+        lints: Vec::new(),
+    };
+    linter.scoped(Vec::new(), |linter| linter.statements(&ast));
+    linter.unreachable_code(&parser.line_starts);
+
+    let allowed = allowed(source);
+    let mut lints: Vec<Lint> = linter
+        .lints
+        .into_iter()
+        .filter(|lint| {
+            let line = lint.span().line;
+            ![line, line.saturating_sub(1)]
+                .iter()
+                .any(|line| allowed.contains(&(*line, lint.rule)))
+        })
+        .collect();
+    lints.sort_by_key(|lint| (lint.span().line, lint.span().col));
+
+    Ok(lints)
+}
+
+/// Lints files, or stdin when no files are given, reporting the rules at the levels set by the
+/// manifest of the project around the current directory. Returns the exit status: failing when
+/// a file can't be linted or a rule set to `deny` is broken
+pub fn lint_files(files: &[PathBuf]) -> i32 {
+    let levels: HashMap<Rule, Level> = match Project::find() {
+        Ok(root) => match read_manifest(&root) {
+            Ok(manifest) => manifest.lints.into_iter().collect(),
+            Err(err) => {
+                display_error(err, "", Output::Stderr);
+                return 1;
+            }
+        },
+        Err(_) => HashMap::new(),
+    };
+
+    let inputs = if files.is_empty() {
+        vec![(
+            "<stdin>".to_string(),
+            read_all(stdin()).map_err(|_| "Could not read stdin".to_string()),
+        )]
+    } else {
+        files
+            .iter()
+            .map(|file| {
+                let name = file.display().to_string();
+                let source = read_to_string(file).map_err(|_| format!("Could not read \"{name}\""));
+                (name, source)
+            })
+            .collect()
+    };
+
+    let mut status = 0;
+    for (name, source) in inputs {
+        let lints = match source.map_err(ErrorType::Generic).and_then(|s| lint(&s)) {
+            Ok(lints) => lints,
+            Err(err) => {
+                display_error(err, &name, Output::Stderr);
+                status = 1;
+                continue;
+            }
+        };
+
+        for lint in lints {
+            let message = format!("{} ({})", lint.message, lint.rule.name());
+            match levels.get(&lint.rule).copied().unwrap_or(Level::Warn) {
+                Level::Allow => {}
+                Level::Warn => display_warning(&message, &lint.labels, &name, Output::Stderr),
+                Level::Deny => {
+                    display_error(
+                        ErrorType::LintError(message, lint.labels),
+                        &name,
+                        Output::Stderr,
+                    );
+                    status = 1;
+                }
+            }
+        }
+    }
+
+    status
+}
+
+/// Finds the lines with a comment allowing rules, along with the rules they allow
+fn allowed(source: &str) -> HashSet<(usize, Rule)> {
+    let mut allowed = HashSet::new();
+    for (i, line) in source.lines().enumerate() {
+        let Some(comment) = line.find("//").map(|start| &line[start..]) else {
+            continue;
+        };
+        let Some(rules) = comment
+            .find(ALLOW)
+            .map(|start| &comment[start + ALLOW.len()..])
+            .and_then(|rest| rest.split(')').next())
+        else {
+            continue;
+        };
+        allowed.extend(
+            rules
+                .split(',')
+                .filter_map(|rule| Rule::from_name(rule.trim()))
+                .map(|rule| (i + 1, rule)),
+        );
+    }
+
+    allowed
+}
+
+/// Variable in scope while linting
+struct Variable {
+    name: String,
+    /// "variable" or "parameter"
+    kind: &'static str,
+    /// Location of the name where the variable is declared
+    span: Span,
+    used: bool,
+}
+
+/// Walks the AST keeping track of the variables in scope
+struct Linter<'a> {
+    tokens: &'a [Token],
+    spans: &'a [Span],
+    /// Variables of every scope of the current function (or of the top level)
+    scopes: Vec<Vec<Variable>>,
+    lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+    /// Runs `f` in a new scope holding the given variables. Variables of the scope which were
+    /// never read are reported when it ends
+    fn scoped(&mut self, variables: Vec<Variable>, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(variables);
+        f(self);
+        let scope = self.scopes.pop().expect("the scope was just pushed");
+
+        for variable in scope {
+            if variable.used || variable.name.starts_with('_') {
+                continue;
+            }
+            self.lints.push(Lint {
+                rule: Rule::UnusedVariable,
+                message: format!("Unused {} `{}`", variable.kind, variable.name),
+                labels: vec![(variable.span, "never used".to_string())],
+            });
+        }
+    }
+
+    fn statements(&mut self, statements: &[Expr]) {
+        for statement in statements {
+            self.expr(statement);
+        }
+    }
+
+    fn block(&mut self, block: &[Expr]) {
+        self.scoped(Vec::new(), |linter| linter.statements(block));
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier(name) => self.read(name),
+            Expr::FuncCall(call) => self.statements(&call.arguments),
+            Expr::EnumVariant(variant) => self.statements(&variant.arguments),
+            Expr::List(elements, _) | Expr::Tuple(elements) => self.statements(elements),
+            Expr::Map(entries, _) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::StructLiteral(literal) => {
+                for (_, value) in &literal.fields {
+                    self.expr(value);
+                }
+            }
+            Expr::BinExpr(bin_expr) => {
+                self.expr(&bin_expr.lhs);
+                self.expr(&bin_expr.rhs);
+            }
+            Expr::Index(index) => {
+                self.expr(&index.target);
+                self.expr(&index.index);
+            }
+            Expr::Field(field) => self.expr(&field.target),
+            Expr::TupleIndex(tuple_index) => self.expr(&tuple_index.target),
+            Expr::Coalesce(coalesce) => {
+                self.expr(&coalesce.value);
+                self.expr(&coalesce.default);
+            }
+            Expr::Return(ret) => self.expr(&ret.value),
+            Expr::Block(block) => self.block(block),
+
+            Expr::Assignment(assignment) => {
+                // Assigning to a variable doesn't read it, assigning to its elements or fields does
+                if !matches!(assignment.target, Expr::Identifier(_)) {
+                    self.expr(&assignment.target);
+                }
+                self.expr(&assignment.value);
+            }
+
+            Expr::VariableDeclaration(var_decl) => {
+                self.expr(&var_decl.value);
+                if let (Some(typ), Some(typ_span)) = (&var_decl.typ, var_decl.typ_span) {
+                    if literal_type(&var_decl.value).as_ref() == Some(typ) {
+                        self.lints.push(Lint {
+                            rule: Rule::RedundantType,
+                            message: format!(
+                                "Type annotation `{typ}` of `{}` is redundant, it's the type of \
+                                 the value",
+                                var_decl.identifier
+                            ),
+                            labels: vec![(typ_span, "can be left out".to_string())],
+                        });
+                    }
+                }
+                let span = self.name_before(&var_decl.identifier, var_decl.value_span);
+                self.declare(&var_decl.identifier, span);
+            }
+
+            Expr::Destructure(destructure) => {
+                self.expr(&destructure.value);
+                for name in destructure.names.iter().filter(|name| *name != "_") {
+                    let span = self.name_before(name, destructure.value_span);
+                    self.declare(name, span);
+                }
+            }
+
+            Expr::IfStatement(if_statement) => {
+                self.expr(&if_statement.condition);
+                if let Expr::Bool(value) = &if_statement.condition {
+                    let (value, branch) = match value {
+                        Bool::True if if_statement.else_block.is_empty() => {
+                            ("true", "the block always runs")
+                        }
+                        Bool::True => ("true", "the `else` branch never runs"),
+                        Bool::False => ("false", "the block never runs"),
+                    };
+                    self.lints.push(Lint {
+                        rule: Rule::ConstantCondition,
+                        message: format!("Condition is always `{value}`"),
+                        labels: vec![(if_statement.span, branch.to_string())],
+                    });
+                }
+
+                let binding = if_statement.binding.as_ref().map(|name| Variable {
+                    name: name.clone(),
+                    kind: "variable",
+                    span: self.name_before(name, if_statement.span),
+                    used: false,
+                });
+                self.scoped(binding.into_iter().collect(), |linter| {
+                    linter.block(&if_statement.block)
+                });
+                self.block(&if_statement.else_block);
+            }
+
+            Expr::For(for_loop) => {
+                self.expr(&for_loop.iterable);
+                let start = self.token_at(for_loop.span);
+                let variables = [Some(&for_loop.variable), for_loop.value_variable.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .filter(|name| *name != "_")
+                    .map(|name| Variable {
+                        name: name.clone(),
+                        kind: "variable",
+                        span: self.name_after(name, start),
+                        used: false,
+                    })
+                    .collect();
+                self.scoped(variables, |linter| linter.block(&for_loop.block));
+            }
+
+            Expr::Match(match_expr) => {
+                self.expr(&match_expr.value);
+                for arm in &match_expr.arms {
+                    let names = arm.pattern.bindings();
+                    let start = self.token_at(arm.span);
+                    let variables = names
+                        .into_iter()
+                        .map(|name| Variable {
+                            span: self.name_after(&name, start),
+                            name,
+                            kind: "variable",
+                            used: false,
+                        })
+                        .collect();
+                    self.scoped(variables, |linter| linter.expr(&arm.body));
+                }
+            }
+
+            Expr::TryCatch(try_catch) => {
+                self.block(&try_catch.block);
+                let variable = try_catch.variable.as_ref().map(|name| Variable {
+                    name: name.clone(),
+                    kind: "variable",
+                    span: try_catch.span,
+                    used: false,
+                });
+                self.scoped(variable.into_iter().collect(), |linter| {
+                    linter.block(&try_catch.catch_block)
+                });
+            }
+
+            Expr::FuncDecl(func_decl) => {
+                // Functions only see their parameters
+                let outer = std::mem::take(&mut self.scopes);
+                let start = self.token_at(func_decl.span) + 1;
+                let params = func_decl
+                    .params
+                    .iter()
+                    .map(|(name, _)| Variable {
+                        name: name.clone(),
+                        kind: "parameter",
+                        span: self.name_after(name, start),
+                        used: false,
+                    })
+                    .collect();
+                self.scoped(params, |linter| linter.block(&func_decl.block));
+                self.scopes = outer;
+            }
+
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::StringLiteral(_)
+            | Expr::None
+            | Expr::StructDecl(_)
+            | Expr::EnumDecl(_)
+            | Expr::Import(_) => {}
+        }
+    }
+
+    /// Marks the innermost variable with the name as used
+    fn read(&mut self, name: &str) {
+        if let Some(variable) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|variable| variable.name == name)
+        {
+            variable.used = true;
+        }
+    }
+
+    /// Declares a variable with `let` in the innermost scope, reporting any variable it shadows
+    fn declare(&mut self, name: &str, span: Span) {
+        let shadowed = self
+            .scopes
+            .iter()
+            .flatten()
+            .rev()
+            .find(|variable| variable.name == name);
+        if let Some(shadowed) = shadowed {
+            self.lints.push(Lint {
+                rule: Rule::ShadowedVariable,
+                message: format!("`{name}` shadows a {} with the same name", shadowed.kind),
+                labels: vec![
+                    (shadowed.span, "first declared here".to_string()),
+                    (span, "declared again here".to_string()),
+                ],
+            });
+        }
+
+        let scope = self.scopes.last_mut().expect("there's always a scope");
+        scope.push(Variable {
+            name: name.to_string(),
+            kind: "variable",
+            span,
+            used: false,
+        });
+    }
+
+    /// Reports the statements following a `return` in the same block, from the source tokens:
+    /// the statements starting at the same bracket depth before the block is closed
+    fn unreachable_code(&mut self, line_starts: &[usize]) {
+        let line_starts: HashSet<usize> = line_starts.iter().copied().collect();
+        let mut returns: Vec<usize> = line_starts
+            .iter()
+            .copied()
+            .filter(|&i| self.tokens[i] == Token::Return)
+            .collect();
+        returns.sort_unstable();
+
+        let mut reported_until = 0;
+        for i in returns {
+            if i < reported_until {
+                continue;
+            }
+            let mut depth = 0;
+            let mut unreachable = None;
+            let mut end = i;
+            for j in i + 1..self.tokens.len() {
+                match self.tokens[j] {
+                    Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+                    Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+                    _ => {}
+                }
+                if depth < 0 {
+                    break;
+                }
+                if depth == 0 && unreachable.is_none() && line_starts.contains(&j) {
+                    unreachable = Some(j);
+                }
+                end = j;
+            }
+
+            if let Some(start) = unreachable {
+                reported_until = end;
+                self.lints.push(Lint {
+                    rule: Rule::UnreachableCode,
+                    message: "Unreachable code after `return`".to_string(),
+                    labels: vec![
+                        (self.spans[i], "returns here".to_string()),
+                        (
+                            self.spans[start].to(&self.spans[end]),
+                            "never runs".to_string(),
+                        ),
+                    ],
+                });
+            }
+        }
+    }
+
+    /// Position of the token starting at the location
+    fn token_at(&self, span: Span) -> usize {
+        self.spans
+            .partition_point(|s| (s.line, s.col) < (span.line, span.col))
+    }
+
+    /// Location of the last occurrence of the name before the location
+    fn name_before(&self, name: &str, span: Span) -> Span {
+        let end = self.token_at(span);
+        (0..end)
+            .rev()
+            .find(|&i| matches!(&self.tokens[i], Token::Identifier(id) if id == name))
+            .map(|i| self.spans[i])
+            .unwrap_or(span)
+    }
+
+    /// Location of the first occurrence of the name at or after the token position
+    fn name_after(&self, name: &str, start: usize) -> Span {
+        (start..self.tokens.len())
+            .find(|&i| matches!(&self.tokens[i], Token::Identifier(id) if id == name))
+            .map(|i| self.spans[i])
+            .unwrap_or_default()
+    }
+}
+
+/// Type of a literal, which a type annotation would only repeat. `None` for values of any other
+/// kind, and for literals such as empty lists which need the annotation
+fn literal_type(value: &Expr) -> Option<Type> {
+    match value {
+        Expr::Number(_) => Some(Type::Int),
+        Expr::StringLiteral(_) => Some(Type::Str),
+        Expr::Bool(_) => Some(Type::Bool),
+        Expr::StructLiteral(literal) => Some(Type::Struct(literal.name.clone())),
+        // Type names are parsed as structs, enums included
+        Expr::EnumVariant(variant) => Some(Type::Struct(variant.enum_name.clone())),
+        Expr::List(elements, _) => {
+            let typ = same_type(elements.iter())?;
+            Some(Type::List(Box::new(typ)))
+        }
+        Expr::Map(entries, _) => {
+            let key = same_type(entries.iter().map(|(key, _)| key))?;
+            let value = same_type(entries.iter().map(|(_, value)| value))?;
+            Some(Type::Map(Box::new(key), Box::new(value)))
+        }
+        Expr::Tuple(elements) => elements
+            .iter()
+            .map(literal_type)
+            .collect::<Option<_>>()
+            .map(Type::Tuple),
+        _ => None,
+    }
+}
+
+/// Type of literals which all have the same type, `None` when there are none
+fn same_type<'a>(mut values: impl Iterator<Item = &'a Expr>) -> Option<Type> {
+    let typ = literal_type(values.next()?)?;
+    values
+        .all(|value| literal_type(value).as_ref() == Some(&typ))
+        .then_some(typ)
+}
@@ -0,0 +1,945 @@
+use crate::{
+    builtins::{builtin, BUILTINS},
+    checker::Checker,
+    formatter::format,
+    json::Json,
+    linter::lint,
+    modules::Loader,
+    parser::{lexer_with_spans, preprocess, Expr, Parser, Span, Token, Type},
+    project::{Project, MANIFEST},
+    utils::ErrorType,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{stdin, stdout, BufRead, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Keywords and type names offered as completions
+const KEYWORDS: &[&str] = &[
+    "let", "fn", "if", "else", "for", "in", "match", "try", "catch", "return", "struct", "enum",
+    "import", "pub", "true", "false", "none", "int", "long", "float", "double", "bool", "str",
+];
+
+/// JSON-RPC error code of requests for methods the server doesn't know
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+/// JSON-RPC error code of messages which aren't valid JSON
+const PARSE_ERROR: f64 = -32700.0;
+
+/// Kind of a declared name
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind {
+    Variable,
+    Function,
+    Struct,
+    Enum,
+}
+
+impl SymbolKind {
+    /// The LSP `CompletionItemKind`
+    fn completion_kind(self) -> usize {
+        match self {
+            SymbolKind::Variable => 6,
+            SymbolKind::Function => 3,
+            SymbolKind::Struct => 22,
+            SymbolKind::Enum => 13,
+        }
+    }
+}
+
+/// A name declared in a document
+#[derive(Debug)]
+struct Symbol {
+    name: String,
+    kind: SymbolKind,
+    /// Position of the token declaring the name
+    token: usize,
+    /// Positions of the tokens the name can be used at
+    scope: Range<usize>,
+    /// The declaration along with its type, shown on hover
+    detail: String,
+}
+
+/// An open document along with everything known about it
+#[derive(Debug, Default)]
+struct Document {
+    text: String,
+    tokens: Vec<Token>,
+    spans: Vec<Span>,
+    symbols: Vec<Symbol>,
+    diagnostics: Vec<Json>,
+}
+
+impl Document {
+    /// Lexes, parses, checks and lints the text. Whatever can't be analyzed is reported in the
+    /// diagnostics, names are known as long as the text parses. Types are only known when the
+    /// program checks. Imports are resolved from the path, along with the packages of its project
+    fn analyze(text: &str, path: Option<&Path>, packages: &[(String, PathBuf)]) -> Self {
+        let mut document = Document {
+            text: text.to_string(),
+            ..Document::default()
+        };
+
+        let (tokens, spans) = match lexer_with_spans(&preprocess(text)) {
+            Ok(lexed) => lexed,
+            Err(err) => {
+                document.error(err, Span::new(1, 1, 0));
+                return document;
+            }
+        };
+        let mut parser = Parser::with_spans(&tokens, &spans);
+        let parsed = parser.parse();
+        let (blocks, line_starts) = (parser.blocks, parser.line_starts);
+        let mut ast = match parsed {
+            Ok(ast) => ast,
+            Err(err) => {
+                document.error(err, Span::new(1, 1, 0));
+                document.tokens = tokens;
+                document.spans = spans;
+                return document;
+            }
+        };
+
+        let loaded = match path {
+            Some(path) => Loader::new(&[], packages).load(&mut ast, path),
+            None if ast.iter().any(|node| matches!(node, Expr::Import(_))) => Err(
+                ErrorType::Generic("Modules can only be imported by saved files".to_string()),
+            ),
+            None => Ok(()),
+        };
+        let mut checker = Checker::new();
+        match loaded {
+            Err(err) => document.error(err, Span::new(1, 1, 0)),
+            Ok(()) => {
+                if let Err(err) = checker.check(&mut ast) {
+                    let span = statement_span(&tokens, &spans, &line_starts, checker.node);
+                    document.error(err, span);
+                }
+            }
+        }
+        for lint in lint(text).unwrap_or_default() {
+            let diagnostic = Json::object([
+                ("range", document.range(lint.span())),
+                ("severity", Json::from(2)),
+                ("source", Json::from("black")),
+                ("code", Json::from(lint.rule.name())),
+                ("message", Json::from(lint.message)),
+            ]);
+            document.diagnostics.push(diagnostic);
+        }
+
+        let mut index = SymbolIndex {
+            tokens: &tokens,
+            spans: &spans,
+            blocks: &blocks,
+            symbols: Vec::new(),
+        };
+        index.statements(&ast);
+        document.symbols = index.symbols;
+        document.tokens = tokens;
+        document.spans = spans;
+
+        document
+    }
+
+    /// Reports an error. Errors without a location of their own are put at the given one
+    fn error(&mut self, err: ErrorType, fallback: Span) {
+        let (message, span) = match err {
+            ErrorType::TypeError(message, labels) | ErrorType::LintError(message, labels) => {
+                let span = labels.last().map(|(span, _)| *span);
+                (message, span)
+            }
+            ErrorType::RuntimeError(message, span)
+            | ErrorType::ImportError(message, span)
+            | ErrorType::ManifestError(message, span)
+            | ErrorType::SyntaxError(message, span)
+            | ErrorType::SemanticError(message, span) => (message, Some(span)),
+            ErrorType::Generic(message) | ErrorType::ToolchainError(message) => (message, None),
+            // The location is in the other file, the error is shown at the start of this one
+            ErrorType::InFile(file, err) => {
+                let message = match *err {
+                    ErrorType::TypeError(message, _)
+                    | ErrorType::LintError(message, _)
+                    | ErrorType::RuntimeError(message, _)
+                    | ErrorType::ImportError(message, _)
+                    | ErrorType::ManifestError(message, _)
+                    | ErrorType::SyntaxError(message, _)
+                    | ErrorType::SemanticError(message, _)
+                    | ErrorType::Generic(message)
+                    | ErrorType::ToolchainError(message) => message,
+                    ErrorType::InFile(..) => "Error in an imported module".to_string(),
+                };
+                (format!("In \"{file}\": {message}"), None)
+            }
+        };
+
+        let span = span.filter(|span| span.line > 0).unwrap_or(fallback);
+        let diagnostic = Json::object([
+            ("range", self.range(span)),
+            ("severity", Json::from(1)),
+            ("source", Json::from("black")),
+            ("message", Json::from(message)),
+        ]);
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// The LSP position of a 1-based line and column, counting UTF-16 code units
+    fn position(&self, line: usize, col: usize) -> Json {
+        let text = self.text.lines().nth(line.saturating_sub(1)).unwrap_or("");
+        let before = text.get(..col.saturating_sub(1)).unwrap_or(text);
+        Json::object([
+            ("line", Json::from(line.saturating_sub(1))),
+            ("character", Json::from(before.encode_utf16().count())),
+        ])
+    }
+
+    /// The LSP range of a span
+    fn range(&self, span: Span) -> Json {
+        Json::object([
+            ("start", self.position(span.line, span.col)),
+            ("end", self.position(span.end_line, span.end_col)),
+        ])
+    }
+
+    /// 1-based line and column of an LSP position
+    fn location(&self, position: &Json) -> Option<(usize, usize)> {
+        let line = position.get("line")?.as_usize()?;
+        let character = position.get("character")?.as_usize()?;
+        let text = self.text.lines().nth(line).unwrap_or("");
+
+        let mut units = 0;
+        let mut col = text.len() + 1;
+        for (i, c) in text.char_indices() {
+            if units >= character {
+                col = i + 1;
+                break;
+            }
+            units += c.len_utf16();
+        }
+        Some((line + 1, col))
+    }
+
+    /// Position of the token at an LSP position. A cursor right after a name is on the name
+    fn token_at(&self, position: &Json) -> Option<usize> {
+        let (line, col) = self.location(position)?;
+        let mut found = (0..self.tokens.len()).filter(|&i| {
+            let span = self.spans[i];
+            span.line == line && span.col <= col && col <= span.end_col
+        });
+        let first = found.next()?;
+        Some(
+            found
+                .chain([first])
+                .find(|&i| matches!(self.tokens[i], Token::Identifier(_)))
+                .unwrap_or(first),
+        )
+    }
+
+    /// The symbol a name refers to, the innermost one in scope. Names after a `.` are fields or
+    /// items of modules, which aren't looked up
+    fn symbol(&self, token: usize) -> Option<&Symbol> {
+        let Token::Identifier(name) = &self.tokens[token] else {
+            return None;
+        };
+        if token > 0 && self.tokens[token - 1] == Token::Dot {
+            return None;
+        }
+        self.symbols
+            .iter()
+            .filter(|symbol| {
+                symbol.name == *name && (symbol.token == token || symbol.scope.contains(&token))
+            })
+            .max_by_key(|symbol| (symbol.token == token, symbol.scope.start))
+    }
+
+    fn hover(&self, position: &Json) -> Json {
+        let Some(token) = self.token_at(position) else {
+            return Json::Null;
+        };
+        let detail = match (&self.tokens[token], self.symbol(token)) {
+            (_, Some(symbol)) => symbol.detail.clone(),
+            (Token::Identifier(name), None) if builtin(name).is_some() => {
+                format!("builtin fn {name}")
+            }
+            _ => return Json::Null,
+        };
+        Json::object([
+            (
+                "contents",
+                Json::object([
+                    ("kind", Json::from("markdown")),
+                    ("value", Json::from(format!("```black\n{detail}\n```"))),
+                ]),
+            ),
+            ("range", self.range(self.spans[token])),
+        ])
+    }
+
+    fn definition(&self, uri: &str, position: &Json) -> Json {
+        match self.token_at(position).and_then(|token| self.symbol(token)) {
+            Some(symbol) => Json::object([
+                ("uri", Json::from(uri)),
+                ("range", self.range(self.spans[symbol.token])),
+            ]),
+            None => Json::Null,
+        }
+    }
+
+    /// Names in scope at the position, then builtins, keywords and any other name used in the
+    /// document, which still works when the document doesn't parse
+    fn completion(&self, position: &Json) -> Json {
+        let before = self
+            .location(position)
+            .map(|(line, col)| {
+                self.spans
+                    .partition_point(|span| (span.line, span.col) < (line, col))
+            })
+            .unwrap_or(0);
+
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        let mut visible: Vec<&Symbol> = self
+            .symbols
+            .iter()
+            .filter(|symbol| symbol.scope.start <= before && before <= symbol.scope.end)
+            .collect();
+        visible.sort_by_key(|symbol| std::cmp::Reverse(symbol.scope.start));
+        for symbol in visible {
+            if seen.insert(symbol.name.as_str()) {
+                items.push(completion_item(
+                    &symbol.name,
+                    symbol.kind.completion_kind(),
+                    &symbol.detail,
+                ));
+            }
+        }
+        for builtin in BUILTINS {
+            if seen.insert(builtin.name) {
+                items.push(completion_item(builtin.name, 3, "builtin"));
+            }
+        }
+        for keyword in KEYWORDS {
+            if seen.insert(keyword) {
+                items.push(completion_item(keyword, 14, "keyword"));
+            }
+        }
+
+        let words: Vec<&str> = if self.tokens.is_empty() {
+            self.text
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .filter(|word| word.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+                .collect()
+        } else {
+            self.tokens
+                .iter()
+                .filter_map(|token| match token {
+                    Token::Identifier(name) => Some(name.as_str()),
+                    _ => None,
+                })
+                .collect()
+        };
+        for word in words {
+            if seen.insert(word) {
+                items.push(completion_item(word, 1, ""));
+            }
+        }
+
+        Json::Array(items)
+    }
+
+    /// A single edit replacing the whole text with the formatted one, no edits when it's
+    /// already formatted. `null` when the text doesn't parse
+    fn formatting(&self) -> Json {
+        match format(&self.text) {
+            Ok(formatted) if formatted == self.text => Json::Array(Vec::new()),
+            Ok(formatted) => {
+                let end = Json::object([
+                    ("line", Json::from(self.text.lines().count() + 1)),
+                    ("character", Json::from(0)),
+                ]);
+                let start = Json::object([("line", Json::from(0)), ("character", Json::from(0))]);
+                Json::Array(vec![Json::object([
+                    ("range", Json::object([("start", start), ("end", end)])),
+                    ("newText", Json::from(formatted)),
+                ])])
+            }
+            Err(_) => Json::Null,
+        }
+    }
+}
+
+/// Location of the first line of a top-level statement, by its index in the AST
+fn statement_span(tokens: &[Token], spans: &[Span], line_starts: &[usize], node: usize) -> Span {
+    let line_starts: HashSet<usize> = line_starts.iter().copied().collect();
+    let mut depth = 0;
+    let mut starts = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if depth == 0 && line_starts.contains(&i) {
+            starts.push(i);
+        }
+        match token {
+            Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+            Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+            _ => {}
+        }
+    }
+
+    let Some(&start) = starts.get(node) else {
+        return Span::new(1, 1, 0);
+    };
+    let end = (start..tokens.len())
+        .take_while(|&i| spans[i].line == spans[start].line)
+        .last()
+        .unwrap_or(start);
+    spans[start].to(&spans[end])
+}
+
+fn completion_item(label: &str, kind: usize, detail: &str) -> Json {
+    Json::object([
+        ("label", Json::from(label)),
+        ("kind", Json::from(kind)),
+        ("detail", Json::from(detail)),
+    ])
+}
+
+/// Collects the names declared by a program along with their scopes. Locations of the names are
+/// found in the tokens, the AST only knows where the values are
+struct SymbolIndex<'a> {
+    tokens: &'a [Token],
+    spans: &'a [Span],
+    /// Positions of the `{` tokens opening a code block, in order
+    blocks: &'a [usize],
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolIndex<'_> {
+    fn add(
+        &mut self,
+        name: &str,
+        kind: SymbolKind,
+        token: usize,
+        scope: Range<usize>,
+        detail: String,
+    ) {
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            kind,
+            token,
+            scope,
+            detail,
+        });
+    }
+
+    fn statements(&mut self, statements: &[Expr]) {
+        for statement in statements {
+            self.expr(statement);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let everywhere = 0..self.tokens.len();
+        match expr {
+            Expr::VariableDeclaration(var_decl) => {
+                self.expr(&var_decl.value);
+                let name = &var_decl.identifier;
+                let detail = match &var_decl.typ {
+                    Some(typ) => format!("let {typ} {name}"),
+                    None => format!("let {name}"),
+                };
+                let token = self.name_before(name, var_decl.value_span);
+                let start = self.token_after(var_decl.value_span);
+                let scope = start..self.block_end(start);
+                self.add(name, SymbolKind::Variable, token, scope, detail);
+            }
+            Expr::Destructure(destructure) => {
+                self.expr(&destructure.value);
+                let start = self.token_after(destructure.value_span);
+                for name in destructure.names.iter().filter(|name| *name != "_") {
+                    let token = self.name_before(name, destructure.value_span);
+                    let scope = start..self.block_end(start);
+                    self.add(
+                        name,
+                        SymbolKind::Variable,
+                        token,
+                        scope,
+                        format!("let {name}"),
+                    );
+                }
+            }
+            Expr::FuncDecl(func_decl) => {
+                let token = self.token_at(func_decl.span);
+                let params: Vec<String> = func_decl
+                    .params
+                    .iter()
+                    .map(|(name, typ)| format!("{typ} {name}"))
+                    .collect();
+                let returns = match func_decl.return_type {
+                    Type::None => String::new(),
+                    ref typ => format!(" -> {typ}"),
+                };
+                let detail = format!("fn {}({}){returns}", func_decl.name, params.join(", "));
+                self.add(
+                    &func_decl.name,
+                    SymbolKind::Function,
+                    token,
+                    everywhere,
+                    detail,
+                );
+
+                for (name, typ) in &func_decl.params {
+                    let param = self.name_after(name, token + 1);
+                    let scope = param..self.body_end(param);
+                    self.add(
+                        name,
+                        SymbolKind::Variable,
+                        param,
+                        scope,
+                        format!("{typ} {name}"),
+                    );
+                }
+                self.statements(&func_decl.block);
+            }
+            Expr::StructDecl(struct_decl) => {
+                let fields: Vec<String> = struct_decl
+                    .fields
+                    .iter()
+                    .map(|(name, typ)| format!("{typ} {name}"))
+                    .collect();
+                let detail = format!("struct {} {{ {} }}", struct_decl.name, fields.join(", "));
+                let token = self.token_at(struct_decl.span);
+                self.add(
+                    &struct_decl.name,
+                    SymbolKind::Struct,
+                    token,
+                    everywhere,
+                    detail,
+                );
+            }
+            Expr::EnumDecl(enum_decl) => {
+                let variants: Vec<String> = enum_decl
+                    .variants
+                    .iter()
+                    .map(|(name, types)| match types.is_empty() {
+                        true => name.clone(),
+                        false => {
+                            let types: Vec<String> =
+                                types.iter().map(ToString::to_string).collect();
+                            format!("{name}({})", types.join(", "))
+                        }
+                    })
+                    .collect();
+                let detail = format!("enum {} {{ {} }}", enum_decl.name, variants.join(", "));
+                let token = self.token_at(enum_decl.span);
+                self.add(&enum_decl.name, SymbolKind::Enum, token, everywhere, detail);
+            }
+            Expr::IfStatement(if_statement) => {
+                self.expr(&if_statement.condition);
+                if let Some(name) = &if_statement.binding {
+                    let token = self.name_before(name, if_statement.span);
+                    let start = self.token_after(if_statement.span);
+                    let scope = start..self.body_end(start);
+                    self.add(
+                        name,
+                        SymbolKind::Variable,
+                        token,
+                        scope,
+                        format!("if let {name}"),
+                    );
+                }
+                self.statements(&if_statement.block);
+                self.statements(&if_statement.else_block);
+            }
+            Expr::For(for_loop) => {
+                self.expr(&for_loop.iterable);
+                let start = self.token_at(for_loop.span);
+                for name in [Some(&for_loop.variable), for_loop.value_variable.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    let token = self.name_after(name, start);
+                    let scope = token..self.body_end(token);
+                    self.add(
+                        name,
+                        SymbolKind::Variable,
+                        token,
+                        scope,
+                        format!("for {name}"),
+                    );
+                }
+                self.statements(&for_loop.block);
+            }
+            Expr::Match(match_expr) => {
+                self.expr(&match_expr.value);
+                for arm in &match_expr.arms {
+                    let start = self.token_at(arm.span);
+                    for name in arm.pattern.bindings() {
+                        let token = self.name_after(&name, start);
+                        let scope = token..self.arm_end(token);
+                        self.add(&name, SymbolKind::Variable, token, scope, name.clone());
+                    }
+                    self.expr(&arm.body);
+                }
+            }
+            Expr::TryCatch(try_catch) => {
+                self.statements(&try_catch.block);
+                if let Some(name) = &try_catch.variable {
+                    let token = self.token_at(try_catch.span);
+                    let scope = token..self.body_end(token);
+                    self.add(
+                        name,
+                        SymbolKind::Variable,
+                        token,
+                        scope,
+                        format!("str {name}"),
+                    );
+                }
+                self.statements(&try_catch.catch_block);
+            }
+            Expr::Block(block) => self.statements(block),
+            Expr::Return(ret) => self.expr(&ret.value),
+            Expr::Assignment(assignment) => self.expr(&assignment.value),
+            Expr::FuncCall(call) => self.statements(&call.arguments),
+            Expr::Coalesce(coalesce) => {
+                self.expr(&coalesce.value);
+                self.expr(&coalesce.default);
+            }
+            _ => {}
+        }
+    }
+
+    /// Position of the token starting at the location
+    fn token_at(&self, span: Span) -> usize {
+        self.spans
+            .partition_point(|s| (s.line, s.col) < (span.line, span.col))
+    }
+
+    /// Position of the first token after the location
+    fn token_after(&self, span: Span) -> usize {
+        self.spans
+            .partition_point(|s| (s.line, s.col) < (span.end_line, span.end_col))
+    }
+
+    /// Position of the last occurrence of the name before the location
+    fn name_before(&self, name: &str, span: Span) -> usize {
+        let end = self.token_at(span);
+        (0..end)
+            .rev()
+            .find(|&i| matches!(&self.tokens[i], Token::Identifier(id) if id == name))
+            .unwrap_or(end)
+    }
+
+    /// Position of the first occurrence of the name at or after the position
+    fn name_after(&self, name: &str, start: usize) -> usize {
+        (start..self.tokens.len())
+            .find(|&i| matches!(&self.tokens[i], Token::Identifier(id) if id == name))
+            .unwrap_or(start)
+    }
+
+    /// Position of the bracket closing the innermost group around the position, the end of
+    /// the file at the top level
+    fn block_end(&self, start: usize) -> usize {
+        let mut depth = 0;
+        for i in start..self.tokens.len() {
+            match self.tokens[i] {
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+                Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+                _ => {}
+            }
+            if depth < 0 {
+                return i;
+            }
+        }
+        self.tokens.len()
+    }
+
+    /// Position of the `}` closing the first code block after the position
+    fn body_end(&self, start: usize) -> usize {
+        match self.blocks.iter().find(|&&open| open >= start) {
+            Some(open) => self.block_end(open + 1),
+            None => self.tokens.len(),
+        }
+    }
+
+    /// Position of the `,` ending the match arm at the position, or of the `}` closing the arms
+    fn arm_end(&self, start: usize) -> usize {
+        let mut depth = 0;
+        for i in start..self.tokens.len() {
+            match self.tokens[i] {
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+                Token::RightParen | Token::RightBracket | Token::RightBrace => depth -= 1,
+                Token::Comma if depth == 0 => return i,
+                _ => {}
+            }
+            if depth < 0 {
+                return i;
+            }
+        }
+        self.tokens.len()
+    }
+}
+
+/// Language server, answering the messages of an editor
+#[derive(Debug, Default)]
+pub struct Server {
+    /// Open documents by their URIs
+    documents: HashMap<String, Document>,
+    /// Packages of the projects of the open documents, by the directories of their manifests
+    packages: HashMap<PathBuf, Vec<(String, PathBuf)>>,
+    /// Whether a shutdown was requested, exiting is only clean after one
+    pub shutdown: bool,
+}
+
+impl Server {
+    /// Handles a message, returning the messages to send back: the response to a request, and
+    /// diagnostics when a document changes
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string();
+
+        let result = match method {
+            "initialize" => initialize(),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                return vec![self.update(&uri, text)];
+            }
+            "textDocument/didChange" => {
+                // The whole text is sent on every change
+                let text = params
+                    .get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or("");
+                return vec![self.update(&uri, text)];
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![diagnostics(&uri, Vec::new())];
+            }
+            "textDocument/hover"
+            | "textDocument/definition"
+            | "textDocument/completion"
+            | "textDocument/formatting" => {
+                let position = params.get("position").unwrap_or(&Json::Null);
+                match self.documents.get(&uri) {
+                    None => Json::Null,
+                    Some(document) => match method {
+                        "textDocument/hover" => document.hover(position),
+                        "textDocument/definition" => document.definition(&uri, position),
+                        "textDocument/completion" => document.completion(position),
+                        _ => document.formatting(),
+                    },
+                }
+            }
+            // Other notifications aren't answered
+            _ if message.get("id").is_none() => return Vec::new(),
+            _ => {
+                return vec![error_response(
+                    message.get("id").cloned().unwrap_or(Json::Null),
+                    METHOD_NOT_FOUND,
+                    &format!("Unknown method `{method}`"),
+                )]
+            }
+        };
+
+        match message.get("id") {
+            Some(id) => vec![Json::object([
+                ("jsonrpc", Json::from("2.0")),
+                ("id", id.clone()),
+                ("result", result),
+            ])],
+            None => Vec::new(),
+        }
+    }
+
+    /// Analyzes the new text of a document, returning its diagnostics
+    fn update(&mut self, uri: &str, text: &str) -> Json {
+        let path = file_path(uri);
+        let packages = match &path {
+            Some(path) => self.project_packages(path),
+            None => Vec::new(),
+        };
+        let document = Document::analyze(text, path.as_deref(), &packages);
+        let message = diagnostics(uri, document.diagnostics.clone());
+        self.documents.insert(uri.to_string(), document);
+        message
+    }
+
+    /// Packages importable by a file of a project. Projects are loaded once, like a build does
+    fn project_packages(&mut self, path: &Path) -> Vec<(String, PathBuf)> {
+        let Some(root) = path
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.join(MANIFEST).is_file())
+        else {
+            return Vec::new();
+        };
+        self.packages
+            .entry(root.to_path_buf())
+            .or_insert_with(|| {
+                Project::load(root)
+                    .map(|project| project.packages)
+                    .unwrap_or_default()
+            })
+            .clone()
+    }
+}
+
+/// Result of the `initialize` request: what the server can do
+fn initialize() -> Json {
+    Json::object([
+        (
+            "capabilities",
+            Json::object([
+                // Documents are synced by sending their whole text
+                ("textDocumentSync", Json::from(1)),
+                ("hoverProvider", Json::from(true)),
+                ("definitionProvider", Json::from(true)),
+                ("completionProvider", Json::object([])),
+                ("documentFormattingProvider", Json::from(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Json::object([
+                ("name", Json::from("black")),
+                ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+            ]),
+        ),
+    ])
+}
+
+fn diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object([
+                ("uri", Json::from(uri)),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: f64, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        (
+            "error",
+            Json::object([
+                ("code", Json::Number(code)),
+                ("message", Json::from(message)),
+            ]),
+        ),
+    ])
+}
+
+/// Path of a `file://` URI
+fn file_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = path
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// Reads a message: `Content-Length` header, blank line and JSON body. `None` once the input is
+/// closed
+pub fn read_message(input: &mut impl BufRead) -> Option<Result<Json, String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(length) = length else {
+        return Some(Err("Missing Content-Length header".to_string()));
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).ok()?;
+    Some(
+        String::from_utf8(body)
+            .map_err(|_| "Message isn't valid UTF-8".to_string())
+            .and_then(|body| Json::parse(&body)),
+    )
+}
+
+/// Writes a message with its `Content-Length` header
+pub fn write_message(output: &mut impl Write, message: &Json) {
+    let body = message.to_string();
+    // Nothing can be done when the editor stops listening, the server exits with the input
+    let _ = write!(output, "Content-Length: {}\r\n\r\n{body}", body.len());
+    let _ = output.flush();
+}
+
+/// Runs the language server over stdin and stdout until the editor asks it to exit. Returns the
+/// exit status: failing when exiting without a shutdown request first
+pub fn serve() -> i32 {
+    let mut server = Server::default();
+    let mut input = stdin().lock();
+    let mut output = stdout().lock();
+
+    while let Some(message) = read_message(&mut input) {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                write_message(&mut output, &error_response(Json::Null, PARSE_ERROR, &err));
+                continue;
+            }
+        };
+        if message.get("method").and_then(Json::as_str) == Some("exit") {
+            return i32::from(!server.shutdown);
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply);
+        }
+    }
+
+    1
+}
@@ -0,0 +1,333 @@
+use crate::{
+    builtins::builtin,
+    parser::{lexer_with_spans, preprocess, Ast, Expr, Import, Parser, Pattern, Type},
+    utils::ErrorType,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{canonicalize, read_to_string},
+    path::{Path, PathBuf},
+};
+
+/// Loads the modules imported by a program. Every module is parsed once, however many files
+/// import it. Items declared by a module are prefixed with a name unique to the module
+/// (`math.square`), so that the whole program can be checked, interpreted and compiled as one
+#[derive(Debug, Default)]
+pub struct Loader {
+    /// Source files given on the command line, which can be imported by name from any file
+    given: HashMap<String, PathBuf>,
+    /// Unique names of the loaded modules by the canonical paths of their files
+    loaded: HashMap<PathBuf, String>,
+    /// Canonical paths of the files being loaded, from the main file to the module being loaded
+    stack: Vec<PathBuf>,
+}
+
+impl Loader {
+    /// Creates a loader. The given source files can be imported by their name from any file, and
+    /// so can the entry files of the packages by the names of the packages
+    pub fn new(modules: &[PathBuf], packages: &[(String, PathBuf)]) -> Self {
+        let given = modules
+            .iter()
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?;
+                Some((name.to_string(), path.clone()))
+            })
+            .chain(packages.iter().cloned())
+            .collect();
+
+        Self {
+            given,
+            ..Self::default()
+        }
+    }
+
+    /// Loads every module imported by the main file of the program, directly or not
+    pub fn load(&mut self, ast: &mut Ast, main: &Path) -> Result<(), ErrorType> {
+        self.stack
+            .push(canonicalize(main).unwrap_or(main.to_path_buf()));
+        let result = self.resolve(ast, main, None);
+        self.stack.pop();
+        result
+    }
+
+    /// Loads the modules imported by a file and qualifies the names used by it. `module` is the
+    /// unique name of the module the file is, `None` for the main file
+    fn resolve(
+        &mut self,
+        ast: &mut Ast,
+        file: &Path,
+        module: Option<&str>,
+    ) -> Result<(), ErrorType> {
+        let mut modules = HashMap::new();
+        for node in ast.iter_mut() {
+            let Expr::Import(import) = node else {
+                continue;
+            };
+            if modules.contains_key(&import.name) {
+                return Err(ErrorType::ImportError(
+                    format!("Module `{}` is already imported", import.name),
+                    import.span,
+                ));
+            }
+            let unique = self.import(import, file)?;
+            modules.insert(import.name.clone(), unique);
+        }
+
+        // Builtins can't be redefined, the checker reports it with the original name
+        let items = match module {
+            Some(_) => ast
+                .iter()
+                .filter_map(|node| match node {
+                    Expr::FuncDecl(decl) if builtin(&decl.name).is_none() => Some(&decl.name),
+                    Expr::StructDecl(decl) => Some(&decl.name),
+                    Expr::EnumDecl(decl) => Some(&decl.name),
+                    _ => None,
+                })
+                .cloned()
+                .collect(),
+            None => HashSet::new(),
+        };
+        let renamer = Renamer {
+            module,
+            items,
+            modules,
+        };
+        ast.iter_mut().for_each(|node| renamer.expr(node));
+
+        Ok(())
+    }
+
+    /// Finds the file of an imported module and loads it, unless it's already loaded. Returns the
+    /// unique name of the module
+    fn import(&mut self, import: &mut Import, importer: &Path) -> Result<String, ErrorType> {
+        let dir = importer.parent().unwrap_or(Path::new(""));
+        let path = match (&import.path, self.given.get(&import.name)) {
+            (Some(path), _) => dir.join(path),
+            (None, Some(path)) => path.clone(),
+            (None, None) => dir.join(format!("{}.blk", import.name)),
+        };
+        let Ok(canonical) = canonicalize(&path) else {
+            return Err(ErrorType::ImportError(
+                format!(
+                    "Module `{}` not found, expected at \"{}\"",
+                    import.name,
+                    path.display()
+                ),
+                import.span,
+            ));
+        };
+
+        if let Some(start) = self.stack.iter().position(|file| *file == canonical) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|file| {
+                    file.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into()
+                })
+                .collect();
+            return Err(ErrorType::ImportError(
+                format!("Circular import: {}", cycle.join(" -> ")),
+                import.span,
+            ));
+        }
+
+        import.file = path.display().to_string();
+        import.module = match self.loaded.get(&canonical) {
+            Some(unique) => unique.clone(),
+            None => {
+                let unique = self.unique_name(&import.name);
+                self.loaded.insert(canonical.clone(), unique.clone());
+                self.stack.push(canonical);
+                let body = self.parse_module(&path, &unique);
+                self.stack.pop();
+                import.body = body.map_err(|err| err.in_file(&import.file))?;
+                unique
+            }
+        };
+
+        Ok(import.module.clone())
+    }
+
+    /// Parses the file of a module and loads its own imports
+    fn parse_module(&mut self, path: &Path, unique: &str) -> Result<Ast, ErrorType> {
+        let source = read_to_string(path).map_err(|_| {
+            ErrorType::Generic(format!("Could not read module \"{}\"", path.display()))
+        })?;
+        let source = preprocess(&source);
+        let (tokens, spans) = lexer_with_spans(&source)?;
+        let mut ast = Parser::with_spans(&tokens, &spans).parse()?;
+        self.resolve(&mut ast, path, Some(unique))?;
+
+        Ok(ast)
+    }
+
+    /// Returns a name for a module which no other loaded module has, modules in different
+    /// directories can have the same name
+    fn unique_name(&self, name: &str) -> String {
+        let taken: HashSet<&String> = self.loaded.values().collect();
+        let mut unique = name.to_string();
+        let mut n = 1;
+        while taken.contains(&unique) {
+            n += 1;
+            unique = format!("{name}_{n}");
+        }
+
+        unique
+    }
+}
+
+/// Qualifies the names used by a file. Module names in qualified names are replaced with the
+/// unique names of the modules, and in a module the names of its own items get prefixed
+struct Renamer<'a> {
+    /// Unique name of the module the file is, `None` for the main file
+    module: Option<&'a str>,
+    /// Functions, structs and enums declared by the file
+    items: HashSet<String>,
+    /// Unique names of the imported modules by the names they're imported as
+    modules: HashMap<String, String>,
+}
+
+impl Renamer<'_> {
+    /// Qualifies the name of a function, a struct or an enum
+    fn name(&self, name: &mut String) {
+        if let Some((module, item)) = name.split_once('.') {
+            if let Some(unique) = self.modules.get(module) {
+                *name = format!("{unique}.{item}");
+            }
+        } else if let Some(module) = self.module {
+            if self.items.contains(name) {
+                *name = format!("{module}.{name}");
+            }
+        }
+    }
+
+    /// Qualifies the struct and enum names used by a type
+    fn typ(&self, typ: &mut Type) {
+        match typ {
+            Type::Struct(name) | Type::Enum(name) => self.name(name),
+            Type::List(elem) | Type::Optional(elem) => self.typ(elem),
+            Type::Map(key, value) => {
+                self.typ(key);
+                self.typ(value);
+            }
+            Type::Tuple(elems) => elems.iter_mut().for_each(|elem| self.typ(elem)),
+            _ => {}
+        }
+    }
+
+    /// Qualifies the names used by every statement of a block
+    fn block(&self, block: &mut [Expr]) {
+        block.iter_mut().for_each(|node| self.expr(node));
+    }
+
+    /// Qualifies the enum names used by a pattern
+    fn pattern(&self, pattern: &mut Pattern) {
+        if let Pattern::Variant {
+            enum_name, fields, ..
+        } = pattern
+        {
+            self.name(enum_name);
+            fields.iter_mut().for_each(|field| self.pattern(field));
+        }
+    }
+
+    /// Qualifies the names used by an expression. Every kind of expression is listed, so that new
+    /// ones aren't forgotten
+    fn expr(&self, expr: &mut Expr) {
+        match expr {
+            Expr::FuncCall(func_call) => {
+                self.name(&mut func_call.name);
+                self.block(&mut func_call.arguments);
+            }
+            Expr::IfStatement(if_statement) => {
+                self.expr(&mut if_statement.condition);
+                self.block(&mut if_statement.block);
+                self.block(&mut if_statement.else_block);
+            }
+            Expr::VariableDeclaration(var_decl) => {
+                if let Some(typ) = &mut var_decl.typ {
+                    self.typ(typ);
+                }
+                self.expr(&mut var_decl.value);
+            }
+            Expr::BinExpr(bin_expr) => {
+                self.expr(&mut bin_expr.lhs);
+                self.expr(&mut bin_expr.rhs);
+            }
+            Expr::Block(block) | Expr::List(block, _) | Expr::Tuple(block) => self.block(block),
+            Expr::Return(ret) => self.expr(&mut ret.value),
+            Expr::Map(entries, _) => entries.iter_mut().for_each(|(key, value)| {
+                self.expr(key);
+                self.expr(value);
+            }),
+            Expr::Index(index) => {
+                self.expr(&mut index.target);
+                self.expr(&mut index.index);
+            }
+            Expr::Assignment(assignment) => {
+                self.expr(&mut assignment.target);
+                self.expr(&mut assignment.value);
+            }
+            Expr::For(for_loop) => {
+                self.expr(&mut for_loop.iterable);
+                self.block(&mut for_loop.block);
+            }
+            Expr::StructDecl(decl) => {
+                self.name(&mut decl.name);
+                decl.fields.iter_mut().for_each(|(_, typ)| self.typ(typ));
+            }
+            Expr::StructLiteral(literal) => {
+                self.name(&mut literal.name);
+                literal
+                    .fields
+                    .iter_mut()
+                    .for_each(|(_, value)| self.expr(value));
+            }
+            Expr::Field(field) => self.expr(&mut field.target),
+            Expr::FuncDecl(decl) => {
+                self.name(&mut decl.name);
+                decl.params.iter_mut().for_each(|(_, typ)| self.typ(typ));
+                self.typ(&mut decl.return_type);
+                self.block(&mut decl.block);
+            }
+            Expr::EnumDecl(decl) => {
+                self.name(&mut decl.name);
+                decl.variants
+                    .iter_mut()
+                    .flat_map(|(_, types)| types)
+                    .for_each(|typ| self.typ(typ));
+            }
+            Expr::EnumVariant(variant) => {
+                self.name(&mut variant.enum_name);
+                self.block(&mut variant.arguments);
+            }
+            Expr::Match(match_expr) => {
+                self.expr(&mut match_expr.value);
+                for arm in &mut match_expr.arms {
+                    self.pattern(&mut arm.pattern);
+                    self.expr(&mut arm.body);
+                }
+            }
+            Expr::TupleIndex(index) => self.expr(&mut index.target),
+            Expr::Destructure(destructure) => self.expr(&mut destructure.value),
+            Expr::Coalesce(coalesce) => {
+                self.expr(&mut coalesce.value);
+                self.expr(&mut coalesce.default);
+            }
+            Expr::TryCatch(try_catch) => {
+                self.block(&mut try_catch.block);
+                self.block(&mut try_catch.catch_block);
+            }
+            // Code of imported modules is already qualified
+            Expr::Import(_) => {}
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::Identifier(_)
+            | Expr::StringLiteral(_)
+            | Expr::None => {}
+        }
+    }
+}
@@ -0,0 +1,730 @@
+use crate::utils::ErrorType;
+use std::{collections::HashMap, fmt::Write};
+
+/// Registers of the integer arguments of calls, in order
+const INT_ARGS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+/// Number of floating point arguments passed in `%xmm0` to `%xmm7`
+const FLOAT_ARGS: usize = 8;
+
+/// Translates the QBE IR generated by the compiler to x86-64 assembly for the System V ABI, in
+/// the syntax of the GNU assembler. Every temporary lives in a stack slot of its own: operands
+/// are loaded into scratch registers, and results stored back right away.
+///
+/// Structs (QBE aggregates) are passed to functions as a pointer to a copy, and returned through
+/// a hidden pointer to memory of the caller, like the MEMORY class of the ABI. Only functions
+/// generated by the compiler take or return structs, so both sides of such calls agree
+pub fn compile(ir: &str) -> Result<String, ErrorType> {
+    let mut native = Native::default();
+    let mut lines = ir.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(typ) = line.strip_prefix("type :") {
+            native.aggregate(typ)?;
+        } else if line.starts_with("data ") || line.starts_with("export data ") {
+            native.data(line)?;
+        } else if line.starts_with("function ") || line.starts_with("export function ") {
+            let mut body = Vec::new();
+            for line in lines.by_ref() {
+                if line.trim() == "}" {
+                    break;
+                }
+                body.push(line.trim());
+            }
+            native.function(line, &body)?;
+        } else {
+            return Err(unsupported(line));
+        }
+    }
+
+    Ok(format!(
+        "{}{}.section .note.GNU-stack,\"\",@progbits\n",
+        native.data, native.text
+    ))
+}
+
+fn unsupported(line: &str) -> ErrorType {
+    ErrorType::Generic(format!(
+        "The native backend doesn't support `{line}`. This is a bug."
+    ))
+}
+
+/// Name of a global symbol in assembly
+fn symbol(name: &str) -> &str {
+    name.trim_start_matches('$')
+}
+
+/// Splits a list of arguments on commas outside of string literals
+fn split_args(list: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (i, c) in list.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                args.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(list[start..].trim());
+    args.retain(|arg| !arg.is_empty());
+    args
+}
+
+/// Bytes of a string literal of the IR, without its quotes
+fn string_bytes(literal: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c) => c,
+                None => break,
+            },
+            c => c,
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    bytes
+}
+
+#[derive(Default)]
+struct Native {
+    /// Sizes of the aggregate types
+    types: HashMap<String, usize>,
+    data: String,
+    text: String,
+    /// Number of functions translated, giving their labels unique names
+    functions: usize,
+}
+
+impl Native {
+    /// Records the size of an aggregate type, `name = { l, d, :inner }`
+    fn aggregate(&mut self, typ: &str) -> Result<(), ErrorType> {
+        let (name, items) = typ.split_once('=').ok_or_else(|| unsupported(typ))?;
+        let items = items.trim().trim_start_matches('{').trim_end_matches('}');
+        let mut size = 0;
+        for item in split_args(items) {
+            size += match item {
+                "l" | "d" => 8,
+                "w" | "s" => 4,
+                "h" => 2,
+                "b" => 1,
+                _ => self.size_of(item)?,
+            };
+        }
+        self.types.insert(name.trim().to_string(), size.max(8));
+        Ok(())
+    }
+
+    fn size_of(&self, typ: &str) -> Result<usize, ErrorType> {
+        let name = typ.trim_start_matches(':');
+        self.types
+            .get(name)
+            .copied()
+            .ok_or_else(|| unsupported(typ))
+    }
+
+    /// Emits a data definition, `data $name = { b "text", b 0, l $other }`
+    fn data(&mut self, line: &str) -> Result<(), ErrorType> {
+        let exported = line.starts_with("export ");
+        let (name, items) = line
+            .trim_start_matches("export ")
+            .trim_start_matches("data ")
+            .split_once('=')
+            .ok_or_else(|| unsupported(line))?;
+        let items = items.trim().trim_start_matches('{').trim_end_matches('}');
+
+        let name = symbol(name.trim());
+        let mut asm = String::from(".data\n.balign 8\n");
+        if exported {
+            let _ = writeln!(asm, ".globl {name}");
+        }
+        let _ = writeln!(asm, "{name}:");
+        for item in split_args(items) {
+            let (class, values) = item.split_once(' ').ok_or_else(|| unsupported(line))?;
+            let directive = match class {
+                "b" => ".byte",
+                "h" => ".short",
+                "w" => ".int",
+                "l" => ".quad",
+                "z" => ".zero",
+                _ => return Err(unsupported(line)),
+            };
+            let value = values.trim();
+            if let Some(literal) = value.strip_prefix('"') {
+                let bytes = string_bytes(literal.strip_suffix('"').unwrap_or(literal));
+                for chunk in bytes.chunks(16) {
+                    let chunk: Vec<_> = chunk.iter().map(u8::to_string).collect();
+                    let _ = writeln!(asm, "  .byte {}", chunk.join(","));
+                }
+            } else {
+                let _ = writeln!(asm, "  {directive} {}", symbol(value));
+            }
+        }
+        self.data.push_str(&asm);
+        Ok(())
+    }
+
+    /// Translates a function, given its header and the lines of its body
+    fn function(&mut self, header: &str, body: &[&str]) -> Result<(), ErrorType> {
+        let exported = header.starts_with("export ");
+        let signature = header
+            .trim_start_matches("export ")
+            .trim_start_matches("function ")
+            .trim_end_matches('{')
+            .trim();
+        let (head, params) = signature
+            .split_once('(')
+            .ok_or_else(|| unsupported(header))?;
+        let params = params.trim_end().trim_end_matches(')');
+        let (ret, name) = match head.trim().rsplit_once(' ') {
+            Some((ret, name)) => (ret.trim(), name.trim()),
+            None => ("", head.trim()),
+        };
+
+        self.functions += 1;
+        let mut function = Function {
+            native: self,
+            id: format!(".L{}_", self.functions),
+            ret,
+            slots: HashMap::new(),
+            frame: 0,
+            asm: String::new(),
+        };
+        function.params(params)?;
+        for line in body {
+            function.line(line)?;
+        }
+
+        let Function { frame, asm, .. } = function;
+        let frame = frame.div_ceil(16) * 16;
+        let name = symbol(name);
+        if exported {
+            let _ = writeln!(self.text, ".globl {name}");
+        }
+        let _ = write!(
+            self.text,
+            ".text\n.balign 16\n{name}:\n  push %rbp\n  mov %rsp, %rbp\n  sub ${frame}, %rsp\n{asm}"
+        );
+        Ok(())
+    }
+}
+
+/// A function being translated
+struct Function<'a> {
+    native: &'a Native,
+    /// Prefix of the labels of its blocks
+    id: String,
+    /// Class of its return value, empty without one
+    ret: &'a str,
+    /// Offsets of the stack slots of temporaries from `%rbp`
+    slots: HashMap<String, usize>,
+    /// Size of the stack frame so far
+    frame: usize,
+    asm: String,
+}
+
+impl Function<'_> {
+    fn emit(&mut self, instruction: &str) {
+        self.asm.push_str("  ");
+        self.asm.push_str(instruction);
+        self.asm.push('\n');
+    }
+
+    /// Reserves memory in the stack frame, giving its offset from `%rbp`
+    fn reserve(&mut self, size: usize, align: usize) -> usize {
+        self.frame = (self.frame + size).div_ceil(align) * align;
+        self.frame
+    }
+
+    /// Stack slot of a temporary, as an operand
+    fn slot(&mut self, temp: &str) -> String {
+        let offset = match self.slots.get(temp) {
+            Some(offset) => *offset,
+            None => {
+                let offset = self.reserve(8, 8);
+                self.slots.insert(temp.to_string(), offset);
+                offset
+            }
+        };
+        format!("-{offset}(%rbp)")
+    }
+
+    /// Loads an integer operand (temporary, constant or symbol) into a 64-bit register
+    fn load(&mut self, operand: &str, reg: &str) -> Result<(), ErrorType> {
+        if operand.starts_with('%') {
+            let slot = self.slot(operand);
+            self.emit(&format!("mov {slot}, {reg}"));
+        } else if operand.starts_with('$') {
+            self.emit(&format!("lea {}(%rip), {reg}", symbol(operand)));
+        } else if let Some(float) = operand.strip_prefix("d_") {
+            let value: f64 = float.parse().map_err(|_| unsupported(operand))?;
+            self.emit(&format!("movabs ${}, {reg}", value.to_bits() as i64));
+        } else {
+            let value: i64 = operand.parse().map_err(|_| unsupported(operand))?;
+            match i32::try_from(value) {
+                Ok(_) => self.emit(&format!("mov ${value}, {reg}")),
+                Err(_) => self.emit(&format!("movabs ${value}, {reg}")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a floating point operand into an `%xmm` register
+    fn load_float(&mut self, operand: &str, reg: &str) -> Result<(), ErrorType> {
+        if operand.starts_with('%') {
+            let slot = self.slot(operand);
+            self.emit(&format!("movsd {slot}, {reg}"));
+        } else {
+            self.load(operand, "%rax")?;
+            self.emit(&format!("movq %rax, {reg}"));
+        }
+        Ok(())
+    }
+
+    fn store(&mut self, reg: &str, temp: &str) {
+        let slot = self.slot(temp);
+        self.emit(&format!("mov {reg}, {slot}"));
+    }
+
+    fn store_float(&mut self, reg: &str, temp: &str) {
+        let slot = self.slot(temp);
+        self.emit(&format!("movsd {reg}, {slot}"));
+    }
+
+    /// Copies `size` bytes from the address in `%rsi` to the one in `%rdi`
+    fn copy_memory(&mut self, size: usize) {
+        let mut offset = 0;
+        while offset + 8 <= size {
+            self.emit(&format!("mov {offset}(%rsi), %rax"));
+            self.emit(&format!("mov %rax, {offset}(%rdi)"));
+            offset += 8;
+        }
+        while offset < size {
+            self.emit(&format!("movb {offset}(%rsi), %al"));
+            self.emit(&format!("movb %al, {offset}(%rdi)"));
+            offset += 1;
+        }
+    }
+
+    /// Stores the parameters, passed in registers or on the stack, into their slots
+    fn params(&mut self, params: &str) -> Result<(), ErrorType> {
+        let (mut ints, mut floats, mut stack) = (0, 0, 16);
+
+        // Structs are returned through a pointer given as the first argument
+        if self.ret.starts_with(':') {
+            self.store(INT_ARGS[0], "%.ret");
+            ints += 1;
+        }
+
+        for param in split_args(params) {
+            let (class, name) = param.split_once(' ').ok_or_else(|| unsupported(param))?;
+            let float = class == "d";
+            let reg = if float && floats < FLOAT_ARGS {
+                floats += 1;
+                format!("%xmm{}", floats - 1)
+            } else if !float && ints < INT_ARGS.len() {
+                ints += 1;
+                INT_ARGS[ints - 1].to_string()
+            } else {
+                self.emit(&format!("mov {stack}(%rbp), %rax"));
+                stack += 8;
+                "%rax".to_string()
+            };
+
+            if let Some(typ) = class.strip_prefix(':') {
+                // The struct is copied, so that changes to it aren't seen by the caller
+                let size = self.native.size_of(typ)?;
+                let offset = self.reserve(size, 16);
+                self.emit(&format!("mov {reg}, %rsi"));
+                self.emit(&format!("lea -{offset}(%rbp), %rdi"));
+                self.copy_memory(size);
+                self.store("%rdi", name);
+            } else if reg.starts_with("%xmm") {
+                self.store_float(&reg, name);
+            } else {
+                self.store(&reg, name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Translates a line of a function body: a label or an instruction
+    fn line(&mut self, line: &str) -> Result<(), ErrorType> {
+        if let Some(label) = line.strip_prefix('@') {
+            let _ = writeln!(self.asm, "{}{label}:", self.id);
+            return Ok(());
+        }
+
+        match line.split_once('=') {
+            Some((dest, rest)) if dest.trim().starts_with('%') && !rest.starts_with('=') => {
+                let dest = dest.trim();
+                let (class, rest) = rest
+                    .trim()
+                    .split_once(' ')
+                    .ok_or_else(|| unsupported(line))?;
+                let (op, args) = rest.split_once(' ').unwrap_or((rest, ""));
+                self.instruction(line, dest, class, op, args.trim())
+            }
+            _ => {
+                let (op, args) = line.split_once(' ').unwrap_or((line, ""));
+                self.statement(line, op, args.trim())
+            }
+        }
+    }
+
+    /// Translates an instruction giving a value
+    fn instruction(
+        &mut self,
+        line: &str,
+        dest: &str,
+        class: &str,
+        op: &str,
+        args: &str,
+    ) -> Result<(), ErrorType> {
+        if op == "call" {
+            return self.call(line, args, Some((dest, class)));
+        }
+
+        let args = split_args(args);
+        let arg = |i: usize| args.get(i).copied().ok_or_else(|| unsupported(line));
+        let float = class == "d";
+
+        match op {
+            "copy" if float => {
+                self.load_float(arg(0)?, "%xmm0")?;
+                self.store_float("%xmm0", dest);
+            }
+            "copy" => {
+                self.load(arg(0)?, "%rax")?;
+                self.store("%rax", dest);
+            }
+            "add" | "sub" | "mul" | "div" if float => {
+                self.load_float(arg(0)?, "%xmm0")?;
+                self.load_float(arg(1)?, "%xmm1")?;
+                self.emit(&format!("{op}sd %xmm1, %xmm0"));
+                self.store_float("%xmm0", dest);
+            }
+            "add" | "sub" | "mul" | "and" | "or" | "xor" => {
+                self.load(arg(0)?, "%rax")?;
+                self.load(arg(1)?, "%rcx")?;
+                let op = if op == "mul" { "imul" } else { op };
+                self.emit(&format!("{op} %rcx, %rax"));
+                self.store("%rax", dest);
+            }
+            "div" | "rem" | "udiv" | "urem" => {
+                self.load(arg(0)?, "%rax")?;
+                self.load(arg(1)?, "%rcx")?;
+                let word = class == "w";
+                match (op.starts_with('u'), word) {
+                    (false, false) => self.emit("cqto\n  idiv %rcx"),
+                    (false, true) => self.emit("cltd\n  idivl %ecx\n  movslq %eax, %rax"),
+                    (true, false) => self.emit("xor %edx, %edx\n  div %rcx"),
+                    (true, true) => self.emit("xor %edx, %edx\n  divl %ecx"),
+                }
+                let result = if op.ends_with("rem") { "%rdx" } else { "%rax" };
+                self.store(result, dest);
+            }
+            "neg" if float => {
+                self.load_float(arg(0)?, "%xmm1")?;
+                self.emit("xorpd %xmm0, %xmm0\n  subsd %xmm1, %xmm0");
+                self.store_float("%xmm0", dest);
+            }
+            "neg" => {
+                self.load(arg(0)?, "%rax")?;
+                self.emit("neg %rax");
+                self.store("%rax", dest);
+            }
+            "shl" | "sar" | "shr" => {
+                self.load(arg(0)?, "%rax")?;
+                self.load(arg(1)?, "%rcx")?;
+                self.emit(&format!("{op} %cl, %rax"));
+                self.store("%rax", dest);
+            }
+            "alloc4" | "alloc8" | "alloc16" => {
+                let size: usize = arg(0)?.parse().map_err(|_| unsupported(line))?;
+                // The compiler only allocates at the start of functions, so each allocation
+                // gets memory of its own in the frame
+                let offset = self.reserve(size, 16);
+                self.emit(&format!("lea -{offset}(%rbp), %rax"));
+                self.store("%rax", dest);
+            }
+            "loadd" => {
+                self.load(arg(0)?, "%rax")?;
+                self.emit("movsd (%rax), %xmm0");
+                self.store_float("%xmm0", dest);
+            }
+            "load" | "loadl" | "loadw" | "loadsw" | "loaduw" | "loadsh" | "loaduh" | "loadsb"
+            | "loadub" => {
+                self.load(arg(0)?, "%rax")?;
+                let load = match op {
+                    "loadw" | "loadsw" => "movslq (%rax), %rax",
+                    "loaduw" => "movl (%rax), %eax",
+                    "loadsh" => "movswq (%rax), %rax",
+                    "loaduh" => "movzwq (%rax), %rax",
+                    "loadsb" => "movsbq (%rax), %rax",
+                    "loadub" => "movzbq (%rax), %rax",
+                    _ if class == "w" => "movslq (%rax), %rax",
+                    _ => "mov (%rax), %rax",
+                };
+                self.emit(load);
+                self.store("%rax", dest);
+            }
+            "extsw" | "extuw" | "extsh" | "extuh" | "extsb" | "extub" => {
+                self.load(arg(0)?, "%rax")?;
+                self.emit(match op {
+                    "extsw" => "movslq %eax, %rax",
+                    "extuw" => "movl %eax, %eax",
+                    "extsh" => "movswq %ax, %rax",
+                    "extuh" => "movzwq %ax, %rax",
+                    "extsb" => "movsbq %al, %rax",
+                    _ => "movzbq %al, %rax",
+                });
+                self.store("%rax", dest);
+            }
+            "sltof" | "swtof" => {
+                self.load(arg(0)?, "%rax")?;
+                match op {
+                    "sltof" => self.emit("cvtsi2sdq %rax, %xmm0"),
+                    _ => self.emit("cvtsi2sdl %eax, %xmm0"),
+                }
+                self.store_float("%xmm0", dest);
+            }
+            "dtosi" => {
+                self.load_float(arg(0)?, "%xmm0")?;
+                self.emit("cvttsd2si %xmm0, %rax");
+                self.store("%rax", dest);
+            }
+            // Reinterprets the bits of a value as the other class
+            "cast" if float => {
+                self.load(arg(0)?, "%rax")?;
+                self.store("%rax", dest);
+            }
+            "cast" => {
+                self.load_float(arg(0)?, "%xmm0")?;
+                self.store_float("%xmm0", dest);
+            }
+            _ if op.starts_with('c') && op.len() > 2 => {
+                self.compare(line, op, arg(0)?, arg(1)?)?;
+                self.store("%rax", dest);
+            }
+            _ => return Err(unsupported(line)),
+        }
+        Ok(())
+    }
+
+    /// Compares two operands into `%rax`, 1 when the comparison holds and 0 otherwise
+    fn compare(&mut self, line: &str, op: &str, lhs: &str, rhs: &str) -> Result<(), ErrorType> {
+        let (kind, class) = op[1..].split_at(op.len() - 2);
+        if class == "d" {
+            // `ucomisd` sets the flags of an unsigned comparison, unordered operands (NaN)
+            // setting the parity flag too
+            let (lhs, rhs, set) = match kind {
+                "eq" => (lhs, rhs, "sete %al\n  setnp %cl\n  and %cl, %al"),
+                "ne" => (lhs, rhs, "setne %al\n  setp %cl\n  or %cl, %al"),
+                "gt" => (lhs, rhs, "seta %al"),
+                "ge" => (lhs, rhs, "setae %al"),
+                "lt" => (rhs, lhs, "seta %al"),
+                "le" => (rhs, lhs, "setae %al"),
+                "o" => (lhs, rhs, "setnp %al"),
+                "uo" => (lhs, rhs, "setp %al"),
+                _ => return Err(unsupported(line)),
+            };
+            self.load_float(lhs, "%xmm0")?;
+            self.load_float(rhs, "%xmm1")?;
+            self.emit("ucomisd %xmm1, %xmm0");
+            self.emit(set);
+        } else {
+            let condition = match kind {
+                "eq" => "e",
+                "ne" => "ne",
+                "slt" => "l",
+                "sle" => "le",
+                "sgt" => "g",
+                "sge" => "ge",
+                "ult" => "b",
+                "ule" => "be",
+                "ugt" => "a",
+                "uge" => "ae",
+                _ => return Err(unsupported(line)),
+            };
+            self.load(lhs, "%rax")?;
+            self.load(rhs, "%rcx")?;
+            match class {
+                "w" => self.emit("cmp %ecx, %eax"),
+                _ => self.emit("cmp %rcx, %rax"),
+            }
+            self.emit(&format!("set{condition} %al"));
+        }
+        self.emit("movzbq %al, %rax");
+        Ok(())
+    }
+
+    /// Translates an instruction without a value
+    fn statement(&mut self, line: &str, op: &str, args: &str) -> Result<(), ErrorType> {
+        let list = split_args(args);
+        let arg = |i: usize| list.get(i).copied().ok_or_else(|| unsupported(line));
+
+        match op {
+            "call" => return self.call(line, args, None),
+            "stored" => {
+                self.load_float(arg(0)?, "%xmm0")?;
+                self.load(arg(1)?, "%rax")?;
+                self.emit("movsd %xmm0, (%rax)");
+            }
+            "storel" | "storew" | "storeh" | "storeb" => {
+                self.load(arg(0)?, "%rcx")?;
+                self.load(arg(1)?, "%rax")?;
+                self.emit(match op {
+                    "storel" => "mov %rcx, (%rax)",
+                    "storew" => "movl %ecx, (%rax)",
+                    "storeh" => "movw %cx, (%rax)",
+                    _ => "movb %cl, (%rax)",
+                });
+            }
+            "blit" => {
+                let size: usize = arg(2)?.parse().map_err(|_| unsupported(line))?;
+                self.load(arg(0)?, "%rsi")?;
+                self.load(arg(1)?, "%rdi")?;
+                self.copy_memory(size);
+            }
+            "jmp" => {
+                let label = arg(0)?.trim_start_matches('@');
+                self.emit(&format!("jmp {}{label}", self.id));
+            }
+            "jnz" => {
+                self.load(arg(0)?, "%rax")?;
+                let yes = arg(1)?.trim_start_matches('@');
+                let no = arg(2)?.trim_start_matches('@');
+                self.emit("test %eax, %eax");
+                self.emit(&format!("jnz {}{yes}", self.id));
+                self.emit(&format!("jmp {}{no}", self.id));
+            }
+            "ret" => {
+                if let Some(value) = list.first() {
+                    if self.ret == "d" {
+                        self.load_float(value, "%xmm0")?;
+                    } else if let Some(typ) = self.ret.strip_prefix(':') {
+                        let size = self.native.size_of(typ)?;
+                        self.load(value, "%rsi")?;
+                        self.load("%.ret", "%rdi")?;
+                        self.copy_memory(size);
+                        self.emit("mov %rdi, %rax");
+                    } else {
+                        self.load(value, "%rax")?;
+                    }
+                }
+                self.emit("leave");
+                self.emit("ret");
+            }
+            "hlt" => self.emit("ud2"),
+            _ => return Err(unsupported(line)),
+        }
+        Ok(())
+    }
+
+    /// Translates a call, `$name(l %a, d %b)`, storing its value in the destination
+    fn call(
+        &mut self,
+        line: &str,
+        call: &str,
+        dest: Option<(&str, &str)>,
+    ) -> Result<(), ErrorType> {
+        let (callee, args) = call.split_once('(').ok_or_else(|| unsupported(line))?;
+        let args = args
+            .trim_end()
+            .strip_suffix(')')
+            .ok_or_else(|| unsupported(line))?;
+
+        // Structs are returned into memory of the caller, given as the first argument
+        let mut returned = None;
+        if let Some((_, class)) = dest.filter(|(_, class)| class.starts_with(':')) {
+            let size = self.native.size_of(class)?;
+            returned = Some(self.reserve(size, 16));
+        }
+
+        let mut ints = Vec::new();
+        let mut floats = Vec::new();
+        let mut stack = Vec::new();
+        let int_regs = &INT_ARGS[usize::from(returned.is_some())..];
+
+        for arg in split_args(args) {
+            // Marks the start of the variadic arguments, which are passed the same way
+            if arg == "..." {
+                continue;
+            }
+            let (class, value) = arg.split_once(' ').ok_or_else(|| unsupported(line))?;
+            if class == "d" && floats.len() < FLOAT_ARGS {
+                floats.push(value);
+            } else if class != "d" && ints.len() < int_regs.len() {
+                ints.push((int_regs[ints.len()], value));
+            } else {
+                stack.push((class, value));
+            }
+        }
+
+        // The stack stays aligned to 16 bytes at the call
+        let padding = if stack.len() % 2 == 1 { 8 } else { 0 };
+        if padding > 0 {
+            self.emit("sub $8, %rsp");
+        }
+        for (class, value) in stack.iter().rev() {
+            match *class {
+                "d" => {
+                    self.load_float(value, "%xmm0")?;
+                    self.emit("movq %xmm0, %rax");
+                }
+                _ => self.load(value, "%rax")?,
+            }
+            self.emit("push %rax");
+        }
+
+        for (i, value) in floats.iter().enumerate() {
+            self.load_float(value, &format!("%xmm{i}"))?;
+        }
+        if callee.trim().starts_with('%') {
+            self.load(callee.trim(), "%r11")?;
+        }
+        for (reg, value) in &ints {
+            self.load(value, reg)?;
+        }
+        if let Some(offset) = returned {
+            self.emit(&format!("lea -{offset}(%rbp), {}", INT_ARGS[0]));
+        }
+
+        // Variadic functions get the number of vector registers used in `%al`
+        self.emit(&format!("mov ${}, %eax", floats.len()));
+        match callee.trim() {
+            callee if callee.starts_with('%') => self.emit("call *%r11"),
+            callee => self.emit(&format!("call {}", symbol(callee))),
+        }
+        let popped = stack.len() * 8 + padding;
+        if popped > 0 {
+            self.emit(&format!("add ${popped}, %rsp"));
+        }
+
+        match dest {
+            Some((dest, "d")) => self.store_float("%xmm0", dest),
+            Some((dest, "w")) => {
+                self.emit("movslq %eax, %rax");
+                self.store("%rax", dest);
+            }
+            Some((dest, _)) => self.store("%rax", dest),
+            None => {}
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,16 @@
 #![allow(dead_code)]
 
 use crate::utils::ErrorType;
-use std::{fmt, iter::Peekable, slice::Iter, str::FromStr};
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::{HashMap, HashSet},
+    fmt,
+    iter::Peekable,
+    path::Path,
+    rc::Rc,
+    slice::Iter,
+    str::FromStr,
+};
 
 /// Represents different token types for the lexer
 #[derive(Debug, PartialEq, Clone)]
@@ -12,6 +21,18 @@ pub enum Token {
     True,
     False,
     Return,
+    For,
+    In,
+    Struct,
+    Fn,
+    Enum,
+    Match,
+    Else,
+    None,
+    Try,
+    Catch,
+    Import,
+    Pub,
 
     // Operators
     Plus,
@@ -19,6 +40,8 @@ pub enum Token {
     Multiply,
     Divide,
     Equals,
+    Question,
+    Coalesce,
 
     // Types
     Type(Type),
@@ -28,7 +51,14 @@ pub enum Token {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
+    Dot,
+    Arrow,
+    DoubleColon,
+    FatArrow,
 
     // Identifiers
     Identifier(String),
@@ -48,9 +78,50 @@ pub enum Type {
     Bool,
     Str,
     None,
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    /// User defined struct type, referred to by its name
+    Struct(String),
+    /// User defined enum type. Type names are parsed as structs, the checker resolves the names
+    /// of enums
+    Enum(String),
+    Tuple(Vec<Type>),
+    /// Either a value of the inner type or `none`, written `int?`
+    Optional(Box<Type>),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Location of a piece of source code. Lines and columns are 1-based, the end is exclusive
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+impl Span {
+    /// Creates a span covering `len` characters of a single line
+    pub fn new(line: usize, col: usize, len: usize) -> Self {
+        Self {
+            line,
+            col,
+            end_line: line,
+            end_col: col + len,
+        }
+    }
+
+    /// Returns a span reaching from the start of `self` to the end of `other`
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            line: self.line,
+            col: self.col,
+            end_line: other.end_line,
+            end_col: other.end_col,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Bool {
     True,
     False,
@@ -66,22 +137,22 @@ impl fmt::Display for Type {
             Type::Str => "str",
             Type::Bool => "bool",
             Type::None => "none",
+            Type::List(elem) => return write!(f, "[{elem}]"),
+            Type::Map(key, value) => return write!(f, "{{{key}: {value}}}"),
+            Type::Struct(name) | Type::Enum(name) => name,
+            Type::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(|elem| elem.to_string()).collect();
+                return match elems.len() {
+                    1 => write!(f, "({},)", elems[0]),
+                    _ => write!(f, "({})", elems.join(", ")),
+                };
+            }
+            Type::Optional(inner) => return write!(f, "{inner}?"),
         };
         write!(f, "{}", type_str)
     }
 }
 
-pub fn type_check(var_type: &Type, value: &Expr) -> bool {
-    matches!(
-        (var_type, value),
-        (Type::Str, Expr::StringLiteral(_))
-            | (Type::Int, Expr::Number(_) | Expr::BinExpr(_))
-            | (Type::Float, Expr::Number(_) | Expr::BinExpr(_))
-            | (Type::Double, Expr::Number(_) | Expr::BinExpr(_))
-            | (Type::Bool, Expr::Bool(_))
-    )
-}
-
 impl Token {
     /// Returns the length of the token as it appears in the source
     fn len(&self) -> usize {
@@ -89,6 +160,18 @@ impl Token {
             Token::Let => 3,
             Token::If => 2,
             Token::Return => 6,
+            Token::For => 3,
+            Token::In => 2,
+            Token::Struct => 6,
+            Token::Fn => 2,
+            Token::Enum => 4,
+            Token::Match => 5,
+            Token::Else | Token::None => 4,
+            Token::Try => 3,
+            Token::Catch => 5,
+            Token::Import => 6,
+            Token::Pub => 3,
+            Token::Arrow | Token::DoubleColon | Token::FatArrow | Token::Coalesce => 2,
 
             Token::StringLiteral(s) => s.len() + 2, // Includes quotes
             Token::Type(Type::Str) => 3,
@@ -100,12 +183,17 @@ impl Token {
             | Token::RightParen
             | Token::LeftBrace
             | Token::RightBrace
+            | Token::LeftBracket
+            | Token::RightBracket
             | Token::Plus
             | Token::Minus
             | Token::Multiply
             | Token::Divide
             | Token::Equals
-            | Token::Comma => 1,
+            | Token::Question
+            | Token::Comma
+            | Token::Colon
+            | Token::Dot => 1,
 
             Token::Type(Type::Int) => 3,
             Token::Type(Type::Long) => 4,
@@ -120,7 +208,15 @@ impl Token {
             Token::True => 4,
             Token::False => 5,
 
-            Token::Type(Type::None) => 0,
+            Token::Type(
+                Type::None
+                | Type::List(_)
+                | Type::Map(..)
+                | Type::Struct(_)
+                | Type::Enum(_)
+                | Type::Tuple(_)
+                | Type::Optional(_),
+            ) => 0,
         }
     }
 }
@@ -131,15 +227,14 @@ impl FromStr for Token {
     /// Parses a string into a Token, if possible
     fn from_str(s: &str) -> Result<Token, ()> {
         // println!("fromstr: {s}");
-        // Helper for parsing keywords followed by whitespace
+        // Helper for parsing keywords not followed by further identifier characters
         fn parse_keyword(s: &str, keyword: &str, token: &Token) -> Option<Token> {
-            if s.starts_with(keyword) {
-                let rest = &s[keyword.len()..];
+            if let Some(rest) = s.strip_prefix(keyword) {
                 if rest.is_empty()
                     || rest
                         .chars()
                         .next()
-                        .map(|c| !c.is_alphanumeric())
+                        .map(|c| !c.is_alphanumeric() && c != '_')
                         .unwrap_or(false)
                 {
                     return Some(token.clone());
@@ -153,15 +248,27 @@ impl FromStr for Token {
         let keywords = [
             ("let", Token::Let),
             ("if", Token::If),
+            ("in", Token::In),
+            ("fn", Token::Fn),
+            ("for", Token::For),
+            ("try", Token::Try),
+            ("pub", Token::Pub),
             ("int", Token::Type(Type::Int)),
             ("str", Token::Type(Type::Str)),
             ("bool", Token::Type(Type::Bool)),
             ("long", Token::Type(Type::Long)),
             ("true", Token::True),
+            ("enum", Token::Enum),
+            ("else", Token::Else),
+            ("none", Token::None),
             ("false", Token::False),
+            ("match", Token::Match),
+            ("catch", Token::Catch),
             ("float", Token::Type(Type::Float)),
             ("double", Token::Type(Type::Double)),
             ("return", Token::Return),
+            ("struct", Token::Struct),
+            ("import", Token::Import),
         ];
 
         for &(keyword, ref token) in &keywords {
@@ -197,6 +304,19 @@ impl FromStr for Token {
             return Ok(Token::Identifier(identifier));
         }
 
+        // Two-character tokens, checked before their single-character prefixes
+        let double_char_tokens = [
+            ("->", Token::Arrow),
+            ("::", Token::DoubleColon),
+            ("=>", Token::FatArrow),
+            ("??", Token::Coalesce),
+        ];
+        for (prefix, token) in double_char_tokens {
+            if s.starts_with(prefix) {
+                return Ok(token);
+            }
+        }
+
         // Single-character tokens
         let single_char_tokens = [
             ('+', Token::Plus),
@@ -207,8 +327,13 @@ impl FromStr for Token {
             (')', Token::RightParen),
             ('{', Token::LeftBrace),
             ('}', Token::RightBrace),
+            ('[', Token::LeftBracket),
+            (']', Token::RightBracket),
             ('=', Token::Equals),
             (',', Token::Comma),
+            (':', Token::Colon),
+            ('.', Token::Dot),
+            ('?', Token::Question),
         ];
 
         if let Some(&c) = s.chars().next().as_ref() {
@@ -221,11 +346,17 @@ impl FromStr for Token {
     }
 }
 
+/// Whether the name can be used as an identifier: letters, digits and underscores, not starting
+/// with a digit
+pub fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
 /// Prepares source code for further processing
 pub fn preprocess(code: &str) -> String {
-    // Handle comments
+    // Handle comments. Comment lines are kept empty so that line numbers stay intact
     code.lines()
-        .filter(|l| !l.starts_with("//"))
         .map(|l| l.split("//").next().unwrap())
         .collect::<Vec<&str>>()
         .join("\n")
@@ -233,27 +364,43 @@ pub fn preprocess(code: &str) -> String {
 
 /// Converts input text into a vector of tokens
 pub fn lexer(input: &str) -> Result<Vec<Token>, ErrorType> {
+    Ok(lexer_with_spans(input)?.0)
+}
+
+/// Converts input text into a vector of tokens along with the source location of each token
+pub fn lexer_with_spans(input: &str) -> Result<(Vec<Token>, Vec<Span>), ErrorType> {
     let mut tokens = Vec::new();
+    let mut spans = Vec::new();
 
-    for line in input.lines() {
-        let mut remaining = line.trim();
+    for (line_nr, line) in input.lines().enumerate() {
+        // Columns count from the start of the line, trailing whitespace (eg. left by a removed
+        // comment) doesn't shift them
+        let line = line.trim_end();
+        let mut remaining = line.trim_start();
         while !remaining.is_empty() {
             match Token::from_str(remaining) {
                 Ok(token) => {
                     let token_length = token.len();
+                    let col = line.len() - remaining.len() + 1;
+                    spans.push(Span::new(line_nr + 1, col, token_length));
                     remaining = remaining[token_length..].trim_start();
                     tokens.push(token);
                 }
                 Err(_) => {
-                    return Err(ErrorType::SyntaxError(format!(
-                        "Unexpected token: {remaining}"
-                    )))
+                    return Err(ErrorType::SyntaxError(
+                        format!("Unexpected token: {remaining}"),
+                        Span::new(
+                            line_nr + 1,
+                            line.len() - remaining.len() + 1,
+                            remaining.len(),
+                        ),
+                    ))
                 }
             }
         }
     }
 
-    Ok(tokens)
+    Ok((tokens, spans))
 }
 
 /// Represents a parsed expression in the abstract syntax tree (AST)
@@ -269,22 +416,256 @@ pub enum Expr {
     Bool(Bool),
     Identifier(String),
     StringLiteral(String),
-    Return(Box<Expr>),
+    Return(Box<Return>),
+    /// Elements along with the type of the list, recorded by the checker
+    List(Vec<Expr>, OnceCell<Type>),
+    /// Entries along with the type of the map, recorded by the checker
+    Map(Vec<(Expr, Expr)>, OnceCell<Type>),
+    Index(Box<Index>),
+    Assignment(Box<Assignment>),
+    For(Box<ForLoop>),
+    StructDecl(Box<StructDecl>),
+    StructLiteral(Box<StructLiteral>),
+    Field(Box<FieldAccess>),
+    FuncDecl(Box<FuncDecl>),
+    EnumDecl(Box<EnumDecl>),
+    EnumVariant(Box<EnumVariant>),
+    Match(Box<Match>),
+    Tuple(Vec<Expr>),
+    TupleIndex(Box<TupleIndex>),
+    Destructure(Box<Destructure>),
+    None,
+    Coalesce(Box<Coalesce>),
+    TryCatch(Box<TryCatch>),
+    Import(Box<Import>),
 }
 
-/// Represents a if statement in the AST
+/// Represents an if statement (`if condition { ... } else { ... }`) in the AST. With a binding it's
+/// an `if let name = optional { ... }`, whose block runs when the optional holds a value
 #[derive(Debug, Clone, PartialEq)]
 pub struct IfStatement {
-    pub comparison: Expr,
+    pub binding: Option<String>,
+    pub condition: Expr,
+    pub block: Vec<Expr>,
+    /// Empty without an `else`, an `else if` is a nested if statement
+    pub else_block: Vec<Expr>,
+    /// Location of the condition
+    pub span: Span,
+}
+
+/// Represents a `try { ... } catch e { ... }` statement in the AST. The catch block runs when an
+/// error is raised inside of the try block, with the error message bound to the variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct TryCatch {
+    pub block: Vec<Expr>,
+    /// `None` for `catch { ... }`, which ignores the message
+    pub variable: Option<String>,
+    pub catch_block: Vec<Expr>,
+    /// Location of the catch variable, or of `catch` without one
+    pub span: Span,
+}
+
+/// Represents an import of a module (`import math` or `import "lib/math.blk"`) in the AST. Items
+/// of the module are referred to by names qualified with the module name: `math.square`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// Name of the module in the importing file, the file name without its extension
+    pub name: String,
+    /// Path of the module given as a string, `None` for an import by name
+    pub path: Option<String>,
+    pub span: Span,
+    /// Unique name of the module in the program, which the names of its items are prefixed with.
+    /// Set by the module loader
+    pub module: String,
+    /// Path of the source file of the module, set by the module loader
+    pub file: String,
+    /// Code of the module, set by the module loader at the first import of the module only. The
+    /// module runs at its first import
+    pub body: Ast,
+}
+
+/// Represents an optional value with a fallback (`value ?? default`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coalesce {
+    pub value: Expr,
+    pub default: Expr,
+    pub span: Span,
+    /// Type of the result, recorded by the checker. An int fallback of an optional float is
+    /// converted to it
+    pub typ: OnceCell<Type>,
+}
+
+/// Represents an index access (`list[index]` or `map[key]`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub target: Expr,
+    pub index: Expr,
+    pub span: Span,
+}
+
+/// Represents a struct declaration (`struct Point { int x, int y }`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: String,
+    /// Whether the struct is declared with `pub`, so that modules importing the file can use it
+    pub public: bool,
+    pub fields: Vec<(String, Type)>,
+    pub span: Span,
+}
+
+/// Represents a struct construction (`Point { x: 1, y: 2 }`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructLiteral {
+    pub name: String,
+    pub fields: Vec<(String, Expr)>,
+    pub span: Span,
+}
+
+/// Represents a field access (`point.x`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldAccess {
+    pub target: Expr,
+    pub field: String,
+    pub span: Span,
+}
+
+/// Represents an access to a tuple element (`pair.0`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct TupleIndex {
+    pub target: Expr,
+    pub index: usize,
+    pub span: Span,
+}
+
+/// Represents a destructuring declaration (`let (a, b) = pair`) in the AST. Elements named `_`
+/// are ignored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destructure {
+    pub names: Vec<String>,
+    pub value: Expr,
+    pub value_span: Span,
+}
+
+/// Represents a function declaration (`fn add(int a, int b) -> int { ... }`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDecl {
+    pub name: String,
+    /// Whether the function is declared with `pub`, so that modules importing the file can call it
+    pub public: bool,
+    pub params: Vec<(String, Type)>,
+    /// `none` when the function doesn't return a value
+    pub return_type: Type,
+    pub block: Vec<Expr>,
+    pub span: Span,
+}
+
+/// Represents an enum declaration (`enum Shape { Circle(int), Rect(int, int) }`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDecl {
+    pub name: String,
+    /// Whether the enum is declared with `pub`, so that modules importing the file can use it
+    pub public: bool,
+    /// Name of every variant along with the types of the values it holds
+    pub variants: Vec<(String, Vec<Type>)>,
+    pub span: Span,
+}
+
+/// Represents the construction of an enum value (`Shape::Circle(5)`) in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariant {
+    pub enum_name: String,
+    pub variant: String,
+    pub arguments: Vec<Expr>,
+    pub span: Span,
+    /// Declared types of the values of the variant, recorded by the checker
+    pub types: OnceCell<Vec<Type>>,
+}
+
+/// Represents a `match value { pattern => body, ... }` expression in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub value: Expr,
+    pub arms: Vec<MatchArm>,
+    /// Location of `match` and the matched value
+    pub span: Span,
+}
+
+/// Represents one arm of a match expression. The body is either an expression or a block
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+    /// Location of the pattern
+    pub span: Span,
+}
+
+/// Represents a pattern of a match arm
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, matches anything
+    Wildcard,
+    /// Matches anything and binds the value to a variable
+    Binding(String),
+    /// Number, string or boolean literal compared with the value
+    Literal(Expr),
+    /// `Shape::Rect(w, h)`, matches a variant and the values it holds
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Pattern>,
+    },
+}
+
+impl Pattern {
+    /// Names of the variables bound by the pattern
+    pub fn bindings(&self) -> Vec<String> {
+        match self {
+            Pattern::Binding(name) => vec![name.clone()],
+            Pattern::Variant { fields, .. } => fields.iter().flat_map(Pattern::bindings).collect(),
+            Pattern::Wildcard | Pattern::Literal(_) => Vec::new(),
+        }
+    }
+}
+
+/// Represents an assignment to an existing variable, list element, map entry or struct field in
+/// the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub target: Expr,
+    pub value: Expr,
+    pub span: Span,
+    /// Type of the target, filled in by the checker
+    pub typ: Option<Type>,
+}
+
+/// Represents a `for item in list { ... }` or `for key, value in map { ... }` loop in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForLoop {
+    pub variable: String,
+    /// Second loop variable, bound to the value when iterating over a map
+    pub value_variable: Option<String>,
+    pub iterable: Expr,
     pub block: Vec<Expr>,
+    /// Location of the loop variables
+    pub span: Span,
 }
 
 /// Represents a variable declaration in the AST
 #[derive(Debug, Clone, PartialEq)]
 pub struct VariableDeclaration {
     pub identifier: String,
+    /// Declared type, filled in with the inferred type by the checker when omitted
     pub typ: Option<Type>,
     pub value: Expr,
+    pub typ_span: Option<Span>,
+    pub value_span: Span,
+}
+
+/// Represents a `return value` statement in the AST
+#[derive(Debug, Clone, PartialEq)]
+pub struct Return {
+    pub value: Expr,
+    pub span: Span,
 }
 
 /// Represents a function call in the AST
@@ -292,6 +673,10 @@ pub struct VariableDeclaration {
 pub struct FuncCall {
     pub name: String,
     pub arguments: Vec<Expr>,
+    pub span: Span,
+    /// Types of the arguments of a builtin call, recorded by the checker for builtins which
+    /// depend on them at runtime (eg. `type_of`)
+    pub arg_types: OnceCell<Vec<Type>>,
 }
 
 /// Represents a binary expression in the AST
@@ -300,6 +685,7 @@ pub struct BinExpr {
     pub lhs: Expr,
     pub rhs: Expr,
     pub kind: BinOpKind,
+    pub span: Span,
 }
 
 /// Represents kinds of binary operators
@@ -328,8 +714,58 @@ impl BinOpKind {
 #[derive(Debug, Clone)]
 pub enum Variable {
     Number(i64),
+    /// Values of `float` and `double` variables, ints stored into them are converted
+    Float(f64),
     StringLiteral(String),
     Bool(Bool),
+    /// Lists are shared by reference, so that eg. `push` is visible through every variable
+    List(ListItems),
+    /// Maps are shared by reference as well
+    Map(MapItems),
+    /// Structs are values, copying a struct copies all of its fields
+    Struct(StructValue),
+    /// Enums are values as well
+    Enum(EnumValue),
+    /// Tuples can't be modified, so they're plain values too
+    Tuple(Vec<Variable>),
+    None,
+}
+
+/// Shared, mutable items of a list value
+pub type ListItems = Rc<RefCell<Vec<Variable>>>;
+
+/// Hashable key of a map value, maps can be keyed by numbers, strings and booleans
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MapKey {
+    Number(i64),
+    StringLiteral(String),
+    Bool(Bool),
+}
+
+/// Entries of a map value. Keys are kept in insertion order, so that iterating and printing a
+/// map gives the same result in the interpreter and in compiled programs
+#[derive(Debug, Clone, Default)]
+pub struct MapEntries {
+    pub keys: Vec<MapKey>,
+    pub values: HashMap<MapKey, Variable>,
+}
+
+/// Shared, mutable entries of a map value
+pub type MapItems = Rc<RefCell<MapEntries>>;
+
+/// Fields of a struct value in declaration order
+#[derive(Debug, Clone)]
+pub struct StructValue {
+    pub name: String,
+    pub fields: Vec<(String, Variable)>,
+}
+
+/// Variant of an enum value along with the values it holds
+#[derive(Debug, Clone)]
+pub struct EnumValue {
+    pub enum_name: String,
+    pub variant: String,
+    pub values: Vec<Variable>,
 }
 
 /// Type alias for the AST, a list of expressions
@@ -338,16 +774,63 @@ pub type Ast = Vec<Expr>;
 /// Parses tokens into expressions and builds an AST
 pub struct Parser<'a> {
     tokens: Peekable<Iter<'a, Token>>,
+    spans: &'a [Span],
+    total: usize,
+    /// Whether `Name {` starts a struct literal. Disabled where a `{` opens a block, eg. after the
+    /// iterable of a for loop
+    struct_literals: bool,
+    /// Names of the modules imported so far, which qualify the names following them
+    modules: HashSet<String>,
+    /// Positions of the tokens starting a statement or a match arm, the formatter puts each of
+    /// them on its own line
+    pub line_starts: Vec<usize>,
+    /// Positions of the `{` tokens opening a code block or the arms of a match
+    pub blocks: Vec<usize>,
 }
 
 impl<'a> Parser<'a> {
     /// Creates a new parser instance from a list of tokens
     pub fn new(tokens: &'a [Token]) -> Self {
+        Self::with_spans(tokens, &[])
+    }
+
+    /// Creates a new parser instance from a list of tokens and their source locations
+    pub fn with_spans(tokens: &'a [Token], spans: &'a [Span]) -> Self {
         Parser {
             tokens: tokens.iter().peekable(),
+            spans,
+            total: tokens.len(),
+            struct_literals: true,
+            modules: HashSet::new(),
+            line_starts: Vec::new(),
+            blocks: Vec::new(),
         }
     }
 
+    /// Returns the position of the next token. After a syntax error, the token following the
+    /// offending one
+    pub fn pos(&self) -> usize {
+        self.total - self.tokens.len()
+    }
+
+    /// Returns the source location of the next token (default span if unknown)
+    fn peek_span(&self) -> Span {
+        self.spans.get(self.pos()).copied().unwrap_or_default()
+    }
+
+    /// Returns the source location of the last consumed token (default span if unknown)
+    fn last_span(&self) -> Span {
+        self.pos()
+            .checked_sub(1)
+            .and_then(|pos| self.spans.get(pos).copied())
+            .unwrap_or_default()
+    }
+
+    /// Builds a syntax error located at the last consumed token, the one parsing stopped at
+    fn syntax_error(&self, message: String) -> ErrorType {
+        ErrorType::SyntaxError(message, self.last_span())
+    }
+
     /// Parses primary expressions (numbers, identifiers, etc.)
     pub fn parse_primary(&mut self) -> Result<Expr, ErrorType> {
         match self.tokens.next() {
@@ -359,33 +842,60 @@ impl<'a> Parser<'a> {
             Some(Token::True) => Ok(Expr::Bool(Bool::True)),
             Some(Token::False) => Ok(Expr::Bool(Bool::False)),
             Some(Token::StringLiteral(s)) => Ok(Expr::StringLiteral(s.to_owned())), // Handle StringLiteral
+            Some(Token::None) => Ok(Expr::None),
             Some(Token::LeftParen) => {
+                // A comma after the first expression makes it a tuple, otherwise it's grouping
                 let expr = self.parse_expr()?;
-                if self.tokens.next() != Some(&Token::RightParen) {
-                    return Err(ErrorType::SyntaxError("Expected ')'".to_string()));
+                match self.tokens.next() {
+                    Some(Token::RightParen) => Ok(expr),
+                    Some(Token::Comma) => self.parse_tuple(expr),
+                    _ => Err(self.syntax_error("Expected ')'".to_string())),
                 }
-                Ok(expr)
             }
-            Some(Token::LeftBrace) => self.parse_block(), // Handle code block start
+            Some(Token::LeftBrace) => self.parse_map(), // Blocks only start statements
+            Some(Token::LeftBracket) => self.parse_list(),
             Some(Token::Identifier(name)) => {
-                if let Some(Token::LeftParen) = self.tokens.peek() {
-                    self.parse_func_call(name)
-                } else {
-                    Ok(Expr::Identifier(name.to_owned()))
+                let start = self.last_span();
+                let name = self.qualified(name);
+                match self.tokens.peek() {
+                    Some(Token::LeftParen) => self.parse_func_call(&name, start),
+                    Some(Token::LeftBrace) if self.struct_literals => {
+                        self.parse_struct_literal(&name, start)
+                    }
+                    Some(Token::DoubleColon) => self.parse_enum_variant(&name, start),
+                    _ => Ok(Expr::Identifier(name)),
                 }
             }
-            Some(token) => Err(ErrorType::SyntaxError(format!(
-                "Unexpected token: {token:?}",
-            ))),
-            None => Err(ErrorType::SyntaxError(
-                "Unexpected end of input".to_string(),
-            )),
+            // Conversions are named after the type they convert to, eg. `int(x)`
+            Some(Token::Type(typ)) if self.tokens.peek() == Some(&&Token::LeftParen) => {
+                let start = self.last_span();
+                self.parse_func_call(&typ.to_string(), start)
+            }
+            Some(token) => Err(self.syntax_error(format!("Unexpected token: {token:?}",))),
+            None => Err(self.syntax_error("Unexpected end of input".to_string())),
+        }
+    }
+
+    /// Completes a name qualified by an imported module, `math` followed by `.square` results in
+    /// `math.square`. Other names are returned as they are
+    fn qualified(&mut self, name: &str) -> String {
+        let mut ahead = self.tokens.clone();
+        if !self.modules.contains(name) || ahead.next() != Some(&Token::Dot) {
+            return name.to_string();
         }
+        let Some(Token::Identifier(item)) = ahead.next() else {
+            return name.to_string();
+        };
+
+        self.tokens.next(); // Consume the '.'
+        self.tokens.next(); // Consume the item name
+        format!("{name}.{item}")
     }
 
     /// Parses a code block: `{ expr1; expr2; ... }`
     pub fn parse_block(&mut self) -> Result<Expr, ErrorType> {
         let mut expressions = Vec::new();
+        self.blocks.push(self.pos() - 1);
 
         // Continue parsing until we reach a RightBrace '}'
         while let Some(token) = self.tokens.peek() {
@@ -394,21 +904,279 @@ impl<'a> Parser<'a> {
                 break;
             }
             // Allow semicolons or newlines to separate expressions (optional)
-            expressions.push(self.parse_expr()?);
+            self.line_starts.push(self.pos());
+            expressions.push(self.parse_statement()?);
         }
 
         Ok(Expr::Block(expressions))
     }
 
+    /// Parses a list literal: `[expr1, expr2, ...]`
+    fn parse_list(&mut self) -> Result<Expr, ErrorType> {
+        let mut elements = Vec::new();
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RightBracket) => {
+                    self.tokens.next(); // Consume the closing bracket ']'
+                    break;
+                }
+                Some(Token::Comma) => {
+                    self.tokens.next(); // Consume the comma separating elements
+                }
+                Some(_) => elements.push(self.parse_expr()?),
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected ']'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::List(elements, OnceCell::new()))
+    }
+
+    /// Parses the rest of a tuple literal after its first element: `(expr1, expr2, ...)`. A
+    /// single element tuple is written with a trailing comma: `(expr1,)`
+    fn parse_tuple(&mut self, first: Expr) -> Result<Expr, ErrorType> {
+        let mut elements = vec![first];
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RightParen) => {
+                    self.tokens.next(); // Consume the closing parenthesis ')'
+                    break;
+                }
+                Some(Token::Comma) => {
+                    self.tokens.next(); // Consume the comma separating elements
+                }
+                Some(_) => elements.push(self.parse_expr()?),
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected ')'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::Tuple(elements))
+    }
+
+    /// Parses a map literal: `{key1: value1, key2: value2, ...}`
+    fn parse_map(&mut self) -> Result<Expr, ErrorType> {
+        let mut entries = Vec::new();
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RightBrace) => {
+                    self.tokens.next(); // Consume the closing brace '}'
+                    break;
+                }
+                Some(Token::Comma) => {
+                    self.tokens.next(); // Consume the comma separating entries
+                }
+                Some(_) => {
+                    let key = self.parse_expr()?;
+                    if self.tokens.next() != Some(&Token::Colon) {
+                        return Err(self.syntax_error("Expected ':' after map key".to_string()));
+                    }
+                    entries.push((key, self.parse_expr()?));
+                }
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected '}'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::Map(entries, OnceCell::new()))
+    }
+
+    /// Parses the fields of a struct literal: `Name { field1: value1, field2: value2 }`
+    fn parse_struct_literal(&mut self, name: &str, start: Span) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume the '{'
+        let mut fields = Vec::new();
+
+        loop {
+            match self.tokens.next() {
+                Some(Token::RightBrace) => break,
+                Some(Token::Comma) => {}
+                Some(Token::Identifier(field)) => {
+                    if self.tokens.next() != Some(&Token::Colon) {
+                        return Err(self.syntax_error("Expected ':' after field name".to_string()));
+                    }
+                    fields.push((field.to_string(), self.parse_expr()?));
+                }
+                Some(token) => {
+                    return Err(self.syntax_error(format!(
+                        "Unexpected token: {token:?}, expected a field name"
+                    )))
+                }
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected '}'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::StructLiteral(Box::new(StructLiteral {
+            name: name.to_string(),
+            fields,
+            span: start.to(&self.last_span()),
+        })))
+    }
+
+    /// Parses the construction of an enum value: `Name::Variant(value1, value2)`, or
+    /// `Name::Variant` for variants without values
+    fn parse_enum_variant(&mut self, name: &str, start: Span) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume the '::'
+        let variant = match self.tokens.next() {
+            Some(Token::Identifier(variant)) => variant.to_string(),
+            _ => return Err(self.syntax_error("Expected variant name after '::'".to_string())),
+        };
+
+        // The values are parsed like the arguments of a function call
+        let arguments = match self.tokens.peek() {
+            Some(Token::LeftParen) => match self.parse_func_call(&variant, start)? {
+                Expr::FuncCall(call) => call.arguments,
+                _ => unreachable!(),
+            },
+            _ => Vec::new(),
+        };
+
+        Ok(Expr::EnumVariant(Box::new(EnumVariant {
+            enum_name: name.to_string(),
+            variant,
+            arguments,
+            span: start.to(&self.last_span()),
+            types: OnceCell::new(),
+        })))
+    }
+
+    /// Parses a primary expression followed by any number of index accesses, field accesses and
+    /// tuple element accesses: `list[i][j]`, `point.x`, `pair.0`
+    fn parse_postfix(&mut self) -> Result<Expr, ErrorType> {
+        let start = self.peek_span();
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::LeftBracket) => {
+                    self.tokens.next(); // Consume the '['
+                    let index = self.parse_expr()?;
+                    if self.tokens.next() != Some(&Token::RightBracket) {
+                        return Err(self.syntax_error("Expected ']'".to_string()));
+                    }
+
+                    expr = Expr::Index(Box::new(Index {
+                        target: expr,
+                        index,
+                        span: start.to(&self.last_span()),
+                    }));
+                }
+                Some(Token::Dot) => {
+                    self.tokens.next(); // Consume the '.'
+                    let field = match self.tokens.next() {
+                        Some(Token::Identifier(field)) => field.to_string(),
+                        Some(Token::Number(index)) => {
+                            expr = Expr::TupleIndex(Box::new(TupleIndex {
+                                target: expr,
+                                index: *index as usize,
+                                span: start.to(&self.last_span()),
+                            }));
+                            continue;
+                        }
+                        _ => {
+                            return Err(
+                                self.syntax_error("Expected field name after '.'".to_string())
+                            )
+                        }
+                    };
+
+                    expr = Expr::Field(Box::new(FieldAccess {
+                        target: expr,
+                        field,
+                        span: start.to(&self.last_span()),
+                    }));
+                }
+                _ => break,
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// Parses a type, either a type keyword, a struct name, a list type `[int]`, a map type
+    /// `{str: int}` or a tuple type `(int, str)`. A `?` after the type makes it optional
+    fn parse_type(&mut self) -> Result<Type, ErrorType> {
+        let typ = self.parse_plain_type()?;
+        if let Some(Token::Question) = self.tokens.peek() {
+            self.tokens.next(); // Consume the '?'
+            return Ok(Type::Optional(Box::new(typ)));
+        }
+
+        Ok(typ)
+    }
+
+    /// Parses a type without the optional marker
+    fn parse_plain_type(&mut self) -> Result<Type, ErrorType> {
+        match self.tokens.next() {
+            Some(Token::Type(t)) => Ok(t.clone()),
+            Some(Token::Identifier(name)) => Ok(Type::Struct(self.qualified(name))),
+            Some(Token::LeftBracket) => {
+                let elem = self.parse_type()?;
+                if self.tokens.next() != Some(&Token::RightBracket) {
+                    return Err(
+                        self.syntax_error("Expected ']' after list element type".to_string())
+                    );
+                }
+                Ok(Type::List(Box::new(elem)))
+            }
+            Some(Token::LeftBrace) => {
+                let key = self.parse_type()?;
+                if self.tokens.next() != Some(&Token::Colon) {
+                    return Err(self.syntax_error("Expected ':' after map key type".to_string()));
+                }
+                let value = self.parse_type()?;
+                if self.tokens.next() != Some(&Token::RightBrace) {
+                    return Err(self.syntax_error("Expected '}' after map value type".to_string()));
+                }
+                Ok(Type::Map(Box::new(key), Box::new(value)))
+            }
+            Some(Token::LeftParen) => {
+                let mut elems = Vec::new();
+                loop {
+                    match self.tokens.peek() {
+                        Some(Token::RightParen) => {
+                            self.tokens.next(); // Consume the closing parenthesis ')'
+                            break;
+                        }
+                        Some(Token::Comma) => {
+                            self.tokens.next(); // Consume the comma separating element types
+                        }
+                        Some(_) => elems.push(self.parse_type()?),
+                        None => {
+                            return Err(self.syntax_error(
+                                "Unexpected end of input, expected ')'".to_string(),
+                            ));
+                        }
+                    }
+                }
+                Ok(Type::Tuple(elems))
+            }
+            _ => Err(self.syntax_error("Expected a type".to_string())),
+        }
+    }
+
     /// Parses function calls
-    fn parse_func_call(&mut self, name: &str) -> Result<Expr, ErrorType> {
+    fn parse_func_call(&mut self, name: &str, start: Span) -> Result<Expr, ErrorType> {
         let mut args = Vec::new();
 
         // Consume the opening parenthesis '('
         if self.tokens.next() != Some(&Token::LeftParen) {
-            return Err(ErrorType::SyntaxError(
-                "Expected '(' after function name".to_string(),
-            ));
+            return Err(self.syntax_error("Expected '(' after function name".to_string()));
         }
 
         // Parse arguments until a closing parenthesis ')'
@@ -426,9 +1194,9 @@ impl<'a> Parser<'a> {
                     args.push(self.parse_expr()?);
                 }
                 None => {
-                    return Err(ErrorType::SyntaxError(
-                        "Unexpected end of input, expected ')'".to_string(),
-                    ));
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected ')'".to_string())
+                    );
                 }
             }
         }
@@ -437,6 +1205,8 @@ impl<'a> Parser<'a> {
         Ok(Expr::FuncCall(FuncCall {
             name: name.to_string(),
             arguments: args,
+            span: start.to(&self.last_span()),
+            arg_types: OnceCell::new(),
         }))
     }
 
@@ -444,12 +1214,54 @@ impl<'a> Parser<'a> {
     pub fn parse_variable_declaration(&mut self) -> Result<Expr, ErrorType> {
         self.tokens.next(); // Consume `Token::Let`
 
-        let typ = if let Some(Token::Type(t)) = self.tokens.peek() {
-            let t = t.clone();
-            self.tokens.next(); // Consume the type token
-            Some(t)
+        // `let (a, b) = ...` destructures a tuple, while `let (int, str) pair = ...` declares a
+        // variable with a tuple type. Only in the former the closing parenthesis is followed by '='
+        if let Some(Token::LeftParen) = self.tokens.peek() {
+            let mut ahead = self.tokens.clone();
+            let mut depth = 0;
+            for token in ahead.by_ref() {
+                match token {
+                    Token::LeftParen => depth += 1,
+                    Token::RightParen => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    break;
+                }
+            }
+            if ahead.next() == Some(&Token::Equals) {
+                return self.parse_destructure();
+            }
+        }
+
+        // A struct type annotation is an identifier, possibly qualified by a module, followed by
+        // the variable name (or by the '?' of an optional type)
+        let mut ahead = self.tokens.clone();
+        if let Some(Token::Identifier(name)) = ahead.peek() {
+            let mut qualified = ahead.clone();
+            qualified.next(); // Skip the module name
+            if self.modules.contains(name.as_str()) && qualified.next() == Some(&Token::Dot) {
+                ahead = qualified;
+            }
+        }
+        let struct_type = matches!(
+            (ahead.next(), ahead.next()),
+            (
+                Some(Token::Identifier(_)),
+                Some(Token::Identifier(_) | Token::Question)
+            )
+        );
+
+        let (typ, typ_span) = if struct_type
+            || matches!(
+                self.tokens.peek(),
+                Some(Token::Type(_) | Token::LeftBracket | Token::LeftBrace | Token::LeftParen)
+            ) {
+            let start = self.peek_span();
+            let typ = self.parse_type()?;
+            (Some(typ), Some(start.to(&self.last_span())))
         } else {
-            None
+            (None, None)
         };
 
         let identifier = self
@@ -459,42 +1271,547 @@ impl<'a> Parser<'a> {
                 Token::Identifier(id) => Some(id),
                 _ => None,
             })
-            .ok_or(ErrorType::SyntaxError(
-                "Expected identifier after variable type".to_string(),
-            ))?;
+            .ok_or(self.syntax_error("Expected identifier after variable type".to_string()))?;
 
         if self.tokens.next() != Some(&Token::Equals) {
-            return Err(ErrorType::SyntaxError(
-                "Expected '=' after variable name".to_string(),
-            ));
+            return Err(self.syntax_error("Expected '=' after variable name".to_string()));
         }
 
+        let start = self.peek_span();
+        let value = self.parse_expr()?;
+        let value_span = start.to(&self.last_span());
+
         Ok(Expr::VariableDeclaration(Box::new(VariableDeclaration {
             identifier: identifier.to_string(),
             typ,
-            value: self.parse_expr()?,
+            value,
+            typ_span,
+            value_span,
+        })))
+    }
+
+    /// Parses the names and the value of a destructuring declaration: `(a, b) = pair`
+    fn parse_destructure(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume the '('
+
+        let mut names = Vec::new();
+        loop {
+            match self.tokens.next() {
+                Some(Token::RightParen) => break,
+                Some(Token::Comma) => {}
+                Some(Token::Identifier(name)) => names.push(name.to_string()),
+                _ => {
+                    return Err(self.syntax_error(
+                        "Expected variable names in parentheses after 'let'".to_string(),
+                    ))
+                }
+            }
+        }
+
+        if self.tokens.next() != Some(&Token::Equals) {
+            return Err(self.syntax_error("Expected '=' after variable names".to_string()));
+        }
+
+        let start = self.peek_span();
+        let value = self.parse_expr()?;
+        let value_span = start.to(&self.last_span());
+
+        Ok(Expr::Destructure(Box::new(Destructure {
+            names,
+            value,
+            value_span,
+        })))
+    }
+
+    /// Parses a for loop: `for item in list { ... }` or `for key, value in map { ... }`
+    pub fn parse_for(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::For`
+
+        let start = self.peek_span();
+        let variable = match self.tokens.next() {
+            Some(Token::Identifier(id)) => id.to_string(),
+            _ => return Err(self.syntax_error("Expected identifier after 'for'".to_string())),
+        };
+
+        let value_variable = if let Some(Token::Comma) = self.tokens.peek() {
+            self.tokens.next(); // Consume the comma
+            match self.tokens.next() {
+                Some(Token::Identifier(id)) => Some(id.to_string()),
+                _ => return Err(self.syntax_error("Expected identifier after ','".to_string())),
+            }
+        } else {
+            None
+        };
+        let span = start.to(&self.last_span());
+
+        if self.tokens.next() != Some(&Token::In) {
+            return Err(self.syntax_error("Expected 'in' after loop variable".to_string()));
+        }
+
+        // The '{' after the iterable opens the loop body, not a struct literal
+        self.struct_literals = false;
+        let iterable = self.parse_coalesce();
+        self.struct_literals = true;
+        let iterable = iterable?;
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after for loop iterable".to_string()));
+        }
+        let block = match self.parse_block()? {
+            Expr::Block(block) => block,
+            _ => unreachable!(),
+        };
+
+        Ok(Expr::For(Box::new(ForLoop {
+            variable,
+            value_variable,
+            iterable,
+            block,
+            span,
+        })))
+    }
+
+    /// Parses a name along with its type, as used by struct fields and function parameters:
+    /// `int x`
+    fn parse_typed_name(&mut self) -> Result<(String, Type), ErrorType> {
+        let typ = self.parse_type()?;
+        match self.tokens.next() {
+            Some(Token::Identifier(name)) => Ok((name.to_string(), typ)),
+            _ => Err(self.syntax_error(format!("Expected a name after type `{typ}`"))),
+        }
+    }
+
+    /// Parses a struct declaration: `struct Point { int x, int y }`
+    pub fn parse_struct_decl(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::Struct`
+
+        let span = self.peek_span();
+        let name = match self.tokens.next() {
+            Some(Token::Identifier(name)) => name.to_string(),
+            _ => return Err(self.syntax_error("Expected struct name after 'struct'".to_string())),
+        };
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after struct name".to_string()));
+        }
+
+        let mut fields = Vec::new();
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RightBrace) => {
+                    self.tokens.next(); // Consume the closing brace '}'
+                    break;
+                }
+                Some(Token::Comma) => {
+                    self.tokens.next(); // Consume the comma separating fields
+                }
+                Some(_) => fields.push(self.parse_typed_name()?),
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected '}'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::StructDecl(Box::new(StructDecl {
+            name,
+            public: false,
+            fields,
+            span,
+        })))
+    }
+
+    /// Parses an enum declaration: `enum Shape { Circle(int), Rect(int, int), Empty }`
+    pub fn parse_enum_decl(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::Enum`
+
+        let span = self.peek_span();
+        let name = match self.tokens.next() {
+            Some(Token::Identifier(name)) => name.to_string(),
+            _ => return Err(self.syntax_error("Expected enum name after 'enum'".to_string())),
+        };
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after enum name".to_string()));
+        }
+
+        let mut variants = Vec::new();
+        loop {
+            match self.tokens.next() {
+                Some(Token::RightBrace) => break,
+                Some(Token::Comma) => {}
+                Some(Token::Identifier(variant)) => {
+                    let mut types = Vec::new();
+                    if let Some(Token::LeftParen) = self.tokens.peek() {
+                        self.tokens.next(); // Consume the '('
+                        loop {
+                            match self.tokens.peek() {
+                                Some(Token::RightParen) => {
+                                    self.tokens.next(); // Consume the ')'
+                                    break;
+                                }
+                                Some(Token::Comma) => {
+                                    self.tokens.next(); // Consume the comma separating types
+                                }
+                                Some(_) => types.push(self.parse_type()?),
+                                None => {
+                                    return Err(self.syntax_error(
+                                        "Unexpected end of input, expected ')'".to_string(),
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                    variants.push((variant.to_string(), types));
+                }
+                Some(token) => {
+                    return Err(self.syntax_error(format!(
+                        "Unexpected token: {token:?}, expected a variant name"
+                    )))
+                }
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected '}'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::EnumDecl(Box::new(EnumDecl {
+            name,
+            public: false,
+            variants,
+            span,
+        })))
+    }
+
+    /// Parses a match expression: `match value { pattern => expr, pattern => { ... } }`
+    pub fn parse_match(&mut self) -> Result<Expr, ErrorType> {
+        let start = self.peek_span();
+        self.tokens.next(); // Consume `Token::Match`
+
+        // The '{' after the matched value opens the arms, not a struct literal
+        self.struct_literals = false;
+        let value = self.parse_coalesce();
+        self.struct_literals = true;
+        let value = value?;
+        let span = start.to(&self.last_span());
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after matched value".to_string()));
+        }
+        self.blocks.push(self.pos() - 1);
+
+        let mut arms = Vec::new();
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RightBrace) => {
+                    self.tokens.next(); // Consume the closing brace '}'
+                    break;
+                }
+                Some(Token::Comma) => {
+                    self.tokens.next(); // Consume the comma separating arms
+                }
+                Some(_) => {
+                    self.line_starts.push(self.pos());
+                    let start = self.peek_span();
+                    let pattern = self.parse_pattern()?;
+                    let span = start.to(&self.last_span());
+                    if self.tokens.next() != Some(&Token::FatArrow) {
+                        return Err(
+                            self.syntax_error("Expected '=>' after match pattern".to_string())
+                        );
+                    }
+
+                    let body = match self.tokens.peek() {
+                        Some(Token::LeftBrace) => {
+                            self.tokens.next(); // Consume the '{'
+                            self.parse_block()?
+                        }
+                        _ => self.parse_expr()?,
+                    };
+                    arms.push(MatchArm {
+                        pattern,
+                        body,
+                        span,
+                    });
+                }
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected '}'".to_string())
+                    );
+                }
+            }
+        }
+
+        Ok(Expr::Match(Box::new(Match { value, arms, span })))
+    }
+
+    /// Parses a pattern of a match arm: `_`, a variable name, a literal or an enum variant with
+    /// nested patterns `Shape::Rect(w, _)`
+    fn parse_pattern(&mut self) -> Result<Pattern, ErrorType> {
+        match self.tokens.next() {
+            Some(Token::Identifier(name)) if name == "_" => Ok(Pattern::Wildcard),
+            Some(Token::Identifier(name)) => {
+                let name = self.qualified(name);
+                if self.tokens.peek() != Some(&&Token::DoubleColon) {
+                    return Ok(Pattern::Binding(name));
+                }
+                self.tokens.next(); // Consume the '::'
+
+                let variant = match self.tokens.next() {
+                    Some(Token::Identifier(variant)) => variant.to_string(),
+                    _ => {
+                        return Err(
+                            self.syntax_error("Expected variant name after '::'".to_string())
+                        )
+                    }
+                };
+
+                let mut fields = Vec::new();
+                if let Some(Token::LeftParen) = self.tokens.peek() {
+                    self.tokens.next(); // Consume the '('
+                    loop {
+                        match self.tokens.peek() {
+                            Some(Token::RightParen) => {
+                                self.tokens.next(); // Consume the ')'
+                                break;
+                            }
+                            Some(Token::Comma) => {
+                                self.tokens.next(); // Consume the comma separating patterns
+                            }
+                            Some(_) => fields.push(self.parse_pattern()?),
+                            None => {
+                                return Err(self.syntax_error(
+                                    "Unexpected end of input, expected ')'".to_string(),
+                                ));
+                            }
+                        }
+                    }
+                }
+
+                Ok(Pattern::Variant {
+                    enum_name: name,
+                    variant,
+                    fields,
+                })
+            }
+            Some(Token::Number(n)) => Ok(Pattern::Literal(Expr::Number(*n))),
+            Some(Token::Minus) => match self.tokens.next() {
+                Some(Token::Number(n)) => Ok(Pattern::Literal(Expr::Number(-n))),
+                _ => Err(self.syntax_error("Expected a number after '-' in pattern".to_string())),
+            },
+            Some(Token::StringLiteral(s)) => Ok(Pattern::Literal(Expr::StringLiteral(s.clone()))),
+            Some(Token::True) => Ok(Pattern::Literal(Expr::Bool(Bool::True))),
+            Some(Token::False) => Ok(Pattern::Literal(Expr::Bool(Bool::False))),
+            Some(token) => {
+                Err(self.syntax_error(format!("Unexpected token: {token:?}, expected a pattern")))
+            }
+            None => {
+                Err(self.syntax_error("Unexpected end of input, expected a pattern".to_string()))
+            }
+        }
+    }
+
+    /// Parses a function declaration: `fn add(int a, int b) -> int { ... }`
+    pub fn parse_func_decl(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::Fn`
+
+        let span = self.peek_span();
+        let name = match self.tokens.next() {
+            Some(Token::Identifier(name)) => name.to_string(),
+            _ => return Err(self.syntax_error("Expected function name after 'fn'".to_string())),
+        };
+
+        if self.tokens.next() != Some(&Token::LeftParen) {
+            return Err(self.syntax_error("Expected '(' after function name".to_string()));
+        }
+
+        let mut params = Vec::new();
+        loop {
+            match self.tokens.peek() {
+                Some(Token::RightParen) => {
+                    self.tokens.next(); // Consume the closing parenthesis ')'
+                    break;
+                }
+                Some(Token::Comma) => {
+                    self.tokens.next(); // Consume the comma separating parameters
+                }
+                Some(_) => params.push(self.parse_typed_name()?),
+                None => {
+                    return Err(
+                        self.syntax_error("Unexpected end of input, expected ')'".to_string())
+                    );
+                }
+            }
+        }
+
+        let return_type = if let Some(Token::Arrow) = self.tokens.peek() {
+            self.tokens.next(); // Consume the '->'
+            self.parse_type()?
+        } else {
+            Type::None
+        };
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' before function body".to_string()));
+        }
+        let block = match self.parse_block()? {
+            Expr::Block(block) => block,
+            _ => unreachable!(),
+        };
+
+        Ok(Expr::FuncDecl(Box::new(FuncDecl {
+            name,
+            public: false,
+            params,
+            return_type,
+            block,
+            span,
+        })))
+    }
+
+    /// Parses a declaration made public with `pub`: `pub fn`, `pub struct` or `pub enum`
+    pub fn parse_pub(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::Pub`
+
+        let mut decl = match self.tokens.peek() {
+            Some(Token::Fn) => self.parse_func_decl()?,
+            Some(Token::Struct) => self.parse_struct_decl()?,
+            Some(Token::Enum) => self.parse_enum_decl()?,
+            _ => {
+                return Err(
+                    self.syntax_error("Expected 'fn', 'struct' or 'enum' after 'pub'".to_string())
+                )
+            }
+        };
+        match &mut decl {
+            Expr::FuncDecl(decl) => decl.public = true,
+            Expr::StructDecl(decl) => decl.public = true,
+            Expr::EnumDecl(decl) => decl.public = true,
+            _ => unreachable!(),
+        }
+
+        Ok(decl)
+    }
+
+    /// Parses an import of a module: `import math`, or `import "lib/math.blk"` with a path relative
+    /// to the importing file. The module is named after its file
+    pub fn parse_import(&mut self) -> Result<Expr, ErrorType> {
+        let start = self.peek_span();
+        self.tokens.next(); // Consume `Token::Import`
+
+        let (name, path) = match self.tokens.next() {
+            Some(Token::Identifier(name)) => (name.to_string(), None),
+            Some(Token::StringLiteral(path)) => {
+                let name = Path::new(path)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or_default();
+                if !is_identifier(name) {
+                    return Err(self.syntax_error(format!(
+                        "Cannot import \"{path}\", the file name isn't a valid module name"
+                    )));
+                }
+                (name.to_string(), Some(path.to_string()))
+            }
+            _ => {
+                return Err(
+                    self.syntax_error("Expected a module name or path after 'import'".to_string())
+                )
+            }
+        };
+        self.modules.insert(name.clone());
+
+        Ok(Expr::Import(Box::new(Import {
+            name,
+            path,
+            span: start.to(&self.last_span()),
+            module: String::new(),
+            file: String::new(),
+            body: Vec::new(),
         })))
     }
 
     /// Parses return statement
     pub fn parse_return(&mut self) -> Result<Expr, ErrorType> {
+        let start = self.peek_span();
         self.tokens.next(); // Consume `Token::Return`
 
-        Ok(Expr::Return(Box::new(self.parse_expr()?)))
+        let value = self.parse_expr()?;
+        Ok(Expr::Return(Box::new(Return {
+            value,
+            span: start.to(&self.last_span()),
+        })))
     }
 
-    // pub fn parse_if_statement(&mut self) -> Result<Expr, ErrorType> {
-    //     self.tokens.next(); // Consume `Token::If`
-    //
-    //     Ok(Expr::IfStatement(Box::new(IfStatement {
-    //         comparison: identifier.to_string(),
-    //         block,
-    //     })))
-    // }
+    /// Parses an if statement: `if condition { ... } else { ... }` or
+    /// `if let name = optional { ... }`, optionally followed by `else if ...`
+    pub fn parse_if_statement(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::If`
+
+        let binding = if let Some(Token::Let) = self.tokens.peek() {
+            self.tokens.next(); // Consume `Token::Let`
+            let name = match self.tokens.next() {
+                Some(Token::Identifier(name)) => name.to_string(),
+                _ => {
+                    return Err(self.syntax_error("Expected identifier after 'if let'".to_string()))
+                }
+            };
+            if self.tokens.next() != Some(&Token::Equals) {
+                return Err(self.syntax_error("Expected '=' after variable name".to_string()));
+            }
+            Some(name)
+        } else {
+            None
+        };
+
+        // The '{' after the condition opens the block, not a struct literal
+        let start = self.peek_span();
+        self.struct_literals = false;
+        let condition = self.parse_coalesce();
+        self.struct_literals = true;
+        let condition = condition?;
+        let span = start.to(&self.last_span());
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after if condition".to_string()));
+        }
+        let block = match self.parse_block()? {
+            Expr::Block(block) => block,
+            _ => unreachable!(),
+        };
+
+        let mut else_block = Vec::new();
+        if let Some(Token::Else) = self.tokens.peek() {
+            self.tokens.next(); // Consume `Token::Else`
+            match self.tokens.peek() {
+                Some(Token::LeftBrace) => {
+                    self.tokens.next(); // Consume the '{'
+                    else_block = match self.parse_block()? {
+                        Expr::Block(block) => block,
+                        _ => unreachable!(),
+                    }
+                }
+                Some(Token::If) => else_block.push(self.parse_if_statement()?),
+                _ => return Err(self.syntax_error("Expected '{' or 'if' after 'else'".to_string())),
+            }
+        }
+
+        Ok(Expr::IfStatement(Box::new(IfStatement {
+            binding,
+            condition,
+            block,
+            else_block,
+            span,
+        })))
+    }
 
     /// Parses binary expressions (e.g., addition, multiplication)
     pub fn parse_binary(&mut self, operators: &[Token]) -> Result<Expr, ErrorType> {
-        let mut left = self.parse_primary()?;
+        let start = self.peek_span();
+        let mut left = self.parse_postfix()?;
 
         while let Some(op) = self.tokens.peek() {
             if operators.contains(op) {
@@ -507,12 +1824,13 @@ impl<'a> Parser<'a> {
                 };
                 self.tokens.next(); // Consume operator
 
-                let right = self.parse_primary()?;
+                let right = self.parse_postfix()?;
 
                 left = Expr::BinExpr(Box::new(BinExpr {
                     lhs: left,
                     kind: operator,
                     rhs: right,
+                    span: start.to(&self.last_span()),
                 }));
             } else {
                 break;
@@ -522,31 +1840,136 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
 
+    /// Parses a try statement: `try { ... } catch e { ... }`
+    pub fn parse_try(&mut self) -> Result<Expr, ErrorType> {
+        self.tokens.next(); // Consume `Token::Try`
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after 'try'".to_string()));
+        }
+        let block = match self.parse_block()? {
+            Expr::Block(block) => block,
+            _ => unreachable!(),
+        };
+
+        if self.tokens.next() != Some(&Token::Catch) {
+            return Err(self.syntax_error("Expected 'catch' after try block".to_string()));
+        }
+        let mut span = self.last_span();
+        let variable = match self.tokens.peek() {
+            Some(Token::Identifier(name)) => {
+                span = self.peek_span();
+                let name = name.to_string();
+                self.tokens.next(); // Consume the variable name
+                Some(name)
+            }
+            _ => None,
+        };
+
+        if self.tokens.next() != Some(&Token::LeftBrace) {
+            return Err(self.syntax_error("Expected '{' after 'catch'".to_string()));
+        }
+        let catch_block = match self.parse_block()? {
+            Expr::Block(block) => block,
+            _ => unreachable!(),
+        };
+
+        Ok(Expr::TryCatch(Box::new(TryCatch {
+            block,
+            variable,
+            catch_block,
+            span,
+        })))
+    }
+
+    /// Parses binary expressions followed by any number of fallbacks for optional values:
+    /// `value ?? default`
+    pub fn parse_coalesce(&mut self) -> Result<Expr, ErrorType> {
+        let start = self.peek_span();
+        let operators = [Token::Multiply, Token::Divide, Token::Plus, Token::Minus];
+        let mut expr = self.parse_binary(&operators)?;
+
+        while let Some(Token::Coalesce) = self.tokens.peek() {
+            self.tokens.next(); // Consume the '??'
+            let default = self.parse_binary(&operators)?;
+            expr = Expr::Coalesce(Box::new(Coalesce {
+                value: expr,
+                default,
+                span: start.to(&self.last_span()),
+                typ: OnceCell::new(),
+            }));
+        }
+
+        Ok(expr)
+    }
+
     /// Parses general expressions
     pub fn parse_expr(&mut self) -> Result<Expr, ErrorType> {
         let peek = match self.tokens.peek() {
             Some(peek) => peek,
-            None => {
-                return Err(ErrorType::SyntaxError(
-                    "Unexpected end of input".to_string(),
-                ))
-            }
+            None => return Err(self.syntax_error("Unexpected end of input".to_string())),
         };
 
         match peek {
             Token::Let => self.parse_variable_declaration(),
             Token::Return => self.parse_return(),
-            // Token::If => self.parse_if_statement(),
-            _ => self.parse_binary(&[Token::Multiply, Token::Divide, Token::Plus, Token::Minus]),
+            Token::For => self.parse_for(),
+            Token::Struct => self.parse_struct_decl(),
+            Token::Fn => self.parse_func_decl(),
+            Token::Enum => self.parse_enum_decl(),
+            Token::Match => self.parse_match(),
+            Token::If => self.parse_if_statement(),
+            Token::Try => self.parse_try(),
+            Token::Pub => self.parse_pub(),
+            Token::Import => {
+                Err(self.syntax_error("Modules can only be imported at the top level".to_string()))
+            }
+            _ => {
+                let start = self.peek_span();
+                let expr = self.parse_coalesce()?;
+
+                // Assignment to a variable, element or field: `target = value`
+                if let (
+                    Some(Token::Equals),
+                    Expr::Identifier(_) | Expr::Index(_) | Expr::Field(_) | Expr::TupleIndex(_),
+                ) = (self.tokens.peek(), &expr)
+                {
+                    self.tokens.next(); // Consume the '='
+                    let value = self.parse_expr()?;
+                    return Ok(Expr::Assignment(Box::new(Assignment {
+                        target: expr,
+                        value,
+                        span: start.to(&self.last_span()),
+                        typ: None,
+                    })));
+                }
+
+                Ok(expr)
+            }
+        }
+    }
+
+    /// Parses a statement. A `{` at the start of a statement opens a code block, anywhere else
+    /// it's a map literal
+    pub fn parse_statement(&mut self) -> Result<Expr, ErrorType> {
+        if let Some(Token::LeftBrace) = self.tokens.peek() {
+            self.tokens.next(); // Consume the '{'
+            return self.parse_block();
         }
+
+        self.parse_expr()
     }
 
     /// Parses a complete program into an AST
     pub fn parse(&mut self) -> Result<Ast, ErrorType> {
         let mut ast = Vec::new();
 
-        while self.tokens.peek().is_some() {
-            let expr = self.parse_expr()?;
+        while let Some(&token) = self.tokens.peek() {
+            self.line_starts.push(self.pos());
+            let expr = match token {
+                Token::Import => self.parse_import()?,
+                _ => self.parse_statement()?,
+            };
             ast.push(expr);
         }
 
@@ -0,0 +1,880 @@
+use crate::{
+    args::{AppArgs, Subcommand},
+    linter::{Level, Rule},
+    parser::{is_identifier, Span},
+    utils::ErrorType,
+};
+use std::{
+    collections::HashMap,
+    env::current_dir,
+    fs::{create_dir_all, read_to_string, remove_dir_all, write},
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+/// File describing a project: its name, entry point and dependencies
+pub const MANIFEST: &str = "black.toml";
+
+/// File pinning the dependencies of a project to the exact versions they resolved to
+pub const LOCKFILE: &str = "black.lock";
+
+/// Directory holding everything built for a project, including checked out dependencies
+pub const TARGET: &str = "target";
+
+/// Value of a key in a manifest. Only the parts of TOML used by manifests are supported
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    /// Inline table: `{ key = value, ... }`
+    Table(Vec<Entry>),
+}
+
+/// A `key = value` line of a manifest, along with the location of the key
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    key: String,
+    value: Value,
+    span: Span,
+}
+
+/// A `[name]` section of a manifest, along with the location of its header
+struct Section {
+    name: String,
+    span: Span,
+    entries: Vec<Entry>,
+}
+
+/// Parses a manifest into its sections and their entries. Entries before the first section
+/// belong to the section named ""
+fn parse_toml(source: &str) -> Result<Vec<Section>, ErrorType> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        span: Span::default(),
+        entries: Vec::new(),
+    }];
+
+    for (i, line) in source.lines().enumerate() {
+        let mut cursor = Cursor {
+            chars: line.chars().collect(),
+            pos: 0,
+            line: i + 1,
+        };
+        cursor.skip_space();
+        if cursor.at_end() {
+            continue;
+        }
+
+        if cursor.eat('[') {
+            let name = cursor.key()?;
+            cursor.skip_space();
+            if !cursor.eat(']') {
+                return Err(cursor.error("Expected ']' after the section name"));
+            }
+            cursor.expect_end()?;
+            let span = Span::new(i + 1, 1, line.trim_end().len());
+            if sections.iter().any(|section| section.name == name) {
+                return Err(ErrorType::ManifestError(
+                    format!("Section `[{name}]` is defined more than once"),
+                    span,
+                ));
+            }
+            sections.push(Section {
+                name,
+                span,
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let entry = cursor.entry()?;
+        cursor.expect_end()?;
+        let section = sections.last_mut().expect("there's always a section");
+        push_entry(&mut section.entries, entry)?;
+    }
+
+    Ok(sections)
+}
+
+/// Adds an entry to a table, keys have to be unique
+fn push_entry(entries: &mut Vec<Entry>, entry: Entry) -> Result<(), ErrorType> {
+    if entries.iter().any(|other| other.key == entry.key) {
+        return Err(ErrorType::ManifestError(
+            format!("Key `{}` is defined more than once", entry.key),
+            entry.span,
+        ));
+    }
+    entries.push(entry);
+
+    Ok(())
+}
+
+/// Position in a line of a manifest
+struct Cursor {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Cursor {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Whether the rest of the line is empty or a comment
+    fn at_end(&self) -> bool {
+        matches!(self.peek(), None | Some('#'))
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consumes the character if it's next
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    /// Error pointing at the current position
+    fn error(&self, message: &str) -> ErrorType {
+        ErrorType::ManifestError(message.to_string(), Span::new(self.line, self.pos + 1, 1))
+    }
+
+    fn expect_end(&mut self) -> Result<(), ErrorType> {
+        self.skip_space();
+        match self.at_end() {
+            true => Ok(()),
+            false => Err(self.error("Unexpected characters at the end of the line")),
+        }
+    }
+
+    /// Parses a bare key (`name`) or a quoted one (`"owner/name"`)
+    fn key(&mut self) -> Result<String, ErrorType> {
+        self.skip_space();
+        if self.peek() == Some('"') {
+            return self.string();
+        }
+
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            self.pos += 1;
+        }
+        match self.pos > start {
+            true => Ok(self.chars[start..self.pos].iter().collect()),
+            false => Err(self.error("Expected a key")),
+        }
+    }
+
+    /// Parses `key = value`
+    fn entry(&mut self) -> Result<Entry, ErrorType> {
+        self.skip_space();
+        let start = self.pos;
+        let key = self.key()?;
+        let span = Span::new(self.line, start + 1, self.pos - start);
+        self.skip_space();
+        if !self.eat('=') {
+            return Err(self.error("Expected '=' after the key"));
+        }
+
+        Ok(Entry {
+            key,
+            value: self.value()?,
+            span,
+        })
+    }
+
+    /// Parses a string, a boolean or an inline table
+    fn value(&mut self) -> Result<Value, ErrorType> {
+        self.skip_space();
+        match self.peek() {
+            Some('"') => Ok(Value::Str(self.string()?)),
+            Some('{') => {
+                self.pos += 1;
+                let mut entries = Vec::new();
+                self.skip_space();
+                if self.eat('}') {
+                    return Ok(Value::Table(entries));
+                }
+                loop {
+                    let entry = self.entry()?;
+                    push_entry(&mut entries, entry)?;
+                    self.skip_space();
+                    if self.eat('}') {
+                        return Ok(Value::Table(entries));
+                    }
+                    if !self.eat(',') {
+                        return Err(self.error("Expected ',' or '}' in the inline table"));
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    self.pos += 1;
+                }
+                let word: String = self.chars[start..self.pos].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => {
+                        self.pos = start;
+                        Err(self.error("Expected a string, a boolean or an inline table"))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses a basic string, supporting the `\"`, `\\`, `\n` and `\t` escapes
+    fn string(&mut self) -> Result<String, ErrorType> {
+        self.pos += 1; // Consume the opening '"'
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        _ => return Err(self.error("Unknown escape sequence")),
+                    };
+                    string.push(escaped);
+                }
+                Some(c) => string.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+            self.pos += 1;
+        }
+    }
+}
+
+/// Where a dependency comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A project in a directory, relative to the project depending on it
+    Path(PathBuf),
+    /// A project in a git repository, at the given revision (tag, branch or commit)
+    Git { url: String, rev: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    /// Name of the dependency, its last `/` separated part is the name it's imported as
+    pub name: String,
+    pub source: Source,
+    /// Location of the dependency in the manifest
+    pub span: Span,
+}
+
+impl Dependency {
+    /// Name the dependency is imported as: `package` for `owner/package`
+    pub fn module(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+}
+
+/// Contents of a `black.toml` manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    /// Source file the program starts at, relative to the manifest
+    pub entry: PathBuf,
+    /// Name of the built binary in the target directory
+    pub output: String,
+    pub static_link: bool,
+    pub dependencies: Vec<Dependency>,
+    /// Levels of the linter rules set in the `[lint]` section
+    pub lints: Vec<(Rule, Level)>,
+}
+
+impl Manifest {
+    /// Parses a manifest. Errors aren't attributed to the manifest file yet
+    pub fn parse(source: &str) -> Result<Self, ErrorType> {
+        let mut name = None;
+        let mut entry = PathBuf::from("src/main.blk");
+        let mut output = None;
+        let mut static_link = false;
+        let mut dependencies = Vec::new();
+        let mut lints = Vec::new();
+
+        for Section {
+            name: section,
+            span,
+            entries,
+        } in parse_toml(source)?
+        {
+            match section.as_str() {
+                "package" => {
+                    for Entry { key, value, span } in entries {
+                        match (key.as_str(), value) {
+                            ("name", Value::Str(value)) => name = Some(value),
+                            ("entry", Value::Str(value)) => entry = PathBuf::from(value),
+                            ("output", Value::Str(value)) => {
+                                // The binary mustn't be written outside of the target directory
+                                let path = Path::new(&value);
+                                if path.has_root()
+                                    || path.components().any(|c| c == Component::ParentDir)
+                                {
+                                    return Err(ErrorType::ManifestError(
+                                        "`output` must be a path inside the target directory, \
+                                         not absolute or containing `..`"
+                                            .to_string(),
+                                        span,
+                                    ));
+                                }
+                                output = Some(value)
+                            }
+                            ("static", Value::Bool(value)) => static_link = value,
+                            ("name" | "entry" | "output", _) => {
+                                return Err(ErrorType::ManifestError(
+                                    format!("`{key}` must be a string"),
+                                    span,
+                                ))
+                            }
+                            ("static", _) => {
+                                return Err(ErrorType::ManifestError(
+                                    "`static` must be `true` or `false`".to_string(),
+                                    span,
+                                ))
+                            }
+                            _ => {
+                                return Err(ErrorType::ManifestError(
+                                    format!("Unknown key `{key}` in `[package]`"),
+                                    span,
+                                ))
+                            }
+                        }
+                    }
+                }
+                "dependencies" => {
+                    for entry in entries {
+                        dependencies.push(Self::dependency(entry)?);
+                    }
+                }
+                "lint" => {
+                    for entry in entries {
+                        lints.push(Self::lint(entry)?);
+                    }
+                }
+                "" if entries.is_empty() => {}
+                "" => {
+                    return Err(ErrorType::ManifestError(
+                        "Keys must be in a section, like `[package]`".to_string(),
+                        entries[0].span,
+                    ))
+                }
+                _ => {
+                    return Err(ErrorType::ManifestError(
+                        format!("Unknown section `[{section}]`"),
+                        span,
+                    ))
+                }
+            }
+        }
+
+        let Some(name) = name else {
+            return Err(ErrorType::Generic(
+                "The manifest needs a `name` in its `[package]` section".to_string(),
+            ));
+        };
+        Ok(Self {
+            output: output.unwrap_or(name.clone()),
+            name,
+            entry,
+            static_link,
+            dependencies,
+            lints,
+        })
+    }
+
+    /// Parses the level of a linter rule: `unused_variable = "deny"`
+    fn lint(Entry { key, value, span }: Entry) -> Result<(Rule, Level), ErrorType> {
+        let Some(rule) = Rule::from_name(&key) else {
+            let rules: Vec<&str> = Rule::ALL.iter().map(Rule::name).collect();
+            return Err(ErrorType::ManifestError(
+                format!(
+                    "Unknown lint rule `{key}`, the rules are: {}",
+                    rules.join(", ")
+                ),
+                span,
+            ));
+        };
+        match value {
+            Value::Str(level) if Level::from_name(&level).is_some() => Ok((
+                rule,
+                Level::from_name(&level).expect("the level was just checked"),
+            )),
+            _ => Err(ErrorType::ManifestError(
+                format!("The level of `{key}` must be \"allow\", \"warn\" or \"deny\""),
+                span,
+            )),
+        }
+    }
+
+    /// Parses a dependency: `name = { path = "..." }` or `name = { git = "...", rev = "..." }`
+    fn dependency(Entry { key, value, span }: Entry) -> Result<Dependency, ErrorType> {
+        let error = |message: String| Err(ErrorType::ManifestError(message, span));
+
+        let fields = match value {
+            Value::Table(fields) => fields,
+            Value::Str(version) => {
+                return error(format!(
+                    "Dependency `{key}` asks for version \"{version}\", fetching packages isn't \
+                     supported yet, use `{{ path = \"...\" }}` or `{{ git = \"file://...\" }}`"
+                ))
+            }
+            _ => return error(format!("Dependency `{key}` must be an inline table")),
+        };
+        let mut path = None;
+        let mut git = None;
+        let mut rev = None;
+        for field in fields {
+            match (field.key.as_str(), field.value) {
+                ("path", Value::Str(value)) => path = Some(value),
+                ("git", Value::Str(value)) => git = Some(value),
+                ("rev", Value::Str(value)) => rev = Some(value),
+                ("path" | "git" | "rev", _) => {
+                    return error(format!(
+                        "`{}` of dependency `{key}` must be a string",
+                        field.key
+                    ))
+                }
+                (field, _) => return error(format!("Unknown key `{field}` of dependency `{key}`")),
+            }
+        }
+
+        let source = match (path, git) {
+            (Some(path), None) if rev.is_none() => Source::Path(PathBuf::from(path)),
+            (None, Some(url)) => Source::Git { url, rev },
+            (Some(_), None) => return error(format!("`rev` of dependency `{key}` needs `git`")),
+            _ => return error(format!("Dependency `{key}` needs either `path` or `git`")),
+        };
+        let dependency = Dependency {
+            name: key,
+            source,
+            span,
+        };
+        if !is_identifier(dependency.module()) {
+            return error(format!(
+                "Dependency `{}` can't be imported, `{}` isn't a valid module name",
+                dependency.name,
+                dependency.module()
+            ));
+        }
+
+        Ok(dependency)
+    }
+}
+
+/// A dependency as pinned by the lockfile
+#[derive(Debug, Clone, PartialEq)]
+struct Locked {
+    source: Source,
+    /// Commit a git dependency resolved to
+    commit: Option<String>,
+}
+
+/// Parses a lockfile into the pinned dependencies by their names
+fn parse_lockfile(source: &str) -> Result<HashMap<String, Locked>, ErrorType> {
+    let mut locked = HashMap::new();
+    for section in parse_toml(source)? {
+        for entry in section.entries {
+            let Value::Table(fields) = entry.value else {
+                continue;
+            };
+            let field = |name: &str| {
+                fields.iter().find_map(|field| match &field.value {
+                    Value::Str(value) if field.key == name => Some(value.clone()),
+                    _ => None,
+                })
+            };
+            let source = match (field("path"), field("git")) {
+                (Some(path), _) => Source::Path(PathBuf::from(path)),
+                (_, Some(url)) => Source::Git {
+                    url,
+                    rev: field("rev"),
+                },
+                _ => continue,
+            };
+            let commit = field("commit");
+            locked.insert(entry.key, Locked { source, commit });
+        }
+    }
+
+    Ok(locked)
+}
+
+/// Quotes a string for a manifest or a lockfile
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Formats a key, quoting it unless it's a valid bare key
+fn key(key: &str) -> String {
+    match key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        true => key.to_string(),
+        false => quote(key),
+    }
+}
+
+/// Formats the lockfile of the resolved dependencies, sorted by name so that it only changes
+/// when the dependencies do
+fn format_lockfile(resolved: &HashMap<String, Locked>) -> String {
+    let mut names: Vec<&String> = resolved.keys().collect();
+    names.sort();
+
+    let mut lockfile = "# Generated by black, pins the dependencies of the project\n".to_string();
+    lockfile.push_str("[dependencies]\n");
+    for name in names {
+        let locked = &resolved[name];
+        let mut fields = match &locked.source {
+            Source::Path(path) => vec![format!("path = {}", quote(&path.to_string_lossy()))],
+            Source::Git { url, rev } => {
+                let mut fields = vec![format!("git = {}", quote(url))];
+                fields.extend(rev.iter().map(|rev| format!("rev = {}", quote(rev))));
+                fields
+            }
+        };
+        fields.extend(
+            locked
+                .commit
+                .iter()
+                .map(|commit| format!("commit = {}", quote(commit))),
+        );
+        lockfile.push_str(&format!("{} = {{ {} }}\n", key(name), fields.join(", ")));
+    }
+
+    lockfile
+}
+
+/// A project with all of its dependencies resolved
+#[derive(Debug)]
+pub struct Project {
+    /// Directory of the manifest
+    pub root: PathBuf,
+    pub manifest: Manifest,
+    /// Entry files of the dependencies, by the names they're imported as
+    pub packages: Vec<(String, PathBuf)>,
+}
+
+impl Project {
+    /// Finds the manifest in the current directory or in one of its parents. The returned
+    /// directory is relative when it's the current one, so that messages show short paths
+    pub fn find() -> Result<PathBuf, ErrorType> {
+        let cwd = current_dir()
+            .map_err(|_| ErrorType::Generic("Could not get the current directory".to_string()))?;
+        cwd.ancestors()
+            .find(|dir| dir.join(MANIFEST).is_file())
+            .map(|dir| dir.strip_prefix(&cwd).unwrap_or(dir).to_path_buf())
+            .ok_or(ErrorType::Generic(format!(
+                "Could not find `{MANIFEST}` in the current directory or any of its parents"
+            )))
+    }
+
+    /// Reads the manifest of the project in the directory and resolves its dependencies,
+    /// checking git dependencies out to the target directory. The lockfile is written whenever
+    /// the resolved dependencies change
+    pub fn load(root: &Path) -> Result<Self, ErrorType> {
+        let manifest = read_manifest(root)?;
+
+        let lock_path = root.join(LOCKFILE);
+        let locked = match read_to_string(&lock_path) {
+            Ok(source) => {
+                parse_lockfile(&source).map_err(|err| err.in_file(&display(&lock_path)))?
+            }
+            Err(_) => HashMap::new(),
+        };
+
+        let mut resolver = Resolver {
+            root: root.to_path_buf(),
+            locked,
+            resolved: HashMap::new(),
+            packages: Vec::new(),
+        };
+        resolver
+            .resolve(&manifest, root)
+            .map_err(|err| err.in_file(&display(&root.join(MANIFEST))))?;
+
+        let lockfile = format_lockfile(&resolver.resolved);
+        if read_to_string(&lock_path).ok().as_deref() != Some(lockfile.as_str()) {
+            write(&lock_path, lockfile).map_err(|_| {
+                ErrorType::Generic(format!("Could not write \"{}\"", display(&lock_path)))
+            })?;
+        }
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            manifest,
+            packages: resolver.packages,
+        })
+    }
+
+    /// Arguments building (or interpreting) the project: its entry file, the dependencies as
+    /// modules and the binary in the target directory
+    pub fn app_args(&self, args: &AppArgs) -> Result<AppArgs, ErrorType> {
+        let target = self.root.join(TARGET);
+        create_dir_all(&target).map_err(|_| {
+            ErrorType::Generic(format!("Could not create \"{}\"", display(&target)))
+        })?;
+
+        let run = args.command == Some(Subcommand::Run);
+        let output = target.join(&self.manifest.output);
+        Ok(AppArgs {
+            input: Some(self.root.join(&self.manifest.entry)),
+            modules: args.modules.clone(),
+            packages: self.packages.clone(),
+            // Emitted stages go next to the binary in target/
+            emit: args
+                .emit
+                .iter()
+                .map(|(kind, _)| (*kind, kind.path(&output)))
+                .collect(),
+            output,
+            qbe: args.qbe.clone(),
+            cc: args.cc.clone(),
+            link_args: args.link_args.clone(),
+            target: args.target,
+            sysroot: args.sysroot.clone(),
+            backend: args.backend,
+            static_link: args.static_link || self.manifest.static_link,
+            // `black run -i` interprets the project instead of building it
+            interpreter: run && args.interpreter,
+            build_and_run: run && !args.interpreter,
+            command: args.command,
+            files: Vec::new(),
+            check: false,
+            program_args: args.program_args.clone(),
+        })
+    }
+}
+
+/// Displays a path the way it's shown in messages
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+/// Removes the `.` components of a path, and the `..` ones following a directory name
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Reads and parses the manifest in the directory
+pub fn read_manifest(dir: &Path) -> Result<Manifest, ErrorType> {
+    let path = dir.join(MANIFEST);
+    let source = read_to_string(&path)
+        .map_err(|_| ErrorType::Generic(format!("Could not read \"{}\"", display(&path))))?;
+    Manifest::parse(&source).map_err(|err| err.in_file(&display(&path)))
+}
+
+/// Resolves the dependencies of a project, and the dependencies of those
+struct Resolver {
+    root: PathBuf,
+    /// Dependencies pinned by the lockfile
+    locked: HashMap<String, Locked>,
+    /// Dependencies resolved so far, written to the lockfile
+    resolved: HashMap<String, Locked>,
+    /// Entry files of the resolved dependencies, by the names they're imported as
+    packages: Vec<(String, PathBuf)>,
+}
+
+impl Resolver {
+    /// Resolves the dependencies of the manifest of the project in the directory
+    fn resolve(&mut self, manifest: &Manifest, dir: &Path) -> Result<(), ErrorType> {
+        for dependency in &manifest.dependencies {
+            let error = |message: String| ErrorType::ManifestError(message, dependency.span);
+
+            let (dep_dir, locked) = match &dependency.source {
+                Source::Path(path) => {
+                    let dep_dir = normalize(&dir.join(path));
+                    // Paths are recorded relative to the root project
+                    let recorded = dep_dir.strip_prefix(&self.root).unwrap_or(&dep_dir);
+                    let locked = Locked {
+                        source: Source::Path(recorded.to_path_buf()),
+                        commit: None,
+                    };
+                    (dep_dir, locked)
+                }
+                Source::Git { url, rev } => {
+                    if !url.starts_with("file://") {
+                        return Err(error(format!(
+                            "Dependency `{}` would need fetching \"{url}\" over the network, \
+                             only local `file://` git repositories are supported for now",
+                            dependency.name
+                        )));
+                    }
+                    let dep_dir = self
+                        .root
+                        .join(TARGET)
+                        .join("deps")
+                        .join(dependency.name.replace('/', "-"));
+                    let commit = self.checkout(dependency, url, rev.as_deref(), &dep_dir)?;
+                    let locked = Locked {
+                        source: dependency.source.clone(),
+                        commit: Some(commit),
+                    };
+                    (dep_dir, locked)
+                }
+            };
+
+            if let Some(other) = self.resolved.get(&dependency.name) {
+                if *other != locked {
+                    return Err(error(format!(
+                        "Dependency `{}` is required from two different sources",
+                        dependency.name
+                    )));
+                }
+                continue;
+            }
+            if self
+                .packages
+                .iter()
+                .any(|(name, _)| name == dependency.module())
+            {
+                return Err(error(format!(
+                    "Two dependencies would be imported as `{}`",
+                    dependency.module()
+                )));
+            }
+            if !dep_dir.join(MANIFEST).is_file() {
+                return Err(error(format!(
+                    "Dependency `{}` has no `{MANIFEST}` in \"{}\"",
+                    dependency.name,
+                    display(&dep_dir)
+                )));
+            }
+
+            // Dependencies are resolved once, which also ends dependency cycles
+            let dep_manifest = read_manifest(&dep_dir)?;
+            self.resolved.insert(dependency.name.clone(), locked);
+            self.packages.push((
+                dependency.module().to_string(),
+                dep_dir.join(&dep_manifest.entry),
+            ));
+            self.resolve(&dep_manifest, &dep_dir)
+                .map_err(|err| err.in_file(&display(&dep_dir.join(MANIFEST))))?;
+        }
+
+        Ok(())
+    }
+
+    /// Clones (or updates) a git dependency into the directory and checks out the commit pinned
+    /// by the lockfile, or the one the requested revision points at. Returns the commit
+    fn checkout(
+        &self,
+        dependency: &Dependency,
+        url: &str,
+        rev: Option<&str>,
+        dir: &Path,
+    ) -> Result<String, ErrorType> {
+        let git = |args: &[&str]| {
+            git(args).map_err(|message| {
+                ErrorType::ManifestError(
+                    format!("Could not check out `{}`: {message}", dependency.name),
+                    dependency.span,
+                )
+            })
+        };
+        let dir_str = dir.to_string_lossy();
+
+        let origin = match dir.join(".git").exists() {
+            true => git(&["-C", &dir_str, "remote", "get-url", "origin"]).ok(),
+            false => None,
+        };
+        if origin.as_deref() == Some(url) {
+            git(&[
+                "-C", &dir_str, "fetch", "--quiet", "--tags", "--force", "origin",
+            ])?;
+        } else {
+            // A clone of the repository the manifest asked for before is replaced
+            if dir.exists() {
+                remove_dir_all(dir).map_err(|err| {
+                    ErrorType::ManifestError(
+                        format!("Could not check out `{}`: {err}", dependency.name),
+                        dependency.span,
+                    )
+                })?;
+            }
+            git(&["clone", "--quiet", "--no-checkout", url, &dir_str])?;
+        }
+
+        // The lockfile only applies as long as the dependency asks for the same repository and
+        // revision
+        let locked = self
+            .locked
+            .get(&dependency.name)
+            .filter(|locked| locked.source == dependency.source && locked.commit.is_some());
+        let commit = match locked.and_then(|locked| locked.commit.clone()) {
+            Some(commit) => commit,
+            None => {
+                // Branches are looked up in the cloned repository, not in the local copy
+                let candidates = match rev {
+                    Some(rev) => vec![format!("origin/{rev}"), rev.to_string()],
+                    None => vec!["origin/HEAD".to_string()],
+                };
+                candidates
+                    .iter()
+                    .find_map(|rev| {
+                        let rev = format!("{rev}^{{commit}}");
+                        git(&["-C", &dir_str, "rev-parse", "--verify", "--quiet", &rev]).ok()
+                    })
+                    .ok_or(ErrorType::ManifestError(
+                        format!(
+                            "Revision \"{}\" of dependency `{}` not found",
+                            rev.unwrap_or("HEAD"),
+                            dependency.name
+                        ),
+                        dependency.span,
+                    ))?
+            }
+        };
+        git(&[
+            "-C", &dir_str, "checkout", "--quiet", "--force", "--detach", &commit,
+        ])?;
+
+        Ok(commit)
+    }
+}
+
+/// Runs git, returning its trimmed output or its error message
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|err| format!("could not run git: {err}"))?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => Err(String::from_utf8_lossy(&output.stderr).trim().to_string()),
+    }
+}
@@ -0,0 +1,683 @@
+// Black runtime, compiled and linked into every binary produced by the compiler.
+// Generated code never hands user data to printf as a format string, all printing goes through
+// the typed helpers below.
+
+#include <ctype.h>
+#include <dirent.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <spawn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+// Stream printed into by `str`, NULL while printing to stdout
+static FILE *black_capture = NULL;
+static char *black_captured = NULL;
+static size_t black_captured_len = 0;
+
+// Stream the print helpers write to
+static FILE *black_out(void) { return black_capture ? black_capture : stdout; }
+
+// Redirects printing into a string until `black_capture_end`
+void black_capture_start(void) {
+    black_capture = open_memstream(&black_captured, &black_captured_len);
+}
+
+// Stops redirecting printing, returns everything printed since `black_capture_start`
+char *black_capture_end(void) {
+    fclose(black_capture);
+    black_capture = NULL;
+    return black_captured;
+}
+
+void print_str(const char *s) { fputs(s, black_out()); }
+
+void print_int(long n) { fprintf(black_out(), "%ld", n); }
+
+void print_bool(long b) { fputs(b ? "true" : "false", black_out()); }
+
+// Prints the shortest digits which read back as the same value, without an exponent (`1e20` is
+// `100000000000000000000`), the way the interpreter prints floats
+void print_float(double f) {
+    FILE *out = black_out();
+    if (f != f) {
+        fputs("NaN", out);
+        return;
+    }
+    if (f == 1.0 / 0.0 || f == -1.0 / 0.0) {
+        fputs(f < 0 ? "-inf" : "inf", out);
+        return;
+    }
+
+    char buf[32];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(buf, sizeof(buf), "%.*e", precision, f);
+        if (strtod(buf, NULL) == f) {
+            break;
+        }
+    }
+
+    // `buf` is `[-]d.ddde[+-]xx`, the value is `d.ddd` times 10 to the power of the exponent
+    char *mantissa = buf;
+    if (*mantissa == '-') {
+        fputc('-', out);
+        mantissa++;
+    }
+    char *e = strchr(mantissa, 'e');
+    int exponent = atoi(e + 1);
+    char digits[32];
+    int len = 0;
+    for (char *c = mantissa; c < e; c++) {
+        if (*c != '.') {
+            digits[len++] = *c;
+        }
+    }
+    while (len > 1 && digits[len - 1] == '0') {
+        len--;
+    }
+
+    if (exponent < 0) {
+        fputs("0.", out);
+        for (int i = 0; i < -exponent - 1; i++) {
+            fputc('0', out);
+        }
+        fwrite(digits, 1, len, out);
+        return;
+    }
+    for (int i = 0; i <= exponent || i < len; i++) {
+        if (i == exponent + 1) {
+            fputc('.', out);
+        }
+        fputc(i < len ? digits[i] : '0', out);
+    }
+}
+
+// Prints a string nested inside of a collection, with quotes
+void print_quoted(const char *s) { fprintf(black_out(), "\"%s\"", s); }
+
+// Prints `none` nested inside of a collection
+void print_none(long unused) {
+    (void)unused;
+    fputs("none", black_out());
+}
+
+// Prints a floating point value passed as its raw bits (as stored inside of lists)
+void print_float_bits(long bits) {
+    double f;
+    memcpy(&f, &bits, sizeof(f));
+    print_float(f);
+}
+
+// -------
+// Program
+// -------
+
+// Arguments passed to the program, without the name of the program
+static int black_argc = 0;
+static char **black_argv = NULL;
+
+// Called at the start of `main`
+void black_init(int argc, char **argv) {
+    black_argc = argc - 1;
+    black_argv = argv + 1;
+}
+
+// ------
+// Errors
+// ------
+
+// Name of the main source file, emitted by the compiler
+extern const char black_source[];
+
+// Source file of the code being run, code of imported modules runs with the file of the module
+static const char *black_current_source = black_source;
+
+// Sets the source file of the code being run, returns the previous one to restore afterwards
+const char *black_set_source(const char *source) {
+    const char *previous = black_current_source;
+    black_current_source = source;
+    return previous;
+}
+
+// Message of the error being raised while unwinding to the innermost try block, NULL otherwise.
+// Compiled code checks it after every call which can fail
+char *black_error = NULL;
+
+// Number of try blocks being run, errors raised outside of them exit the program
+long black_try_depth = 0;
+
+// Raises a runtime error at the given source location. Inside of a try block the error is only
+// recorded and the caller unwinds, otherwise the error is reported and the program exits
+void black_error_at(long line, long col, const char *message) {
+    if (black_try_depth > 0) {
+        black_error = strdup(message);
+        return;
+    }
+
+    fflush(stdout);
+    fprintf(stderr, "[Runtime Error] %s:%ld:%ld %s\n", black_current_source, line, col, message);
+    exit(1);
+}
+
+void black_try_enter(void) { black_try_depth++; }
+
+void black_try_exit(void) { black_try_depth--; }
+
+// Stops the unwinding of an error caught by a try block, returns the message of the error
+char *black_catch(void) {
+    char *message = black_error;
+    black_error = NULL;
+    black_try_depth--;
+    return message;
+}
+
+// Raises the error of a string which can't be parsed as the given kind of number
+void black_parse_error(const char *s, const char *kind, long line, long col) {
+    size_t size = strlen(s) + strlen(kind) + 32;
+    char *message = malloc(size);
+    snprintf(message, size, "Cannot parse \"%s\" as %s", s, kind);
+    black_error_at(line, col, message);
+    free(message);
+}
+
+void black_index_error(long index, long len, long line, long col) {
+    char message[96];
+    snprintf(message, sizeof(message), "Index %ld out of bounds for list of length %ld", index,
+             len);
+    black_error_at(line, col, message);
+}
+
+// ------
+// Memory
+// ------
+
+// Allocates zeroed memory for a struct
+void *black_alloc(long size) { return calloc(1, size); }
+
+// -----
+// Lists
+// -----
+
+// Every element takes one 8 byte slot. The compiler reads `data` and `len` directly when
+// indexing, so the field order is part of the ABI
+typedef struct {
+    long *data;
+    long len;
+    long cap;
+} BlackList;
+
+BlackList *black_list_new(long cap) {
+    BlackList *list = malloc(sizeof(BlackList));
+    list->cap = cap > 4 ? cap : 4;
+    list->len = 0;
+    list->data = malloc(list->cap * sizeof(long));
+    return list;
+}
+
+void black_list_push(BlackList *list, long value) {
+    if (list->len == list->cap) {
+        list->cap *= 2;
+        list->data = realloc(list->data, list->cap * sizeof(long));
+    }
+    list->data[list->len++] = value;
+}
+
+long black_list_pop(BlackList *list, long line, long col) {
+    if (list->len == 0) {
+        black_error_at(line, col, "Cannot pop from an empty list");
+        return 0;
+    }
+    return list->data[--list->len];
+}
+
+void black_print_list(BlackList *list, void (*print_item)(long)) {
+    fputs("[", black_out());
+    for (long i = 0; i < list->len; i++) {
+        if (i > 0) {
+            fputs(", ", black_out());
+        }
+        print_item(list->data[i]);
+    }
+    fputs("]", black_out());
+}
+
+// Arguments passed to the program, as a list of strings
+BlackList *black_args(void) {
+    BlackList *args = black_list_new(black_argc);
+    for (int i = 0; i < black_argc; i++) {
+        black_list_push(args, (long)black_argv[i]);
+    }
+    return args;
+}
+
+// ----
+// Maps
+// ----
+
+// Kinds of map keys, decides how keys are hashed, compared and shown in error messages
+#define BLACK_KEY_INT 0
+#define BLACK_KEY_STR 1
+#define BLACK_KEY_BOOL 2
+
+// Hash table with keys and values in 8 byte slots. Entries are stored in insertion order in
+// `keys` and `values`, `slots` is an open addressing index into them (0 is an empty slot, i + 1
+// points at entry i). Removing an entry rebuilds the index, so there are no tombstones
+typedef struct {
+    long *keys;
+    long *values;
+    long len;
+    long cap;
+    long *slots;
+    long nslots;
+    long key_kind;
+} BlackMap;
+
+static unsigned long black_map_hash(BlackMap *map, long key) {
+    if (map->key_kind == BLACK_KEY_STR) {
+        // FNV-1a
+        unsigned long hash = 14695981039346656037UL;
+        for (const unsigned char *s = (const unsigned char *)key; *s; s++) {
+            hash ^= *s;
+            hash *= 1099511628211UL;
+        }
+        return hash;
+    }
+    return (unsigned long)key * 11400714819323198485UL;
+}
+
+static int black_map_key_eq(BlackMap *map, long a, long b) {
+    if (map->key_kind == BLACK_KEY_STR) {
+        return strcmp((const char *)a, (const char *)b) == 0;
+    }
+    return a == b;
+}
+
+// Returns the slot pointing at the key, or the empty slot where it would be inserted
+static long black_map_slot(BlackMap *map, long key) {
+    unsigned long mask = map->nslots - 1;
+    unsigned long i = black_map_hash(map, key) & mask;
+    while (map->slots[i] != 0 && !black_map_key_eq(map, map->keys[map->slots[i] - 1], key)) {
+        i = (i + 1) & mask;
+    }
+    return i;
+}
+
+static void black_map_reindex(BlackMap *map, long nslots) {
+    free(map->slots);
+    map->nslots = nslots;
+    map->slots = calloc(nslots, sizeof(long));
+    for (long i = 0; i < map->len; i++) {
+        map->slots[black_map_slot(map, map->keys[i])] = i + 1;
+    }
+}
+
+BlackMap *black_map_new(long key_kind) {
+    BlackMap *map = malloc(sizeof(BlackMap));
+    map->key_kind = key_kind;
+    map->len = 0;
+    map->cap = 8;
+    map->keys = malloc(map->cap * sizeof(long));
+    map->values = malloc(map->cap * sizeof(long));
+    map->slots = NULL;
+    black_map_reindex(map, 16);
+    return map;
+}
+
+void black_map_set(BlackMap *map, long key, long value) {
+    long slot = black_map_slot(map, key);
+    if (map->slots[slot] != 0) {
+        map->values[map->slots[slot] - 1] = value;
+        return;
+    }
+
+    if (map->len == map->cap) {
+        map->cap *= 2;
+        map->keys = realloc(map->keys, map->cap * sizeof(long));
+        map->values = realloc(map->values, map->cap * sizeof(long));
+    }
+    // Keep the index at most half full
+    if ((map->len + 1) * 2 > map->nslots) {
+        black_map_reindex(map, map->nslots * 2);
+        slot = black_map_slot(map, key);
+    }
+
+    map->keys[map->len] = key;
+    map->values[map->len] = value;
+    map->slots[slot] = ++map->len;
+}
+
+long black_map_get(BlackMap *map, long key, long line, long col) {
+    long slot = black_map_slot(map, key);
+    if (map->slots[slot] == 0) {
+        char message[256];
+        switch (map->key_kind) {
+        case BLACK_KEY_STR:
+            snprintf(message, sizeof(message), "Key \"%s\" not found in map", (const char *)key);
+            break;
+        case BLACK_KEY_BOOL:
+            snprintf(message, sizeof(message), "Key %s not found in map", key ? "true" : "false");
+            break;
+        default:
+            snprintf(message, sizeof(message), "Key %ld not found in map", key);
+        }
+        black_error_at(line, col, message);
+        return 0;
+    }
+    return map->values[map->slots[slot] - 1];
+}
+
+long black_map_has(BlackMap *map, long key) { return map->slots[black_map_slot(map, key)] != 0; }
+
+void black_map_remove(BlackMap *map, long key) {
+    long slot = black_map_slot(map, key);
+    if (map->slots[slot] == 0) {
+        return;
+    }
+
+    long i = map->slots[slot] - 1;
+    long moved = map->len - i - 1;
+    memmove(&map->keys[i], &map->keys[i + 1], moved * sizeof(long));
+    memmove(&map->values[i], &map->values[i + 1], moved * sizeof(long));
+    map->len--;
+    black_map_reindex(map, map->nslots);
+}
+
+long black_map_len(BlackMap *map) { return map->len; }
+
+long black_map_key_at(BlackMap *map, long i) { return map->keys[i]; }
+
+long black_map_value_at(BlackMap *map, long i) { return map->values[i]; }
+
+BlackList *black_map_keys(BlackMap *map) {
+    BlackList *list = black_list_new(map->len);
+    for (long i = 0; i < map->len; i++) {
+        black_list_push(list, map->keys[i]);
+    }
+    return list;
+}
+
+void black_print_map(BlackMap *map, void (*print_key)(long), void (*print_value)(long)) {
+    fputs("{", black_out());
+    for (long i = 0; i < map->len; i++) {
+        if (i > 0) {
+            fputs(", ", black_out());
+        }
+        print_key(map->keys[i]);
+        fputs(": ", black_out());
+        print_value(map->values[i]);
+    }
+    fputs("}", black_out());
+}
+
+// -------
+// Numbers
+// -------
+
+long black_abs_long(long n) { return n < 0 ? -n : n; }
+
+double black_abs_double(double f) { return f < 0 ? -f : f; }
+
+long black_min_long(long a, long b) { return a < b ? a : b; }
+
+long black_max_long(long a, long b) { return a > b ? a : b; }
+
+double black_min_double(double a, double b) { return a < b ? a : b; }
+
+double black_max_double(double a, double b) { return a > b ? a : b; }
+
+// Parses a whole string, surrounding whitespace aside, as a base 10 integer
+long black_parse_int(const char *s, long line, long col) {
+    char *end;
+    errno = 0;
+    long n = strtol(s, &end, 10);
+    while (isspace((unsigned char)*end)) {
+        end++;
+    }
+    if (end == s || *end || errno == ERANGE) {
+        black_parse_error(s, "an int", line, col);
+        return 0;
+    }
+    return n;
+}
+
+// Parses a whole string, surrounding whitespace aside, as a floating point number
+double black_parse_float(const char *s, long line, long col) {
+    char *end;
+    double f = strtod(s, &end);
+    while (isspace((unsigned char)*end)) {
+        end++;
+    }
+    if (end == s || *end) {
+        black_parse_error(s, "a float", line, col);
+        return 0;
+    }
+    return f;
+}
+
+// -----
+// Input
+// -----
+
+// Line buffer filled by `getline`, which the generated code calls directly
+char *black_line = NULL;
+size_t black_line_cap = 0;
+
+FILE *black_stdin(void) { return stdin; }
+
+// Returns a copy of the line of the given length read into the line buffer, without its line
+// ending. NULL at the end of the input (a negative length)
+char *black_take_line(long len) {
+    if (len < 0) {
+        return NULL;
+    }
+    if (len > 0 && black_line[len - 1] == '\n') {
+        len--;
+        if (len > 0 && black_line[len - 1] == '\r') {
+            len--;
+        }
+    }
+    return strndup(black_line, len);
+}
+
+// -----------
+// File system
+// -----------
+
+// Raises the error of a failed file system operation, described by `errno`
+static void black_io_error(const char *action, const char *path, long line, long col) {
+    const char *reason = strerror(errno);
+    size_t size = strlen(action) + strlen(path) + strlen(reason) + 16;
+    char *message = malloc(size);
+    snprintf(message, size, "Cannot %s \"%s\": %s", action, path, reason);
+    black_error_at(line, col, message);
+    free(message);
+}
+
+// Reads everything left in the stream, NULL when reading fails
+static char *black_read_stream(FILE *file) {
+    size_t len = 0, cap = 4096;
+    char *contents = malloc(cap);
+    size_t read;
+    while ((read = fread(contents + len, 1, cap - len - 1, file)) > 0) {
+        len += read;
+        if (cap - len - 1 == 0) {
+            cap *= 2;
+            contents = realloc(contents, cap);
+        }
+    }
+    if (ferror(file)) {
+        free(contents);
+        return NULL;
+    }
+    contents[len] = '\0';
+    return contents;
+}
+
+char *black_read_file(const char *path, long line, long col) {
+    FILE *file = fopen(path, "rb");
+    if (!file) {
+        black_io_error("read", path, line, col);
+        return NULL;
+    }
+
+    char *contents = black_read_stream(file);
+    if (!contents) {
+        black_io_error("read", path, line, col);
+    }
+    fclose(file);
+    return contents;
+}
+
+// Writes the contents to the file in the given `fopen` mode, creating it when it doesn't exist
+static void black_write(const char *path, const char *contents, const char *mode, long line,
+                        long col) {
+    FILE *file = fopen(path, mode);
+    if (!file) {
+        black_io_error("write", path, line, col);
+        return;
+    }
+    size_t len = strlen(contents);
+    int failed = fwrite(contents, 1, len, file) != len;
+    if (fclose(file) != 0 || failed) {
+        black_io_error("write", path, line, col);
+    }
+}
+
+long black_write_file(const char *path, const char *contents, long line, long col) {
+    black_write(path, contents, "wb", line, col);
+    return 0;
+}
+
+long black_append_file(const char *path, const char *contents, long line, long col) {
+    black_write(path, contents, "ab", line, col);
+    return 0;
+}
+
+long black_exists(const char *path) {
+    struct stat info;
+    return stat(path, &info) == 0;
+}
+
+static int black_compare_names(const void *a, const void *b) {
+    return strcmp(*(char *const *)a, *(char *const *)b);
+}
+
+// Names of the entries of the directory, sorted
+BlackList *black_list_dir(const char *path, long line, long col) {
+    DIR *dir = opendir(path);
+    if (!dir) {
+        black_io_error("list", path, line, col);
+        return NULL;
+    }
+
+    BlackList *names = black_list_new(0);
+    struct dirent *entry;
+    while ((entry = readdir(dir))) {
+        if (strcmp(entry->d_name, ".") && strcmp(entry->d_name, "..")) {
+            black_list_push(names, (long)strdup(entry->d_name));
+        }
+    }
+    closedir(dir);
+    qsort(names->data, names->len, sizeof(long), black_compare_names);
+    return names;
+}
+
+long black_remove_file(const char *path, long line, long col) {
+    if (unlink(path) != 0) {
+        black_io_error("remove", path, line, col);
+    }
+    return 0;
+}
+
+// ---------
+// Processes
+// ---------
+
+extern char **environ;
+
+// Starts the command, searched for in `PATH` like by shells, with the list of string arguments.
+// Returns the pid, or -1 after raising an error when the command can't be started
+static pid_t black_spawn(const char *cmd, BlackList *args, posix_spawn_file_actions_t *actions,
+                         long line, long col) {
+    char **argv = malloc((args->len + 2) * sizeof(char *));
+    argv[0] = (char *)cmd;
+    for (long i = 0; i < args->len; i++) {
+        argv[i + 1] = (char *)args->data[i];
+    }
+    argv[args->len + 1] = NULL;
+
+    pid_t pid;
+    int err = posix_spawnp(&pid, cmd, actions, NULL, argv, environ);
+    free(argv);
+    if (err != 0) {
+        errno = err;
+        black_io_error("run", cmd, line, col);
+        return -1;
+    }
+    return pid;
+}
+
+// Waits for the process to finish and returns its exit status. Like in shells, a process killed
+// by a signal gets 128 plus the number of the signal
+static long black_wait(pid_t pid) {
+    int status;
+    while (waitpid(pid, &status, 0) < 0) {
+        if (errno != EINTR) {
+            return 1;
+        }
+    }
+    return WIFSIGNALED(status) ? 128 + WTERMSIG(status) : WEXITSTATUS(status);
+}
+
+// Runs the command with its output captured and without stdin. The output goes to temporary
+// files, which unlike pipes can't fill up and block the command. Returns the tuple of the exit
+// status, stdout and stderr
+long *black_run(const char *cmd, BlackList *args, long line, long col) {
+    FILE *out = tmpfile();
+    FILE *err = tmpfile();
+    if (!out || !err) {
+        black_io_error("run", cmd, line, col);
+        if (out) {
+            fclose(out);
+        }
+        if (err) {
+            fclose(err);
+        }
+        return NULL;
+    }
+
+    posix_spawn_file_actions_t actions;
+    posix_spawn_file_actions_init(&actions);
+    posix_spawn_file_actions_addopen(&actions, 0, "/dev/null", O_RDONLY, 0);
+    posix_spawn_file_actions_adddup2(&actions, fileno(out), 1);
+    posix_spawn_file_actions_adddup2(&actions, fileno(err), 2);
+    pid_t pid = black_spawn(cmd, args, &actions, line, col);
+    posix_spawn_file_actions_destroy(&actions);
+
+    long *result = NULL;
+    if (pid >= 0) {
+        result = black_alloc(3 * sizeof(long));
+        result[0] = black_wait(pid);
+        rewind(out);
+        rewind(err);
+        char *stdout_text = black_read_stream(out);
+        char *stderr_text = black_read_stream(err);
+        result[1] = (long)(stdout_text ? stdout_text : strdup(""));
+        result[2] = (long)(stderr_text ? stderr_text : strdup(""));
+    }
+    fclose(out);
+    fclose(err);
+    return result;
+}
+
+// Runs the command with the standard streams of the program, returns its exit status
+long black_run_stream(const char *cmd, BlackList *args, long line, long col) {
+    // Output printed so far has to come before the output of the command
+    fflush(stdout);
+    pid_t pid = black_spawn(cmd, args, NULL, line, col);
+    return pid < 0 ? 0 : black_wait(pid);
+}
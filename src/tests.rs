@@ -1,20 +1,35 @@
 #![allow(dead_code, unused_imports)]
 
 use crate::{
-    args::{get_args, AppArgs},
+    args::{get_args, AppArgs, Backend, Emit, Subcommand},
+    builtins::BUILTINS,
+    checker::Checker,
     compiler::Compiler,
+    formatter::format,
     interpreter::Interpreter,
-    parser::{lexer, preprocess, Parser},
+    json::Json,
+    linter::{lint, Rule},
+    lsp::Server,
+    parser::{lexer, lexer_with_spans, preprocess, Expr, Parser, Span, Type},
+    toolchain::Target,
     utils::{get_tmp_fname, ErrorType},
 };
 use std::{
-    fs::{remove_file, OpenOptions},
+    fs::{
+        create_dir, create_dir_all, read_to_string, remove_dir_all, remove_file, write, OpenOptions,
+    },
     io::Write,
-    path::PathBuf,
-    process::{Command, Output},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
 };
 
 fn compile_and_run(code: &str) -> String {
+    compile_and_run_with_input(code, "")
+}
+
+/// Compiles and runs the code, feeding the input to its stdin
+fn compile_and_run_with_input(code: &str, input: &str) -> String {
     let code_fname = get_tmp_fname("blkcode");
     let bin_fname = get_tmp_fname("blkbin");
 
@@ -27,13 +42,11 @@ fn compile_and_run(code: &str) -> String {
     tmp.write_all(code.as_bytes()).unwrap();
 
     Command::new("cargo")
-        .args(["run", "--", "--output", &bin_fname, &code_fname])
+        .args(["run", "--", "build", "--output", &bin_fname, &code_fname])
         .output()
         .expect("Failed to execute cargo");
 
-    let output = Command::new(&bin_fname)
-        .output()
-        .expect("Failed to execute test bin");
+    let output = run_with_input(Command::new(&bin_fname), input);
 
     remove_file(code_fname).unwrap();
     remove_file(bin_fname).unwrap();
@@ -42,6 +55,11 @@ fn compile_and_run(code: &str) -> String {
 }
 
 fn interpret(code: &str) -> String {
+    interpret_with_input(code, "")
+}
+
+/// Interprets the code, feeding the input to its stdin
+fn interpret_with_input(code: &str, input: &str) -> String {
     let code_fname = get_tmp_fname("blkcode");
 
     let mut tmp = OpenOptions::new()
@@ -52,16 +70,32 @@ fn interpret(code: &str) -> String {
         .unwrap();
     tmp.write_all(code.as_bytes()).unwrap();
 
-    let output = Command::new("cargo")
-        .args(["run", "--", "-i", &code_fname])
-        .output()
-        .expect("Failed to execute cargo");
+    let mut command = Command::new("cargo");
+    command.args(["run", "--", "run", "-i", &code_fname]);
+    let output = run_with_input(command, input);
 
     remove_file(code_fname).unwrap();
 
     String::from_utf8_lossy(&output.stdout).trim().to_string()
 }
 
+/// Runs the command to completion with the input written to its stdin
+fn run_with_input(mut command: Command, input: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute test bin");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
 fn run_compiler(compiler_args: Vec<&str>) -> Output {
     let mut args = vec!["run", "--"];
     args.extend(compiler_args);
@@ -101,6 +135,101 @@ fn run_compiler(compiler_args: Vec<&str>) -> Output {
     out
 }
 
+/// Runs the code with `cargo run` in the given mode, passing the program arguments after `--`
+fn run_with_program_args(code: &str, mode: &[&str], program_args: &[&str]) -> Output {
+    let code_fname = get_tmp_fname("blkcode");
+    let bin_fname = get_tmp_fname("blkbin");
+
+    let mut tmp = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(code_fname.clone())
+        .unwrap();
+    tmp.write_all(code.as_bytes()).unwrap();
+
+    let out = Command::new("cargo")
+        .args(["run", "--"])
+        .args(mode)
+        .args(["-o", &bin_fname, &code_fname, "--"])
+        .args(program_args)
+        .env("BLACK_TEST_NAME", "black")
+        .env_remove("BLACK_TEST_UNSET")
+        .output()
+        .expect("Failed to execute cargo");
+
+    remove_file(code_fname).unwrap();
+    let _ = remove_file(bin_fname);
+
+    out
+}
+
+/// Writes the files of a program to a temporary directory, then runs its first file with `cargo
+/// run` in the given mode. Further arguments are passed to black after the main file
+fn run_files(files: &[(&str, &str)], mode: &[&str], extra: &[&str]) -> Output {
+    let dir = PathBuf::from(get_tmp_fname("blkmodules"));
+    for (name, code) in files {
+        let path = dir.join(name);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, code).unwrap();
+    }
+
+    let out = Command::new("cargo")
+        .args(["run", "--"])
+        .args(mode)
+        .arg("-o")
+        .arg(dir.join("bin"))
+        .arg(dir.join(files[0].0))
+        .args(extra.iter().map(|file| dir.join(file)))
+        .output()
+        .expect("Failed to execute cargo");
+
+    remove_dir_all(dir).unwrap();
+
+    out
+}
+
+/// Writes files to a new temporary directory, returning the directory
+fn write_files(files: &[(&str, &str)]) -> PathBuf {
+    let dir = PathBuf::from(get_tmp_fname("blkproject"));
+    for (name, code) in files {
+        let path = dir.join(name);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, code).unwrap();
+    }
+
+    dir
+}
+
+/// Runs black with `cargo run` in the given directory
+fn run_in_dir(dir: &Path, black_args: &[&str]) -> Output {
+    let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    Command::new("cargo")
+        .args(["run", "--quiet", "--manifest-path"])
+        .arg(manifest)
+        .arg("--")
+        .args(black_args)
+        .current_dir(dir)
+        .output()
+        .expect("Failed to execute cargo")
+}
+
+/// Runs git in the given directory, committing as a test user
+fn git(dir: &Path, git_args: &[&str]) {
+    let status = Command::new("git")
+        .args([
+            "-c",
+            "user.name=black",
+            "-c",
+            "user.email=black@example.com",
+        ])
+        .args(git_args)
+        .current_dir(dir)
+        .status()
+        .expect("Failed to execute git");
+    assert!(status.success());
+}
+
 fn get_stdout(out: &Output) -> String {
     String::from_utf8(out.stdout.clone())
         .expect("Failed to get stdout")
@@ -108,6 +237,10 @@ fn get_stdout(out: &Output) -> String {
         .to_string()
 }
 
+fn get_stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).to_string()
+}
+
 fn args(args: &[&str]) -> Vec<String> {
     args.iter().map(|e| e.to_string()).collect()
 }
@@ -117,28 +250,28 @@ fn get_compiler_res(code: &str) -> Result<(), ErrorType> {
     let code = preprocess(code);
 
     // Lexer
-    let tokens = match lexer(&code) {
+    let (tokens, spans) = match lexer_with_spans(&code) {
         Ok(tokens) => tokens,
         Err(_) => unreachable!(),
     };
 
     // Parser
-    let mut parser = Parser::new(&tokens);
-    let ast = match parser.parse() {
+    let mut parser = Parser::with_spans(&tokens, &spans);
+    let mut ast = match parser.parse() {
         Ok(ast) => ast,
         Err(_) => unreachable!(),
     };
 
+    // Checker
+    Checker::new().check(&mut ast)?;
+
     // Compiler
     let mut compiler = Compiler::from_ast(ast);
     let bin_fname = get_tmp_fname("blkbin");
 
     compiler.compile(&AppArgs {
         output: bin_fname.into(),
-        static_link: false,
-        interpreter: false,
-        build_and_run: false,
-        input: None,
+        ..Default::default()
     })
 }
 
@@ -147,27 +280,51 @@ fn get_interpreter_res(code: &str) -> Result<(), ErrorType> {
     let code = preprocess(code);
 
     // Lexer
-    let tokens = match lexer(&code) {
+    let (tokens, spans) = match lexer_with_spans(&code) {
         Ok(tokens) => tokens,
         Err(_) => unreachable!(),
     };
 
     // Parser
-    let mut parser = Parser::new(&tokens);
-    let ast = match parser.parse() {
+    let mut parser = Parser::with_spans(&tokens, &spans);
+    let mut ast = match parser.parse() {
         Ok(ast) => ast,
         Err(_) => unreachable!(),
     };
 
+    // Checker
+    Checker::new().check(&mut ast)?;
+
     // Interpreter
     let mut interpreter = Interpreter::from_ast(ast);
 
     interpreter.run()
 }
 
+fn get_checker_res(code: &str) -> Result<(), ErrorType> {
+    // Preprocessor
+    let code = preprocess(code);
+
+    // Lexer
+    let (tokens, spans) = match lexer_with_spans(&code) {
+        Ok(tokens) => tokens,
+        Err(_) => unreachable!(),
+    };
+
+    // Parser
+    let mut parser = Parser::with_spans(&tokens, &spans);
+    let mut ast = match parser.parse() {
+        Ok(ast) => ast,
+        Err(_) => unreachable!(),
+    };
+
+    // Checker
+    Checker::new().check(&mut ast)
+}
+
 fn assert_error(result: Result<(), ErrorType>, expected: &ErrorType) {
     match result {
-        Err(err) => assert!(err == *expected),
+        Err(err) => assert_eq!(err, *expected),
         Ok(_) => panic!("Expected an error, but got Ok"),
     }
 }
@@ -307,8 +464,35 @@ print(a)
 "#;
     let expected = "1\n2";
     assert!(interpret(code) == expected);
-    // FIXME
-    // assert!(compile_and_run(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn print_format_specifiers() {
+    let code = r#"
+let a = "%s%s"
+print("100%", a, "%d")
+"#;
+    let expected = "100% %s%s %d";
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn print_through_runtime() {
+    let code = r#"
+let a = "%s"
+print(a, "%d", 1, true)
+"#;
+    let (tokens, spans) = lexer_with_spans(&preprocess(code)).unwrap();
+    let mut ast = Parser::with_spans(&tokens, &spans).parse().unwrap();
+    Checker::new().check(&mut ast).unwrap();
+    let ir = Compiler::from_ast(ast).generate_ir().unwrap();
+
+    assert!(!ir.contains("printf"));
+    assert!(ir.contains("call $print_str(l %v"));
+    assert!(ir.contains("call $print_int(l 1)"));
+    assert!(ir.contains("call $print_bool(l 1)"));
 }
 
 #[test]
@@ -394,9 +578,8 @@ fn args_interpreter() {
             == AppArgs {
                 input: Some(PathBuf::from("input")),
                 interpreter: true,
-                build_and_run: false,
-                static_link: false,
-                output: PathBuf::from("out.app")
+                output: PathBuf::from("out.app"),
+                ..Default::default()
             }
     );
 }
@@ -407,11 +590,8 @@ fn args_compiler_out() {
     assert!(
         app_args
             == AppArgs {
-                input: None,
-                interpreter: false,
-                build_and_run: false,
-                static_link: false,
-                output: PathBuf::from("outfile")
+                output: PathBuf::from("outfile"),
+                ..Default::default()
             }
     );
 }
@@ -422,22 +602,18 @@ fn args_build_and_run_out() {
     assert!(
         app_args
             == AppArgs {
-                input: None,
-                interpreter: false,
                 build_and_run: true,
-                static_link: false,
-                output: PathBuf::from("outfile")
+                output: PathBuf::from("outfile"),
+                ..Default::default()
             }
     );
     let app_args = get_args(&args(&["binary", "-o", "outfile", "-r"]));
     assert!(
         app_args
             == AppArgs {
-                input: None,
-                interpreter: false,
                 build_and_run: true,
-                static_link: false,
-                output: PathBuf::from("outfile")
+                output: PathBuf::from("outfile"),
+                ..Default::default()
             }
     );
 }
@@ -445,7 +621,10 @@ fn args_build_and_run_out() {
 #[test]
 fn err_unknown_func() {
     let code = r#"prnt("test")"#;
-    let expected = ErrorType::Generic("Function `prnt` is not implemented".to_string());
+    let expected = ErrorType::SemanticError(
+        "Function `prnt` is not implemented, did you mean `print`?".to_string(),
+        Span::new(1, 1, 12),
+    );
 
     assert_error(get_compiler_res(code), &expected);
     assert_error(get_interpreter_res(code), &expected);
@@ -454,7 +633,10 @@ fn err_unknown_func() {
 #[test]
 fn err_variable_doesnt_exist() {
     let code = r#"print(a)"#;
-    let expected = ErrorType::Generic("Variable doesn't exist: `a`".to_string());
+    let expected = ErrorType::SemanticError(
+        "Variable doesn't exist: `a`".to_string(),
+        Span::new(1, 1, 8),
+    );
 
     assert_error(get_compiler_res(code), &expected);
     assert_error(get_interpreter_res(code), &expected);
@@ -463,7 +645,8 @@ fn err_variable_doesnt_exist() {
 #[test]
 fn err_invalid_print_arg() {
     let code = r#"print(let a = 2)"#;
-    let expected = ErrorType::Generic("Invalid argument to print".to_string());
+    let expected =
+        ErrorType::SemanticError("Invalid argument to print".to_string(), Span::new(1, 1, 16));
 
     assert_error(get_compiler_res(code), &expected);
     assert_error(get_interpreter_res(code), &expected);
@@ -472,7 +655,10 @@ fn err_invalid_print_arg() {
 #[test]
 fn err_add_not_num() {
     let code = r#"print(1+"")"#;
-    let expected = ErrorType::Generic("Cannot add variable which is not a number".to_string());
+    let expected = ErrorType::SemanticError(
+        "Cannot add variable which is not a number".to_string(),
+        Span::new(1, 7, 4),
+    );
 
     assert_error(get_compiler_res(code), &expected);
     assert_error(get_interpreter_res(code), &expected);
@@ -492,12 +678,95 @@ fn err_invalid_expr_type() {
 #[test]
 fn err_var_type_str_but_not_str() {
     let code = r#"let str a = 1"#;
-    let expected = ErrorType::Generic("Variable type `str` does not match value type".to_string());
+    let expected = ErrorType::TypeError(
+        "expected str, found int".to_string(),
+        vec![
+            (
+                Span::new(1, 5, 3),
+                "expected `str` because of this".to_string(),
+            ),
+            (Span::new(1, 13, 1), "this is of type `int`".to_string()),
+        ],
+    );
 
     assert_error(get_compiler_res(code), &expected);
     assert_error(get_interpreter_res(code), &expected);
 }
 
+#[test]
+fn err_nested_bin_expr_not_num() {
+    let code = r#"let int x = "a" + 1"#;
+    let expected = ErrorType::SemanticError(
+        "Cannot add variable which is not a number".to_string(),
+        Span::new(1, 13, 7),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_variable_doesnt_exist_in_decl() {
+    let code = r#"let a = b + 1"#;
+    let expected = ErrorType::SemanticError(
+        "Variable doesn't exist: `b`".to_string(),
+        Span::new(1, 9, 5),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_var_type_int_but_str_expr() {
+    let code = r#"
+let a = "x"
+let int b = a
+"#;
+    let expected = ErrorType::TypeError(
+        "expected int, found str".to_string(),
+        vec![
+            (
+                Span::new(3, 5, 3),
+                "expected `int` because of this".to_string(),
+            ),
+            (Span::new(3, 13, 1), "this is of type `str`".to_string()),
+        ],
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn inferred_var_type() {
+    let code = r#"
+let a = 1 + 2
+let b = "x"
+let c = true
+"#;
+    let (tokens, spans) = lexer_with_spans(&preprocess(code)).unwrap();
+    let mut ast = Parser::with_spans(&tokens, &spans).parse().unwrap();
+    Checker::new().check(&mut ast).unwrap();
+
+    let types: Vec<Option<Type>> = ast
+        .iter()
+        .map(|node| match node {
+            Expr::VariableDeclaration(var_decl) => var_decl.typ.clone(),
+            _ => None,
+        })
+        .collect();
+    assert!(types == vec![Some(Type::Int), Some(Type::Str), Some(Type::Bool)]);
+}
+
+#[test]
+fn checker_runs_before_execution() {
+    let code = r#"
+print("a")
+print(b)
+"#;
+    let expected = "";
+
+    assert!(interpret(code) == expected);
+}
+
 #[test]
 fn code_block() {
     let code = r#"
@@ -556,6 +825,2473 @@ print(false)
     assert!(compile_and_run(code) == expected);
 }
 
+#[test]
+fn list_literal_and_index() {
+    let code = r#"
+let xs = [1, 2, 3]
+let [str] names = ["a", "b"]
+print(xs, names, xs[0] + xs[2], names[1])
+"#;
+    let expected = r#"[1, 2, 3] ["a", "b"] 4 b"#;
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn list_push_pop_len() {
+    let code = r#"
+let [int] xs = []
+push(xs, 1)
+push(xs, 2)
+push(xs, 3)
+let last = pop(xs)
+print(last, len(xs), xs)
+"#;
+    let expected = "3 2 [1, 2]";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn list_index_assignment() {
+    let code = r#"
+let xs = [[1, 2], [3]]
+xs[0][1] = 5
+push(xs[1], 4)
+print(xs)
+"#;
+    let expected = "[[1, 5], [3, 4]]";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn list_for_loop() {
+    let code = r#"
+let xs = [1, 2, 3]
+let sum = 0
+for x in xs {
+    sum = sum + x
+    print(x)
+}
+print(sum)
+"#;
+    let expected = "1\n2\n3\n6";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_list_index_out_of_bounds() {
+    let code = r#"
+let xs = [1, 2, 3]
+print(xs[3])
+"#;
+    let expected = ErrorType::RuntimeError(
+        "Index 3 out of bounds for list of length 3".to_string(),
+        Span::new(3, 7, 5),
+    );
+
+    assert_error(get_interpreter_res(code), &expected);
+}
+
+#[test]
+fn err_division_by_zero() {
+    let code = r#"
+let zero = 0
+print(1 / zero)
+"#;
+    let expected = ErrorType::RuntimeError("Division by zero".to_string(), Span::new(3, 7, 8));
+
+    assert_error(get_interpreter_res(code), &expected);
+}
+
+#[test]
+fn err_list_pop_empty() {
+    let code = r#"
+let [int] xs = []
+pop(xs)
+"#;
+    let expected = ErrorType::RuntimeError(
+        "Cannot pop from an empty list".to_string(),
+        Span::new(3, 1, 7),
+    );
+
+    assert_error(get_interpreter_res(code), &expected);
+}
+
+#[test]
+fn err_empty_list_without_type() {
+    let code = r#"let xs = []"#;
+    let expected = ErrorType::SemanticError(
+        "Cannot infer the type of `xs`, add a type annotation".to_string(),
+        Span::new(1, 10, 2),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_list_mixed_elements() {
+    let code = r#"let xs = [1, "a"]"#;
+    let expected = ErrorType::SemanticError(
+        "List elements must have the same type, expected int, found str".to_string(),
+        Span::new(1, 10, 8),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn block_variables_shadow() {
+    let code = r#"
+let a = "s"
+for i in [1, 2] {
+    let a = i * 10
+    print(a)
+}
+print(a)
+let int? o = 5
+if let a = o {
+    print(a + 1)
+}
+try {
+    let a = 3
+    error("x")
+} catch a {
+    print(a)
+}
+for k in ["p"] {
+    a = k
+}
+print(a)
+"#;
+    let expected = "10\n20\ns\n6\nx\np";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_for_variables_out_of_scope() {
+    let code = r#"
+let [str] xs = []
+for x in xs {
+    let y = x
+}
+print(y)
+"#;
+    let expected = ErrorType::SemanticError(
+        "Variable doesn't exist: `y`".to_string(),
+        Span::new(6, 1, 8),
+    );
+    assert_error(get_checker_res(code), &expected);
+
+    let code = r#"
+for x in [1] {
+    print(x)
+}
+print(x)
+"#;
+    let expected = ErrorType::SemanticError(
+        "Variable doesn't exist: `x`".to_string(),
+        Span::new(5, 1, 8),
+    );
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_for_shadowing_out_of_scope() {
+    let code = r#"
+let a = "s"
+for i in [1] {
+    let a = 1
+}
+print(a + 1)
+"#;
+    let expected = ErrorType::SemanticError(
+        "Cannot add variable which is not a number".to_string(),
+        Span::new(6, 7, 5),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_located_in_function_body() {
+    let code = r#"
+fn area(int w, int h) -> int {
+    let a = w * h
+    print(a)
+    return a * hh
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Variable doesn't exist: `hh`".to_string(),
+        Span::new(5, 12, 6),
+    );
+    assert_error(get_checker_res(code), &expected);
+
+    let code = r#"
+fn f(int a) {
+    for i in [1] {
+        print(i)
+    }
+    let b = a + "s"
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Cannot add variable which is not a number".to_string(),
+        Span::new(6, 13, 7),
+    );
+    assert_error(get_checker_res(code), &expected);
+
+    let syntax_error = |code| {
+        let (tokens, spans) = lexer_with_spans(code)?;
+        Parser::with_spans(&tokens, &spans).parse()
+    };
+    assert_eq!(
+        syntax_error("let a = 1\nlet b = 2 $ 3\n"),
+        Err(ErrorType::SyntaxError(
+            "Unexpected token: $ 3".to_string(),
+            Span::new(2, 11, 3)
+        ))
+    );
+    assert_eq!(
+        syntax_error("let a = (1\nprint(a)\n"),
+        Err(ErrorType::SyntaxError(
+            "Expected ')'".to_string(),
+            Span::new(2, 1, 5)
+        ))
+    );
+}
+
+#[test]
+fn map_literal_and_index() {
+    let code = r#"
+let ages = {"alice": 30, "bob": 25}
+ages["carol"] = 41
+ages["bob"] = 26
+print(ages, len(ages), ages["alice"])
+"#;
+    let expected = r#"{"alice": 30, "bob": 26, "carol": 41} 3 30"#;
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn map_has_keys_remove() {
+    let code = r#"
+let {int: str} names = {}
+names[2] = "two"
+names[1] = "one"
+print(has(names, 1), has(names, 3))
+remove(names, 2)
+remove(names, 3)
+print(keys(names), names)
+"#;
+    let expected = "true false\n[1] {1: \"one\"}";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn map_for_loop() {
+    let code = r#"
+let scores = {"b": [1, 2], "a": [3]}
+for name, values in scores {
+    print(name, len(values))
+}
+for name in scores {
+    print(name)
+}
+"#;
+    let expected = "b 2\na 1\nb\na";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn map_braces_in_statement_position_are_blocks() {
+    let code = r#"
+{
+    let m = {"a": {"b": 1}}
+    print(m["a"]["b"])
+}
+"#;
+    let expected = "1";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_map_missing_key() {
+    let code = r#"
+let m = {"a": 1}
+print(m["b"])
+"#;
+    let expected =
+        ErrorType::RuntimeError("Key \"b\" not found in map".to_string(), Span::new(3, 7, 6));
+
+    assert_error(get_interpreter_res(code), &expected);
+}
+
+#[test]
+fn err_map_key_type() {
+    let code = r#"
+let m = {"a": 1}
+print(m[1])
+"#;
+    let expected = ErrorType::TypeError(
+        "expected str, found int".to_string(),
+        vec![(Span::new(3, 7, 4), "this is of type `int`".to_string())],
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_map_unhashable_key() {
+    let code = r#"let {[int]: int} m = {}"#;
+    let expected = ErrorType::SemanticError(
+        "Map keys must be integers, strings or booleans, found `[int]`".to_string(),
+        Span::new(1, 5, 12),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn struct_fields_and_printing() {
+    let code = r#"
+struct Point { int x, int y }
+struct Line {
+    Point from
+    Point to
+    str name
+}
+let p = Point { y: 2, x: 1 }
+p.x = p.x + 2
+let line = Line { from: p, to: Point { x: 5, y: 6 }, name: "diag" }
+line.to.y = 42
+print(p, p.x)
+print(line)
+"#;
+    let expected = r#"Point { x: 3, y: 2 } 3
+Line { from: Point { x: 3, y: 2 }, to: Point { x: 5, y: 42 }, name: "diag" }"#;
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn struct_copied_on_assignment() {
+    let code = r#"
+struct Point { int x, int y }
+let p = Point { x: 1, y: 2 }
+let q = p
+q.x = 10
+let ps = [p]
+ps[0].y = 7
+for item in ps {
+    item.x = 0
+}
+print(p, q, ps)
+"#;
+    let expected = "Point { x: 1, y: 2 } Point { x: 10, y: 2 } [Point { x: 1, y: 7 }]";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn struct_passed_to_and_returned_from_function() {
+    let code = r#"
+let moved = shift(origin(), 5)
+print(moved)
+
+struct Point { int x, int y }
+
+fn origin() -> Point {
+    return Point { x: 0, y: 0 }
+}
+
+fn shift(Point p, int by) -> Point {
+    p.x = p.x + by
+    p.y = p.y + by
+    return p
+}
+
+let p = origin()
+let q = shift(p, 1)
+print(p, q)
+"#;
+    let expected = "Point { x: 5, y: 5 }\nPoint { x: 0, y: 0 } Point { x: 1, y: 1 }";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn function_locals_are_separate() {
+    let code = r#"
+fn sum([int] xs) -> int {
+    let total = 0
+    for x in xs {
+        total = total + x
+    }
+    return total
+}
+let total = 1
+print(sum([1, 2, 3]), total)
+"#;
+    let expected = "6 1";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_struct_missing_field() {
+    let code = r#"
+struct Point { int x, int y }
+let p = Point { x: 1 }
+"#;
+    let expected = ErrorType::SemanticError(
+        "Missing field `y` in `Point`".to_string(),
+        Span::new(3, 9, 14),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_struct_unknown_field() {
+    let code = r#"
+struct Point { int x, int y }
+let p = Point { x: 1, y: 2 }
+print(p.z)
+"#;
+    let expected = ErrorType::SemanticError(
+        "Struct `Point` has no field `z`".to_string(),
+        Span::new(4, 7, 3),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_struct_contains_itself() {
+    let code = r#"struct Node { int value, Node next }"#;
+    let expected = ErrorType::SemanticError(
+        "Struct `Node` cannot contain itself".to_string(),
+        Span::new(1, 8, 4),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_function_argument_type() {
+    let code = r#"
+fn twice(int n) -> int {
+    return n * 2
+}
+twice("a")
+"#;
+    let expected = ErrorType::TypeError(
+        "expected int, found str".to_string(),
+        vec![(Span::new(5, 1, 10), "this is of type `str`".to_string())],
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_function_missing_return() {
+    let code = r#"
+fn twice(int n) -> int {
+    print(n)
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Function `twice` must return a value of type `int`".to_string(),
+        Span::new(2, 4, 5),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn enum_match_with_payloads() {
+    let code = r#"
+enum Shape { Circle(int), Rect(int, int), Empty }
+
+fn area(Shape s) -> int {
+    return match s {
+        Shape::Circle(r) => 3 * r * r,
+        Shape::Rect(w, h) => w * h,
+        Shape::Empty => 0,
+    }
+}
+
+let shapes = [Shape::Circle(2), Shape::Rect(3, 4), Shape::Empty]
+for s in shapes {
+    print(s, area(s))
+}
+"#;
+    let expected = "Circle(2) 12\nRect(3, 4) 12\nEmpty 0";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn match_literal_and_wildcard_patterns() {
+    let code = r#"
+enum Token { Word(str), Flag(bool) }
+let tokens = [Token::Word("let"), Token::Word("x"), Token::Flag(true), Token::Flag(false)]
+for token in tokens {
+    match token {
+        Token::Word("let") => print("keyword")
+        Token::Word(word) => print("word", word)
+        Token::Flag(true) => {
+            print("on")
+        }
+        _ => print("off")
+    }
+}
+
+let name = match 2 {
+    1 => "one"
+    2 => "two"
+    _ => "many"
+}
+print(name)
+"#;
+    let expected = "keyword\nword x\non\noff\ntwo";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn recursive_enum() {
+    let code = r#"
+enum Tree { Leaf, Node(Tree, int, Tree) }
+
+fn sum(Tree tree) -> int {
+    match tree {
+        Tree::Leaf => {
+            return 0
+        }
+        Tree::Node(left, value, right) => {
+            return sum(left) + value + sum(right)
+        }
+    }
+}
+
+let tree = Tree::Node(Tree::Node(Tree::Leaf, 1, Tree::Leaf), 2, Tree::Leaf)
+print(sum(tree), tree)
+"#;
+    let expected = "3 Node(Node(Leaf, 1, Leaf), 2, Leaf)";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_match_not_exhaustive() {
+    let code = r#"
+enum Shape { Circle(int), Rect(int, int) }
+let s = Shape::Circle(1)
+match s {
+    Shape::Circle(_) => print("circle")
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Non-exhaustive match, `Shape::Rect(_, _)` is not covered".to_string(),
+        Span::new(4, 1, 7),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_match_not_exhaustive_nested() {
+    let code = r#"
+enum Answer { Known(bool), Unknown }
+match Answer::Unknown {
+    Answer::Known(true) => print("yes")
+    Answer::Unknown => print("?")
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Non-exhaustive match, `Answer::Known(false)` is not covered".to_string(),
+        Span::new(3, 1, 21),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_match_literals_need_wildcard() {
+    let code = r#"
+let n = 3
+let name = match n {
+    1 => "one"
+    2 => "two"
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Non-exhaustive match, `_` is not covered".to_string(),
+        Span::new(3, 12, 7),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_match_arm_types() {
+    let code = r#"
+let name = match 1 {
+    1 => "one"
+    _ => 2
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Match arms must have the same type, expected str, found int".to_string(),
+        Span::new(2, 12, 7),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_enum_unknown_variant() {
+    let code = r#"
+enum Shape { Circle(int) }
+let s = Shape::Square(1)
+"#;
+    let expected = ErrorType::SemanticError(
+        "Enum `Shape` has no variant `Square`".to_string(),
+        Span::new(3, 9, 16),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn tuple_literals_and_element_access() {
+    let code = r#"
+struct Point { int x, int y }
+let pair = (1, "a")
+let nested = ((1, 2), [3], Point { x: 4, y: 5 })
+let single = (5,)
+print(pair, pair.0, pair.1)
+print(nested, nested.0.1, nested.2.x)
+print(single, (2 + 3) * 2)
+"#;
+    let expected = r#"(1, "a") 1 a
+((1, 2), [3], Point { x: 4, y: 5 }) 2 4
+(5,) 10"#;
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn tuple_destructuring_and_multiple_returns() {
+    let code = r#"
+fn divmod(int a, int b) -> (int, int) {
+    let q = a / b
+    let taken = q * b
+    return (q, a - taken)
+}
+
+let (q, r) = divmod(17, 5)
+print(q, r)
+
+struct Point { int x, int y }
+let origin = Point { x: 0, y: 0 }
+let (_, p) = (1, origin)
+p.x = 9
+let ([int], int) empty = ([], 1)
+push(empty.0, 7)
+print(p, origin, empty)
+"#;
+    let expected = "3 2\nPoint { x: 9, y: 0 } Point { x: 0, y: 0 } ([7], 1)";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_tuple_element_out_of_range() {
+    let code = r#"
+let pair = (1, "a")
+print(pair.2)
+"#;
+    let expected = ErrorType::SemanticError(
+        "Tuple of type `(int, str)` has no element 2".to_string(),
+        Span::new(3, 7, 6),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_tuple_destructure_length() {
+    let code = r#"let (a, b, c) = (1, 2)"#;
+    let expected = ErrorType::SemanticError(
+        "Cannot destructure value of type `(int, int)` into 3 variables".to_string(),
+        Span::new(1, 17, 6),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_tuple_element_assignment() {
+    let code = r#"
+let pair = (1, 2)
+pair.0 = 5
+"#;
+    let expected = ErrorType::SemanticError(
+        "Tuple elements cannot be modified".to_string(),
+        Span::new(3, 1, 10),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn optional_values_and_fallbacks() {
+    let code = r#"
+fn first([int] xs) -> int? {
+    for x in xs {
+        return x
+    }
+    return none
+}
+let int? a = first([4, 5])
+let int? b = first([])
+print(a, b)
+print(a ?? 0, b ?? 0)
+let [str?] names = ["a", none]
+print(names)
+let m = {"x": 1, "y": none}
+print(m)
+"#;
+    let expected = "4 none\n4 0\n[\"a\", none]\n{\"x\": 1, \"y\": none}";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn if_let_and_else() {
+    let code = r#"
+let int? a = 2
+let int? b = none
+if let n = a {
+    print("a is", n + 1)
+} else {
+    print("a is none")
+}
+if let n = b {
+    print("b is", n)
+} else if has({"x": 1}, "x") {
+    print("b is none")
+}
+let n = "outer"
+if let n = a {
+    print(n)
+}
+print(n)
+"#;
+    let expected = "a is 3\nb is none\n2\nouter";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn optional_struct_fields() {
+    let code = r#"
+struct Node { int value, Node? next }
+fn sum(Node node) -> int {
+    if let next = node.next {
+        return node.value + sum(next)
+    }
+    return node.value
+}
+let tail = Node { value: 2, next: none }
+let head = Node { value: 1, next: tail }
+tail.value = 5
+print(head)
+print(sum(head))
+"#;
+    let expected = "Node { value: 1, next: Node { value: 2, next: none } }\n3";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_optional_used_as_value() {
+    let code = r#"
+let int? a = 1
+let int b = a
+"#;
+    let expected = ErrorType::TypeError(
+        "expected int, found int?".to_string(),
+        vec![
+            (
+                Span::new(3, 5, 3),
+                "expected `int` because of this".to_string(),
+            ),
+            (Span::new(3, 13, 1), "this is of type `int?`".to_string()),
+        ],
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_optional_in_arithmetic() {
+    let code = r#"
+let int? a = 1
+print(a + 1)
+"#;
+    let expected = ErrorType::TypeError(
+        "value of type `int?` may be none, unwrap it with `??` or `if let`".to_string(),
+        vec![(Span::new(3, 7, 5), "this is of type `int?`".to_string())],
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_none_needs_annotation() {
+    let code = r#"
+let a = none
+"#;
+    let expected = ErrorType::SemanticError(
+        "Cannot infer the type of `a`, add a type annotation".to_string(),
+        Span::new(2, 9, 4),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_if_let_needs_optional() {
+    let code = r#"
+if let n = 5 {
+    print(n)
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "`if let` expects an optional value, found `int`".to_string(),
+        Span::new(2, 12, 1),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn try_catch_error() {
+    let code = r#"
+try {
+    print("before")
+    error("something went wrong")
+    print("unreachable")
+} catch e {
+    print("caught:", e)
+}
+try {
+    print("no error")
+} catch {
+    print("unreachable")
+}
+print("after")
+"#;
+    let expected = "before\ncaught: something went wrong\nno error\nafter";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn try_catch_builtin_errors() {
+    let code = r#"
+let [int] xs = []
+try {
+    pop(xs)
+} catch e {
+    print(e)
+}
+try {
+    print({"a": 1}["b"])
+} catch e {
+    print(e)
+}
+let ys = [1, 2]
+try {
+    ys[5] = 3
+} catch e {
+    print(e)
+}
+print(ys)
+try {
+    print(1 / (len(ys) - 2))
+} catch e {
+    print(e)
+}
+"#;
+    let expected = "Cannot pop from an empty list\nKey \"b\" not found in map\nIndex 5 out of bounds for list of length 2\n[1, 2]\nDivision by zero";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn try_catch_across_functions() {
+    let code = r#"
+fn inner(int n) -> int {
+    error("inner failed")
+    return n
+}
+fn outer(int n) -> int {
+    let m = inner(n)
+    print("unreachable")
+    return m
+}
+fn safe(int n) -> int {
+    try {
+        return outer(n)
+    } catch e {
+        print(e)
+    }
+    return 0
+}
+print(safe(3))
+try {
+    outer(1)
+} catch e {
+    print("top level:", e)
+}
+"#;
+    let expected = "inner failed\n0\ntop level: inner failed";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn err_uncaught_error() {
+    let code = r#"
+print("start")
+error("fatal")
+"#;
+    let expected = ErrorType::RuntimeError("fatal".to_string(), Span::new(3, 1, 14));
+
+    assert_error(get_interpreter_res(code), &expected);
+}
+
+#[test]
+fn err_error_needs_str() {
+    let code = r#"error(5)"#;
+
+    assert!(get_checker_res(code).is_err());
+}
+
+#[test]
+fn builtin_functions() {
+    let code = r#"
+let xs = [3, 1, 2]
+let long big = 5
+let int? nothing = none
+print(len(xs), len({"a": 1}), len("hello"))
+print(str(xs), str(42), str("s"), str(nothing))
+let s = str({"k": [1, 2]})
+print(s, len(s))
+print(int(7), abs(0 - 4), min(3, 9), max(3, 9), max(big, big))
+print(type_of(xs), type_of(big), type_of(nothing), type_of({"a": [true]}), type_of(("a", 1)))
+"#;
+    let expected = "3 1 5\n[3, 1, 2] 42 s none\n{\"k\": [1, 2]} 13\n7 4 3 9 5\n[int] long int? {str: [bool]} (str, int)";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn builtins_are_documented() {
+    let docs = include_str!("../docs/content/docs/getting-started.md");
+    for builtin in BUILTINS {
+        assert!(
+            docs.contains(&format!("`{}(", builtin.name)),
+            "builtin `{}` is missing from the documentation",
+            builtin.name
+        );
+    }
+}
+
+#[test]
+fn err_builtin_redefined() {
+    let code = r#"
+fn len(int n) -> int {
+    return n
+}
+"#;
+    let expected = ErrorType::SemanticError(
+        "Function `len` is already defined".to_string(),
+        Span::new(2, 4, 3),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_builtin_argument_kind() {
+    let code = r#"print(abs("a"))"#;
+    let expected = ErrorType::SemanticError(
+        "Function `abs` expects a number, found `str`".to_string(),
+        Span::new(1, 7, 8),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_builtin_arity() {
+    let code = r#"print(min(1))"#;
+    let expected = ErrorType::SemanticError(
+        "Function `min` takes 2 argument(s) but 1 were given".to_string(),
+        Span::new(1, 7, 6),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn err_min_mixed_types() {
+    let code = r#"
+let long a = 1
+print(min(a, "b"))
+"#;
+
+    assert!(get_checker_res(code).is_err());
+}
+
+#[test]
+fn read_input() {
+    let code = r#"
+let name = input("name? ")
+print("hello", name)
+let int total = 0
+for i in [1, 2, 3] {
+    if let line = read_line() {
+        total = total + int(line)
+    } else {
+        print("end of input")
+    }
+}
+print(total, input("more? "))
+"#;
+    let input = "black\n 21 \n10\r\n";
+    let expected = "name? hello black\nend of input\n31 more?";
+
+    assert!(interpret_with_input(code, input) == expected);
+    assert!(compile_and_run_with_input(code, input) == expected);
+}
+
+#[test]
+fn parse_numbers() {
+    let code = r#"
+let x = float("2.5")
+print(x, x * 2, float(3), int(x), int(" -7 "), abs(float("-1.5")))
+try {
+    print(int("12abc"))
+} catch e {
+    print(e)
+}
+try {
+    print(float(""))
+} catch e {
+    print(e)
+}
+"#;
+    let expected =
+        "2.5 5 3 2 -7 1.5\nCannot parse \"12abc\" as an int\nCannot parse \"\" as a float";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+}
+
+#[test]
+fn ints_stored_as_floats() {
+    let code = r#"
+struct P { float y }
+fn half(float x) -> float {
+    return x / 2
+}
+fn three() -> float {
+    return 3
+}
+let float f = 3
+let float g = float("1.5")
+g = 5
+let float? o = 9
+print(f / 2, half(3), g / 2, three() / 2, P { y: 7 }.y / 2, o ?? 0)
+"#;
+    let expected = "1.5 1.5 2.5 1.5 3.5 9";
+
+    assert!(interpret(code) == expected);
+    assert!(compile_and_run(code) == expected);
+
+    // Ints among floats in literals, pushed into float lists, as fallbacks of optional floats,
+    // as float payloads and as the second value of `min`
+    let code = r#"
+enum E { A(float) }
+let float f = float("1.5")
+let float? n = none
+let xs = [f, 3]
+let ys = [3, f]
+push(xs, 5)
+let m = {"a": f, "b": 3}
+print(xs[1] / 2, ys[0] / 2, xs[2] / 2, m["b"] / 2, (n ?? 3) / 2, min(f, 1) / 2)
+match E::A(3) {
+    E::A(x) => print(x / 2)
+}
+"#;
+    let expected = "1.5 1.5 2.5 1.5 1.5 0.5\n1.5";
+
+    assert_eq!(interpret(code), expected);
+    assert_eq!(compile_and_run(code), expected);
+}
+
+#[test]
+fn float_printing() {
+    let code = r#"
+let xs = [float("1e20"), float("10"), float("0.001"), float("-0.25"), float("1e-7"), float("0")]
+print(xs)
+print(float("123456.789"), str(float("1e21")), float("3") / float("7"))
+"#;
+    let expected = "[100000000000000000000, 10, 0.001, -0.25, 0.0000001, 0]\n\
+                    123456.789 1000000000000000000000 0.42857142857142855";
+
+    assert_eq!(interpret(code), expected);
+    assert_eq!(compile_and_run(code), expected);
+}
+
+#[test]
+fn err_int_of_bool() {
+    let code = r#"print(int(true))"#;
+    let expected = ErrorType::SemanticError(
+        "Function `int` expects a number or a string, found `bool`".to_string(),
+        Span::new(1, 7, 9),
+    );
+
+    assert_error(get_checker_res(code), &expected);
+}
+
+#[test]
+fn file_system() {
+    let dir = get_tmp_fname("blkdir");
+    let code = r#"
+let dir = "DIR"
+write_file("DIR/b.txt", "hello")
+append_file("DIR/b.txt", " world")
+write_file("DIR/a.txt", "a")
+print(read_file("DIR/b.txt"))
+print(exists("DIR/a.txt"), exists("DIR/missing"))
+print(list_dir(dir))
+remove_file("DIR/a.txt")
+print(list_dir(dir))
+try {
+    read_file("DIR/missing")
+} catch e {
+    print(e)
+}
+try {
+    list_dir("DIR/b.txt")
+} catch e {
+    print(e)
+}
+"#
+    .replace("DIR", &dir);
+    let expected = format!(
+        "hello world\ntrue false\n[\"a.txt\", \"b.txt\"]\n[\"b.txt\"]\nCannot read \"{dir}/missing\": No such file or directory\nCannot list \"{dir}/b.txt\": Not a directory"
+    );
+
+    create_dir(&dir).unwrap();
+    assert!(interpret(&code) == expected);
+    remove_file(format!("{dir}/b.txt")).unwrap();
+    assert!(compile_and_run(&code) == expected);
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn err_file_not_found() {
+    let code = r#"print(read_file("/nonexistent/file.txt"))"#;
+    let expected = ErrorType::RuntimeError(
+        "Cannot read \"/nonexistent/file.txt\": No such file or directory".to_string(),
+        Span::new(1, 7, 34),
+    );
+
+    assert_error(get_interpreter_res(code), &expected);
+}
+
+#[test]
+fn args_program_args() {
+    let app_args = get_args(&args(&["binary", "-i", "input", "--", "a", "-o"]));
+    assert_eq!(app_args.input, Some(PathBuf::from("input")));
+    assert_eq!(app_args.program_args, vec!["a", "-o"]);
+}
+
+#[test]
+fn program_args_and_exit_status() {
+    let code = r#"
+print(args())
+print(env("BLACK_TEST_NAME") ?? "unset", env("BLACK_TEST_UNSET") ?? "unset")
+return len(args())
+"#;
+    let expected = "[\"one\", \"two words\"]\nblack unset";
+
+    for mode in [&["run", "-i"][..], &["run"][..]] {
+        let out = run_with_program_args(code, mode, &["one", "two words"]);
+        assert_eq!(get_stdout(&out), expected);
+        assert_eq!(out.status.code(), Some(2));
+    }
+}
+
+#[test]
+fn exit_builtin() {
+    let code = r#"
+fn stop() {
+    print("stopping")
+    exit(4)
+}
+
+stop()
+print("unreachable")
+"#;
+
+    for mode in [&["run", "-i"][..], &["run"][..]] {
+        let out = run_with_program_args(code, mode, &[]);
+        assert_eq!(get_stdout(&out), "stopping");
+        assert_eq!(out.status.code(), Some(4));
+    }
+}
+
+#[test]
+fn division_by_zero_exits() {
+    let code = r#"
+print("before")
+let zero = 0
+print(1 / zero)
+print("unreachable")
+"#;
+
+    for mode in [&["run", "-i"][..], &["run"][..]] {
+        let out = run_with_program_args(code, mode, &[]);
+        assert_eq!(get_stdout(&out), "before");
+        assert!(get_stderr(&out).contains(":4:7"));
+        assert!(get_stderr(&out).contains("Division by zero"));
+        assert_eq!(out.status.code(), Some(1));
+    }
+}
+
+#[test]
+fn err_top_level_return_type() {
+    let code = r#"
+return "done"
+"#;
+
+    assert_error(
+        get_checker_res(code),
+        &ErrorType::SemanticError(
+            "Top-level `return` sets the exit status, which must be an `int`, found `str`"
+                .to_string(),
+            Span::new(2, 1, 13),
+        ),
+    );
+}
+
+#[test]
+fn run_commands() {
+    let code = r#"
+let (status, out, err) = run("sh", ["-c", "echo out; echo err >&2; exit 3"])
+print(status, out, err)
+print("before")
+print(run_stream("sh", ["-c", "echo streamed; exit 2"]))
+try {
+    run("black-no-such-command", [])
+} catch e {
+    print(e)
+}
+"#;
+    let expected = "3 out\n err\n\nbefore\nstreamed\n2\n\
+        Cannot run \"black-no-such-command\": No such file or directory";
+
+    assert_eq!(interpret(code), expected);
+    assert_eq!(compile_and_run(code), expected);
+}
+
+#[test]
+fn err_run_args_not_a_list() {
+    let code = r#"
+run("ls", "-l")
+"#;
+
+    assert_error(
+        get_checker_res(code),
+        &ErrorType::TypeError(
+            "expected [str], found str".to_string(),
+            vec![(Span::new(2, 1, 15), "this is of type `str`".to_string())],
+        ),
+    );
+}
+
+#[test]
+fn args_modules() {
+    let app_args = get_args(&args(&[
+        "binary",
+        "-i",
+        "main.blk",
+        "lib/util.blk",
+        "--",
+        "a",
+    ]));
+    assert_eq!(app_args.input, Some(PathBuf::from("main.blk")));
+    assert_eq!(app_args.modules, vec![PathBuf::from("lib/util.blk")]);
+    assert_eq!(app_args.program_args, vec!["a"]);
+}
+
+#[test]
+fn modules() {
+    let main = r#"
+import math
+import "shapes/geo.blk"
+
+print(math.square(4))
+let geo.Point p = geo.Point { x: 2, y: 1 }
+print(geo.sum(p))
+try {
+    math.fail()
+} catch err {
+    print(err)
+}
+math.fail()
+"#;
+    let math = r#"
+let count = 3
+print("loading math", count)
+
+pub fn square(int n) -> int {
+    return helper(n)
+}
+
+fn helper(int n) -> int {
+    return n * n
+}
+
+pub fn fail() {
+    error("boom")
+}
+"#;
+    let geo = r#"
+import "../math.blk"
+
+pub struct Point { int x, int y }
+
+pub fn sum(Point p) -> int {
+    return math.square(p.x) + p.y
+}
+"#;
+    let files = [
+        ("main.blk", main),
+        ("math.blk", math),
+        ("shapes/geo.blk", geo),
+    ];
+
+    for mode in [&["run", "-i"][..], &["run"][..]] {
+        let out = run_files(&files, mode, &[]);
+        assert_eq!(get_stdout(&out), "loading math 3\n16\n5\nboom");
+        assert!(String::from_utf8_lossy(&out.stderr).contains("math.blk:14:5"));
+        assert_eq!(out.status.code(), Some(1));
+    }
+}
+
+#[test]
+fn modules_load_lazily() {
+    let main = r#"
+print(twice(2))
+import lib
+print("imported")
+
+fn twice(int n) -> int {
+    return lib.scale(n)
+}
+"#;
+    let lib = r#"
+print("loading lib")
+
+pub fn scale(int n) -> int {
+    return n * 2
+}
+"#;
+    let early_return = "print(1)\nreturn 0\nimport lib\n";
+
+    for mode in [&["run", "-i"][..], &["run"][..]] {
+        let out = run_files(&[("main.blk", main), ("lib.blk", lib)], mode, &[]);
+        assert_eq!(get_stdout(&out), "4\nloading lib\nimported");
+
+        let out = run_files(&[("main.blk", early_return), ("lib.blk", lib)], mode, &[]);
+        assert_eq!(get_stdout(&out), "1");
+    }
+}
+
+#[test]
+fn modules_given_on_the_command_line() {
+    let main = r#"
+import util
+print(util.twice(21))
+"#;
+    let util = r#"
+pub fn twice(int n) -> int {
+    return n * 2
+}
+"#;
+    let files = [("main.blk", main), ("lib/util.blk", util)];
+
+    for mode in [&["run", "-i"][..], &["run"][..]] {
+        let out = run_files(&files, mode, &["lib/util.blk"]);
+        assert_eq!(get_stdout(&out), "42");
+    }
+}
+
+#[test]
+fn err_modules() {
+    let private = r#"
+fn helper() {
+    print("private")
+}
+"#;
+    let cases = [
+        (
+            "import lib\nlib.helper()\n",
+            "Function `helper` of module `lib` is private, declare it with `pub` to use it here",
+        ),
+        ("import missing\n", "Module `missing` not found"),
+        (
+            "import lib\nimport lib\n",
+            "Module `lib` is already imported",
+        ),
+        (
+            "fn f() {\n    import lib\n}\n",
+            "Modules can only be imported at the top level",
+        ),
+    ];
+
+    for (main, expected) in cases {
+        let out = run_files(
+            &[("main.blk", main), ("lib.blk", private)],
+            &["run", "-i"],
+            &[],
+        );
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains(expected), "{stderr}");
+        assert_eq!(out.status.code(), Some(1));
+    }
+
+    let out = run_files(
+        &[("a.blk", "import b\n"), ("b.blk", "import a\n")],
+        &["run", "-i"],
+        &[],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("b.blk:1:1"), "{stderr}");
+    assert!(stderr.contains("Circular import: a.blk -> b.blk -> a.blk"));
+    let out = run_files(
+        &[
+            ("main.blk", "import lib\nprint(lib.f())\n"),
+            (
+                "lib.blk",
+                "pub fn f() -> int {\n    return 1\n}\n\nlet z = q\n",
+            ),
+        ],
+        &["run", "-i"],
+        &[],
+    );
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("lib.blk:5:9"), "{stderr}");
+    assert!(stderr.contains("Variable doesn't exist: `q`"));
+}
+
+#[test]
+fn args_project_commands() {
+    let app_args = get_args(&args(&["binary", "run", "-i", "--", "a"]));
+    assert_eq!(app_args.command, Some(Subcommand::Run));
+    assert!(app_args.interpreter);
+    assert_eq!(app_args.input, None);
+    assert_eq!(app_args.program_args, vec!["a"]);
+
+    let app_args = get_args(&args(&["binary", "build", "-s"]));
+    assert_eq!(app_args.command, Some(Subcommand::Build));
+    assert!(app_args.static_link);
+}
+
+#[test]
+fn args_file_commands() {
+    let app_args = get_args(&args(&["binary", "run", "main.blk", "lib.blk", "--", "a"]));
+    assert_eq!(app_args.command, Some(Subcommand::Run));
+    assert_eq!(app_args.input, Some(PathBuf::from("main.blk")));
+    assert_eq!(app_args.modules, vec![PathBuf::from("lib.blk")]);
+    assert!(app_args.build_and_run);
+    assert!(!app_args.interpreter);
+    assert_eq!(app_args.program_args, vec!["a"]);
+
+    let app_args = get_args(&args(&["binary", "run", "-i", "main.blk"]));
+    assert!(app_args.interpreter);
+    assert!(!app_args.build_and_run);
+
+    let app_args = get_args(&args(&["binary", "build", "main.blk", "-o", "out"]));
+    assert_eq!(app_args.command, Some(Subcommand::Build));
+    assert_eq!(app_args.input, Some(PathBuf::from("main.blk")));
+    assert_eq!(app_args.output, PathBuf::from("out"));
+    assert!(!app_args.build_and_run);
+
+    let app_args = get_args(&args(&["binary", "check", "main.blk"]));
+    assert_eq!(app_args.command, Some(Subcommand::Check));
+    assert_eq!(app_args.input, Some(PathBuf::from("main.blk")));
+
+    let app_args = get_args(&args(&["binary"]));
+    assert_eq!(app_args.command, Some(Subcommand::Repl));
+}
+
+#[test]
+fn cli_commands() {
+    let out = run_compiler(vec!["run", "-i", "TMP"]);
+    assert!(out.status.success());
+    assert_eq!(get_stdout(&out), "Hello, World!");
+    assert!(!get_stderr(&out).contains("Warning"));
+
+    // Checking runs nothing
+    let out = run_compiler(vec!["check", "TMP"]);
+    assert!(out.status.success());
+    assert_eq!(get_stdout(&out), "");
+
+    let dir = write_files(&[("bad.blk", "let int x = \"text\"\n")]);
+    let out = run_compiler(vec!["check", dir.join("bad.blk").to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(
+        get_stderr(&out).contains("Type Error"),
+        "{}",
+        get_stderr(&out)
+    );
+    remove_dir_all(dir).unwrap();
+
+    let out = run_compiler(vec!["build", "--help"]);
+    assert!(out.status.success());
+    assert!(get_stdout(&out).contains("black build"));
+
+    let out = run_compiler(vec!["build", "-i", "TMP"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(get_stderr(&out).contains("see `black build --help`"));
+
+    // The flags without a command still work, with a warning
+    let out = run_compiler(vec!["-i", "TMP"]);
+    assert!(out.status.success());
+    assert_eq!(get_stdout(&out), "Hello, World!");
+    assert!(get_stderr(&out).contains("use `black run -i FILE`"));
+}
+
+#[test]
+fn args_emit() {
+    let app_args = get_args(&args(&["binary", "build", "main.blk", "--emit=ir,asm"]));
+    assert_eq!(
+        app_args.emit,
+        vec![
+            (Emit::Ir, PathBuf::from("out.ssa")),
+            (Emit::Asm, PathBuf::from("out.s"))
+        ]
+    );
+    assert_eq!(app_args.last_stage(), Emit::Asm);
+    assert_eq!(app_args.emit_path(Emit::Exe), None);
+
+    // A single stage is written to the output path itself
+    let app_args = get_args(&args(&[
+        "binary", "build", "main.blk", "--emit", "ir", "-o", "-",
+    ]));
+    assert_eq!(app_args.emit, vec![(Emit::Ir, PathBuf::from("-"))]);
+
+    let app_args = get_args(&args(&["binary", "build", "main.blk", "-o", "app"]));
+    assert_eq!(app_args.emit_path(Emit::Exe), Some(Path::new("app")));
+    assert_eq!(app_args.last_stage(), Emit::Exe);
+}
+
+#[test]
+fn cli_emit() {
+    let dir = write_files(&[("main.blk", "let x = 1\nprint(x)\n")]);
+    let main = dir.join("main.blk");
+    let main = main.to_str().unwrap();
+
+    let out = run_compiler(vec!["build", main, "--emit=tokens", "-o", "-"]);
+    assert!(out.status.success());
+    assert!(get_stdout(&out).starts_with("1:1 Let\n1:5 Identifier(\"x\")"));
+
+    let output = dir.join("app");
+    let output = output.to_str().unwrap();
+    let out = run_compiler(vec!["build", main, "--emit=ast,ir", "-o", output]);
+    assert!(out.status.success(), "{}", get_stderr(&out));
+    let ast = read_to_string(dir.join("app.ast")).unwrap();
+    assert!(ast.contains("VariableDeclaration"));
+    let ir = read_to_string(dir.join("app.ssa")).unwrap();
+    assert!(ir.contains("export function w $main("), "{ir}");
+    // Nothing is built past the last stage
+    assert!(!dir.join("app").exists());
+
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn args_toolchain() {
+    let app_args = get_args(&args(&[
+        "binary",
+        "build",
+        "main.blk",
+        "--qbe",
+        "/opt/qbe",
+        "--cc=clang",
+        "-lm",
+        "-L",
+        "lib",
+    ]));
+    assert_eq!(app_args.qbe, Some(PathBuf::from("/opt/qbe")));
+    assert_eq!(app_args.cc, Some(PathBuf::from("clang")));
+    assert_eq!(app_args.link_args, vec!["-lm", "-Llib"]);
+}
+
+#[test]
+fn cli_toolchain() {
+    // Stands in for qbe, giving the assembly of a program which exits with 3
+    let fake_qbe = "#!/bin/sh\ncat > /dev/null\nprintf '.globl main\\nmain:\\n  mov $3, %%eax\\n  ret\\n.data\\n.globl black_source\\nblack_source:\\n  .byte 0\\n.section .note.GNU-stack,\"\",@progbits\\n'\n";
+    let failing_qbe = "#!/bin/sh\necho 'invalid input' >&2\nexit 1\n";
+    let dir = write_files(&[
+        ("main.blk", "print(1)\n"),
+        ("qbe", fake_qbe),
+        ("failing_qbe", failing_qbe),
+    ]);
+    for tool in ["qbe", "failing_qbe"] {
+        let path = dir.join(tool);
+        let mut permissions = path.metadata().unwrap().permissions();
+        permissions.set_mode(0o755);
+        std::fs::set_permissions(path, permissions).unwrap();
+    }
+    let black = |args: &[&str], qbe: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "build"])
+            .arg(dir.join("main.blk"))
+            .arg("-o")
+            .arg(dir.join("app"))
+            .args(args)
+            .env("BLACK_QBE", dir.join(qbe))
+            .output()
+            .expect("Failed to execute cargo")
+    };
+
+    let out = black(&["-lm"], "qbe");
+    assert!(out.status.success(), "{}", get_stderr(&out));
+    let status = Command::new(dir.join("app")).status().unwrap();
+    assert_eq!(status.code(), Some(3));
+
+    let out = black(&["-lblack_missing_library"], "qbe");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(get_stderr(&out).contains("[Toolchain Error]"));
+    assert!(get_stderr(&out).contains("black_missing_library"));
+
+    let out = black(&[], "failing_qbe");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(get_stderr(&out).contains("qbe failed"));
+    assert!(get_stderr(&out).contains("invalid input"));
+
+    // --qbe takes precedence over BLACK_QBE
+    let out = black(&["--qbe", "/black/missing/qbe"], "qbe");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(get_stderr(&out).contains("The qbe `/black/missing/qbe` was not found"));
+
+    let out = black(&["--cc", "black_missing_cc"], "qbe");
+    assert!(get_stderr(&out).contains("Install a C compiler"));
+
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn args_target() {
+    let app_args = get_args(&args(&[
+        "binary",
+        "build",
+        "main.blk",
+        "--target=rv64",
+        "--sysroot",
+        "/sysroot",
+    ]));
+    assert_eq!(app_args.target, Some(Target::Rv64));
+    assert_eq!(app_args.sysroot, Some(PathBuf::from("/sysroot")));
+    assert_eq!(Target::from_name("amd64_apple"), Some(Target::Amd64Apple));
+    assert_eq!(Target::from_name("x86"), None);
+}
+
+#[test]
+fn cli_target() {
+    // Stand in for qbe and the cross compiler, writing the arguments they're given
+    let fake_qbe = "#!/bin/sh\necho \"$@\" > \"$(dirname \"$0\")/qbe_args\"\ncat > /dev/null\necho '# assembly'\n";
+    let fake_cc = "#!/bin/sh\necho \"$@\" > \"$(dirname \"$0\")/cc_args\"\ncat > /dev/null\n";
+    let dir = write_files(&[
+        ("main.blk", "print(1)\n"),
+        ("qbe", fake_qbe),
+        ("cc", fake_cc),
+    ]);
+    for tool in ["qbe", "cc"] {
+        let path = dir.join(tool);
+        let mut permissions = path.metadata().unwrap().permissions();
+        permissions.set_mode(0o755);
+        std::fs::set_permissions(path, permissions).unwrap();
+    }
+    let black = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "build"])
+            .arg(dir.join("main.blk"))
+            .args(args)
+            .env("BLACK_QBE", dir.join("qbe"))
+            .env("BLACK_CC_RV64", dir.join("cc"))
+            .env("BLACK_CC_ARM64", "/black/missing/cc")
+            .output()
+            .expect("Failed to execute cargo")
+    };
+
+    // The assembly is checked without a cross linker
+    let out = black(&["--target", "rv64", "--emit=asm", "-o", "-"]);
+    assert!(out.status.success(), "{}", get_stderr(&out));
+    assert_eq!(get_stdout(&out), "# assembly");
+    let qbe_args = read_to_string(dir.join("qbe_args")).unwrap();
+    assert_eq!(qbe_args.trim(), "-t rv64 -");
+
+    let app = dir.join("app");
+    let out = black(&[
+        "--target=rv64",
+        "--sysroot=/rv64",
+        "-o",
+        app.to_str().unwrap(),
+    ]);
+    assert!(out.status.success(), "{}", get_stderr(&out));
+    let cc_args = read_to_string(dir.join("cc_args")).unwrap();
+    assert!(cc_args.starts_with("--sysroot=/rv64 -x c"), "{cc_args}");
+    assert!(
+        cc_args.contains(&format!("-o {}", app.display())),
+        "{cc_args}"
+    );
+
+    let out = black(&["--target", "arm64"]);
+    assert_eq!(out.status.code(), Some(1));
+    assert!(get_stderr(&out).contains("`/black/missing/cc` for arm64 was not found"));
+
+    let out = black(&["--target", "arm64_apple", "--static"]);
+    assert!(get_stderr(&out).contains("can't be statically linked"));
+
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn args_backend() {
+    let app_args = get_args(&args(&[
+        "binary",
+        "build",
+        "main.blk",
+        "--backend",
+        "native",
+    ]));
+    assert_eq!(app_args.backend, Backend::Native);
+    let app_args = get_args(&args(&["binary", "run", "main.blk", "--backend=qbe"]));
+    assert_eq!(app_args.backend, Backend::Qbe);
+    let app_args = get_args(&args(&["binary", "build", "main.blk"]));
+    assert_eq!(app_args.backend, Backend::Qbe);
+}
+
+#[test]
+fn native_backend() {
+    let code = r#"
+struct Point { int x, float y, str name }
+
+fn many(int a, int b, int c, int d, int e, int f, int g, int h, float i, float j) -> float {
+    print(a + b + c + d + e + f + g + h, g, h)
+    return i / j
+}
+
+fn floats(float a, float b, float c, float d, float e, float f, float g, float h, float i) -> float {
+    return a + b + c + d + e + f + g + h + i
+}
+
+fn make(int x, str name) -> Point {
+    return Point { x: x * 2, y: float(x) / float("4"), name: name }
+}
+
+fn shift(Point p, int by) -> Point {
+    return Point { x: p.x + by, y: p.y * float("2"), name: p.name }
+}
+
+let one = float("1")
+let p = shift(make(5, "pt"), 7)
+print(p)
+print(many(1, 2, 3, 4, 5, 6, 7, 8, float("9"), float("2")))
+print(floats(one, one, one, one, one, one, one, one, float("0.5")))
+print(17 / 5, 0 - 17 / 5)
+let xs = [3, 1, 2]
+push(xs, 10)
+for x in xs {
+    print(x * x)
+}
+let m = {"a": 1, "b": 2}
+m["c"] = 3
+print(m["c"] + m["a"], len(xs))
+try {
+    error("boom")
+} catch e {
+    print("caught", e)
+}
+"#;
+    let expected = "Point { x: 17, y: 2.5, name: \"pt\" }\n36 7 8\n4.5\n8.5\n3 -3\n9\n1\n4\n100\n4 4\ncaught boom";
+
+    assert_eq!(interpret(code), expected);
+    if Target::host() == Target::Amd64Sysv {
+        let out = run_with_program_args(code, &["run", "--backend", "native"], &[]);
+        assert!(out.status.success(), "{}", get_stderr(&out));
+        assert_eq!(get_stdout(&out), expected);
+    }
+
+    // Other targets are left to qbe
+    let out = run_files(
+        &[("main.blk", code)],
+        &["build", "--backend=native", "--target=rv64"],
+        &[],
+    );
+    assert_eq!(out.status.code(), Some(1));
+    assert!(get_stderr(&out).contains("only generates code for amd64_sysv"));
+}
+
+#[test]
+fn project_dependencies() {
+    let dir = write_files(&[
+        (
+            "app/black.toml",
+            r#"
+# Dependencies are imported by the last part of their name
+[package]
+name = "app"
+
+[dependencies]
+utils = { path = "../utils" }
+"someone/greet" = { git = "file://GIT" }
+"#,
+        ),
+        (
+            "app/src/main.blk",
+            "import utils\nimport greet\nprint(utils.twice(3))\ngreet.hello()\nprint(args())\n",
+        ),
+        (
+            "utils/black.toml",
+            "[package]\nname = \"utils\"\nentry = \"lib.blk\"\n\n[dependencies]\nsquares = { path = \"../squares\" }\n",
+        ),
+        (
+            "utils/lib.blk",
+            "import squares\npub fn twice(int n) -> int {\n    return squares.square(n) * 2\n}\n",
+        ),
+        ("squares/black.toml", "[package]\nname = \"squares\"\nentry = \"lib.blk\"\n"),
+        ("squares/lib.blk", "pub fn square(int n) -> int {\n    return n * n\n}\n"),
+        ("greet/black.toml", "[package]\nname = \"greet\"\nentry = \"lib.blk\"\n"),
+        ("greet/lib.blk", "pub fn hello() {\n    print(\"hello v1\")\n}\n"),
+    ]);
+    let app = dir.join("app");
+    let greet = dir.join("greet");
+    let manifest = read_to_string(app.join("black.toml")).unwrap();
+    write(
+        app.join("black.toml"),
+        manifest.replace("GIT", greet.to_str().unwrap()),
+    )
+    .unwrap();
+    git(&greet, &["init", "--quiet"]);
+    git(&greet, &["add", "."]);
+    git(&greet, &["commit", "--quiet", "-m", "v1"]);
+
+    let out = run_in_dir(&app, &["run", "-i", "--", "x"]);
+    assert_eq!(get_stdout(&out), "18\nhello v1\n[\"x\"]");
+    let lockfile = read_to_string(app.join("black.lock")).unwrap();
+    assert!(lockfile.contains("squares = { path = \"../squares\" }"));
+    assert!(lockfile.contains("utils = { path = \"../utils\" }"));
+    assert!(lockfile.contains("\"someone/greet\" = { git = \"file://"));
+    assert!(app.join("target/deps/someone-greet/lib.blk").exists());
+
+    // The lockfile keeps the dependency at the commit it resolved to
+    write(
+        greet.join("lib.blk"),
+        "pub fn hello() {\n    print(\"hello v2\")\n}\n",
+    )
+    .unwrap();
+    git(&greet, &["commit", "--quiet", "-am", "v2"]);
+    let out = run_in_dir(&app, &["run", "-i"]);
+    assert_eq!(get_stdout(&out), "18\nhello v1\n[]");
+    assert_eq!(read_to_string(app.join("black.lock")).unwrap(), lockfile);
+
+    remove_file(app.join("black.lock")).unwrap();
+    let out = run_in_dir(&app.join("src"), &["run", "-i"]);
+    assert_eq!(get_stdout(&out), "18\nhello v2\n[]");
+
+    // Moving the dependency to another repository clones it again, the pinned commit is dropped
+    let fork = dir.join("fork");
+    create_dir(&fork).unwrap();
+    write(
+        fork.join("black.toml"),
+        "[package]\nname = \"greet\"\nentry = \"lib.blk\"\n",
+    )
+    .unwrap();
+    write(
+        fork.join("lib.blk"),
+        "pub fn hello() {\n    print(\"hello fork\")\n}\n",
+    )
+    .unwrap();
+    git(&fork, &["init", "--quiet"]);
+    git(&fork, &["add", "."]);
+    git(&fork, &["commit", "--quiet", "-m", "fork"]);
+    let manifest = read_to_string(app.join("black.toml")).unwrap();
+    write(
+        app.join("black.toml"),
+        manifest.replace(greet.to_str().unwrap(), fork.to_str().unwrap()),
+    )
+    .unwrap();
+    let out = run_in_dir(&app, &["run", "-i"]);
+    assert_eq!(get_stdout(&out), "18\nhello fork\n[]");
+    let lockfile = read_to_string(app.join("black.lock")).unwrap();
+    let commit = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&fork)
+        .output()
+        .unwrap();
+    let commit = String::from_utf8_lossy(&commit.stdout).trim().to_string();
+    assert!(lockfile.contains(&format!(
+        "{{ git = \"file://{}\", commit = \"{commit}\" }}",
+        fork.display()
+    )));
+
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn err_project_manifest() {
+    let cases = [
+        (
+            "[package]\nname = \"app\"\n\n[dependencies]\n\"owner/package\" = \"0.1\"\n",
+            "Dependency `owner/package` asks for version \"0.1\", fetching packages isn't supported yet",
+        ),
+        (
+            "[package]\nname = \"app\"\n\n[dependencies]\nremote = { git = \"https://example.com/remote\" }\n",
+            "only local `file://` git repositories are supported for now",
+        ),
+        (
+            "[package]\nname = \"app\"\n\n[dependencies]\nmissing = { path = \"../missing\" }\n",
+            "Dependency `missing` has no `black.toml` in \"../missing\"",
+        ),
+        ("[package]\nname = \"app\"\nstatic = \"yes\"\n", "`static` must be `true` or `false`"),
+        ("[package]\nname = \"app\"\nversion = \"1\"\n", "Unknown key `version` in `[package]`"),
+        (
+            "[package]\nname = \"app\"\noutput = \"../app\"\n",
+            "black.toml:3:1",
+        ),
+        (
+            "[package]\nname = \"app\"\noutput = \"/tmp/app\"\n",
+            "`output` must be a path inside the target directory",
+        ),
+        ("[package]\nname = \"app\n", "Unterminated string"),
+        (
+            "[package]\nname = \"app\"\n\n[lint]\nunused = \"deny\"\n",
+            "Unknown lint rule `unused`",
+        ),
+        (
+            "[package]\nname = \"app\"\n\n[lint]\nunused_variable = \"error\"\n",
+            "The level of `unused_variable` must be \"allow\", \"warn\" or \"deny\"",
+        ),
+    ];
+
+    for (manifest, expected) in cases {
+        let dir = write_files(&[("black.toml", manifest), ("src/main.blk", "print(1)\n")]);
+        let out = run_in_dir(&dir, &["run", "-i"]);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains(expected), "{stderr}");
+        assert!(stderr.contains("[Manifest Error]"), "{stderr}");
+        assert_eq!(out.status.code(), Some(1));
+        remove_dir_all(dir).unwrap();
+    }
+
+    let dir = write_files(&[("src/main.blk", "print(1)\n")]);
+    let out = run_in_dir(&dir, &["build"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Could not find `black.toml`"));
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn format_code() {
+    let code = r#"
+// Shapes
+import   math
+
+
+struct Point {int x,int y}
+enum Shape{Circle(int),Rect(int,int)}
+fn area(Shape s)->int{
+  return match s{
+  Shape::Circle(r)=>3*r*r,   // circle
+  Shape::Rect(w,h)=>w*h
+  }
+}
+let p=Point{x:1,y:2}   let m = {"a":1,"b":[1,2]}
+let xs = [
+  1, 2,
+  // three
+  3,
+]
+if let n = m["a"] { print(n) } else {
+
+  print( "none" , p.x , xs[0] ?? 0)
+
+}
+let total = 1 + // trailing
+  2
+if true {
+ print(x)
+}
+// between
+else {
+ print("no")
+}
+"#;
+    let expected = r#"// Shapes
+import math
+
+struct Point { int x, int y }
+enum Shape { Circle(int), Rect(int, int) }
+fn area(Shape s) -> int {
+    return match s {
+        Shape::Circle(r) => 3 * r * r, // circle
+        Shape::Rect(w, h) => w * h
+    }
+}
+let p = Point { x: 1, y: 2 }
+let m = {"a": 1, "b": [1, 2]}
+let xs = [
+    1,
+    2,
+    // three
+    3,
+]
+if let n = m["a"] {
+    print(n)
+} else {
+    print("none", p.x, xs[0] ?? 0)
+}
+let total = 1 + // trailing
+    2
+if true {
+    print(x)
+}
+// between
+else {
+    print("no")
+}
+"#;
+    assert_eq!(format(code).unwrap(), expected);
+    assert_eq!(format(expected).unwrap(), expected);
+}
+
+#[test]
+fn format_keeps_behaviour() {
+    let code = r#"
+fn fib(int n)->int{ return match n { 0=>0, 1=>1, _=>fib(n-1)+fib(n-2) } }
+let xs=[1,2,3]   for x in xs{print(x*2)} // doubled
+print(fib(10))
+"#;
+    let formatted = format(code).unwrap();
+    assert_eq!(interpret(code), "2\n4\n6\n55");
+    assert_eq!(interpret(&formatted), interpret(code));
+    assert_eq!(format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn format_command() {
+    let mut command = Command::new("cargo");
+    command.args(["run", "--", "fmt"]);
+    let out = run_with_input(command, "print( 1 ,2)");
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "print(1, 2)\n");
+
+    let mut command = Command::new("cargo");
+    command.args(["run", "--", "fmt", "--check"]);
+    assert_eq!(
+        run_with_input(command, "print(1, 2)\n").status.code(),
+        Some(0)
+    );
+    let mut command = Command::new("cargo");
+    command.args(["run", "--", "fmt", "--check"]);
+    assert_eq!(
+        run_with_input(command, "print(1,2)\n").status.code(),
+        Some(1)
+    );
+
+    let dir = write_files(&[("a.blk", "print(1,2)\n"), ("b.blk", "print(3)\n")]);
+    let out = run_in_dir(&dir, &["fmt", "--check", "a.blk", "b.blk"]);
+    assert_eq!(get_stdout(&out), "Not formatted: a.blk");
+    assert_eq!(out.status.code(), Some(1));
+    let out = run_in_dir(&dir, &["fmt", "a.blk", "b.blk"]);
+    assert_eq!(out.status.code(), Some(0));
+    assert_eq!(read_to_string(dir.join("a.blk")).unwrap(), "print(1, 2)\n");
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn err_format_invalid_code() {
+    assert!(matches!(format("let = 1"), Err(ErrorType::SyntaxError(..))));
+}
+
+/// Rules and lines of the problems the linter finds in the code
+fn lints(code: &str) -> Vec<(Rule, usize)> {
+    lint(code)
+        .unwrap()
+        .iter()
+        .map(|lint| (lint.rule, lint.span().line))
+        .collect()
+}
+
+#[test]
+fn lint_rules() {
+    let code = r#"
+let int x = 5
+let y = 3
+let y = 4
+print(y)
+
+fn add(int a, int b) -> int {
+    return a
+    print("unreachable")
+}
+
+if true {
+    print(add(1, 2))
+}
+for i in [1, 2] {
+    let [int] list = [i]
+    print(list)
+}
+match y {
+    n => print("any"),
+}
+try {
+    print(1)
+} catch e {
+    print(2)
+}
+let _ignored = 1
+"#;
+    assert_eq!(
+        lints(code),
+        [
+            (Rule::RedundantType, 2),
+            (Rule::UnusedVariable, 2),
+            (Rule::UnusedVariable, 3),
+            (Rule::ShadowedVariable, 4),
+            (Rule::UnusedVariable, 7),
+            (Rule::UnreachableCode, 9),
+            (Rule::ConstantCondition, 12),
+            (Rule::UnusedVariable, 20),
+            (Rule::UnusedVariable, 24),
+        ]
+    );
+
+    // Uses in nested blocks, assignments to elements and types the value doesn't spell out
+    let code = r#"
+let [int] list = []
+let int? maybe = 5
+let long big = 5
+let count = 0
+count = 1
+list[0] = 1
+if let value = maybe {
+    print(value)
+}
+fn id(int a) -> int {
+    let b = a
+    return b
+}
+print(id(1))
+"#;
+    assert_eq!(
+        lints(code),
+        [(Rule::UnusedVariable, 4), (Rule::UnusedVariable, 5)]
+    );
+}
+
+#[test]
+fn lint_allow_comments() {
+    let code = r#"
+let x = 1 // black:allow(unused_variable)
+// black:allow(redundant_type, unused_variable)
+let int y = 2
+let z = 3 // black:allow(redundant_type)
+"#;
+    assert_eq!(lints(code), [(Rule::UnusedVariable, 5)]);
+    assert!(lint("let = 1").is_err());
+}
+
+#[test]
+fn lint_command() {
+    let code = "let int x = 1\nif false {\n    print(2)\n}\n";
+    let dir = write_files(&[("a.blk", code)]);
+    let out = run_in_dir(&dir, &["lint", "a.blk"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("[Warning]"), "{stderr}");
+    assert!(
+        stderr.contains("Unused variable `x` (unused_variable)"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Condition is always `false`"), "{stderr}");
+    assert_eq!(out.status.code(), Some(0));
+
+    let manifest = "[package]\nname = \"app\"\n\n[lint]\nunused_variable = \"deny\"\nredundant_type = \"allow\"\n";
+    write(dir.join("black.toml"), manifest).unwrap();
+    let out = run_in_dir(&dir, &["lint", "a.blk"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("[Lint Error]"), "{stderr}");
+    assert!(!stderr.contains("redundant_type"), "{stderr}");
+    assert_eq!(out.status.code(), Some(1));
+    remove_dir_all(dir).unwrap();
+
+    let mut command = Command::new("cargo");
+    command.args(["run", "--", "lint"]);
+    let out = run_with_input(command, "let x = 1\nprint(x)\n");
+    assert_eq!(
+        String::from_utf8_lossy(&out.stderr)
+            .matches("[Warning]")
+            .count(),
+        0
+    );
+    assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn json_values() {
+    let source =
+        r#"{"id": 1, "text": "a \"quote\"\né😀", "list": [true, null, -2.5e1], "empty": {}}"#;
+    let json = Json::parse(source).unwrap();
+    assert_eq!(json.get("id").and_then(Json::as_usize), Some(1));
+    assert_eq!(
+        json.get("text").and_then(Json::as_str),
+        Some("a \"quote\"\né😀")
+    );
+    assert_eq!(
+        json.to_string(),
+        r#"{"id":1,"text":"a \"quote\"\né😀","list":[true,null,-25],"empty":{}}"#
+    );
+    assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+    assert!(Json::parse("{\"a\": }").is_err());
+    assert!(Json::parse("[1] 2").is_err());
+}
+
+/// Sends a scripted message to the language server, returning the messages it sends back
+fn lsp_send(server: &mut Server, message: &str) -> Vec<Json> {
+    server.handle(&Json::parse(message).unwrap())
+}
+
+/// Sends a request about a position of the document to the language server, returning the
+/// result of the response
+fn lsp_request(server: &mut Server, method: &str, line: usize, character: usize) -> Json {
+    let message = format!(
+        r#"{{"jsonrpc": "2.0", "id": 2, "method": "textDocument/{method}", "params": {{"textDocument": {{"uri": "untitled:doc"}}, "position": {{"line": {line}, "character": {character}}}, "options": {{}}}}}}"#
+    );
+    let mut replies = lsp_send(server, &message);
+    assert_eq!(replies.len(), 1);
+    replies.pop().unwrap().get("result").cloned().unwrap()
+}
+
+#[test]
+fn lsp_messages() {
+    let mut server = Server::default();
+    let replies = lsp_send(
+        &mut server,
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}"#,
+    );
+    let capabilities = replies[0]
+        .get("result")
+        .unwrap()
+        .get("capabilities")
+        .unwrap();
+    assert_eq!(capabilities.get("hoverProvider"), Some(&Json::Bool(true)));
+    assert!(lsp_send(
+        &mut server,
+        r#"{"jsonrpc": "2.0", "method": "initialized"}"#
+    )
+    .is_empty());
+
+    // Diagnostics are published when a document is opened
+    let code = r#"let x = 5
+fn add(int a, int b) -> int {
+    return a + b
+}
+print(add(x, 2))
+let y = "a" + 1
+"#;
+    let open = Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/didOpen")),
+        (
+            "params",
+            Json::object([(
+                "textDocument",
+                Json::object([
+                    ("uri", Json::from("untitled:doc")),
+                    ("text", Json::from(code)),
+                ]),
+            )]),
+        ),
+    ]);
+    let replies = server.handle(&open);
+    assert_eq!(
+        replies[0].get("method").and_then(Json::as_str),
+        Some("textDocument/publishDiagnostics")
+    );
+    let diagnostics = replies[0]
+        .get("params")
+        .unwrap()
+        .get("diagnostics")
+        .unwrap();
+    assert_eq!(
+        diagnostics.to_string(),
+        r#"[{"range":{"start":{"line":5,"character":8},"end":{"line":5,"character":15}},"severity":1,"source":"black","message":"Cannot add variable which is not a number"},{"range":{"start":{"line":5,"character":4},"end":{"line":5,"character":5}},"severity":2,"source":"black","code":"unused_variable","message":"Unused variable `y`"}]"#
+    );
+
+    // Hover shows inferred types and signatures
+    let hover = lsp_request(&mut server, "hover", 4, 11);
+    assert_eq!(
+        hover
+            .get("contents")
+            .unwrap()
+            .get("value")
+            .and_then(Json::as_str),
+        Some("```black\nlet int x\n```")
+    );
+    let hover = lsp_request(&mut server, "hover", 4, 7);
+    assert_eq!(
+        hover
+            .get("contents")
+            .unwrap()
+            .get("value")
+            .and_then(Json::as_str),
+        Some("```black\nfn add(int a, int b) -> int\n```")
+    );
+    assert_eq!(lsp_request(&mut server, "hover", 1, 0), Json::Null);
+
+    // Parameters are defined in the function, functions anywhere
+    let definition = lsp_request(&mut server, "definition", 2, 15);
+    assert_eq!(
+        definition.get("range").unwrap().to_string(),
+        r#"{"start":{"line":1,"character":18},"end":{"line":1,"character":19}}"#
+    );
+    let definition = lsp_request(&mut server, "definition", 4, 8);
+    assert_eq!(
+        definition.get("range").unwrap().to_string(),
+        r#"{"start":{"line":1,"character":3},"end":{"line":1,"character":6}}"#
+    );
+
+    // Names in scope come first, then builtins and keywords
+    let completion = lsp_request(&mut server, "completion", 2, 4);
+    let labels: Vec<&str> = completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|item| item.get("label").and_then(Json::as_str))
+        .collect();
+    assert_eq!(labels[..4], ["b", "a", "x", "add"]);
+    assert!(labels.contains(&"print") && labels.contains(&"match"));
+
+    // Changes replace the whole text
+    let change = r#"{"jsonrpc": "2.0", "method": "textDocument/didChange", "params": {"textDocument": {"uri": "untitled:doc"}, "contentChanges": [{"text": "print( 1 ,2)\n"}]}}"#;
+    let replies = lsp_send(&mut server, change);
+    let diagnostics = replies[0]
+        .get("params")
+        .unwrap()
+        .get("diagnostics")
+        .unwrap();
+    assert_eq!(diagnostics, &Json::Array(Vec::new()));
+    let edits = lsp_request(&mut server, "formatting", 0, 0);
+    assert_eq!(
+        edits.as_array().unwrap()[0]
+            .get("newText")
+            .and_then(Json::as_str),
+        Some("print(1, 2)\n")
+    );
+
+    let replies = lsp_send(
+        &mut server,
+        r#"{"jsonrpc": "2.0", "id": 3, "method": "unknown"}"#,
+    );
+    assert_eq!(
+        replies[0].get("error").unwrap().get("code"),
+        Some(&Json::Number(-32601.0))
+    );
+    let replies = lsp_send(
+        &mut server,
+        r#"{"jsonrpc": "2.0", "id": 4, "method": "shutdown"}"#,
+    );
+    assert_eq!(replies[0].get("result"), Some(&Json::Null));
+    assert!(server.shutdown);
+}
+
+#[test]
+fn lsp_stdio() {
+    let frame = |message: &str| format!("Content-Length: {}\r\n\r\n{message}", message.len());
+    let initialize = frame(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#);
+    let shutdown = frame(r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#);
+    let exit = frame(r#"{"jsonrpc":"2.0","method":"exit"}"#);
+
+    let mut command = Command::new("cargo");
+    command.args(["run", "--quiet", "--", "lsp"]);
+    let out = run_with_input(command, &format!("{initialize}{shutdown}{exit}"));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.starts_with("Content-Length: "), "{stdout}");
+    assert!(
+        stdout.contains(r#""id":1,"result":{"capabilities""#),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with(r#"{"jsonrpc":"2.0","id":2,"result":null}"#),
+        "{stdout}"
+    );
+    assert_eq!(out.status.code(), Some(0));
+
+    // Exiting without a shutdown request fails
+    let mut command = Command::new("cargo");
+    command.args(["run", "--quiet", "--", "lsp"]);
+    let out = run_with_input(command, &format!("{}{exit}", frame("not json")));
+    assert!(String::from_utf8_lossy(&out.stdout).contains(r#""code":-32700"#));
+    assert_eq!(out.status.code(), Some(1));
+}
+
 // #[test]
 // fn if_statement() {
 //     let code = r#"
@@ -0,0 +1,296 @@
+use crate::{
+    args::{AppArgs, Backend},
+    utils::{dbg, get_tmp_fname, measure_time, write_output, ErrorType},
+};
+use std::{
+    env,
+    ffi::OsStr,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+/// How to install qbe, shown when it isn't found
+const QBE_HINT: &str = "Install it with your package manager (e.g. `apt install qbe` or \
+`brew install qbe`) or from https://c9x.me/compile/, or give its path with --qbe or BLACK_QBE";
+
+/// How to install a C compiler, shown when it isn't found
+const CC_HINT: &str = "Install a C compiler such as gcc or clang, or give its path with --cc or \
+BLACK_CC";
+
+/// Platform the compiler generates code for, as named by QBE
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Target {
+    Amd64Sysv,
+    Amd64Apple,
+    Arm64,
+    Arm64Apple,
+    Rv64,
+}
+
+impl Target {
+    pub const ALL: [Target; 5] = [
+        Target::Amd64Sysv,
+        Target::Amd64Apple,
+        Target::Arm64,
+        Target::Arm64Apple,
+        Target::Rv64,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Amd64Sysv => "amd64_sysv",
+            Target::Amd64Apple => "amd64_apple",
+            Target::Arm64 => "arm64",
+            Target::Arm64Apple => "arm64_apple",
+            Target::Rv64 => "rv64",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Target> {
+        Target::ALL.into_iter().find(|target| target.name() == name)
+    }
+
+    /// The target of the machine black runs on
+    pub fn host() -> Target {
+        if cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+            Target::Arm64Apple
+        } else if cfg!(target_os = "macos") {
+            Target::Amd64Apple
+        } else if cfg!(target_arch = "aarch64") {
+            Target::Arm64
+        } else if cfg!(target_arch = "riscv64") {
+            Target::Rv64
+        } else {
+            Target::Amd64Sysv
+        }
+    }
+
+    /// C compiler building for the target from another machine, along with its arguments
+    fn cross_cc(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Target::Amd64Sysv => ("x86_64-linux-gnu-gcc", &[]),
+            Target::Arm64 => ("aarch64-linux-gnu-gcc", &[]),
+            Target::Rv64 => ("riscv64-linux-gnu-gcc", &[]),
+            // Apple binaries are built by clang, given an SDK with --sysroot
+            Target::Amd64Apple => ("clang", &["--target=x86_64-apple-darwin"]),
+            Target::Arm64Apple => ("clang", &["--target=arm64-apple-darwin"]),
+        }
+    }
+
+    /// Environment variable giving the C compiler of the target, e.g. `BLACK_CC_ARM64`
+    fn cc_var(self) -> String {
+        format!("BLACK_CC_{}", self.name().to_uppercase())
+    }
+
+    fn is_apple(self) -> bool {
+        matches!(self, Target::Amd64Apple | Target::Arm64Apple)
+    }
+}
+
+/// External programs the compiler runs: `qbe` to generate assembly and `cc` to assemble and
+/// link it together with the runtime
+#[derive(Debug)]
+pub struct Toolchain {
+    pub qbe: PathBuf,
+    pub cc: PathBuf,
+    /// Arguments `cc` is always given, selecting the target and its sysroot
+    pub cc_args: Vec<String>,
+    /// `-l` and `-L` flags passed to `cc` when linking
+    pub link_args: Vec<String>,
+    /// Target given to `qbe`, its default target when `None`
+    pub target: Option<Target>,
+}
+
+impl Toolchain {
+    /// The tools given on the command line, then by `BLACK_QBE` and `BLACK_CC`, then the ones
+    /// in `PATH`. Other targets than the host's get their cross compiler instead, given by
+    /// `--cc`, by `BLACK_CC_<TARGET>` or found in `PATH`
+    pub fn new(args: &AppArgs) -> Toolchain {
+        let qbe = args
+            .qbe
+            .clone()
+            .or_else(|| env::var_os("BLACK_QBE").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("qbe"));
+
+        let mut cc_args = Vec::new();
+        let cc = match (&args.cc, args.target) {
+            (Some(cc), _) => cc.clone(),
+            (None, Some(target)) if target != Target::host() => env::var_os(target.cc_var())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| {
+                    let (cc, target_args) = target.cross_cc();
+                    cc_args.extend(target_args.iter().map(|arg| arg.to_string()));
+                    PathBuf::from(cc)
+                }),
+            (None, _) => env::var_os("BLACK_CC")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("cc")),
+        };
+        if let Some(sysroot) = &args.sysroot {
+            cc_args.push(format!("--sysroot={}", sysroot.display()));
+        }
+
+        Toolchain {
+            qbe,
+            cc,
+            cc_args,
+            link_args: args.link_args.clone(),
+            target: args.target,
+        }
+    }
+
+    /// Whether binaries for the target run on this machine
+    pub fn runs_on_host(target: Option<Target>) -> bool {
+        target.is_none_or(|target| target == Target::host())
+    }
+
+    /// Checks the options which the target doesn't support
+    pub fn check_target(&self, static_link: bool, backend: Backend) -> Result<(), ErrorType> {
+        let target = self.target.unwrap_or_else(Target::host);
+        if static_link && target.is_apple() {
+            return Err(ErrorType::ToolchainError(format!(
+                "Binaries for {} can't be statically linked",
+                target.name()
+            )));
+        }
+        if backend == Backend::Native && target != Target::Amd64Sysv {
+            return Err(ErrorType::ToolchainError(format!(
+                "The native backend only generates code for amd64_sysv, use the qbe backend for {}",
+                target.name()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that the tools exist before anything is compiled
+    pub fn check(&self, qbe: bool, cc: bool) -> Result<(), ErrorType> {
+        if qbe && find(&self.qbe).is_none() {
+            return Err(not_found("qbe", &self.qbe, QBE_HINT));
+        }
+        if cc && find(&self.cc).is_none() {
+            return Err(match self.target {
+                Some(target) if target != Target::host() => ErrorType::ToolchainError(format!(
+                    "The C compiler `{}` for {} was not found. Install a cross compiler for the \
+                    target, or give its path with --cc or {}",
+                    self.cc.display(),
+                    target.name(),
+                    target.cc_var()
+                )),
+                _ => not_found("cc", &self.cc, CC_HINT),
+            });
+        }
+        Ok(())
+    }
+
+    /// Compiles the IR to assembly
+    pub fn qbe(&self, ir: &str) -> Result<String, ErrorType> {
+        measure_time("QBE execution", || {
+            let mut qbe = Command::new(&self.qbe);
+            if let Some(target) = self.target {
+                qbe.args(["-t", target.name()]);
+            }
+            qbe.arg("-");
+            let asm = run(qbe, ir, "qbe")?;
+            Ok(String::from_utf8_lossy(&asm).to_string())
+        })
+    }
+
+    /// Runs `cc` with the arguments and the assembly as its input, writing the output to the path
+    pub fn cc(&self, cc_args: &[&str], asm: &str, path: &Path) -> Result<(), ErrorType> {
+        // Binaries written to stdout are built in a temporary file first
+        let to_stdout = path == Path::new("-");
+        let out_file = if to_stdout {
+            PathBuf::from(get_tmp_fname("black_out"))
+        } else {
+            path.to_path_buf()
+        };
+
+        measure_time("CC execution", || {
+            let mut cc = Command::new(&self.cc);
+            cc.args(&self.cc_args)
+                .args(cc_args)
+                .arg("-o")
+                .arg(&out_file)
+                .args(["-x", "assembler", "-"]);
+            // Libraries come after the inputs which use them
+            if !cc_args.contains(&"-r") {
+                cc.args(&self.link_args);
+            }
+            let output = run(cc, asm, "cc")?;
+            if !output.is_empty() {
+                dbg("CC output", &String::from_utf8_lossy(&output));
+            }
+            Ok::<_, ErrorType>(())
+        })?;
+
+        if to_stdout {
+            let binary = std::fs::read(&out_file).map_err(|_| {
+                ErrorType::ToolchainError("Could not read the output of cc".to_string())
+            })?;
+            let _ = std::fs::remove_file(&out_file);
+            write_output(path, &binary)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Path of the tool, searched in `PATH` unless it's a path itself
+fn find(tool: &Path) -> Option<PathBuf> {
+    let executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+    if tool.components().count() > 1 {
+        return executable(tool).then(|| tool.to_path_buf());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(tool))
+        .find(|path| executable(path))
+}
+
+fn not_found(name: &str, tool: &Path, hint: &str) -> ErrorType {
+    let message = if tool.as_os_str() == OsStr::new(name) {
+        format!("`{name}` was not found in PATH. {hint}")
+    } else {
+        format!("The {name} `{}` was not found. {hint}", tool.display())
+    };
+    ErrorType::ToolchainError(message)
+}
+
+/// Runs the command with the input written to its stdin, giving its stdout. Failures carry the
+/// stderr of the command
+fn run(mut command: Command, input: &str, name: &str) -> Result<Vec<u8>, ErrorType> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| ErrorType::ToolchainError(format!("Could not start {name}: {err}")))?;
+
+    // The input is written from another thread, so that a full stdout pipe can't block it
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .map_err(|err| ErrorType::ToolchainError(format!("Could not wait for {name}: {err}")))?;
+    let written = writer.join().expect("Failed to join the stdin writer");
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(ErrorType::ToolchainError(format!(
+            "{name} failed with {}\n{}",
+            output.status,
+            stderr.trim_end()
+        )));
+    }
+    written
+        .map_err(|err| ErrorType::ToolchainError(format!("Could not write to {name}: {err}")))?;
+
+    Ok(output.stdout)
+}
@@ -1,17 +1,16 @@
-use crate::{
-    compiler::Compiler,
-    parser::{lexer, Parser},
-};
+use crate::parser::Span;
 use std::{
     env,
     fmt::{Debug, Display},
-    fs::{read_to_string, OpenOptions},
+    fs::{read_to_string, write, OpenOptions},
     io::{stdout, Write},
+    path::Path,
     time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 /// ANSI color codes for CLI output
 #[allow(dead_code)]
+#[derive(Clone, Copy)]
 pub enum Color {
     Gray,
     Black,
@@ -150,8 +149,53 @@ pub fn measure_time<T, F: FnOnce() -> T>(label: &str, f: F) -> T {
 
 #[derive(Debug, PartialEq)]
 pub enum ErrorType {
-    SyntaxError(String),
+    /// Invalid syntax, along with the location of the offending token
+    SyntaxError(String, Span),
     Generic(String),
+    /// Error found by the checker, along with the location of the offending code
+    SemanticError(String, Span),
+    /// Type error message along with labeled source locations it refers to
+    TypeError(String, Vec<(Span, String)>),
+    /// Error raised while running the program, along with the location of the failing expression
+    RuntimeError(String, Span),
+    /// Module which can't be imported, along with the location of the import
+    ImportError(String, Span),
+    /// Error in the source file of an imported module, along with the path of the file
+    InFile(String, Box<ErrorType>),
+    /// Invalid project manifest or lockfile, along with the location of the offending line
+    ManifestError(String, Span),
+    /// Problem found by the linter at the `deny` level, along with labeled source locations
+    LintError(String, Vec<(Span, String)>),
+    /// Missing or failing `qbe` or `cc`
+    ToolchainError(String),
+}
+
+impl ErrorType {
+    /// Attributes the error to the given source file, unless it's already attributed to a file
+    pub fn in_file(self, file: &str) -> ErrorType {
+        match self {
+            ErrorType::InFile(..) => self,
+            err => ErrorType::InFile(file.to_string(), Box::new(err)),
+        }
+    }
+
+    /// Locates an error found by the checker at the given span, unless it already has a location
+    /// of its own
+    pub fn at(self, span: Span) -> ErrorType {
+        match self {
+            ErrorType::Generic(message) => ErrorType::SemanticError(message, span),
+            err => err,
+        }
+    }
+
+    /// Message of a runtime error, which can be caught by a try statement
+    pub fn runtime_message(&self) -> Option<&str> {
+        match self {
+            ErrorType::RuntimeError(message, _) => Some(message),
+            ErrorType::InFile(_, err) => err.runtime_message(),
+            _ => None,
+        }
+    }
 }
 
 // fn get_line_nr_str(line_nr: Option<usize>) -> String {
@@ -161,6 +205,12 @@ pub enum ErrorType {
 //     }
 // }
 
+/// Colors of the prefix and of the underlined spans of errors
+const ERROR_COLORS: (Color, Color) = (Color::LightRed, Color::Red);
+
+/// Colors of the prefix and of the underlined spans of warnings
+const WARNING_COLORS: (Color, Color) = (Color::Yellow, Color::Gold);
+
 #[derive(Debug, PartialEq)]
 pub enum Output {
     Stdout,
@@ -175,59 +225,121 @@ pub fn display_error(err: ErrorType, filename: &str, target: Output) {
         Output::Stderr => eprintln!("{}", msg),
     };
 
-    // Extract error prefix and message based on error type
-    let (prefix, message) = match err {
-        ErrorType::SyntaxError(msg) => ("[Syntax Error]", msg),
-        ErrorType::Generic(msg) => ("[Error]", msg),
+    // Extract error prefix, message and labeled locations based on error type
+    let (prefix, message, labels) = match err {
+        ErrorType::Generic(msg) => ("[Error]", msg, Vec::new()),
+        ErrorType::ToolchainError(msg) => ("[Toolchain Error]", msg, Vec::new()),
+        ErrorType::SyntaxError(msg, span) => ("[Syntax Error]", msg.clone(), vec![(span, msg)]),
+        ErrorType::SemanticError(msg, span) => ("[Error]", msg.clone(), vec![(span, msg)]),
+        ErrorType::TypeError(msg, labels) => ("[Type Error]", msg, labels),
+        ErrorType::RuntimeError(msg, span) => ("[Runtime Error]", msg.clone(), vec![(span, msg)]),
+        ErrorType::ImportError(msg, span) => ("[Import Error]", msg.clone(), vec![(span, msg)]),
+        ErrorType::ManifestError(msg, span) => ("[Manifest Error]", msg.clone(), vec![(span, msg)]),
+        ErrorType::LintError(msg, labels) => ("[Lint Error]", msg, labels),
+        ErrorType::InFile(file, err) => return display_error(*err, &file, target),
     };
 
-    // Short-circuit if line numbering is disabled
-    if env::var("DISABLE_LINE_NUMBER_BACKTRACING").is_ok() {
-        return output_fn(&format!("{} {}", color(prefix, Color::LightRed), message));
+    // The last label is the primary location, line 0 means that it's unknown
+    let located = labels.last().is_some_and(|(span, _)| span.line > 0);
+    if env::var("DISABLE_LINE_NUMBER_BACKTRACING").is_ok() || !located {
+        return output_fn(&format!("{} {message}", color(prefix, Color::LightRed)));
     }
+    output_fn(&format_labels(
+        prefix,
+        &message,
+        &labels,
+        filename,
+        ERROR_COLORS,
+    ));
+}
 
-    // Attempt to read source file and locate error line
-    let source = read_to_string(filename).ok();
-    let Some(line_nr) = source.as_ref().and_then(|src| find_error_line_number(src)) else {
-        return output_fn(&format!("{} {}", color(prefix, Color::LightRed), message));
+/// Display a warning to the user, the way errors with labeled spans are displayed but in yellow
+pub fn display_warning(message: &str, labels: &[(Span, String)], filename: &str, target: Output) {
+    let formatted = if env::var("DISABLE_LINE_NUMBER_BACKTRACING").is_ok() || labels.is_empty() {
+        format!("{} {message}", color("[Warning]", Color::Yellow))
+    } else {
+        format_labels("[Warning]", message, labels, filename, WARNING_COLORS)
     };
+    match target {
+        Output::Stdout => println!("{formatted}"),
+        Output::Stderr => eprintln!("{formatted}"),
+    }
+}
+
+/// Formats an error message along with the source lines of all labeled spans, underlining the
+/// spans and annotating them with their labels. `colors` are the ones of the prefix and of the
+/// underlines
+fn format_labels(
+    prefix: &str,
+    message: &str,
+    labels: &[(Span, String)],
+    filename: &str,
+    (prefix_color, highlight): (Color, Color),
+) -> String {
+    let source = read_to_string(filename).unwrap_or_default();
+    let (primary, _) = labels[labels.len() - 1];
 
-    // Build visual elements for error formatting
     let horizontal_rule = color("─────────────────────────────────", Color::Gray);
     let mut formatted_lines = vec![horizontal_rule.clone()];
 
-    // Add header with filename and line number
+    // Header pointing at the primary (last) span
     formatted_lines.push(format!(
-        "{} {}",
-        color(prefix, Color::LightRed),
-        color(&format!("{filename}:{line_nr}:1"), Color::Underline)
+        "{} {} {message}",
+        color(prefix, prefix_color),
+        color(
+            &format!("{filename}:{}:{}", primary.line, primary.col),
+            Color::Underline
+        )
     ));
 
-    // Add source code snippet if available
-    if let Some(line) = source.as_ref().and_then(|src| src.lines().nth(line_nr - 1)) {
-        let padding = " ".repeat(line_nr.to_string().len()); // Alignment spacing
-
-        formatted_lines.extend([
-            // Line number gutter
-            format!(" {padding}{}", color("|", Color::Gray)),
-            // Source code line
-            format!(
-                "{}{} {line}",
-                color(&line_nr.to_string(), Color::Gray),
+    let padding = " ".repeat(
+        labels
+            .iter()
+            .map(|(span, _)| span.line.to_string().len())
+            .max()
+            .unwrap_or(1),
+    );
+    formatted_lines.push(format!(" {padding}{}", color("|", Color::Gray)));
+
+    let mut last_line = 0;
+    for (span, label) in labels {
+        let line = source
+            .lines()
+            .nth(span.line.saturating_sub(1))
+            .unwrap_or("");
+
+        // Only show the source line once for consecutive labels on the same line
+        if span.line != last_line {
+            let line_nr = span.line.to_string();
+            formatted_lines.push(format!(
+                "{}{}{} {line}",
+                color(&line_nr, Color::Gray),
+                " ".repeat(padding.len() - line_nr.len()),
                 color(" |", Color::Gray)
+            ));
+            last_line = span.line;
+        }
+
+        // Spans reaching over multiple lines are underlined until the end of the first line
+        let end_col = if span.end_line == span.line {
+            span.end_col
+        } else {
+            line.len() + 1
+        };
+        formatted_lines.push(format!(
+            " {padding}{} {}{} {}",
+            color("|", Color::Gray),
+            " ".repeat(span.col.saturating_sub(1)),
+            color(
+                &"‾".repeat(end_col.saturating_sub(span.col).max(1)),
+                highlight
             ),
-            // Error underline and message
-            format!(
-                " {padding}{} {} {}",
-                color("|", Color::Gray),
-                color(&"‾".repeat(line.len()), Color::Red), // Red underline
-                color(&message, Color::Red)
-            ),
-            horizontal_rule, // Closing rule
-        ]);
+            color(label, highlight)
+        ));
     }
 
-    output_fn(&formatted_lines.join("\n"));
+    formatted_lines.push(horizontal_rule);
+    formatted_lines.join("\n")
 }
 
 /// Escapes backslashes and double quotes in a string for safe inclusion in string literals
@@ -235,6 +347,24 @@ pub fn escape_string(s: &str) -> String {
     s.replace("\\", "\\\\").replace("\"", "\\\"")
 }
 
+/// Number of single character insertions, deletions and substitutions turning one string into
+/// the other (Levenshtein distance)
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
 impl From<String> for ErrorType {
     /// Converts a String message into an ErrorType::Generic variant
     fn from(message: String) -> Self {
@@ -242,6 +372,16 @@ impl From<String> for ErrorType {
     }
 }
 
+/// Writes an output file of the compiler, `-` being stdout
+pub fn write_output(path: &Path, data: &[u8]) -> Result<(), ErrorType> {
+    let written = if path == Path::new("-") {
+        stdout().write_all(data).and_then(|_| stdout().flush())
+    } else {
+        write(path, data)
+    };
+    written.map_err(|_| ErrorType::Generic(format!("Could not write \"{}\"", path.display())))
+}
+
 /// Writes data to a file if the given environment variable is set
 pub fn dbg_file_if_env(data: &str, file: &str, var: &str) {
     if env::var(var).is_ok() {
@@ -255,58 +395,3 @@ pub fn dbg_file_if_env(data: &str, file: &str, var: &str) {
             .unwrap();
     }
 }
-
-/// Finds the line number where a syntax error occurs in the given source code
-pub fn find_error_line_number(source: &str) -> Option<usize> {
-    // Early return if disabled line number backtracing
-    if env::var("DISABLE_LINE_NUMBER_BACKTRACING").is_ok() {
-        return None;
-    }
-
-    let mut current_line = 1;
-    let mut context = String::new();
-    let mut compiler = Compiler::new();
-
-    // Iterate through the source line by line
-    for line in source.lines() {
-        // Handle line comments and empty lines
-        if line.starts_with("//") || line.is_empty() {
-            current_line += 1;
-            continue;
-        }
-
-        // Handle inline comments
-        let line = line.split("//").collect::<Vec<&str>>()[0];
-
-        // Append context
-        context.push_str(&format!("{line}\n"));
-
-        // Tokenize and parse the context
-        let context_tokens = match lexer(&context) {
-            Ok(tokens) => tokens,
-            Err(_) => return Some(current_line), // Return current line if lexer fails
-        };
-
-        let mut parser = Parser::new(&context_tokens);
-        let ast = match parser.parse() {
-            Ok(ast) => {
-                context.clear();
-                ast
-            }
-            Err(_) => {
-                // Return the current line where parsing fails
-                return Some(current_line);
-            }
-        };
-
-        compiler.load_ast(ast);
-        if compiler.generate_ir().is_err() {
-            return Some(current_line);
-        }
-
-        // Increment current line counter
-        current_line += 1;
-    }
-
-    None // Return None if no error line is found
-}